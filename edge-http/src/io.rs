@@ -13,6 +13,7 @@ use crate::{BodyType, Headers, Method, RequestHeaders, ResponseHeaders};
 
 pub mod client;
 pub mod server;
+pub mod ssdp;
 
 /// An error in parsing the headers or the body.
 #[derive(Debug)]
@@ -24,6 +25,7 @@ pub enum Error<E> {
     TooLongBody,
     IncompleteHeaders,
     IncompleteBody,
+    UnsupportedContentType,
     InvalidState,
     Timeout,
     WsUpgradeError(UpgradeError),
@@ -75,6 +77,7 @@ where
             Self::TooLongBody => write!(f, "HTTP body is too long"),
             Self::IncompleteHeaders => write!(f, "HTTP headers section is incomplete"),
             Self::IncompleteBody => write!(f, "HTTP body is incomplete"),
+            Self::UnsupportedContentType => write!(f, "HTTP body has unsupported content type"),
             Self::InvalidState => write!(f, "Connection is not in requested state"),
             Self::Timeout => write!(f, "Timeout"),
             Self::WsUpgradeError(e) => write!(f, "WebSocket upgrade error: {e}"),
@@ -137,6 +140,35 @@ impl<'b, const N: usize> RequestHeaders<'b, N> {
         }
     }
 
+    /// Parse the request line and the headers of a request which is fully contained in `buf`
+    /// (e.g. a request received as a UDP datagram).
+    ///
+    /// Returns the length of the headers section, i.e. the offset of the body in `buf`.
+    pub fn parse<E>(&mut self, buf: &'b [u8]) -> Result<usize, Error<E>> {
+        let mut parser = httparse::Request::new(&mut self.headers.0);
+
+        let Status::Complete(headers_len) = parser.parse(buf)? else {
+            return Err(Error::IncompleteHeaders);
+        };
+
+        self.http11 = if let Some(version) = parser.version {
+            if version > 1 {
+                Err(Error::InvalidHeaders)?;
+            }
+
+            Some(version == 1)
+        } else {
+            None
+        };
+
+        self.method = parser.method.and_then(Method::new);
+        self.path = parser.path;
+
+        trace!("Parsed:\n{}", self);
+
+        Ok(headers_len)
+    }
+
     pub async fn send<W>(&self, mut output: W) -> Result<BodyType, Error<W::Error>>
     where
         W: Write,
@@ -285,6 +317,11 @@ where
     let mut body = BodyType::Unknown;
 
     for (name, value) in headers.into_iter() {
+        // Empty names denote optional headers which are not set
+        if name.is_empty() {
+            continue;
+        }
+
         if body == BodyType::Unknown {
             body = BodyType::from_header(name, unsafe { str::from_utf8_unchecked(value) });
         }
@@ -314,6 +351,35 @@ impl<'b, const N: usize> Headers<'b, N> {
     }
 }
 
+/// Read the whole body into `buf`, failing with `Error::TooLongBody` if it does not fit.
+#[cfg(feature = "serde")]
+pub(crate) async fn read_to_end<R, E>(mut input: R, buf: &mut [u8]) -> Result<usize, Error<E>>
+where
+    R: Read<Error = Error<E>>,
+{
+    let mut len = 0;
+
+    loop {
+        if len == buf.len() {
+            let mut byte = [0];
+
+            if input.read(&mut byte).await? > 0 {
+                Err(Error::TooLongBody)?;
+            }
+
+            break Ok(len);
+        }
+
+        let read = input.read(&mut buf[len..]).await?;
+
+        if read == 0 {
+            break Ok(len);
+        }
+
+        len += read;
+    }
+}
+
 #[allow(private_interfaces)]
 pub enum Body<'b, R> {
     Close(PartiallyRead<'b, R>),
@@ -1128,6 +1194,35 @@ mod test {
         expect(b"4\r\nabcdefg", None);
     }
 
+    #[test]
+    fn test_send_headers_skips_empty() {
+        let mut output = [0; 64];
+
+        let (body, len) = embassy_futures::block_on(async {
+            let mut out = &mut output[..];
+
+            let body = core::pin::pin!(send_headers(
+                &[
+                    ("", ""),
+                    ("Upgrade", "websocket"),
+                    ("", ""),
+                    ("Content-Length", "3"),
+                ],
+                &mut out,
+            ))
+            .await
+            .unwrap();
+
+            (body, 64 - out.len())
+        });
+
+        assert_eq!(body, BodyType::ContentLen(3));
+        assert_eq!(
+            &output[..len],
+            b"Upgrade: websocket\r\nContent-Length: 3\r\n"
+        );
+    }
+
     fn expect(input: &[u8], expected: Option<&[u8]>) {
         embassy_futures::block_on(async move {
             let mut buf1 = [0; 64];
@@ -1,3 +1,4 @@
+use core::fmt;
 use core::mem;
 use core::net::SocketAddr;
 use core::str;
@@ -7,7 +8,11 @@ use embedded_io_async::{ErrorType, Read, Write};
 use edge_nal::TcpConnect;
 
 use crate::{
-    ws::{upgrade_request_headers, MAX_BASE64_KEY_LEN, MAX_BASE64_KEY_RESPONSE_LEN, NONCE_LEN},
+    range::{ContentRange, ResumeState},
+    ws::{
+        upgrade_request_headers_with, WsUpgradeOptions, MAX_BASE64_KEY_LEN,
+        MAX_BASE64_KEY_RESPONSE_LEN, NONCE_LEN,
+    },
     DEFAULT_MAX_HEADERS_COUNT,
 };
 
@@ -85,7 +90,30 @@ where
         nonce: &[u8; NONCE_LEN],
         nonce_base64_buf: &mut [u8; MAX_BASE64_KEY_LEN],
     ) -> Result<(), Error<T::Error>> {
-        let headers = upgrade_request_headers(host, origin, version, nonce, nonce_base64_buf);
+        let options = WsUpgradeOptions {
+            host,
+            origin,
+            version,
+            ..Default::default()
+        };
+
+        self.initiate_ws_upgrade_request_with(uri, &options, nonce, nonce_base64_buf)
+            .await
+    }
+
+    /// Like `initiate_ws_upgrade_request`, but also offering the subprotocols and
+    /// the `permessage-deflate` extension of `options`.
+    pub async fn initiate_ws_upgrade_request_with(
+        &mut self,
+        uri: &str,
+        options: &WsUpgradeOptions<'_>,
+        nonce: &[u8; NONCE_LEN],
+        nonce_base64_buf: &mut [u8; MAX_BASE64_KEY_LEN],
+    ) -> Result<(), Error<T::Error>> {
+        let mut extensions_buf = heapless::String::new();
+
+        let headers =
+            upgrade_request_headers_with(options, nonce, nonce_base64_buf, &mut extensions_buf);
 
         self.initiate_request(true, Method::Get, uri, &headers)
             .await
@@ -99,6 +127,214 @@ where
         Ok(self.headers()?.is_ws_upgrade_accepted(nonce, buf))
     }
 
+    /// Return the subprotocol selected by the server out of the `offered` ones (as passed to
+    /// `initiate_ws_upgrade_request_with`), or `None` if the server did not select any.
+    ///
+    /// Fails with `UpgradeError::InvalidProtocol` if the server selected a subprotocol which was not offered,
+    /// in which case the connection should be closed.
+    pub fn ws_upgrade_protocol<'o>(
+        &self,
+        offered: Option<&'o str>,
+    ) -> Result<Option<&'o str>, Error<T::Error>> {
+        Ok(self.headers()?.ws_protocol(offered)?)
+    }
+
+    /// Initiate a GET request which continues a download from the offset recorded in `state`.
+    ///
+    /// If some data had already been received, a `Range` header is sent, as well as an `If-Range` header
+    /// with the validator of the resource, so that the server sends the whole resource anew if it had changed.
+    pub async fn initiate_resume_request(
+        &mut self,
+        uri: &str,
+        headers: &[(&str, &str)],
+        state: &ResumeState,
+    ) -> Result<(), Error<T::Error>> {
+        let mut range_buf = heapless::String::new();
+
+        let range_headers = [
+            if state.offset > 0 {
+                ("Range", state.range(&mut range_buf))
+            } else {
+                ("", "")
+            },
+            if state.offset > 0 && !state.validator.is_empty() {
+                ("If-Range", state.validator.as_str())
+            } else {
+                ("", "")
+            },
+        ];
+
+        self.start_request(
+            true,
+            Method::Get,
+            uri,
+            headers
+                .iter()
+                .chain(range_headers.iter().filter(|(name, _)| !name.is_empty())),
+        )
+        .await
+    }
+
+    /// Complete a request initiated with `initiate_resume_request` and receive the response headers.
+    ///
+    /// The response is checked against `state`, which is updated accordingly:
+    /// - A 206 response must carry a `Content-Range` starting exactly at the current offset,
+    ///   or else `Error::InvalidHeaders` is returned
+    /// - A 200 response means that the server sends the whole resource, so the offset is reset to 0
+    ///   and the data received so far must be discarded
+    /// - A 416 response means that there is nothing more to receive, if the offset is at the end of the resource
+    pub async fn initiate_resume_response(
+        &mut self,
+        state: &mut ResumeState,
+    ) -> Result<Resumption, Error<T::Error>> {
+        self.initiate_response().await?;
+
+        let headers = self.headers()?;
+
+        match headers.code {
+            Some(206) => {
+                let content_range = headers
+                    .headers
+                    .content_range()
+                    .and_then(ContentRange::parse)
+                    .ok_or(Error::InvalidHeaders)?;
+
+                let range = content_range.range.ok_or(Error::InvalidHeaders)?;
+
+                if range.start != state.offset
+                    || state.total.is_some() && content_range.total != state.total
+                {
+                    return Err(Error::InvalidHeaders);
+                }
+
+                state.total = content_range.total;
+
+                Ok(Resumption::Resumed)
+            }
+            Some(200) => {
+                state.offset = 0;
+                state.total = headers.headers.content_len();
+                state.update_validator(headers.headers.iter());
+
+                Ok(Resumption::Restarted)
+            }
+            Some(416) => {
+                let total = headers
+                    .headers
+                    .content_range()
+                    .and_then(ContentRange::parse)
+                    .and_then(|content_range| content_range.total);
+
+                if total.is_some() && total == Some(state.offset) {
+                    state.total = total;
+
+                    Ok(Resumption::Complete)
+                } else {
+                    Ok(Resumption::Rejected(416))
+                }
+            }
+            code => Ok(Resumption::Rejected(code.unwrap_or(0))),
+        }
+    }
+
+    /// Read the body of a response received with `initiate_resume_response`,
+    /// advancing the offset recorded in `state`.
+    pub async fn read_resumable(
+        &mut self,
+        state: &mut ResumeState,
+        buf: &mut [u8],
+    ) -> Result<usize, Error<T::Error>> {
+        let read = self.read(buf).await?;
+
+        state.offset += read as u64;
+
+        Ok(read)
+    }
+
+    /// Initiate a request with `value` serialized into `buf` as its JSON body.
+    ///
+    /// The `Content-Type` and `Content-Length` headers are set automatically,
+    /// and should not be present in `headers`.
+    #[cfg(feature = "serde")]
+    pub async fn initiate_json_request<S>(
+        &mut self,
+        method: Method,
+        uri: &str,
+        headers: &[(&str, &str)],
+        value: &S,
+        buf: &mut [u8],
+    ) -> Result<(), Error<T::Error>>
+    where
+        S: serde::Serialize + ?Sized,
+    {
+        let len = crate::json::to_slice(value, buf).map_err(|_| Error::TooLongBody)?;
+
+        let len_str: heapless::String<20> = (len as u64).try_into().unwrap();
+
+        let json_headers = [
+            ("Content-Type", crate::json::CONTENT_TYPE),
+            ("Content-Length", len_str.as_str()),
+        ];
+
+        self.start_request(true, method, uri, json_headers.iter().chain(headers.iter()))
+            .await?;
+
+        self.write_all(&buf[..len]).await
+    }
+
+    /// Read the JSON body of the response into `buf` and deserialize it.
+    ///
+    /// Fails with `Error::UnsupportedContentType` if the response does not have a JSON body,
+    /// with `Error::TooLongBody` if the body does not fit in `buf` and with `Error::InvalidBody`
+    /// if it cannot be deserialized.
+    #[cfg(feature = "serde")]
+    pub async fn read_json<'a, D>(&mut self, buf: &'a mut [u8]) -> Result<D, Error<T::Error>>
+    where
+        D: serde::Deserialize<'a>,
+    {
+        if !crate::json::is_json(self.headers()?.headers.content_type()) {
+            return Err(Error::UnsupportedContentType);
+        }
+
+        let len = super::read_to_end(&mut *self, buf).await?;
+
+        crate::json::from_slice(&buf[..len]).map_err(|_| Error::InvalidBody)
+    }
+
+    /// Copy the whole of `source` into the body of the initiated request.
+    ///
+    /// Each chunk read from `source` is fully written to the connection before the next one is read.
+    /// Unless a `Content-Length` header was provided, HTTP/1.1 requests use chunked encoding,
+    /// so the length of `source` need not be known upfront.
+    ///
+    /// Returns the number of bytes copied.
+    pub async fn write_from<R>(
+        &mut self,
+        mut source: R,
+        buf: &mut [u8],
+    ) -> Result<u64, WriteFromError<T::Error, R::Error>>
+    where
+        R: Read,
+    {
+        self.request_mut()?;
+
+        let mut copied = 0;
+
+        loop {
+            let read = source.read(buf).await.map_err(WriteFromError::Source)?;
+
+            if read == 0 {
+                break;
+            }
+
+            self.write_all(&buf[..read]).await?;
+
+            copied += read as u64;
+        }
+
+        Ok(copied)
+    }
+
     #[allow(clippy::type_complexity)]
     pub fn split(&mut self) -> (&ResponseHeaders<'b, N>, &mut Body<'b, T::Socket<'b>>) {
         let response = self.response_mut().expect("Not in response mode");
@@ -124,13 +360,16 @@ where
         (io, state.buf)
     }
 
-    async fn start_request(
+    async fn start_request<'h, H>(
         &mut self,
         http11: bool,
         method: Method,
         uri: &str,
-        headers: &[(&str, &str)],
-    ) -> Result<(), Error<T::Error>> {
+        headers: H,
+    ) -> Result<(), Error<T::Error>>
+    where
+        H: IntoIterator<Item = &'h (&'h str, &'h str)>,
+    {
         let _ = self.complete().await;
 
         let state = self.unbound_mut()?;
@@ -162,7 +401,13 @@ where
 
             let io = state.io.as_mut().unwrap();
 
-            let body_type = send_headers(headers, &mut *io).await?;
+            let mut body_type = send_headers(headers, &mut *io).await?;
+
+            if http11 && matches!(body_type, BodyType::Unknown) && method_has_body(method) {
+                send_headers(&[("Transfer-Encoding", "Chunked")], &mut *io).await?;
+                body_type = BodyType::Chunked;
+            }
+
             send_headers_end(io).await?;
 
             Ok(body_type)
@@ -372,6 +617,65 @@ where
     }
 }
 
+/// The outcome of resuming a download with `Connection::initiate_resume_response`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Resumption {
+    /// The body continues at the offset recorded in the `ResumeState`
+    Resumed,
+    /// The body contains the whole resource, starting at offset 0
+    Restarted,
+    /// The whole resource had already been received
+    Complete,
+    /// The server answered with a status code which does not allow resuming the download
+    Rejected(u16),
+}
+
+/// An error returned by `Connection::write_from`.
+#[derive(Debug)]
+pub enum WriteFromError<C, E> {
+    Connection(Error<C>),
+    Source(E),
+}
+
+impl<C, E> From<Error<C>> for WriteFromError<C, E> {
+    fn from(e: Error<C>) -> Self {
+        Self::Connection(e)
+    }
+}
+
+impl<C, E> fmt::Display for WriteFromError<C, E>
+where
+    C: fmt::Display,
+    E: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connection(e) => write!(f, "Connection error: {}", e),
+            Self::Source(e) => write!(f, "Source error: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<C, E> std::error::Error for WriteFromError<C, E>
+where
+    C: std::error::Error,
+    E: std::error::Error,
+{
+}
+
+fn method_has_body(method: Method) -> bool {
+    !matches!(
+        method,
+        Method::Get
+            | Method::Head
+            | Method::Delete
+            | Method::Options
+            | Method::Trace
+            | Method::Connect
+    )
+}
+
 struct TransitionState(());
 
 struct UnboundState<'b, T, const N: usize>
@@ -484,3 +788,443 @@ mod embedded_svc_compat {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use core::cell::{Cell, RefCell};
+    use core::convert::Infallible;
+    use core::net::{Ipv4Addr, SocketAddr};
+    use core::pin::pin;
+
+    use embedded_io_async::{ErrorKind, ErrorType, Read, Write};
+
+    use edge_nal::{Readable, TcpConnect};
+
+    use super::*;
+
+    const RESPONSE: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n";
+
+    /// A server replying with a canned response (`RESPONSE` by default) and recording the request
+    struct Server {
+        response: &'static [u8],
+        request: RefCell<heapless::Vec<u8, 512>>,
+        read: Cell<usize>,
+    }
+
+    impl Server {
+        const fn new() -> Self {
+            Self::with_response(RESPONSE)
+        }
+
+        const fn with_response(response: &'static [u8]) -> Self {
+            Self {
+                response,
+                request: RefCell::new(heapless::Vec::new()),
+                read: Cell::new(0),
+            }
+        }
+
+        fn request(&self) -> heapless::String<512> {
+            heapless::String::from_utf8(self.request.borrow().clone()).unwrap()
+        }
+    }
+
+    struct Socket<'a>(&'a Server);
+
+    impl ErrorType for Socket<'_> {
+        type Error = Infallible;
+    }
+
+    impl Read for Socket<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let offset = self.0.read.get();
+            let response = self.0.response;
+            let len = buf.len().min(response.len() - offset);
+
+            buf[..len].copy_from_slice(&response[offset..offset + len]);
+            self.0.read.set(offset + len);
+
+            Ok(len)
+        }
+    }
+
+    impl Write for Socket<'_> {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.0.request.borrow_mut().extend_from_slice(buf).unwrap();
+
+            Ok(buf.len())
+        }
+    }
+
+    impl Readable for Socket<'_> {
+        async fn readable(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    impl TcpConnect for Server {
+        type Error = Infallible;
+
+        type Socket<'a>
+            = Socket<'a>
+        where
+            Self: 'a;
+
+        async fn connect(&self, _remote: SocketAddr) -> Result<Self::Socket<'_>, Self::Error> {
+            Ok(Socket(self))
+        }
+    }
+
+    /// A source failing after yielding some data
+    struct FailingSource(bool);
+
+    impl ErrorType for FailingSource {
+        type Error = ErrorKind;
+    }
+
+    impl Read for FailingSource {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            if self.0 {
+                return Err(ErrorKind::BrokenPipe);
+            }
+
+            self.0 = true;
+            buf[..5].copy_from_slice(b"hello");
+
+            Ok(5)
+        }
+    }
+
+    const ADDR: SocketAddr = SocketAddr::new(core::net::IpAddr::V4(Ipv4Addr::LOCALHOST), 80);
+
+    fn send(
+        http11: bool,
+        method: Method,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> heapless::String<512> {
+        let server = Server::new();
+
+        embassy_futures::block_on(async {
+            let mut buf = [0; 256];
+            let mut connection: Connection<_> = Connection::new(&mut buf, &server, ADDR);
+
+            pin!(connection.initiate_request(http11, method, "/", headers))
+                .await
+                .unwrap();
+            pin!(connection.write_all(body)).await.unwrap();
+            pin!(connection.complete()).await.unwrap();
+        });
+
+        server.request()
+    }
+
+    #[test]
+    fn test_chunked_request() {
+        let request = send(true, Method::Post, &[], b"hello");
+
+        assert!(request.starts_with("POST / HTTP/1.1\r\n"));
+        assert!(request.contains("Transfer-Encoding: Chunked\r\n"));
+        assert!(!request.contains("Content-Length"));
+        assert!(request.ends_with("\r\n\r\n5\r\nhello\r\n0\r\n\r\n"));
+
+        // No body for requests with methods which do not have one
+        let request = send(true, Method::Get, &[], b"");
+
+        assert!(!request.contains("Transfer-Encoding"));
+        assert!(request.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_content_len_request() {
+        let request = send(true, Method::Post, &[("Content-Length", "5")], b"hello");
+
+        assert!(request.contains("Content-Length: 5\r\n"));
+        assert!(!request.contains("Transfer-Encoding"));
+        assert!(request.ends_with("\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn test_http10_request() {
+        let request = send(false, Method::Post, &[("Content-Length", "5")], b"hello");
+
+        assert!(request.starts_with("POST / HTTP/1.0\r\n"));
+        assert!(!request.contains("Transfer-Encoding"));
+        assert!(request.ends_with("\r\n\r\nhello"));
+
+        // HTTP/1.0 has no chunked encoding
+        let request = send(false, Method::Post, &[], b"");
+
+        assert!(!request.contains("Transfer-Encoding"));
+        assert!(request.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_write_from() {
+        let server = Server::new();
+
+        embassy_futures::block_on(async {
+            let mut buf = [0; 256];
+            let mut connection: Connection<_> = Connection::new(&mut buf, &server, ADDR);
+
+            pin!(connection.initiate_request(true, Method::Put, "/", &[]))
+                .await
+                .unwrap();
+
+            let mut copy_buf = [0; 16];
+
+            let result = pin!(connection.write_from(FailingSource(false), &mut copy_buf)).await;
+
+            assert!(matches!(
+                result,
+                Err(WriteFromError::Source(ErrorKind::BrokenPipe))
+            ));
+        });
+
+        // The data read before the error was sent
+        assert!(server.request().ends_with("\r\n\r\n5\r\nhello\r\n"));
+
+        let server = Server::new();
+
+        embassy_futures::block_on(async {
+            let mut buf = [0; 256];
+            let mut connection: Connection<_> = Connection::new(&mut buf, &server, ADDR);
+
+            // No request initiated
+            let mut copy_buf = [0; 16];
+
+            let result = pin!(connection.write_from(FailingSource(false), &mut copy_buf)).await;
+
+            assert!(matches!(
+                result,
+                Err(WriteFromError::Connection(Error::InvalidState))
+            ));
+        });
+    }
+
+    /// Resume the download recorded in `state` from a server replying with `response`,
+    /// returning the outcome, the received body and the request
+    fn resume(
+        response: &'static [u8],
+        state: &mut ResumeState,
+    ) -> (
+        Result<Resumption, Error<Infallible>>,
+        heapless::Vec<u8, 64>,
+        heapless::String<512>,
+    ) {
+        let server = Server::with_response(response);
+        let mut body = heapless::Vec::new();
+
+        let result = embassy_futures::block_on(async {
+            let mut buf = [0; 256];
+            let mut connection: Connection<_> = Connection::new(&mut buf, &server, ADDR);
+
+            pin!(connection.initiate_resume_request("/file", &[], state))
+                .await
+                .unwrap();
+
+            let resumption = pin!(connection.initiate_resume_response(state)).await?;
+
+            let mut read_buf = [0; 4];
+
+            loop {
+                let read = pin!(connection.read_resumable(state, &mut read_buf)).await?;
+
+                if read == 0 {
+                    break;
+                }
+
+                body.extend_from_slice(&read_buf[..read]).unwrap();
+            }
+
+            Ok(resumption)
+        });
+
+        (result, body, server.request())
+    }
+
+    fn resume_state(offset: u64, total: Option<u64>, validator: &str) -> ResumeState {
+        let mut state = ResumeState::new();
+
+        state.offset = offset;
+        state.total = total;
+        state.validator.push_str(validator).unwrap();
+
+        state
+    }
+
+    #[test]
+    fn test_resume() {
+        let mut state = resume_state(4, Some(10), "\"v1\"");
+
+        let (result, body, request) = resume(
+            b"HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 4-9/10\r\nContent-Length: 6\r\n\r\n456789",
+            &mut state,
+        );
+
+        assert!(matches!(result, Ok(Resumption::Resumed)));
+        assert_eq!(body, b"456789");
+        assert_eq!(state.offset, 10);
+        assert!(state.is_complete());
+        assert!(request.starts_with("GET /file HTTP/1.1\r\n"));
+        assert!(request.contains("Range: bytes=4-\r\n"));
+        assert!(request.contains("If-Range: \"v1\"\r\n"));
+
+        // Nothing received yet, so no range is requested
+        let mut state = ResumeState::new();
+
+        let (result, body, request) = resume(
+            b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\n0123",
+            &mut state,
+        );
+
+        assert!(matches!(result, Ok(Resumption::Restarted)));
+        assert_eq!(body, b"0123");
+        assert!(!request.contains("Range"));
+    }
+
+    #[test]
+    fn test_resume_content_range() {
+        for response in [
+            // Not starting at the offset
+            &b"HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 5-9/10\r\nContent-Length: 5\r\n\r\n56789"[..],
+            // Another total length
+            b"HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 4-9/12\r\nContent-Length: 6\r\n\r\n456789",
+            // No range
+            b"HTTP/1.1 206 Partial Content\r\nContent-Length: 6\r\n\r\n456789",
+            b"HTTP/1.1 206 Partial Content\r\nContent-Range: bytes */10\r\nContent-Length: 6\r\n\r\n456789",
+        ] {
+            let mut state = resume_state(4, Some(10), "\"v1\"");
+
+            let (result, _, _) = resume(response, &mut state);
+
+            assert!(matches!(result, Err(Error::InvalidHeaders)));
+            assert_eq!(state.offset, 4);
+        }
+    }
+
+    #[test]
+    fn test_resume_restart() {
+        let mut state = resume_state(4, Some(10), "\"v1\"");
+
+        // The resource changed, so the server sends all of it
+        let (result, body, _) = resume(
+            b"HTTP/1.1 200 OK\r\nContent-Length: 12\r\nETag: \"v2\"\r\n\r\nabcdefghijkl",
+            &mut state,
+        );
+
+        assert!(matches!(result, Ok(Resumption::Restarted)));
+        assert_eq!(body, b"abcdefghijkl");
+        assert_eq!(state.offset, 12);
+        assert_eq!(state.total, Some(12));
+        assert_eq!(state.validator, "\"v2\"");
+    }
+
+    #[test]
+    fn test_resume_complete() {
+        let response = b"HTTP/1.1 416 Range Not Satisfiable\r\nContent-Range: bytes */10\r\nContent-Length: 0\r\n\r\n";
+
+        let mut state = resume_state(10, None, "\"v1\"");
+
+        let (result, body, _) = resume(response, &mut state);
+
+        assert!(matches!(result, Ok(Resumption::Complete)));
+        assert!(body.is_empty());
+        assert_eq!(state.total, Some(10));
+        assert!(state.is_complete());
+
+        // The offset is not at the end of the resource
+        let mut state = resume_state(4, None, "\"v1\"");
+
+        let (result, _, _) = resume(response, &mut state);
+
+        assert!(matches!(result, Ok(Resumption::Rejected(416))));
+        assert_eq!(state.offset, 4);
+
+        let mut state = resume_state(4, None, "");
+
+        let (result, _, request) = resume(
+            b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n",
+            &mut state,
+        );
+
+        assert!(matches!(result, Ok(Resumption::Rejected(404))));
+        assert!(!request.contains("If-Range"));
+    }
+
+    /// Send a JSON request, and read the JSON body of `response` into a buffer of `json_len` bytes
+    #[cfg(feature = "serde")]
+    fn json(
+        response: &'static [u8],
+        json_len: usize,
+    ) -> (
+        Result<(u32, bool), Error<Infallible>>,
+        heapless::String<512>,
+    ) {
+        let server = Server::with_response(response);
+
+        let result = embassy_futures::block_on(async {
+            let mut buf = [0; 256];
+            let mut connection: Connection<_> = Connection::new(&mut buf, &server, ADDR);
+
+            let mut json_buf = [0; 64];
+
+            pin!(connection.initiate_json_request(
+                Method::Post,
+                "/",
+                &[("Accept", "application/json")],
+                &(1, false),
+                &mut json_buf,
+            ))
+            .await
+            .unwrap();
+            pin!(connection.initiate_response()).await.unwrap();
+
+            let result = pin!(connection.read_json(&mut json_buf[..json_len])).await;
+
+            result
+        });
+
+        (result, server.request())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json() {
+        let (result, request) = json(
+            b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 8\r\n\r\n[2,true]",
+            64,
+        );
+
+        assert!(matches!(result, Ok((2, true))));
+
+        assert!(request.starts_with("POST / HTTP/1.1\r\n"));
+        assert!(request.contains("Content-Type: application/json\r\n"));
+        assert!(request.contains("Content-Length: 9\r\n"));
+        assert!(request.contains("Accept: application/json\r\n"));
+        assert!(!request.contains("Transfer-Encoding"));
+        assert!(request.ends_with("\r\n\r\n[1,false]"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_errors() {
+        let (result, _) = json(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 8\r\n\r\n[2,true]",
+            64,
+        );
+        assert!(matches!(result, Err(Error::UnsupportedContentType)));
+
+        let (result, _) = json(
+            b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 8\r\n\r\n[2,true]",
+            4,
+        );
+        assert!(matches!(result, Err(Error::TooLongBody)));
+
+        let (result, _) = json(
+            b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 5\r\n\r\n[2,3]",
+            64,
+        );
+        assert!(matches!(result, Err(Error::InvalidBody)));
+    }
+}
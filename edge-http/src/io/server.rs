@@ -7,7 +7,7 @@ use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 use embassy_sync::mutex::Mutex;
 use embassy_time::{Duration, Timer};
 
-use embedded_io_async::{ErrorType, Read, Write};
+use embedded_io_async::{ErrorType, Read, Seek, SeekFrom, Write};
 
 use log::{debug, info, warn};
 
@@ -15,18 +15,28 @@ use super::{
     send_headers, send_headers_end, send_status, Body, BodyType, Error, RequestHeaders, SendBody,
 };
 
-use crate::ws::{upgrade_response_headers, MAX_BASE64_KEY_RESPONSE_LEN};
-use crate::DEFAULT_MAX_HEADERS_COUNT;
+use crate::range::{
+    multipart_end, multipart_len, multipart_part_header, ByteRange, ContentRange, Ranges,
+    MAX_BOUNDARY_LEN, MAX_CONTENT_RANGE_LEN,
+};
+use crate::ws::{
+    upgrade_response_headers, upgrade_response_headers_with, WsUpgradeResponseOptions,
+    MAX_BASE64_KEY_RESPONSE_LEN,
+};
+use crate::{Method, DEFAULT_MAX_HEADERS_COUNT};
 
 #[allow(unused_imports)]
 #[cfg(feature = "embedded-svc")]
 pub use embedded_svc_compat::*;
 
+pub mod webdav;
+
 pub const DEFAULT_HANDLER_TASKS_COUNT: usize = 4;
 pub const DEFAULT_BUF_SIZE: usize = 2048;
 pub const DEFAULT_TIMEOUT_MS: u32 = 5000;
 
 const COMPLETION_BUF_SIZE: usize = 64;
+const COPY_BUF_SIZE: usize = 256;
 
 #[allow(private_interfaces)]
 pub enum Connection<'b, T, const N: usize = DEFAULT_MAX_HEADERS_COUNT> {
@@ -98,6 +108,14 @@ where
         self.complete_request(Some(status), message, headers).await
     }
 
+    /// Select the first of the `supported` subprotocols which is offered by the client, if any.
+    pub fn select_ws_protocol<'s>(
+        &self,
+        supported: &[&'s str],
+    ) -> Result<Option<&'s str>, Error<T::Error>> {
+        Ok(self.headers()?.select_ws_protocol(supported))
+    }
+
     pub async fn initiate_ws_upgrade_response(
         &mut self,
         buf: &mut [u8; MAX_BASE64_KEY_RESPONSE_LEN],
@@ -107,10 +125,265 @@ where
         self.initiate_response(101, None, &headers).await
     }
 
+    /// Like `initiate_ws_upgrade_response`, but also accepting the subprotocol
+    /// (see `select_ws_protocol`) and the `permessage-deflate` extension of `options`.
+    pub async fn initiate_ws_upgrade_response_with(
+        &mut self,
+        options: &WsUpgradeResponseOptions<'_>,
+        buf: &mut [u8; MAX_BASE64_KEY_RESPONSE_LEN],
+    ) -> Result<(), Error<T::Error>> {
+        let mut extensions_buf = heapless::String::new();
+
+        let headers = upgrade_response_headers_with(
+            self.headers()?.headers.iter(),
+            options,
+            buf,
+            &mut extensions_buf,
+        )?;
+
+        self.initiate_response(101, None, &headers).await
+    }
+
     pub fn is_response_initiated(&self) -> bool {
         matches!(self, Self::Response(_))
     }
 
+    /// Read the JSON body of the request into `buf` and deserialize it.
+    ///
+    /// If the request does not have a JSON body, or if the body does not fit in `buf`, or if it
+    /// cannot be deserialized, an error response (415, 413 or 400 respectively) is sent and an error is returned.
+    #[cfg(feature = "serde")]
+    pub async fn read_json<'a, D>(&mut self, buf: &'a mut [u8]) -> Result<D, Error<T::Error>>
+    where
+        D: serde::Deserialize<'a>,
+    {
+        if !crate::json::is_json(self.headers()?.headers.content_type()) {
+            self.initiate_response(
+                415,
+                Some("Unsupported Media Type"),
+                &[("Content-Length", "0")],
+            )
+            .await?;
+
+            return Err(Error::UnsupportedContentType);
+        }
+
+        let len = match super::read_to_end(&mut *self, buf).await {
+            Ok(len) => len,
+            Err(Error::TooLongBody) => {
+                self.initiate_response(413, Some("Payload Too Large"), &[("Content-Length", "0")])
+                    .await?;
+
+                return Err(Error::TooLongBody);
+            }
+            Err(e) => return Err(e),
+        };
+
+        match crate::json::from_slice(&buf[..len]) {
+            Ok(value) => Ok(value),
+            Err(e) => {
+                warn!("Invalid JSON request body: {e}");
+
+                self.initiate_response(400, Some("Bad Request"), &[("Content-Length", "0")])
+                    .await?;
+
+                Err(Error::InvalidBody)
+            }
+        }
+    }
+
+    /// Serialize `value` into `buf` and send it as the JSON body of a response with the provided status.
+    ///
+    /// The `Content-Type` and `Content-Length` headers are set automatically,
+    /// and should not be present in `headers`.
+    #[cfg(feature = "serde")]
+    pub async fn respond_json<S>(
+        &mut self,
+        status: u16,
+        message: Option<&str>,
+        headers: &[(&str, &str)],
+        value: &S,
+        buf: &mut [u8],
+    ) -> Result<(), Error<T::Error>>
+    where
+        S: serde::Serialize + ?Sized,
+    {
+        let len = crate::json::to_slice(value, buf).map_err(|_| Error::TooLongBody)?;
+
+        let len_str: heapless::String<20> = (len as u64).try_into().unwrap();
+
+        let json_headers = [
+            ("Content-Type", crate::json::CONTENT_TYPE),
+            ("Content-Length", len_str.as_str()),
+        ];
+
+        self.complete_request(
+            Some(status),
+            message,
+            json_headers.iter().chain(headers.iter()),
+        )
+        .await?;
+
+        self.write_all(&buf[..len]).await
+    }
+
+    /// Respond to the request with (parts of) a seekable representation of length `len`.
+    ///
+    /// The `Range` and `If-Range` request headers are evaluated, and - depending on the outcome -
+    /// either the full representation is sent with a 200 status, or the requested ranges are sent
+    /// with a 206 status, or a 416 status is sent if none of the ranges is satisfiable.
+    ///
+    /// `validator` is the strong `ETag` (quotes included) or the `Last-Modified` date of the representation,
+    /// if the handler has one. It is compared against `If-Range`, and is sent back as `ETag` if it is an entity tag.
+    ///
+    /// Multiple ranges are sent as a `multipart/byteranges` body using the provided `multipart_boundary`.
+    /// Without a boundary, multiple ranges are coalesced into a single range covering all of them.
+    pub async fn serve_seekable<S>(
+        &mut self,
+        mut source: S,
+        len: u64,
+        content_type: Option<&str>,
+        validator: Option<&str>,
+        multipart_boundary: Option<&str>,
+    ) -> Result<(), HandleRequestError<T::Error, S::Error>>
+    where
+        S: Read + Seek,
+    {
+        let headers = self.headers()?;
+
+        // Only the headers are sent in reply to a HEAD request
+        let head = matches!(headers.method, Some(Method::Head));
+
+        let ranges = if matches!(headers.method, Some(Method::Get)) {
+            Ranges::from_headers(headers.headers.iter(), len, validator)
+        } else {
+            Ranges::Full
+        };
+
+        let multipart_boundary = multipart_boundary
+            .filter(|boundary| !boundary.is_empty() && boundary.len() <= MAX_BOUNDARY_LEN);
+
+        let etag = validator.filter(|validator| validator.trim_start().starts_with('"'));
+
+        let len_buf: heapless::String<20>;
+        let mut content_range_buf = heapless::String::<MAX_CONTENT_RANGE_LEN>::new();
+        let mut content_type_buf = heapless::String::<{ MAX_BOUNDARY_LEN + 32 }>::new();
+
+        let mut response_headers = heapless::Vec::<_, 5>::new();
+
+        let _ = response_headers.push(("Accept-Ranges", "bytes"));
+
+        if let Some(etag) = etag {
+            let _ = response_headers.push(("ETag", etag));
+        }
+
+        match &ranges {
+            Ranges::Full => {
+                len_buf = len.try_into().unwrap();
+
+                let _ = response_headers.push(("Content-Length", len_buf.as_str()));
+
+                if let Some(content_type) = content_type {
+                    let _ = response_headers.push(("Content-Type", content_type));
+                }
+
+                self.initiate_response(200, Some("OK"), &response_headers)
+                    .await?;
+
+                if head {
+                    // The response to a HEAD request has no body, regardless of its `Content-Length`
+                    let io = self.unbind_mut();
+                    *self = Self::Response(SendBody::new(BodyType::ContentLen(0), io));
+
+                    return Ok(());
+                }
+
+                self.copy_range(
+                    &mut source,
+                    (len > 0).then_some(ByteRange {
+                        start: 0,
+                        end: len.saturating_sub(1),
+                    }),
+                )
+                .await
+            }
+            Ranges::Unsatisfiable => {
+                use core::fmt::Write as _;
+
+                write!(
+                    &mut content_range_buf,
+                    "{}",
+                    ContentRange::new(None, Some(len))
+                )
+                .unwrap();
+
+                let _ = response_headers.push(("Content-Range", content_range_buf.as_str()));
+                let _ = response_headers.push(("Content-Length", "0"));
+
+                self.initiate_response(416, Some("Range Not Satisfiable"), &response_headers)
+                    .await?;
+
+                Ok(())
+            }
+            Ranges::Partial(parts) if parts.len() > 1 && multipart_boundary.is_some() => {
+                use core::fmt::Write as _;
+
+                let boundary = multipart_boundary.unwrap();
+
+                len_buf = multipart_len(parts, len, boundary, content_type)
+                    .try_into()
+                    .unwrap();
+                write!(
+                    &mut content_type_buf,
+                    "multipart/byteranges; boundary={boundary}"
+                )
+                .unwrap();
+
+                let _ = response_headers.push(("Content-Length", len_buf.as_str()));
+                let _ = response_headers.push(("Content-Type", content_type_buf.as_str()));
+
+                self.initiate_response(206, Some("Partial Content"), &response_headers)
+                    .await?;
+
+                for range in parts {
+                    let content_range = range.content_range(Some(len), &mut content_range_buf);
+
+                    for piece in multipart_part_header(boundary, content_type, content_range) {
+                        self.write_all(piece.as_bytes()).await?;
+                    }
+
+                    self.copy_range(&mut source, Some(*range)).await?;
+                }
+
+                for piece in multipart_end(boundary) {
+                    self.write_all(piece.as_bytes()).await?;
+                }
+
+                Ok(())
+            }
+            Ranges::Partial(_) => {
+                let range = ranges.coalesced().unwrap();
+
+                len_buf = range.len().try_into().unwrap();
+
+                let _ = response_headers.push(("Content-Length", len_buf.as_str()));
+                let _ = response_headers.push((
+                    "Content-Range",
+                    range.content_range(Some(len), &mut content_range_buf),
+                ));
+
+                if let Some(content_type) = content_type {
+                    let _ = response_headers.push(("Content-Type", content_type));
+                }
+
+                self.initiate_response(206, Some("Partial Content"), &response_headers)
+                    .await?;
+
+                self.copy_range(&mut source, Some(range)).await
+            }
+        }
+    }
+
     pub async fn complete(&mut self) -> Result<(), Error<T::Error>> {
         if self.is_request_initiated() {
             self.complete_request(Some(200), Some("OK"), &[]).await?;
@@ -158,12 +431,15 @@ where
         Ok(self.io_mut())
     }
 
-    async fn complete_request(
+    async fn complete_request<'h, H>(
         &mut self,
         status: Option<u16>,
         reason: Option<&str>,
-        headers: &[(&str, &str)],
-    ) -> Result<(), Error<T::Error>> {
+        headers: H,
+    ) -> Result<(), Error<T::Error>>
+    where
+        H: IntoIterator<Item = &'h (&'h str, &'h str)>,
+    {
         let request = self.request_mut()?;
 
         let mut buf = [0; COMPLETION_BUF_SIZE];
@@ -176,7 +452,7 @@ where
         let result = async {
             send_status(http11, status, reason, &mut io).await?;
             let mut body_type = send_headers(
-                headers.iter().filter(|(k, v)| {
+                headers.into_iter().filter(|(k, v)| {
                     http11
                         || !k.eq_ignore_ascii_case("Transfer-Encoding")
                         || !v.eq_ignore_ascii_case("Chunked")
@@ -223,6 +499,46 @@ where
         Ok(())
     }
 
+    async fn copy_range<S>(
+        &mut self,
+        mut source: S,
+        range: Option<ByteRange>,
+    ) -> Result<(), HandleRequestError<T::Error, S::Error>>
+    where
+        S: Read + Seek,
+    {
+        let Some(range) = range else {
+            return Ok(());
+        };
+
+        source
+            .seek(SeekFrom::Start(range.start))
+            .await
+            .map_err(HandleRequestError::Handler)?;
+
+        let mut buf = [0; COPY_BUF_SIZE];
+        let mut remaining = range.len();
+
+        while remaining > 0 {
+            let len = remaining.min(buf.len() as u64) as usize;
+
+            let read = source
+                .read(&mut buf[..len])
+                .await
+                .map_err(HandleRequestError::Handler)?;
+
+            if read == 0 {
+                Err(Error::IncompleteBody)?;
+            }
+
+            self.write_all(&buf[..read]).await?;
+
+            remaining -= read as u64;
+        }
+
+        Ok(())
+    }
+
     fn unbind_mut(&mut self) -> T {
         let state = mem::replace(self, Self::Transition(TransitionState(())));
 
@@ -782,3 +1098,347 @@ mod embedded_svc_compat {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use core::convert::Infallible;
+
+    use embedded_io_async::{ErrorType, Read, Seek, SeekFrom, Write};
+
+    use super::*;
+
+    /// A connection replaying a request and recording the response
+    struct Conn<'a> {
+        input: &'a [u8],
+        output: &'a mut [u8],
+        written: usize,
+    }
+
+    impl ErrorType for Conn<'_> {
+        type Error = Infallible;
+    }
+
+    impl Read for Conn<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let len = buf.len().min(self.input.len());
+            buf[..len].copy_from_slice(&self.input[..len]);
+
+            self.input = &self.input[len..];
+
+            Ok(len)
+        }
+    }
+
+    impl Write for Conn<'_> {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.output[self.written..self.written + buf.len()].copy_from_slice(buf);
+            self.written += buf.len();
+
+            Ok(buf.len())
+        }
+    }
+
+    /// A seekable in-memory representation
+    struct Source(&'static [u8], usize);
+
+    impl ErrorType for Source {
+        type Error = Infallible;
+    }
+
+    impl Read for Source {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let len = buf.len().min(self.0.len() - self.1);
+            buf[..len].copy_from_slice(&self.0[self.1..self.1 + len]);
+
+            self.1 += len;
+
+            Ok(len)
+        }
+    }
+
+    impl Seek for Source {
+        async fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+            let SeekFrom::Start(offset) = pos else {
+                unreachable!();
+            };
+
+            self.1 = offset as usize;
+
+            Ok(offset)
+        }
+    }
+
+    fn serve<'o>(request: &[u8], output: &'o mut [u8]) -> &'o str {
+        serve_with(request, None, None, output)
+    }
+
+    fn serve_with<'o>(
+        request: &[u8],
+        validator: Option<&str>,
+        multipart_boundary: Option<&str>,
+        output: &'o mut [u8],
+    ) -> &'o str {
+        let written = embassy_futures::block_on(async {
+            let mut buf = [0; 512];
+
+            let mut conn = Conn {
+                input: request,
+                output: &mut *output,
+                written: 0,
+            };
+
+            {
+                let mut connection: Connection<_> =
+                    pin!(Connection::new(&mut buf, &mut conn, None))
+                        .await
+                        .unwrap();
+
+                pin!(connection.serve_seekable(
+                    Source(b"0123456789", 0),
+                    10,
+                    Some("text/plain"),
+                    validator,
+                    multipart_boundary,
+                ))
+                .await
+                .unwrap();
+                pin!(connection.complete()).await.unwrap();
+            }
+
+            conn.written
+        });
+
+        core::str::from_utf8(&output[..written]).unwrap()
+    }
+
+    #[test]
+    fn test_serve_seekable() {
+        let mut output = [0; 512];
+
+        let response = serve(b"GET /file HTTP/1.1\r\n\r\n", &mut output);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Length: 10\r\n"));
+        assert!(response.ends_with("\r\n\r\n0123456789"));
+
+        let response = serve(
+            b"GET /file HTTP/1.1\r\nRange: bytes=2-4\r\n\r\n",
+            &mut output,
+        );
+        assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert!(response.contains("Content-Range: bytes 2-4/10\r\n"));
+        assert!(response.ends_with("\r\n\r\n234"));
+    }
+
+    #[test]
+    fn test_serve_seekable_head() {
+        let mut output = [0; 512];
+
+        let response = serve(b"HEAD /file HTTP/1.1\r\n\r\n", &mut output);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Length: 10\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+
+        // Ranges are ignored, and the headers of the full representation are sent
+        let response = serve(
+            b"HEAD /file HTTP/1.1\r\nRange: bytes=2-4\r\n\r\n",
+            &mut output,
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_serve_seekable_multipart() {
+        let mut output = [0; 512];
+
+        let response = serve_with(
+            b"GET /file HTTP/1.1\r\nRange: bytes=0-1, 8-\r\n\r\n",
+            None,
+            Some("sep"),
+            &mut output,
+        );
+        assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert!(response.contains("Content-Type: multipart/byteranges; boundary=sep\r\n"));
+
+        let (headers, body) = response.split_once("\r\n\r\n").unwrap();
+        let content_len = headers
+            .split("\r\n")
+            .find_map(|header| header.strip_prefix("Content-Length: "))
+            .unwrap();
+        assert_eq!(content_len.parse::<usize>(), Ok(body.len()));
+        assert_eq!(
+            body,
+            "\r\n--sep\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/10\r\n\r\n01\
+             \r\n--sep\r\nContent-Type: text/plain\r\nContent-Range: bytes 8-9/10\r\n\r\n89\
+             \r\n--sep--\r\n"
+        );
+
+        // Without a boundary, the ranges are coalesced
+        let response = serve_with(
+            b"GET /file HTTP/1.1\r\nRange: bytes=0-1, 8-\r\n\r\n",
+            None,
+            None,
+            &mut output,
+        );
+        assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert!(response.contains("Content-Range: bytes 0-9/10\r\n"));
+        assert!(response.ends_with("\r\n\r\n0123456789"));
+    }
+
+    #[test]
+    fn test_serve_seekable_if_range() {
+        let mut output = [0; 512];
+
+        let request = b"GET /file HTTP/1.1\r\nRange: bytes=2-4\r\nIf-Range: \"v1\"\r\n\r\n";
+
+        // The validator matches, so the range is served
+        let response = serve_with(request, Some("\"v1\""), None, &mut output);
+        assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert!(response.contains("ETag: \"v1\"\r\n"));
+        assert!(response.ends_with("\r\n\r\n234"));
+
+        // The representation changed, so all of it is served
+        let response = serve_with(request, Some("\"v2\""), None, &mut output);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("ETag: \"v2\"\r\n"));
+        assert!(response.ends_with("\r\n\r\n0123456789"));
+
+        let response = serve_with(
+            b"GET /file HTTP/1.1\r\nRange: bytes=20-\r\n\r\n",
+            None,
+            None,
+            &mut output,
+        );
+        assert!(response.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"));
+        assert!(response.contains("Content-Range: bytes */10\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_head_body() {
+        let mut output = [0; 512];
+
+        // Handlers other than `serve_seekable` control the body of their HEAD responses
+        let written = embassy_futures::block_on(async {
+            let mut buf = [0; 512];
+
+            let mut conn = Conn {
+                input: b"HEAD /file HTTP/1.1\r\n\r\n",
+                output: &mut output,
+                written: 0,
+            };
+
+            {
+                let mut connection: Connection<_> =
+                    pin!(Connection::new(&mut buf, &mut conn, None))
+                        .await
+                        .unwrap();
+
+                pin!(connection.initiate_response(200, Some("OK"), &[("Content-Length", "5")]))
+                    .await
+                    .unwrap();
+                pin!(connection.write_all(b"hello")).await.unwrap();
+                pin!(connection.complete()).await.unwrap();
+            }
+
+            conn.written
+        });
+
+        let response = core::str::from_utf8(&output[..written]).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nhello"));
+    }
+
+    /// Read the JSON body of `request` into a buffer of `json_len` bytes, and respond with the value read, if any
+    #[cfg(feature = "serde")]
+    fn json<'o>(
+        request: &[u8],
+        json_len: usize,
+        output: &'o mut [u8],
+    ) -> (Result<(u32, bool), Error<Infallible>>, &'o str) {
+        let (result, written) = embassy_futures::block_on(async {
+            let mut buf = [0; 512];
+
+            let mut conn = Conn {
+                input: request,
+                output: &mut *output,
+                written: 0,
+            };
+
+            let result = {
+                let mut connection: Connection<_> =
+                    pin!(Connection::new(&mut buf, &mut conn, None))
+                        .await
+                        .unwrap();
+
+                let mut json_buf = [0; 64];
+
+                let result = pin!(connection.read_json(&mut json_buf[..json_len])).await;
+
+                if let Ok(value) = &result {
+                    pin!(connection.respond_json(200, Some("OK"), &[], value, &mut json_buf))
+                        .await
+                        .unwrap();
+                }
+
+                pin!(connection.complete()).await.unwrap();
+
+                result
+            };
+
+            (result, conn.written)
+        });
+
+        (result, core::str::from_utf8(&output[..written]).unwrap())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json() {
+        let mut output = [0; 512];
+
+        let (result, response) = json(
+            b"POST / HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 8\r\n\r\n[2,true]",
+            64,
+            &mut output,
+        );
+
+        assert!(matches!(result, Ok((2, true))));
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Type: application/json\r\n"));
+        assert!(response.contains("Content-Length: 8\r\n"));
+        assert!(response.ends_with("\r\n\r\n[2,true]"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_errors() {
+        let mut output = [0; 512];
+
+        let (result, response) = json(
+            b"POST / HTTP/1.1\r\nContent-Type: text/plain\r\nContent-Length: 8\r\n\r\n[2,true]",
+            64,
+            &mut output,
+        );
+        assert!(matches!(result, Err(Error::UnsupportedContentType)));
+        assert!(response.starts_with("HTTP/1.1 415 Unsupported Media Type\r\n"));
+
+        let (result, response) = json(
+            b"POST / HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 8\r\n\r\n[2,true]",
+            4,
+            &mut output,
+        );
+        assert!(matches!(result, Err(Error::TooLongBody)));
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+
+        let (result, response) = json(
+            b"POST / HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 5\r\n\r\n[2,3]",
+            64,
+            &mut output,
+        );
+        assert!(matches!(result, Err(Error::InvalidBody)));
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(response.contains("Content-Length: 0\r\n"));
+    }
+}
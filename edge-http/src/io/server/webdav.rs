@@ -0,0 +1,1247 @@
+//! A WebDAV class 1 (RFC 4918) handler serving a small, user-implemented file system.
+//!
+//! The handler supports `OPTIONS`, `GET`, `HEAD`, `PUT`, `DELETE`, `MKCOL`, `COPY`, `MOVE`, `PROPFIND` and `PROPPATCH`.
+//! `PROPFIND` responses are streamed as a `multistatus` XML body, so no allocation is necessary.
+//! `PROPFIND` requests with an explicit `Depth: infinity` are rejected as per RFC 4918 §9.1
+//! (`propfind-finite-depth`), while a missing `Depth` is leniently treated as `1`.
+//!
+//! Locking (class 2) is not supported, hence clients which insist on locking (like the macOS Finder)
+//! will mount the file system read-only.
+
+use core::fmt::Debug;
+use core::str;
+
+use embedded_io_async::{ErrorType, Read, Seek, SeekFrom, Write};
+
+use crate::Method;
+
+use super::{Connection, HandleRequestError, Handler};
+
+/// The maximum length of a (decoded) path handled by `WebDav`.
+pub const MAX_PATH_LEN: usize = 256;
+/// The maximum length of a directory entry name returned by `FileSystem::list`.
+pub const MAX_NAME_LEN: usize = 128;
+
+const COPY_BUF_SIZE: usize = 256;
+
+const ALLOW: &str = "OPTIONS, GET, HEAD, PUT, DELETE, MKCOL, COPY, MOVE, PROPFIND, PROPPATCH";
+
+/// The metadata of a file system entry.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    /// Whether the entry is a directory
+    pub dir: bool,
+    /// The length of the file in bytes; ignored for directories
+    pub len: u64,
+}
+
+impl Metadata {
+    pub const fn file(len: u64) -> Self {
+        Self { dir: false, len }
+    }
+
+    pub const fn dir() -> Self {
+        Self { dir: true, len: 0 }
+    }
+}
+
+/// The file system served by `WebDav`.
+///
+/// Paths are absolute, always start with `/` and never end with `/` (except for the root itself).
+/// They contain neither `.` nor `..` segments.
+pub trait FileSystem {
+    type Error: Debug;
+
+    /// Return the metadata of the entry at `path`, or `None` if there is no such entry.
+    async fn metadata(&self, path: &str) -> Result<Option<Metadata>, Self::Error>;
+
+    /// Return the `index`-th entry of the directory at `path`, or `None` if there are no more entries.
+    ///
+    /// The name of the entry is written into `name` and its length is returned together with its metadata.
+    async fn list(
+        &self,
+        path: &str,
+        index: usize,
+        name: &mut [u8],
+    ) -> Result<Option<(usize, Metadata)>, Self::Error>;
+
+    /// Read the file at `path` starting at `offset` into `buf` and return the number of bytes read.
+    async fn read(&self, path: &str, offset: u64, buf: &mut [u8]) -> Result<usize, Self::Error>;
+
+    /// Write `data` into the file at `path` starting at `offset`.
+    ///
+    /// When `offset` is 0, the file is created if it does not exist and truncated otherwise.
+    async fn write(&self, path: &str, offset: u64, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Delete the file at `path`, or the directory at `path` together with all of its contents.
+    async fn delete(&self, path: &str) -> Result<(), Self::Error>;
+
+    /// Create the directory at `path`. Its parent is guaranteed to exist.
+    async fn mkdir(&self, path: &str) -> Result<(), Self::Error>;
+
+    /// Rename the entry at `from` to `to`. The parent of `to` is guaranteed to exist.
+    ///
+    /// If `to` exists, it should be replaced (i.e. deleted together with all of its contents).
+    ///
+    /// Returns `false` if renaming is not supported, in which case `to` must be left untouched:
+    /// files are then moved by copying, and directories cannot be moved.
+    async fn rename(&self, _from: &str, _to: &str) -> Result<bool, Self::Error> {
+        Ok(false)
+    }
+}
+
+impl<F> FileSystem for &F
+where
+    F: FileSystem,
+{
+    type Error = F::Error;
+
+    async fn metadata(&self, path: &str) -> Result<Option<Metadata>, Self::Error> {
+        (**self).metadata(path).await
+    }
+
+    async fn list(
+        &self,
+        path: &str,
+        index: usize,
+        name: &mut [u8],
+    ) -> Result<Option<(usize, Metadata)>, Self::Error> {
+        (**self).list(path, index, name).await
+    }
+
+    async fn read(&self, path: &str, offset: u64, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        (**self).read(path, offset, buf).await
+    }
+
+    async fn write(&self, path: &str, offset: u64, data: &[u8]) -> Result<(), Self::Error> {
+        (**self).write(path, offset, data).await
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), Self::Error> {
+        (**self).delete(path).await
+    }
+
+    async fn mkdir(&self, path: &str) -> Result<(), Self::Error> {
+        (**self).mkdir(path).await
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<bool, Self::Error> {
+        (**self).rename(from, to).await
+    }
+}
+
+type Path = heapless::String<MAX_PATH_LEN>;
+
+/// A `Handler` serving a `FileSystem` over WebDAV under the `root` URL path.
+///
+/// Requests for paths outside of `root` are answered with 404.
+pub struct WebDav<'a, F> {
+    root: &'a str,
+    fs: F,
+}
+
+impl<'a, F> WebDav<'a, F>
+where
+    F: FileSystem,
+{
+    /// Create a new handler serving `fs` under `root` (e.g. `/dav`, or `/` for the whole server).
+    pub const fn new(root: &'a str, fs: F) -> Self {
+        Self { root, fs }
+    }
+
+    /// Handle a WebDAV request on the connection.
+    ///
+    /// Useful for serving WebDAV from a handler which also serves other content.
+    pub async fn handle<T, const N: usize>(
+        &self,
+        conn: &mut Connection<'_, T, N>,
+    ) -> Result<(), HandleRequestError<T::Error, F::Error>>
+    where
+        T: Read + Write,
+    {
+        let headers = conn.headers()?;
+
+        let method = headers.method;
+
+        let mut path = Path::new();
+        let path_valid = self.resolve(headers.path.unwrap_or(""), &mut path);
+
+        let depth = headers.headers.get("Depth").map(str::trim);
+        let depth_zero = depth == Some("0");
+        let depth_infinity = depth.is_some_and(|depth| depth.eq_ignore_ascii_case("infinity"));
+        let overwrite = !headers
+            .headers
+            .get("Overwrite")
+            .map(|overwrite| overwrite.trim().eq_ignore_ascii_case("F"))
+            .unwrap_or(false);
+
+        let mut destination = Path::new();
+        let destination_valid = headers
+            .headers
+            .get("Destination")
+            .map(|uri| self.resolve(strip_authority(uri), &mut destination));
+
+        if !path_valid {
+            return respond(conn, 404, "Not Found").await;
+        }
+
+        match method {
+            Some(Method::Options) => {
+                conn.initiate_response(
+                    200,
+                    Some("OK"),
+                    &[("DAV", "1"), ("Allow", ALLOW), ("Content-Length", "0")],
+                )
+                .await?;
+
+                Ok(())
+            }
+            Some(Method::Get | Method::Head) => self.get(conn, &path).await,
+            Some(Method::Put) => self.put(conn, &path).await,
+            Some(Method::Delete) => self.delete(conn, &path).await,
+            Some(Method::MkCol) => self.mkcol(conn, &path).await,
+            Some(Method::Propfind) if depth_infinity => {
+                conn.initiate_response(
+                    403,
+                    Some("Forbidden"),
+                    &[("Content-Type", "application/xml; charset=utf-8")],
+                )
+                .await?;
+
+                conn.write_all(b"<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:error xmlns:D=\"DAV:\"><D:propfind-finite-depth/></D:error>\n")
+                    .await?;
+
+                Ok(())
+            }
+            Some(Method::Propfind) => self.propfind(conn, &path, depth_zero).await,
+            Some(Method::Proppatch) => self.proppatch(conn, &path).await,
+            Some(method @ (Method::Copy | Method::Move)) => match destination_valid {
+                Some(true) => {
+                    self.copy(
+                        conn,
+                        &path,
+                        &destination,
+                        overwrite,
+                        matches!(method, Method::Move),
+                    )
+                    .await
+                }
+                Some(false) => respond(conn, 502, "Bad Gateway").await,
+                None => respond(conn, 400, "Bad Request").await,
+            },
+            _ => {
+                conn.initiate_response(
+                    405,
+                    Some("Method Not Allowed"),
+                    &[("Allow", ALLOW), ("Content-Length", "0")],
+                )
+                .await?;
+
+                Ok(())
+            }
+        }
+    }
+
+    async fn get<T, const N: usize>(
+        &self,
+        conn: &mut Connection<'_, T, N>,
+        path: &str,
+    ) -> Result<(), HandleRequestError<T::Error, F::Error>>
+    where
+        T: Read + Write,
+    {
+        match self.metadata(path).await? {
+            Some(metadata) if !metadata.dir => {
+                let reader = FileReader {
+                    fs: &self.fs,
+                    path,
+                    offset: 0,
+                    len: metadata.len,
+                };
+
+                conn.serve_seekable(reader, metadata.len, None, None, None)
+                    .await
+                    .map_err(|e| match e {
+                        HandleRequestError::Connection(e) => HandleRequestError::Connection(e),
+                        HandleRequestError::Handler(FsError(e)) => HandleRequestError::Handler(e),
+                    })
+            }
+            Some(_) => respond(conn, 403, "Forbidden").await,
+            None => respond(conn, 404, "Not Found").await,
+        }
+    }
+
+    async fn put<T, const N: usize>(
+        &self,
+        conn: &mut Connection<'_, T, N>,
+        path: &str,
+    ) -> Result<(), HandleRequestError<T::Error, F::Error>>
+    where
+        T: Read + Write,
+    {
+        let existing = self.metadata(path).await?;
+
+        if matches!(existing, Some(metadata) if metadata.dir) {
+            return respond(conn, 405, "Method Not Allowed").await;
+        }
+
+        if !self.is_dir(parent(path)).await? {
+            return respond(conn, 409, "Conflict").await;
+        }
+
+        let mut buf = [0; COPY_BUF_SIZE];
+        let mut offset = 0;
+
+        loop {
+            let read = conn.read(&mut buf).await?;
+
+            if read == 0 && offset > 0 {
+                break;
+            }
+
+            self.fs
+                .write(path, offset, &buf[..read])
+                .await
+                .map_err(HandleRequestError::Handler)?;
+
+            if read == 0 {
+                break;
+            }
+
+            offset += read as u64;
+        }
+
+        if existing.is_some() {
+            respond(conn, 204, "No Content").await
+        } else {
+            respond(conn, 201, "Created").await
+        }
+    }
+
+    async fn delete<T, const N: usize>(
+        &self,
+        conn: &mut Connection<'_, T, N>,
+        path: &str,
+    ) -> Result<(), HandleRequestError<T::Error, F::Error>>
+    where
+        T: Read + Write,
+    {
+        if path == "/" {
+            return respond(conn, 403, "Forbidden").await;
+        }
+
+        if self.metadata(path).await?.is_none() {
+            return respond(conn, 404, "Not Found").await;
+        }
+
+        self.fs
+            .delete(path)
+            .await
+            .map_err(HandleRequestError::Handler)?;
+
+        respond(conn, 204, "No Content").await
+    }
+
+    async fn mkcol<T, const N: usize>(
+        &self,
+        conn: &mut Connection<'_, T, N>,
+        path: &str,
+    ) -> Result<(), HandleRequestError<T::Error, F::Error>>
+    where
+        T: Read + Write,
+    {
+        if conn.headers()?.headers.content_len().unwrap_or(0) > 0 {
+            return respond(conn, 415, "Unsupported Media Type").await;
+        }
+
+        if self.metadata(path).await?.is_some() {
+            return respond(conn, 405, "Method Not Allowed").await;
+        }
+
+        if !self.is_dir(parent(path)).await? {
+            return respond(conn, 409, "Conflict").await;
+        }
+
+        self.fs
+            .mkdir(path)
+            .await
+            .map_err(HandleRequestError::Handler)?;
+
+        respond(conn, 201, "Created").await
+    }
+
+    async fn copy<T, const N: usize>(
+        &self,
+        conn: &mut Connection<'_, T, N>,
+        from: &str,
+        to: &str,
+        overwrite: bool,
+        remove: bool,
+    ) -> Result<(), HandleRequestError<T::Error, F::Error>>
+    where
+        T: Read + Write,
+    {
+        let Some(metadata) = self.metadata(from).await? else {
+            return respond(conn, 404, "Not Found").await;
+        };
+
+        if from == to || from == "/" || to == "/" || is_ancestor(from, to) {
+            return respond(conn, 403, "Forbidden").await;
+        }
+
+        if !self.is_dir(parent(to)).await? {
+            return respond(conn, 409, "Conflict").await;
+        }
+
+        if metadata.dir && !remove {
+            // Copying directories would need recursion, which is not possible without allocation
+            return respond(conn, 403, "Forbidden").await;
+        }
+
+        let existed = self.metadata(to).await?.is_some();
+
+        if existed && !overwrite {
+            return respond(conn, 412, "Precondition Failed").await;
+        }
+
+        // Try renaming first, so that the destination is not deleted when a directory cannot be moved
+        let renamed = remove
+            && self
+                .fs
+                .rename(from, to)
+                .await
+                .map_err(HandleRequestError::Handler)?;
+
+        if !renamed {
+            if metadata.dir {
+                return respond(conn, 403, "Forbidden").await;
+            }
+
+            if existed {
+                self.fs
+                    .delete(to)
+                    .await
+                    .map_err(HandleRequestError::Handler)?;
+            }
+
+            self.copy_file(from, to).await?;
+
+            if remove {
+                self.fs
+                    .delete(from)
+                    .await
+                    .map_err(HandleRequestError::Handler)?;
+            }
+        }
+
+        if existed {
+            respond(conn, 204, "No Content").await
+        } else {
+            respond(conn, 201, "Created").await
+        }
+    }
+
+    async fn propfind<T, const N: usize>(
+        &self,
+        conn: &mut Connection<'_, T, N>,
+        path: &str,
+        depth_zero: bool,
+    ) -> Result<(), HandleRequestError<T::Error, F::Error>>
+    where
+        T: Read + Write,
+    {
+        let Some(metadata) = self.metadata(path).await? else {
+            return respond(conn, 404, "Not Found").await;
+        };
+
+        conn.initiate_response(
+            207,
+            Some("Multi-Status"),
+            &[("Content-Type", "application/xml; charset=utf-8")],
+        )
+        .await?;
+
+        conn.write_all(
+            b"<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n",
+        )
+        .await?;
+
+        self.write_response(conn, path, &metadata).await?;
+
+        // A missing `Depth` is treated as `1`
+        if metadata.dir && !depth_zero {
+            let mut name = [0; MAX_NAME_LEN];
+            let mut index = 0;
+
+            while let Some((len, metadata)) = self
+                .fs
+                .list(path, index, &mut name)
+                .await
+                .map_err(HandleRequestError::Handler)?
+            {
+                index += 1;
+
+                let Some(child) = str::from_utf8(&name[..len])
+                    .ok()
+                    .and_then(|name| child(path, name))
+                else {
+                    continue;
+                };
+
+                self.write_response(conn, &child, &metadata).await?;
+            }
+        }
+
+        conn.write_all(b"</D:multistatus>\n").await?;
+
+        Ok(())
+    }
+
+    async fn proppatch<T, const N: usize>(
+        &self,
+        conn: &mut Connection<'_, T, N>,
+        path: &str,
+    ) -> Result<(), HandleRequestError<T::Error, F::Error>>
+    where
+        T: Read + Write,
+    {
+        let Some(metadata) = self.metadata(path).await? else {
+            return respond(conn, 404, "Not Found").await;
+        };
+
+        // Dead properties are not stored; report success so that clients
+        // (e.g. Windows setting timestamps) do not abort their operations
+        conn.initiate_response(
+            207,
+            Some("Multi-Status"),
+            &[("Content-Type", "application/xml; charset=utf-8")],
+        )
+        .await?;
+
+        conn.write_all(b"<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n<D:response><D:href>")
+            .await?;
+        self.write_href(conn, path, metadata.dir).await?;
+        conn.write_all(b"</D:href><D:propstat><D:prop/><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n</D:multistatus>\n")
+            .await?;
+
+        Ok(())
+    }
+
+    async fn write_response<W>(
+        &self,
+        out: &mut W,
+        path: &str,
+        metadata: &Metadata,
+    ) -> Result<(), W::Error>
+    where
+        W: Write,
+    {
+        out.write_all(b"<D:response><D:href>").await?;
+        self.write_href(out, path, metadata.dir).await?;
+        out.write_all(b"</D:href><D:propstat><D:prop>").await?;
+
+        if metadata.dir {
+            out.write_all(b"<D:resourcetype><D:collection/></D:resourcetype>")
+                .await?;
+        } else {
+            let len: heapless::String<20> = metadata.len.try_into().unwrap();
+
+            out.write_all(b"<D:resourcetype/><D:getcontentlength>")
+                .await?;
+            out.write_all(len.as_bytes()).await?;
+            out.write_all(b"</D:getcontentlength>").await?;
+        }
+
+        out.write_all(b"</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n")
+            .await
+    }
+
+    async fn write_href<W>(&self, out: &mut W, path: &str, dir: bool) -> Result<(), W::Error>
+    where
+        W: Write,
+    {
+        let root = self.root.trim_end_matches('/');
+
+        write_encoded(&mut *out, root).await?;
+
+        if path != "/" {
+            write_encoded(&mut *out, path).await?;
+        }
+
+        if dir {
+            out.write_all(b"/").await?;
+        }
+
+        Ok(())
+    }
+
+    async fn copy_file<C>(
+        &self,
+        from: &str,
+        to: &str,
+    ) -> Result<(), HandleRequestError<C, F::Error>> {
+        let mut buf = [0; COPY_BUF_SIZE];
+        let mut offset = 0;
+
+        loop {
+            let read = self
+                .fs
+                .read(from, offset, &mut buf)
+                .await
+                .map_err(HandleRequestError::Handler)?;
+
+            if read == 0 && offset > 0 {
+                break;
+            }
+
+            self.fs
+                .write(to, offset, &buf[..read])
+                .await
+                .map_err(HandleRequestError::Handler)?;
+
+            if read == 0 {
+                break;
+            }
+
+            offset += read as u64;
+        }
+
+        Ok(())
+    }
+
+    async fn metadata<C>(
+        &self,
+        path: &str,
+    ) -> Result<Option<Metadata>, HandleRequestError<C, F::Error>> {
+        self.fs
+            .metadata(path)
+            .await
+            .map_err(HandleRequestError::Handler)
+    }
+
+    async fn is_dir<C>(&self, path: &str) -> Result<bool, HandleRequestError<C, F::Error>> {
+        Ok(matches!(self.metadata(path).await?, Some(metadata) if metadata.dir))
+    }
+
+    fn resolve(&self, uri_path: &str, path: &mut Path) -> bool {
+        let uri_path = uri_path.split(['?', '#']).next().unwrap_or("");
+
+        let root = self.root.trim_end_matches('/');
+
+        let Some(rest) = uri_path.strip_prefix(root) else {
+            return false;
+        };
+
+        if !rest.is_empty() && !rest.starts_with('/') {
+            return false;
+        }
+
+        normalize(rest, path)
+    }
+}
+
+impl<'b, T, const N: usize, F> Handler<'b, T, N> for WebDav<'_, F>
+where
+    T: Read + Write,
+    F: FileSystem,
+{
+    type Error = HandleRequestError<T::Error, F::Error>;
+
+    async fn handle(&self, connection: &mut Connection<'b, T, N>) -> Result<(), Self::Error> {
+        WebDav::handle(self, connection).await
+    }
+}
+
+async fn respond<T, const N: usize, E>(
+    conn: &mut Connection<'_, T, N>,
+    status: u16,
+    message: &str,
+) -> Result<(), HandleRequestError<T::Error, E>>
+where
+    T: Read + Write,
+{
+    conn.initiate_response(status, Some(message), &[("Content-Length", "0")])
+        .await?;
+
+    Ok(())
+}
+
+/// Strip the scheme and the authority of an absolute URI (as used in the `Destination` header).
+fn strip_authority(uri: &str) -> &str {
+    match uri.split_once("://") {
+        Some((_, rest)) => rest.find('/').map(|pos| &rest[pos..]).unwrap_or("/"),
+        None => uri,
+    }
+}
+
+/// Percent-decode `uri_path` and normalize it into `path`.
+///
+/// Returns `false` if the path is not valid UTF-8, contains `..` segments or is too long.
+fn normalize(uri_path: &str, path: &mut Path) -> bool {
+    let mut decoded = heapless::Vec::<u8, MAX_PATH_LEN>::new();
+
+    let mut bytes = uri_path.bytes();
+
+    while let Some(byte) = bytes.next() {
+        let byte = if byte == b'%' {
+            let (Some(hi), Some(lo)) = (bytes.next(), bytes.next()) else {
+                return false;
+            };
+
+            let (Some(hi), Some(lo)) = ((hi as char).to_digit(16), (lo as char).to_digit(16))
+            else {
+                return false;
+            };
+
+            (hi * 16 + lo) as u8
+        } else {
+            byte
+        };
+
+        if decoded.push(byte).is_err() {
+            return false;
+        }
+    }
+
+    let Ok(decoded) = str::from_utf8(&decoded) else {
+        return false;
+    };
+
+    path.clear();
+
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => (),
+            ".." => return false,
+            segment => {
+                if path.push('/').is_err() || path.push_str(segment).is_err() {
+                    return false;
+                }
+            }
+        }
+    }
+
+    if path.is_empty() {
+        path.push('/').unwrap();
+    }
+
+    true
+}
+
+fn parent(path: &str) -> &str {
+    match path.rsplit_once('/') {
+        Some(("", _)) | None => "/",
+        Some((parent, _)) => parent,
+    }
+}
+
+fn child(path: &str, name: &str) -> Option<Path> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        return None;
+    }
+
+    let mut child = Path::new();
+
+    if path != "/" {
+        child.push_str(path).ok()?;
+    }
+
+    child.push('/').ok()?;
+    child.push_str(name).ok()?;
+
+    Some(child)
+}
+
+fn is_ancestor(path: &str, descendant: &str) -> bool {
+    descendant
+        .strip_prefix(path)
+        .map(|rest| rest.starts_with('/'))
+        .unwrap_or(false)
+}
+
+/// Write `path` percent-encoded, leaving only unreserved characters and `/` as-is.
+///
+/// The result is also safe to embed in XML without escaping.
+async fn write_encoded<W>(mut out: W, path: &str) -> Result<(), W::Error>
+where
+    W: Write,
+{
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+
+    let bytes = path.as_bytes();
+    let mut start = 0;
+
+    for (index, &byte) in bytes.iter().enumerate() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~' | b'/') {
+            continue;
+        }
+
+        out.write_all(&bytes[start..index]).await?;
+        out.write_all(&[b'%', HEX[(byte >> 4) as usize], HEX[(byte & 0xf) as usize]])
+            .await?;
+
+        start = index + 1;
+    }
+
+    out.write_all(&bytes[start..]).await
+}
+
+/// Adapts a file of a `FileSystem` to `Read + Seek`, so that it can be served with ranges.
+struct FileReader<'a, F> {
+    fs: &'a F,
+    path: &'a str,
+    offset: u64,
+    len: u64,
+}
+
+#[derive(Debug)]
+struct FsError<E>(E);
+
+impl<E> embedded_io_async::Error for FsError<E>
+where
+    E: Debug,
+{
+    fn kind(&self) -> embedded_io_async::ErrorKind {
+        embedded_io_async::ErrorKind::Other
+    }
+}
+
+impl<F> ErrorType for FileReader<'_, F>
+where
+    F: FileSystem,
+{
+    type Error = FsError<F::Error>;
+}
+
+impl<F> Read for FileReader<'_, F>
+where
+    F: FileSystem,
+{
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let read = self
+            .fs
+            .read(self.path, self.offset, buf)
+            .await
+            .map_err(FsError)?;
+
+        self.offset += read as u64;
+
+        Ok(read)
+    }
+}
+
+impl<F> Seek for FileReader<'_, F>
+where
+    F: FileSystem,
+{
+    async fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        self.offset = match pos {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::End(delta) => self.len.saturating_add_signed(delta),
+            SeekFrom::Current(delta) => self.offset.saturating_add_signed(delta),
+        };
+
+        Ok(self.offset)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::cell::RefCell;
+    use core::convert::Infallible;
+    use core::pin::pin;
+
+    use super::*;
+
+    struct Entry {
+        path: heapless::String<32>,
+        data: Option<heapless::Vec<u8, 32>>,
+    }
+
+    /// An in-memory file system; directories are the entries without data
+    struct MemFs {
+        entries: RefCell<heapless::Vec<Entry, 8>>,
+        rename: bool,
+    }
+
+    impl MemFs {
+        fn new(rename: bool, entries: &[(&str, Option<&[u8]>)]) -> Self {
+            let fs = Self {
+                entries: RefCell::new(heapless::Vec::new()),
+                rename,
+            };
+
+            for (path, data) in [("/", None)].iter().chain(entries) {
+                fs.entries
+                    .borrow_mut()
+                    .push(Entry {
+                        path: (*path).try_into().unwrap(),
+                        data: data.map(|data| data.try_into().unwrap()),
+                    })
+                    .map_err(|_| ())
+                    .unwrap();
+            }
+
+            fs
+        }
+
+        fn data(&self, path: &str) -> Option<heapless::Vec<u8, 32>> {
+            self.entries
+                .borrow()
+                .iter()
+                .find(|entry| entry.path == path)
+                .and_then(|entry| entry.data.clone())
+        }
+
+        fn exists(&self, path: &str) -> bool {
+            self.entries.borrow().iter().any(|entry| entry.path == path)
+        }
+
+        fn remove(&self, path: &str) {
+            self.entries
+                .borrow_mut()
+                .retain(|entry| entry.path != path && !is_ancestor(path, &entry.path));
+        }
+    }
+
+    impl FileSystem for MemFs {
+        type Error = Infallible;
+
+        async fn metadata(&self, path: &str) -> Result<Option<Metadata>, Self::Error> {
+            Ok(self
+                .entries
+                .borrow()
+                .iter()
+                .find(|entry| entry.path == path)
+                .map(|entry| match &entry.data {
+                    Some(data) => Metadata::file(data.len() as _),
+                    None => Metadata::dir(),
+                }))
+        }
+
+        async fn list(
+            &self,
+            path: &str,
+            index: usize,
+            name: &mut [u8],
+        ) -> Result<Option<(usize, Metadata)>, Self::Error> {
+            Ok(self
+                .entries
+                .borrow()
+                .iter()
+                .filter(|entry| entry.path != "/" && parent(&entry.path) == path)
+                .nth(index)
+                .map(|entry| {
+                    let entry_name = entry.path.rsplit('/').next().unwrap();
+                    name[..entry_name.len()].copy_from_slice(entry_name.as_bytes());
+
+                    let metadata = match &entry.data {
+                        Some(data) => Metadata::file(data.len() as _),
+                        None => Metadata::dir(),
+                    };
+
+                    (entry_name.len(), metadata)
+                }))
+        }
+
+        async fn read(
+            &self,
+            path: &str,
+            offset: u64,
+            buf: &mut [u8],
+        ) -> Result<usize, Self::Error> {
+            let data = self.data(path).unwrap();
+            let data = &data[(offset as usize).min(data.len())..];
+
+            let len = buf.len().min(data.len());
+            buf[..len].copy_from_slice(&data[..len]);
+
+            Ok(len)
+        }
+
+        async fn write(&self, path: &str, offset: u64, data: &[u8]) -> Result<(), Self::Error> {
+            let mut entries = self.entries.borrow_mut();
+
+            if offset == 0 {
+                entries.retain(|entry| entry.path != path);
+                entries
+                    .push(Entry {
+                        path: path.try_into().unwrap(),
+                        data: Some(heapless::Vec::new()),
+                    })
+                    .map_err(|_| ())
+                    .unwrap();
+            }
+
+            let entry = entries.iter_mut().find(|entry| entry.path == path).unwrap();
+            entry
+                .data
+                .as_mut()
+                .unwrap()
+                .extend_from_slice(data)
+                .unwrap();
+
+            Ok(())
+        }
+
+        async fn delete(&self, path: &str) -> Result<(), Self::Error> {
+            self.remove(path);
+
+            Ok(())
+        }
+
+        async fn mkdir(&self, path: &str) -> Result<(), Self::Error> {
+            self.entries
+                .borrow_mut()
+                .push(Entry {
+                    path: path.try_into().unwrap(),
+                    data: None,
+                })
+                .map_err(|_| ())
+                .unwrap();
+
+            Ok(())
+        }
+
+        async fn rename(&self, from: &str, to: &str) -> Result<bool, Self::Error> {
+            if !self.rename {
+                return Ok(false);
+            }
+
+            self.remove(to);
+
+            for entry in self.entries.borrow_mut().iter_mut() {
+                if entry.path == from || is_ancestor(from, &entry.path) {
+                    let mut path = heapless::String::new();
+                    path.push_str(to).unwrap();
+                    path.push_str(&entry.path[from.len()..]).unwrap();
+
+                    entry.path = path;
+                }
+            }
+
+            Ok(true)
+        }
+    }
+
+    /// A connection replaying a request and recording the response
+    struct Conn<'a> {
+        input: &'a [u8],
+        output: heapless::Vec<u8, 1024>,
+    }
+
+    impl ErrorType for Conn<'_> {
+        type Error = Infallible;
+    }
+
+    impl Read for Conn<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let len = buf.len().min(self.input.len());
+            buf[..len].copy_from_slice(&self.input[..len]);
+
+            self.input = &self.input[len..];
+
+            Ok(len)
+        }
+    }
+
+    impl Write for Conn<'_> {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.output.extend_from_slice(buf).unwrap();
+
+            Ok(buf.len())
+        }
+    }
+
+    fn serve(fs: &MemFs, request: &str) -> heapless::String<1024> {
+        let dav = WebDav::new("/dav", fs);
+
+        let mut conn = Conn {
+            input: request.as_bytes(),
+            output: heapless::Vec::new(),
+        };
+
+        embassy_futures::block_on(async {
+            let mut buf = [0; 512];
+
+            let mut connection: Connection<_> = pin!(Connection::new(&mut buf, &mut conn, None))
+                .await
+                .unwrap();
+
+            pin!(dav.handle(&mut connection)).await.unwrap();
+            pin!(connection.complete()).await.unwrap();
+        });
+
+        heapless::String::from_utf8(conn.output).unwrap()
+    }
+
+    #[test]
+    fn test_options() {
+        let fs = MemFs::new(false, &[]);
+
+        let response = serve(&fs, "OPTIONS /dav HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("DAV: 1\r\n"));
+        assert!(response.contains("HEAD"));
+
+        let response = serve(&fs, "GET /other HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_get_put() {
+        let fs = MemFs::new(false, &[("/d", None)]);
+
+        let response = serve(
+            &fs,
+            "PUT /dav/d/f HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello",
+        );
+        assert!(response.starts_with("HTTP/1.1 201 Created\r\n"));
+        assert_eq!(fs.data("/d/f").unwrap(), b"hello");
+
+        let response = serve(&fs, "GET /dav/d/f HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nhello"));
+
+        let response = serve(&fs, "HEAD /dav/d/f HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Length: 5\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+
+        let response = serve(&fs, "GET /dav/d HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+
+        // No parent
+        let response = serve(
+            &fs,
+            "PUT /dav/x/f HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello",
+        );
+        assert!(response.starts_with("HTTP/1.1 409 Conflict\r\n"));
+    }
+
+    #[test]
+    fn test_mkcol_delete() {
+        let fs = MemFs::new(false, &[]);
+
+        let response = serve(&fs, "MKCOL /dav/d HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 201 Created\r\n"));
+        assert!(fs.exists("/d"));
+
+        let response = serve(&fs, "MKCOL /dav/d HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+
+        let response = serve(&fs, "DELETE /dav/d HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert!(!fs.exists("/d"));
+
+        let response = serve(&fs, "DELETE /dav HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+    }
+
+    #[test]
+    fn test_propfind() {
+        // HTTP/1.0, so that the XML body is not split in chunks
+        let fs = MemFs::new(false, &[("/a b", Some(b"xyz")), ("/d", None)]);
+
+        let response = serve(&fs, "PROPFIND /dav/ HTTP/1.0\r\nDepth: 1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.0 207 Multi-Status\r\n"));
+        assert!(response.contains("<D:href>/dav/</D:href>"));
+        assert!(response.contains("<D:href>/dav/a%20b</D:href>"));
+        assert!(response.contains("<D:getcontentlength>3</D:getcontentlength>"));
+        assert!(response.contains("<D:href>/dav/d/</D:href>"));
+
+        let response = serve(&fs, "PROPFIND /dav/ HTTP/1.0\r\nDepth: 0\r\n\r\n");
+        assert!(response.contains("<D:href>/dav/</D:href>"));
+        assert!(!response.contains("<D:href>/dav/d/</D:href>"));
+
+        let response = serve(&fs, "PROPFIND /dav/ HTTP/1.0\r\nDepth: Infinity\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.0 403 Forbidden\r\n"));
+        assert!(response.contains("<D:propfind-finite-depth/>"));
+    }
+
+    #[test]
+    fn test_copy_move_file() {
+        let fs = MemFs::new(false, &[("/f", Some(b"hello")), ("/g", Some(b"old"))]);
+
+        let response = serve(
+            &fs,
+            "COPY /dav/f HTTP/1.1\r\nDestination: http://host/dav/g\r\nOverwrite: F\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 412 Precondition Failed\r\n"));
+        assert_eq!(fs.data("/g").unwrap(), b"old");
+
+        let response = serve(
+            &fs,
+            "COPY /dav/f HTTP/1.1\r\nDestination: http://host/dav/g\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert_eq!(fs.data("/g").unwrap(), b"hello");
+
+        // Moved by copying, as the file system cannot rename
+        let response = serve(&fs, "MOVE /dav/f HTTP/1.1\r\nDestination: /dav/h\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 201 Created\r\n"));
+        assert_eq!(fs.data("/h").unwrap(), b"hello");
+        assert!(!fs.exists("/f"));
+    }
+
+    #[test]
+    fn test_move_dir() {
+        let entries: &[(&str, Option<&[u8]>)] = &[
+            ("/a", None),
+            ("/a/x", Some(b"x")),
+            ("/b", None),
+            ("/b/y", Some(b"y")),
+        ];
+
+        // Directories cannot be moved without renaming; the destination must stay intact
+        let fs = MemFs::new(false, entries);
+
+        let response = serve(
+            &fs,
+            "MOVE /dav/a HTTP/1.1\r\nDestination: /dav/b\r\nOverwrite: T\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+        assert_eq!(fs.data("/a/x").unwrap(), b"x");
+        assert_eq!(fs.data("/b/y").unwrap(), b"y");
+
+        let fs = MemFs::new(true, entries);
+
+        let response = serve(
+            &fs,
+            "MOVE /dav/a HTTP/1.1\r\nDestination: /dav/b\r\nOverwrite: T\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert!(!fs.exists("/a"));
+        assert!(!fs.exists("/b/y"));
+        assert_eq!(fs.data("/b/x").unwrap(), b"x");
+
+        // Copying directories is not supported
+        let response = serve(&fs, "COPY /dav/b HTTP/1.1\r\nDestination: /dav/c\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+        assert!(!fs.exists("/c"));
+    }
+
+    #[test]
+    fn test_normalize() {
+        let mut path = Path::new();
+
+        assert!(normalize("", &mut path));
+        assert_eq!(path, "/");
+
+        assert!(normalize("/a%20b//./c/", &mut path));
+        assert_eq!(path, "/a b/c");
+
+        assert!(!normalize("/a/../b", &mut path));
+        assert!(!normalize("/a%2", &mut path));
+        assert!(!normalize("/%ff", &mut path));
+
+        assert_eq!(parent("/a b/c"), "/a b");
+        assert_eq!(parent("/a"), "/");
+
+        assert!(is_ancestor("/a", "/a/b"));
+        assert!(!is_ancestor("/a", "/ab"));
+
+        assert_eq!(strip_authority("http://host:80/dav/x"), "/dav/x");
+        assert_eq!(strip_authority("/dav/x"), "/dav/x");
+    }
+}
@@ -0,0 +1,787 @@
+//! An SSDP (Simple Service Discovery Protocol) responder, as used by UPnP device discovery.
+//!
+//! SSDP messages are HTTP messages sent over UDP (HTTPU), so the responder reuses
+//! the HTTP header parsing and writing of this crate on datagrams.
+
+use core::fmt::Write as _;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4};
+use core::pin::pin;
+
+use embassy_futures::select::{select3, Either3};
+use embassy_time::{Duration, Instant, Timer};
+
+use edge_nal::{MulticastV4, Readable, UdpBind, UdpReceive, UdpSend};
+
+use embedded_io_async::Write;
+
+use log::{debug, warn};
+
+use super::{send_headers, send_headers_end, send_request, send_status, Error};
+use crate::{Method, RequestHeaders};
+
+/// A quick-and-dirty socket address that binds to a "default" interface.
+/// Don't use in production code.
+pub const DEFAULT_SOCKET: SocketAddr = SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), PORT);
+
+/// The IPv4 SSDP multicast address, as per spec.
+pub const IP_MULTICAST_ADDR: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+
+/// The SSDP port, as per spec.
+pub const PORT: u16 = 1900;
+
+/// The maximum length of a notification type or a unique service name.
+pub const MAX_TARGET_LEN: usize = 192;
+
+/// The maximum number of delayed responses to multicast `M-SEARCH` requests which `Responder::run` keeps
+/// pending at the same time. Requests received while this many responses are pending are ignored.
+pub const MAX_PENDING_SEARCHES: usize = 8;
+
+const HOST: &str = "239.255.255.250:1900";
+
+const MAX_HEADERS_COUNT: usize = 16;
+
+/// The maximum `MX` value honored when delaying responses, as per the UPnP Device Architecture 1.1.
+const MAX_MX_SECS: u64 = 5;
+
+/// A UPnP root device advertised by the responder.
+#[derive(Debug, Clone)]
+pub struct Device<'a> {
+    /// The UUID of the device, without the `uuid:` prefix
+    pub uuid: &'a str,
+    /// The URL of the device description XML
+    pub location: &'a str,
+    /// The `SERVER` header, i.e. `<OS>/<version> UPnP/<version> <product>/<version>`
+    pub server: &'a str,
+    /// The device type, e.g. `urn:schemas-upnp-org:device:Basic:1`
+    pub device_type: &'a str,
+    /// The types of the services of the device, e.g. `urn:schemas-upnp-org:service:SwitchPower:1`
+    pub services: &'a [&'a str],
+    /// The number of seconds for which the advertisements are valid
+    pub max_age: u32,
+}
+
+impl Device<'_> {
+    fn targets(&self) -> impl Iterator<Item = Target<'_>> + '_ {
+        [
+            Target::RootDevice,
+            Target::Uuid,
+            Target::Type(self.device_type),
+        ]
+        .into_iter()
+        .chain(self.services.iter().map(|service| Target::Type(service)))
+    }
+
+    fn target(&self, search_target: &str) -> Option<Target<'_>> {
+        self.targets().find(|target| match target {
+            Target::RootDevice => search_target == "upnp:rootdevice",
+            Target::Uuid => search_target
+                .strip_prefix("uuid:")
+                .map(|uuid| uuid.eq_ignore_ascii_case(self.uuid))
+                .unwrap_or(false),
+            Target::Type(ty) => search_target == *ty,
+        })
+    }
+
+    fn write_target(
+        &self,
+        target: Target<'_>,
+        nt: &mut heapless::String<MAX_TARGET_LEN>,
+        usn: &mut heapless::String<MAX_TARGET_LEN>,
+    ) -> Result<(), core::fmt::Error> {
+        nt.clear();
+        usn.clear();
+
+        match target {
+            Target::RootDevice => {
+                nt.push_str("upnp:rootdevice")
+                    .map_err(|_| core::fmt::Error)?;
+                write!(usn, "uuid:{}::upnp:rootdevice", self.uuid)
+            }
+            Target::Uuid => {
+                write!(nt, "uuid:{}", self.uuid)?;
+                write!(usn, "uuid:{}", self.uuid)
+            }
+            Target::Type(ty) => {
+                nt.push_str(ty).map_err(|_| core::fmt::Error)?;
+                write!(usn, "uuid:{}::{}", self.uuid, ty)
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Target<'a> {
+    RootDevice,
+    Uuid,
+    Type(&'a str),
+}
+
+/// The targets of the device matched by an `M-SEARCH` request.
+#[derive(Copy, Clone, Debug)]
+enum Search<'a> {
+    All,
+    Target(Target<'a>),
+}
+
+/// A delayed response to a multicast `M-SEARCH` request.
+#[derive(Copy, Clone, Debug)]
+struct PendingSearch<'a> {
+    at: Instant,
+    remote: SocketAddr,
+    search: Search<'a>,
+}
+
+/// A utility method to bind a socket suitable for SSDP, by using the provided
+/// stack and address, and joining the SSDP multicast group on the provided interface.
+pub async fn bind<S>(
+    stack: &S,
+    addr: SocketAddr,
+    interface: Ipv4Addr,
+) -> Result<S::Socket<'_>, Error<S::Error>>
+where
+    S: UdpBind,
+{
+    let mut socket = stack.bind(addr).await.map_err(Error::Io)?;
+
+    socket
+        .join_v4(IP_MULTICAST_ADDR, interface)
+        .await
+        .map_err(Error::Io)?;
+
+    Ok(socket)
+}
+
+/// An SSDP responder answering `M-SEARCH` requests for a device and periodically
+/// announcing it with `NOTIFY` messages.
+pub struct Responder<'a> {
+    device: Device<'a>,
+    rand: fn(&mut [u8]),
+}
+
+impl<'a> Responder<'a> {
+    /// Create a new responder for the provided device.
+    ///
+    /// `rand` is used to randomly delay the responses to multicast `M-SEARCH` requests.
+    pub const fn new(device: Device<'a>, rand: fn(&mut [u8])) -> Self {
+        Self { device, rand }
+    }
+
+    /// Run the responder on a socket bound to the SSDP port and joined to the SSDP multicast group
+    /// (see `bind`).
+    ///
+    /// The device is announced when the responder starts and then every `max_age / 2` seconds.
+    ///
+    /// The responses to multicast `M-SEARCH` requests are delayed (see `respond`) without blocking
+    /// the processing of other requests, up to `MAX_PENDING_SEARCHES` at a time.
+    pub async fn run<T>(
+        &self,
+        mut socket: T,
+        rx_buf: &mut [u8],
+        tx_buf: &mut [u8],
+    ) -> Result<(), Error<T::Error>>
+    where
+        T: UdpReceive + UdpSend + Readable,
+    {
+        let interval = Duration::from_secs(((self.device.max_age / 2).max(1)) as _);
+
+        let mut next_announcement = Instant::now();
+
+        let mut pending = heapless::Vec::<PendingSearch<'_>, MAX_PENDING_SEARCHES>::new();
+
+        loop {
+            let next_response = pending
+                .iter()
+                .map(|pending| pending.at)
+                .min()
+                .unwrap_or(Instant::MAX);
+
+            let event = select3(
+                socket.readable(),
+                Timer::at(next_announcement),
+                Timer::at(next_response),
+            )
+            .await;
+
+            match event {
+                Either3::First(result) => {
+                    result.map_err(Error::Io)?;
+
+                    let (len, remote) = socket.receive(rx_buf).await.map_err(Error::Io)?;
+
+                    debug!("Received {len} bytes from {remote}");
+
+                    let Some((search, delay)) = self.search(&rx_buf[..len], remote) else {
+                        continue;
+                    };
+
+                    if let Some(delay) = delay {
+                        let search = PendingSearch {
+                            at: Instant::now() + delay,
+                            remote,
+                            search,
+                        };
+
+                        if pending.push(search).is_ok() {
+                            debug!("Delaying search response to {remote} by {delay}");
+                        } else {
+                            warn!("Too many pending search responses, ignoring the request from {remote}");
+                        }
+                    } else {
+                        self.send_search_responses(&mut socket, remote, search, tx_buf)
+                            .await?;
+                    }
+                }
+                Either3::Second(_) => {
+                    self.notify(&mut socket, true, tx_buf).await?;
+
+                    next_announcement = Instant::now() + interval;
+                }
+                Either3::Third(_) => {
+                    let now = Instant::now();
+
+                    while let Some(index) = pending.iter().position(|pending| pending.at <= now) {
+                        let search = pending.swap_remove(index);
+
+                        self.send_search_responses(
+                            &mut socket,
+                            search.remote,
+                            search.search,
+                            tx_buf,
+                        )
+                        .await?;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Answer the provided datagram if it is an `M-SEARCH` request matching the device.
+    ///
+    /// Responses to multicast requests (i.e. the ones with an `MX` header) are delayed by a random
+    /// duration between 0 and `MX` seconds, so that the devices on the network do not all reply at once.
+    /// Note that the delay is awaited before returning; `run` rather keeps the delayed responses pending.
+    pub async fn respond<T>(
+        &self,
+        data: &[u8],
+        remote: SocketAddr,
+        socket: T,
+        tx_buf: &mut [u8],
+    ) -> Result<(), Error<T::Error>>
+    where
+        T: UdpSend,
+    {
+        let Some((search, delay)) = self.search(data, remote) else {
+            return Ok(());
+        };
+
+        if let Some(delay) = delay {
+            debug!("Delaying search response to {remote} by {delay}");
+
+            Timer::after(delay).await;
+        }
+
+        self.send_search_responses(socket, remote, search, tx_buf)
+            .await
+    }
+
+    /// Parse the provided datagram, returning the targets matched by it if it is an `M-SEARCH` request
+    /// for the device, as well as the random delay of the response if it is a multicast request.
+    fn search(&self, data: &[u8], remote: SocketAddr) -> Option<(Search<'_>, Option<Duration>)> {
+        let mut request = RequestHeaders::<MAX_HEADERS_COUNT>::new();
+
+        if let Err(e) = request.parse::<()>(data) {
+            warn!("Got invalid SSDP message from {remote}, skipping: {e:?}");
+            return None;
+        }
+
+        if request.method != Some(Method::MSearch) || request.path != Some("*") {
+            return None;
+        }
+
+        let discover = request
+            .headers
+            .get("MAN")
+            .map(|man| man.trim() == "\"ssdp:discover\"")
+            .unwrap_or(false);
+
+        let search_target = request.headers.get("ST").map(str::trim)?;
+
+        if !discover {
+            return None;
+        }
+
+        let search = if search_target == "ssdp:all" {
+            Search::All
+        } else {
+            Search::Target(self.device.target(search_target)?)
+        };
+
+        let delay = request
+            .headers
+            .get("MX")
+            .and_then(|mx| mx.trim().parse::<u64>().ok())
+            .map(|mx| self.random_delay(mx));
+
+        Some((search, delay))
+    }
+
+    /// Send `ssdp:alive` (if `alive` is `true`) or `ssdp:byebye` notifications for all the targets of the device.
+    ///
+    /// `ssdp:byebye` notifications should be sent before the device leaves the network.
+    pub async fn notify<T>(
+        &self,
+        mut socket: T,
+        alive: bool,
+        tx_buf: &mut [u8],
+    ) -> Result<(), Error<T::Error>>
+    where
+        T: UdpSend,
+    {
+        let remote = SocketAddr::V4(SocketAddrV4::new(IP_MULTICAST_ADDR, PORT));
+
+        let max_age = self.max_age();
+
+        let mut nt = heapless::String::new();
+        let mut usn = heapless::String::new();
+
+        for target in self.device.targets() {
+            self.device
+                .write_target(target, &mut nt, &mut usn)
+                .map_err(|_| Error::TooLongHeaders)?;
+
+            let mut out = &mut tx_buf[..];
+
+            let alive_headers = [
+                ("HOST", HOST),
+                ("CACHE-CONTROL", max_age.as_str()),
+                ("LOCATION", self.device.location),
+                ("NT", nt.as_str()),
+                ("NTS", "ssdp:alive"),
+                ("SERVER", self.device.server),
+                ("USN", usn.as_str()),
+            ];
+
+            let byebye_headers = [
+                ("HOST", HOST),
+                ("NT", nt.as_str()),
+                ("NTS", "ssdp:byebye"),
+                ("USN", usn.as_str()),
+            ];
+
+            let headers: &[_] = if alive {
+                &alive_headers
+            } else {
+                &byebye_headers
+            };
+
+            let result = pin!(send_message(&mut out, None, headers)).await;
+
+            result.map_err(|_| Error::TooLongHeaders)?;
+
+            let remaining = out.len();
+            let len = tx_buf.len() - remaining;
+
+            socket
+                .send(remote, &tx_buf[..len])
+                .await
+                .map_err(Error::Io)?;
+
+            debug!(
+                "Sent {len} bytes of {} notification",
+                if alive { "alive" } else { "byebye" }
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn send_search_responses<T>(
+        &self,
+        mut socket: T,
+        remote: SocketAddr,
+        search: Search<'_>,
+        tx_buf: &mut [u8],
+    ) -> Result<(), Error<T::Error>>
+    where
+        T: UdpSend,
+    {
+        match search {
+            Search::All => {
+                for target in self.device.targets() {
+                    self.send_search_response(&mut socket, remote, target, tx_buf)
+                        .await?;
+                }
+            }
+            Search::Target(target) => {
+                self.send_search_response(&mut socket, remote, target, tx_buf)
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn send_search_response<T>(
+        &self,
+        mut socket: T,
+        remote: SocketAddr,
+        target: Target<'_>,
+        tx_buf: &mut [u8],
+    ) -> Result<(), Error<T::Error>>
+    where
+        T: UdpSend,
+    {
+        let max_age = self.max_age();
+
+        let mut st = heapless::String::new();
+        let mut usn = heapless::String::new();
+
+        self.device
+            .write_target(target, &mut st, &mut usn)
+            .map_err(|_| Error::TooLongHeaders)?;
+
+        let mut out = &mut tx_buf[..];
+
+        let headers = [
+            ("CACHE-CONTROL", max_age.as_str()),
+            ("EXT", ""),
+            ("LOCATION", self.device.location),
+            ("SERVER", self.device.server),
+            ("ST", st.as_str()),
+            ("USN", usn.as_str()),
+        ];
+
+        pin!(send_message(&mut out, Some(200), &headers))
+            .await
+            .map_err(|_| Error::TooLongHeaders)?;
+
+        let remaining = out.len();
+        let len = tx_buf.len() - remaining;
+
+        socket
+            .send(remote, &tx_buf[..len])
+            .await
+            .map_err(Error::Io)?;
+
+        debug!("Sent {len} bytes of search response to {remote}");
+
+        Ok(())
+    }
+
+    fn random_delay(&self, mx: u64) -> Duration {
+        let mut b = [0; 2];
+        (self.rand)(&mut b);
+
+        Duration::from_millis(u16::from_le_bytes(b) as u64 * mx.min(MAX_MX_SECS) * 1000 / 65536)
+    }
+
+    fn max_age(&self) -> heapless::String<24> {
+        let mut max_age = heapless::String::new();
+
+        write!(&mut max_age, "max-age={}", self.device.max_age).unwrap();
+
+        max_age
+    }
+}
+
+/// Write an SSDP message: a `200 OK` response if `status` is provided, or a `NOTIFY` request otherwise.
+async fn send_message<W>(
+    mut output: W,
+    status: Option<u16>,
+    headers: &[(&str, &str)],
+) -> Result<(), Error<W::Error>>
+where
+    W: Write,
+{
+    if let Some(status) = status {
+        send_status(true, Some(status), Some("OK"), &mut output).await?;
+    } else {
+        send_request(true, Some(Method::Notify), Some("*"), &mut output).await?;
+    }
+
+    send_headers(headers, &mut output).await?;
+    send_headers_end(output).await
+}
+
+#[cfg(test)]
+mod test {
+    use core::cell::RefCell;
+    use core::convert::Infallible;
+
+    use embassy_futures::select::select;
+
+    use embedded_io_async::ErrorType;
+
+    use super::*;
+
+    const DEVICE: Device<'static> = Device {
+        uuid: "2fac1234-31f8-11b4-a222-08002b34c003",
+        location: "http://192.168.1.2:80/description.xml",
+        server: "edge/1.0 UPnP/1.1 test/1.0",
+        device_type: "urn:schemas-upnp-org:device:Basic:1",
+        services: &["urn:schemas-upnp-org:service:SwitchPower:1"],
+        max_age: 1800,
+    };
+
+    const REMOTE: SocketAddr =
+        SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 3), 5000));
+
+    /// A socket recording the sent datagrams
+    #[derive(Default)]
+    struct Socket(heapless::Vec<(SocketAddr, heapless::String<512>), 10>);
+
+    impl ErrorType for Socket {
+        type Error = Infallible;
+    }
+
+    impl UdpSend for Socket {
+        async fn send(&mut self, remote: SocketAddr, data: &[u8]) -> Result<(), Self::Error> {
+            let data = heapless::Vec::from_slice(data).unwrap();
+
+            self.0
+                .push((remote, heapless::String::from_utf8(data).unwrap()))
+                .unwrap();
+
+            Ok(())
+        }
+    }
+
+    /// A socket receiving `requests` once and then nothing, and recording the sent datagrams in `sent`
+    struct RunSocket<'a> {
+        requests: &'a [&'a str],
+        sent: &'a RefCell<Socket>,
+    }
+
+    impl ErrorType for RunSocket<'_> {
+        type Error = Infallible;
+    }
+
+    impl Readable for RunSocket<'_> {
+        async fn readable(&mut self) -> Result<(), Self::Error> {
+            if self.requests.is_empty() {
+                core::future::pending().await
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    impl UdpReceive for RunSocket<'_> {
+        async fn receive(&mut self, buffer: &mut [u8]) -> Result<(usize, SocketAddr), Self::Error> {
+            let (request, requests) = self.requests.split_first().unwrap();
+            self.requests = requests;
+
+            buffer[..request.len()].copy_from_slice(request.as_bytes());
+
+            Ok((request.len(), REMOTE))
+        }
+    }
+
+    impl UdpSend for RunSocket<'_> {
+        async fn send(&mut self, remote: SocketAddr, data: &[u8]) -> Result<(), Self::Error> {
+            let data = heapless::Vec::from_slice(data).unwrap();
+
+            self.sent
+                .borrow_mut()
+                .0
+                .push((remote, heapless::String::from_utf8(data).unwrap()))
+                .unwrap();
+
+            Ok(())
+        }
+    }
+
+    fn respond(request: &str) -> Socket {
+        let responder = Responder::new(DEVICE, |buf| buf.fill(0));
+
+        let mut socket = Socket::default();
+        let mut tx_buf = [0; 512];
+
+        embassy_futures::block_on(pin!(responder.respond(
+            request.as_bytes(),
+            REMOTE,
+            &mut socket,
+            &mut tx_buf
+        )))
+        .unwrap();
+
+        socket
+    }
+
+    fn search(st: &str) -> heapless::String<256> {
+        let mut request = heapless::String::new();
+
+        write!(
+            &mut request,
+            "M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nMAN: \"ssdp:discover\"\r\nMX: 1\r\nST: {st}\r\n\r\n"
+        )
+        .unwrap();
+
+        request
+    }
+
+    #[test]
+    fn test_search() {
+        let socket = respond(&search("ssdp:all"));
+
+        assert_eq!(socket.0.len(), 4);
+
+        for (remote, response) in &socket.0 {
+            assert_eq!(*remote, REMOTE);
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+            assert!(response.contains("CACHE-CONTROL: max-age=1800\r\n"));
+            assert!(response.contains("LOCATION: http://192.168.1.2:80/description.xml\r\n"));
+            assert!(response.ends_with("\r\n\r\n"));
+        }
+
+        let response = &socket.0[0].1;
+        assert!(response.contains("ST: upnp:rootdevice\r\n"));
+        assert!(response
+            .contains("USN: uuid:2fac1234-31f8-11b4-a222-08002b34c003::upnp:rootdevice\r\n"));
+
+        let response = &socket.0[1].1;
+        assert!(response.contains("ST: uuid:2fac1234-31f8-11b4-a222-08002b34c003\r\n"));
+        assert!(response.contains("USN: uuid:2fac1234-31f8-11b4-a222-08002b34c003\r\n"));
+
+        let socket = respond(&search("urn:schemas-upnp-org:service:SwitchPower:1"));
+
+        assert_eq!(socket.0.len(), 1);
+        assert!(socket.0[0]
+            .1
+            .contains("ST: urn:schemas-upnp-org:service:SwitchPower:1\r\n"));
+        assert!(socket.0[0].1.contains(
+            "USN: uuid:2fac1234-31f8-11b4-a222-08002b34c003::urn:schemas-upnp-org:service:SwitchPower:1\r\n"
+        ));
+
+        // UUIDs are matched case-insensitively
+        let socket = respond(&search("uuid:2FAC1234-31F8-11B4-A222-08002B34C003"));
+        assert_eq!(socket.0.len(), 1);
+
+        let socket = respond(&search("urn:schemas-upnp-org:device:Other:1"));
+        assert!(socket.0.is_empty());
+    }
+
+    #[test]
+    fn test_search_ignored() {
+        // Missing `MAN`
+        let socket = respond("M-SEARCH * HTTP/1.1\r\nST: ssdp:all\r\n\r\n");
+        assert!(socket.0.is_empty());
+
+        // Missing `ST`
+        let socket = respond("M-SEARCH * HTTP/1.1\r\nMAN: \"ssdp:discover\"\r\n\r\n");
+        assert!(socket.0.is_empty());
+
+        let socket = respond("NOTIFY * HTTP/1.1\r\nNT: upnp:rootdevice\r\nNTS: ssdp:alive\r\n\r\n");
+        assert!(socket.0.is_empty());
+
+        let socket = respond("garbage");
+        assert!(socket.0.is_empty());
+
+        // Unicast requests have no `MX` header
+        let socket =
+            respond("M-SEARCH * HTTP/1.1\r\nMAN: \"ssdp:discover\"\r\nST: upnp:rootdevice\r\n\r\n");
+        assert_eq!(socket.0.len(), 1);
+    }
+
+    #[test]
+    fn test_notify() {
+        let responder = Responder::new(DEVICE, |buf| buf.fill(0));
+
+        let mut socket = Socket::default();
+        let mut tx_buf = [0; 512];
+
+        embassy_futures::block_on(pin!(responder.notify(&mut socket, true, &mut tx_buf))).unwrap();
+
+        assert_eq!(socket.0.len(), 4);
+
+        for (remote, notification) in &socket.0 {
+            assert_eq!(
+                *remote,
+                SocketAddr::V4(SocketAddrV4::new(IP_MULTICAST_ADDR, PORT))
+            );
+            assert!(notification.starts_with("NOTIFY * HTTP/1.1\r\n"));
+            assert!(notification.contains("HOST: 239.255.255.250:1900\r\n"));
+            assert!(notification.contains("NTS: ssdp:alive\r\n"));
+            assert!(notification.contains("LOCATION: http://192.168.1.2:80/description.xml\r\n"));
+            assert!(notification.contains("SERVER: edge/1.0 UPnP/1.1 test/1.0\r\n"));
+        }
+
+        assert!(socket.0[2]
+            .1
+            .contains("NT: urn:schemas-upnp-org:device:Basic:1\r\n"));
+
+        let mut socket = Socket::default();
+
+        embassy_futures::block_on(pin!(responder.notify(&mut socket, false, &mut tx_buf))).unwrap();
+
+        assert_eq!(socket.0.len(), 4);
+
+        for (_, notification) in &socket.0 {
+            assert!(notification.contains("NTS: ssdp:byebye\r\n"));
+            assert!(!notification.contains("LOCATION"));
+        }
+    }
+
+    #[test]
+    fn test_random_delay() {
+        let responder = Responder::new(DEVICE, |buf| buf.fill(0xff));
+
+        assert_eq!(responder.random_delay(0), Duration::from_millis(0));
+        assert_eq!(responder.random_delay(1), Duration::from_millis(999));
+        // `MX` values above 5 are treated as 5
+        assert_eq!(responder.random_delay(120), Duration::from_millis(4999));
+
+        let responder = Responder::new(DEVICE, |buf| buf.fill(0));
+
+        assert_eq!(responder.random_delay(3), Duration::from_millis(0));
+    }
+
+    #[test]
+    fn test_run() {
+        // Multicast responses are delayed by ~1s
+        let responder = Responder::new(DEVICE, |buf| buf.fill(0xff));
+
+        let requests = [
+            search("upnp:rootdevice"),
+            "M-SEARCH * HTTP/1.1\r\nMAN: \"ssdp:discover\"\r\nST: ssdp:all\r\n\r\n"
+                .try_into()
+                .unwrap(),
+        ];
+        let requests = [requests[0].as_str(), requests[1].as_str()];
+
+        let sent = RefCell::new(Socket::default());
+        let socket = RunSocket {
+            requests: &requests,
+            sent: &sent,
+        };
+
+        let mut rx_buf = [0; 512];
+        let mut tx_buf = [0; 512];
+
+        let mut run = pin!(responder.run(socket, &mut rx_buf, &mut tx_buf));
+
+        // The unicast request is answered while the response to the multicast one is pending
+        embassy_futures::block_on(select(
+            run.as_mut(),
+            Timer::after(Duration::from_millis(500)),
+        ));
+
+        let count = |prefix| {
+            sent.borrow()
+                .0
+                .iter()
+                .filter(|(_, data)| data.starts_with(prefix))
+                .count()
+        };
+
+        assert_eq!(count("NOTIFY * HTTP/1.1\r\n"), 4);
+        assert_eq!(count("HTTP/1.1 200 OK\r\n"), 4);
+
+        embassy_futures::block_on(select(run, Timer::after(Duration::from_millis(1000))));
+
+        let sent = sent.borrow();
+        assert_eq!(sent.0.len(), 9);
+        assert_eq!(sent.0[8].0, REMOTE);
+        assert!(sent.0[8].1.contains("ST: upnp:rootdevice\r\n"));
+    }
+}
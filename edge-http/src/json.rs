@@ -0,0 +1,70 @@
+//! Compute-only helpers for JSON request and response bodies, based on `serde-json-core`.
+
+pub use serde_json_core::{de, ser};
+
+/// The content type of JSON bodies.
+pub const CONTENT_TYPE: &str = "application/json";
+
+/// Check whether a `Content-Type` header value denotes a JSON body.
+///
+/// Parameters (like `charset`) are ignored, and structured syntax suffixes
+/// (like `application/problem+json`) are accepted as well.
+pub fn is_json(content_type: Option<&str>) -> bool {
+    let Some(content_type) = content_type else {
+        return false;
+    };
+
+    let media_type = content_type.split(';').next().unwrap_or("").trim();
+
+    if media_type.eq_ignore_ascii_case(CONTENT_TYPE) {
+        return true;
+    }
+
+    const SUFFIX: &str = "+json";
+
+    let Some((main_type, sub_type)) = media_type.split_once('/') else {
+        return false;
+    };
+
+    main_type.eq_ignore_ascii_case("application")
+        && sub_type.len() > SUFFIX.len()
+        && sub_type
+            .get(sub_type.len() - SUFFIX.len()..)
+            .map(|suffix| suffix.eq_ignore_ascii_case(SUFFIX))
+            .unwrap_or(false)
+}
+
+/// Deserialize a value from a JSON body.
+pub fn from_slice<'a, T>(body: &'a [u8]) -> Result<T, de::Error>
+where
+    T: serde::Deserialize<'a>,
+{
+    serde_json_core::from_slice(body).map(|(value, _)| value)
+}
+
+/// Serialize a value as a JSON body into the provided buffer.
+///
+/// Returns the length of the body.
+pub fn to_slice<T>(value: &T, buf: &mut [u8]) -> Result<usize, ser::Error>
+where
+    T: serde::Serialize + ?Sized,
+{
+    serde_json_core::to_slice(value, buf)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_json() {
+        assert!(is_json(Some("application/json")));
+        assert!(is_json(Some("Application/JSON; charset=utf-8")));
+        assert!(is_json(Some("application/problem+json")));
+
+        assert!(!is_json(None));
+        assert!(!is_json(Some("text/plain")));
+        assert!(!is_json(Some("text/json+xml")));
+        assert!(!is_json(Some("application/+json")));
+    }
+}
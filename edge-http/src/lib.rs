@@ -12,6 +12,9 @@ pub const DEFAULT_MAX_HEADERS_COUNT: usize = 64;
 
 #[cfg(feature = "io")]
 pub mod io;
+#[cfg(feature = "serde")]
+pub mod json;
+pub mod range;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "std", derive(Hash))]
@@ -101,7 +104,8 @@ impl Method {
             Some(Self::Checkout)
         } else if method.eq_ignore_ascii_case("Merge") {
             Some(Self::Merge)
-        } else if method.eq_ignore_ascii_case("MSearch") {
+        } else if method.eq_ignore_ascii_case("M-Search") || method.eq_ignore_ascii_case("MSearch")
+        {
             Some(Self::MSearch)
         } else if method.eq_ignore_ascii_case("Notify") {
             Some(Self::Notify)
@@ -150,7 +154,7 @@ impl Method {
             Self::MkActivity => "MKACTIVITY",
             Self::Checkout => "CHECKOUT",
             Self::Merge => "MERGE",
-            Self::MSearch => "MSEARCH",
+            Self::MSearch => "M-SEARCH",
             Self::Notify => "NOTIFY",
             Self::Subscribe => "SUBSCRIBE",
             Self::Unsubscribe => "UNSUBSCRIBE",
@@ -211,6 +215,22 @@ impl<'b, const N: usize> Headers<'b, N> {
         self.get("Upgrade")
     }
 
+    pub fn range(&self) -> Option<&str> {
+        self.get("Range")
+    }
+
+    pub fn if_range(&self) -> Option<&str> {
+        self.get("If-Range")
+    }
+
+    pub fn content_range(&self) -> Option<&str> {
+        self.get("Content-Range")
+    }
+
+    pub fn etag(&self) -> Option<&str> {
+        self.get("ETag")
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
         self.iter_raw()
             .map(|(name, value)| (name, unsafe { str::from_utf8_unchecked(value) }))
@@ -351,6 +371,24 @@ impl<'b, const N: usize> Headers<'b, N> {
         self
     }
 
+    /// Like `set_ws_upgrade_request_headers`, but also offering the subprotocols and
+    /// the `permessage-deflate` extension of `options`.
+    ///
+    /// The extension offer is formatted into `extensions_buf`.
+    pub fn set_ws_upgrade_request_headers_with(
+        &mut self,
+        options: &ws::WsUpgradeOptions<'b>,
+        nonce: &[u8; ws::NONCE_LEN],
+        buf: &'b mut [u8; ws::MAX_BASE64_KEY_LEN],
+        extensions_buf: &'b mut heapless::String<{ ws::MAX_DEFLATE_PARAMS_LEN }>,
+    ) -> &mut Self {
+        for (name, value) in ws::upgrade_request_headers_with(options, nonce, buf, extensions_buf) {
+            self.set(name, value);
+        }
+
+        self
+    }
+
     pub fn set_ws_upgrade_response_headers<'a, H>(
         &mut self,
         request_headers: H,
@@ -366,6 +404,29 @@ impl<'b, const N: usize> Headers<'b, N> {
 
         Ok(self)
     }
+
+    /// Like `set_ws_upgrade_response_headers`, but also accepting the subprotocol and
+    /// the `permessage-deflate` extension of `options`.
+    ///
+    /// The extension response is formatted into `extensions_buf`.
+    pub fn set_ws_upgrade_response_headers_with<'a, H>(
+        &mut self,
+        request_headers: H,
+        options: &ws::WsUpgradeResponseOptions<'b>,
+        buf: &'b mut [u8; ws::MAX_BASE64_KEY_RESPONSE_LEN],
+        extensions_buf: &'b mut heapless::String<{ ws::MAX_DEFLATE_PARAMS_LEN }>,
+    ) -> Result<&mut Self, ws::UpgradeError>
+    where
+        H: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        for (name, value) in
+            ws::upgrade_response_headers_with(request_headers, options, buf, extensions_buf)?
+        {
+            self.set(name, value);
+        }
+
+        Ok(self)
+    }
 }
 
 impl<'b, const N: usize> Default for Headers<'b, N> {
@@ -435,6 +496,11 @@ impl<'b, const N: usize> RequestHeaders<'b, N> {
     pub fn is_ws_upgrade_request(&self) -> bool {
         is_upgrade_request(self.method, self.headers.iter())
     }
+
+    /// Select the first of the `supported` WebSocket subprotocols which is offered by the client.
+    pub fn select_ws_protocol<'s>(&self, supported: &[&'s str]) -> Option<&'s str> {
+        ws::select_protocol(self.headers.iter(), supported)
+    }
 }
 
 impl<'b, const N: usize> Display for RequestHeaders<'b, N> {
@@ -485,6 +551,14 @@ impl<'b, const N: usize> ResponseHeaders<'b, N> {
     ) -> bool {
         is_upgrade_accepted(self.code, self.headers.iter(), nonce, buf)
     }
+
+    /// Return the WebSocket subprotocol selected by the server, failing if it is not one of the `offered` ones.
+    pub fn ws_protocol<'o>(
+        &self,
+        offered: Option<&'o str>,
+    ) -> Result<Option<&'o str>, ws::UpgradeError> {
+        ws::accepted_protocol(self.headers.iter(), offered)
+    }
 }
 
 impl<'b, const N: usize> Display for ResponseHeaders<'b, N> {
@@ -523,6 +597,40 @@ pub mod ws {
     pub const UPGRADE_REQUEST_HEADERS_LEN: usize = 7;
     pub const UPGRADE_RESPONSE_HEADERS_LEN: usize = 4;
 
+    /// The number of headers returned by `upgrade_request_headers_with`.
+    pub const UPGRADE_REQUEST_HEADERS_WITH_LEN: usize = UPGRADE_REQUEST_HEADERS_LEN + 2;
+    /// The number of headers returned by `upgrade_response_headers_with`.
+    pub const UPGRADE_RESPONSE_HEADERS_WITH_LEN: usize = UPGRADE_RESPONSE_HEADERS_LEN + 2;
+
+    /// The maximum length of a formatted `permessage-deflate` extension offer or response.
+    pub const MAX_DEFLATE_PARAMS_LEN: usize = 128;
+
+    /// The parameters of a WebSocket upgrade request.
+    #[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+    pub struct WsUpgradeOptions<'a> {
+        /// The `Host` header, if any
+        pub host: Option<&'a str>,
+        /// The `Origin` header, if any
+        pub origin: Option<&'a str>,
+        /// The WebSocket version; `13` if not provided
+        pub version: Option<&'a str>,
+        /// A comma-separated list of the subprotocols offered to the server, if any (e.g. `graphql-ws, mqtt`)
+        pub protocols: Option<&'a str>,
+        /// The `permessage-deflate` parameters offered to the server, if any
+        pub deflate: Option<DeflateParams>,
+    }
+
+    /// The parameters of a WebSocket upgrade response.
+    #[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+    pub struct WsUpgradeResponseOptions<'a> {
+        /// The WebSocket version expected from the client; `13` if not provided
+        pub version: Option<&'a str>,
+        /// The subprotocol selected by the server, if any (see `select_protocol`)
+        pub protocol: Option<&'a str>,
+        /// The `permessage-deflate` parameters accepted by the server, if any (see `DeflateParams::negotiate`)
+        pub deflate: Option<DeflateParams>,
+    }
+
     pub fn upgrade_request_headers<'a>(
         host: Option<&'a str>,
         origin: Option<&'a str>,
@@ -530,8 +638,54 @@ pub mod ws {
         nonce: &[u8; NONCE_LEN],
         buf: &'a mut [u8; MAX_BASE64_KEY_LEN],
     ) -> [(&'a str, &'a str); UPGRADE_REQUEST_HEADERS_LEN] {
-        let host = host.map(|host| ("Host", host)).unwrap_or(("", ""));
-        let origin = origin.map(|origin| ("Origin", origin)).unwrap_or(("", ""));
+        let options = WsUpgradeOptions {
+            host,
+            origin,
+            version,
+            ..Default::default()
+        };
+
+        let [host, origin, len, connection, upgrade, version, key, _, _] =
+            request_headers(&options, None, nonce, buf);
+
+        [host, origin, len, connection, upgrade, version, key]
+    }
+
+    /// Like `upgrade_request_headers`, but also offering the subprotocols and
+    /// the `permessage-deflate` extension of `options`.
+    ///
+    /// The extension offer is formatted into `extensions_buf`.
+    pub fn upgrade_request_headers_with<'a>(
+        options: &WsUpgradeOptions<'a>,
+        nonce: &[u8; NONCE_LEN],
+        buf: &'a mut [u8; MAX_BASE64_KEY_LEN],
+        extensions_buf: &'a mut heapless::String<MAX_DEFLATE_PARAMS_LEN>,
+    ) -> [(&'a str, &'a str); UPGRADE_REQUEST_HEADERS_WITH_LEN] {
+        let extensions = options
+            .deflate
+            .map(|deflate| deflate.format(extensions_buf));
+
+        request_headers(options, extensions, nonce, buf)
+    }
+
+    fn request_headers<'a>(
+        options: &WsUpgradeOptions<'a>,
+        extensions: Option<&'a str>,
+        nonce: &[u8; NONCE_LEN],
+        buf: &'a mut [u8; MAX_BASE64_KEY_LEN],
+    ) -> [(&'a str, &'a str); UPGRADE_REQUEST_HEADERS_WITH_LEN] {
+        let host = options.host.map(|host| ("Host", host)).unwrap_or(("", ""));
+        let origin = options
+            .origin
+            .map(|origin| ("Origin", origin))
+            .unwrap_or(("", ""));
+        let protocols = options
+            .protocols
+            .map(|protocols| ("Sec-WebSocket-Protocol", protocols))
+            .unwrap_or(("", ""));
+        let extensions = extensions
+            .map(|extensions| ("Sec-WebSocket-Extensions", extensions))
+            .unwrap_or(("", ""));
 
         [
             host,
@@ -539,8 +693,10 @@ pub mod ws {
             ("Content-Length", "0"),
             ("Connection", "Upgrade"),
             ("Upgrade", "websocket"),
-            ("Sec-WebSocket-Version", version.unwrap_or("13")),
+            ("Sec-WebSocket-Version", options.version.unwrap_or("13")),
             ("Sec-WebSocket-Key", sec_key_encode(nonce, buf)),
+            protocols,
+            extensions,
         ]
     }
 
@@ -571,6 +727,8 @@ pub mod ws {
         NoVersion,
         NoSecKey,
         UnsupportedVersion,
+        InvalidExtensions,
+        InvalidProtocol,
     }
 
     impl fmt::Display for UpgradeError {
@@ -579,6 +737,8 @@ pub mod ws {
                 Self::NoVersion => write!(f, "No Sec-WebSocket-Version header"),
                 Self::NoSecKey => write!(f, "No Sec-WebSocket-Key header"),
                 Self::UnsupportedVersion => write!(f, "Unsupported Sec-WebSocket-Version"),
+                Self::InvalidExtensions => write!(f, "Invalid Sec-WebSocket-Extensions header"),
+                Self::InvalidProtocol => write!(f, "Invalid Sec-WebSocket-Protocol header"),
             }
         }
     }
@@ -591,6 +751,48 @@ pub mod ws {
         version: Option<&'a str>,
         buf: &'b mut [u8; MAX_BASE64_KEY_RESPONSE_LEN],
     ) -> Result<[(&'b str, &'b str); UPGRADE_RESPONSE_HEADERS_LEN], UpgradeError>
+    where
+        H: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        let [len, connection, upgrade, accept, _, _] =
+            response_headers(request_headers, version, None, None, buf)?;
+
+        Ok([len, connection, upgrade, accept])
+    }
+
+    /// Like `upgrade_response_headers`, but also accepting the subprotocol and
+    /// the `permessage-deflate` extension of `options`.
+    ///
+    /// The extension response is formatted into `extensions_buf`.
+    pub fn upgrade_response_headers_with<'a, 'b, H>(
+        request_headers: H,
+        options: &WsUpgradeResponseOptions<'b>,
+        buf: &'b mut [u8; MAX_BASE64_KEY_RESPONSE_LEN],
+        extensions_buf: &'b mut heapless::String<MAX_DEFLATE_PARAMS_LEN>,
+    ) -> Result<[(&'b str, &'b str); UPGRADE_RESPONSE_HEADERS_WITH_LEN], UpgradeError>
+    where
+        H: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        let extensions = options
+            .deflate
+            .map(|deflate| deflate.format(extensions_buf));
+
+        response_headers(
+            request_headers,
+            options.version,
+            options.protocol,
+            extensions,
+            buf,
+        )
+    }
+
+    fn response_headers<'a, 'b, H>(
+        request_headers: H,
+        version: Option<&str>,
+        protocol: Option<&'b str>,
+        extensions: Option<&'b str>,
+        buf: &'b mut [u8; MAX_BASE64_KEY_RESPONSE_LEN],
+    ) -> Result<[(&'b str, &'b str); UPGRADE_RESPONSE_HEADERS_WITH_LEN], UpgradeError>
     where
         H: IntoIterator<Item = (&'a str, &'a str)>,
     {
@@ -618,6 +820,12 @@ pub mod ws {
                     ("Sec-WebSocket-Accept", unsafe {
                         core::str::from_utf8_unchecked(&buf[..sec_key_resp_len])
                     }),
+                    protocol
+                        .map(|protocol| ("Sec-WebSocket-Protocol", protocol))
+                        .unwrap_or(("", "")),
+                    extensions
+                        .map(|extensions| ("Sec-WebSocket-Extensions", extensions))
+                        .unwrap_or(("", "")),
                 ])
             } else {
                 Err(UpgradeError::NoSecKey)
@@ -664,6 +872,285 @@ pub mod ws {
         connection && upgrade && sec_key_response
     }
 
+    /// Select the first of the `supported` subprotocols which is offered in the `Sec-WebSocket-Protocol`
+    /// headers of an upgrade request.
+    ///
+    /// Subprotocol names are case-sensitive.
+    pub fn select_protocol<'a, 's, H>(request_headers: H, supported: &[&'s str]) -> Option<&'s str>
+    where
+        H: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        request_headers
+            .into_iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("Sec-WebSocket-Protocol"))
+            .flat_map(|(_, value)| value.split(','))
+            .filter_map(|offered| {
+                supported
+                    .iter()
+                    .position(|protocol| *protocol == offered.trim())
+            })
+            .min()
+            .map(|index| supported[index])
+    }
+
+    /// Validate the `Sec-WebSocket-Protocol` header of an upgrade response against the comma-separated
+    /// list of subprotocols `offered` by the client.
+    ///
+    /// Return the selected subprotocol, or `None` if the server did not select any.
+    pub fn accepted_protocol<'a, 'o, H>(
+        response_headers: H,
+        offered: Option<&'o str>,
+    ) -> Result<Option<&'o str>, UpgradeError>
+    where
+        H: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        let mut selected = None;
+
+        for (name, value) in response_headers {
+            if name.eq_ignore_ascii_case("Sec-WebSocket-Protocol") {
+                let protocol = offered
+                    .into_iter()
+                    .flat_map(|offered| offered.split(','))
+                    .map(str::trim)
+                    .find(|protocol| *protocol == value.trim());
+
+                if selected.is_some() || protocol.is_none() {
+                    return Err(UpgradeError::InvalidProtocol);
+                }
+
+                selected = protocol;
+            }
+        }
+
+        Ok(selected)
+    }
+
+    /// The parameters of the `permessage-deflate` extension (RFC 7692).
+    ///
+    /// The same structure is used for the extension offer of the client, the server configuration
+    /// and the negotiated parameters.
+    #[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+    pub struct DeflateParams {
+        /// The server resets its compression context after each message
+        pub server_no_context_takeover: bool,
+        /// The client resets its compression context after each message
+        pub client_no_context_takeover: bool,
+        /// The base-2 logarithm (8 - 15) of the LZ77 window used by the server to compress messages
+        pub server_max_window_bits: Option<u8>,
+        /// The base-2 logarithm (8 - 15) of the LZ77 window used by the client to compress messages
+        pub client_max_window_bits: Option<u8>,
+    }
+
+    impl DeflateParams {
+        pub const NAME: &'static str = "permessage-deflate";
+
+        pub const MIN_WINDOW_BITS: u8 = 8;
+        pub const MAX_WINDOW_BITS: u8 = 15;
+
+        /// Parse a single extension of a `Sec-WebSocket-Extensions` header value,
+        /// e.g. `permessage-deflate; client_max_window_bits`.
+        ///
+        /// Return `Ok(None)` if the extension is not `permessage-deflate`.
+        pub fn parse(extension: &str) -> Result<Option<Self>, UpgradeError> {
+            let mut parts = extension.split(';').map(str::trim);
+
+            if !parts
+                .next()
+                .map(|name| name.eq_ignore_ascii_case(Self::NAME))
+                .unwrap_or(false)
+            {
+                return Ok(None);
+            }
+
+            let mut params = Self::default();
+            let mut seen = 0_u8;
+
+            for part in parts {
+                let (name, value) = match part.split_once('=') {
+                    Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                    None => (part, None),
+                };
+
+                let index = if name.eq_ignore_ascii_case("server_no_context_takeover") {
+                    if value.is_some() {
+                        return Err(UpgradeError::InvalidExtensions);
+                    }
+
+                    params.server_no_context_takeover = true;
+                    0
+                } else if name.eq_ignore_ascii_case("client_no_context_takeover") {
+                    if value.is_some() {
+                        return Err(UpgradeError::InvalidExtensions);
+                    }
+
+                    params.client_no_context_takeover = true;
+                    1
+                } else if name.eq_ignore_ascii_case("server_max_window_bits") {
+                    params.server_max_window_bits = Some(Self::parse_window_bits(
+                        value.ok_or(UpgradeError::InvalidExtensions)?,
+                    )?);
+                    2
+                } else if name.eq_ignore_ascii_case("client_max_window_bits") {
+                    // Without a value, the client just advertises support for the parameter
+                    params.client_max_window_bits = Some(
+                        value
+                            .map(Self::parse_window_bits)
+                            .transpose()?
+                            .unwrap_or(Self::MAX_WINDOW_BITS),
+                    );
+                    3
+                } else {
+                    return Err(UpgradeError::InvalidExtensions);
+                };
+
+                if seen & (1 << index) != 0 {
+                    return Err(UpgradeError::InvalidExtensions);
+                }
+
+                seen |= 1 << index;
+            }
+
+            Ok(Some(params))
+        }
+
+        /// Return the first valid `permessage-deflate` offer in the `Sec-WebSocket-Extensions` headers
+        /// of an upgrade request.
+        pub fn offered<'a, H>(request_headers: H) -> Option<Self>
+        where
+            H: IntoIterator<Item = (&'a str, &'a str)>,
+        {
+            request_headers
+                .into_iter()
+                .filter(|(name, _)| name.eq_ignore_ascii_case("Sec-WebSocket-Extensions"))
+                .flat_map(|(_, value)| value.split(','))
+                .find_map(|extension| Self::parse(extension).ok().flatten())
+        }
+
+        /// Negotiate the parameters to be used, given the offer of the client (`self`) and
+        /// the configuration of the server.
+        ///
+        /// Return `None` if the offer is not acceptable, in which case the extension
+        /// should not be used.
+        pub fn negotiate(&self, config: &Self) -> Option<Self> {
+            let server_max_window_bits =
+                match (self.server_max_window_bits, config.server_max_window_bits) {
+                    (Some(offered), Some(configured)) => Some(offered.min(configured)),
+                    (offered, configured) => offered.or(configured),
+                };
+
+            // The server can only limit the window of the client if the client supports it
+            let client_max_window_bits = match config.client_max_window_bits {
+                Some(configured) if configured < Self::MAX_WINDOW_BITS => {
+                    Some(configured.min(self.client_max_window_bits?))
+                }
+                _ => None,
+            };
+
+            Some(Self {
+                server_no_context_takeover: self.server_no_context_takeover
+                    || config.server_no_context_takeover,
+                client_no_context_takeover: self.client_no_context_takeover
+                    || config.client_no_context_takeover,
+                server_max_window_bits,
+                client_max_window_bits,
+            })
+        }
+
+        /// Validate the `Sec-WebSocket-Extensions` headers of an upgrade response
+        /// against the offer of the client (`self`).
+        ///
+        /// Return the negotiated parameters, or `None` if the server declined the extension.
+        pub fn accepted<'a, H>(&self, response_headers: H) -> Result<Option<Self>, UpgradeError>
+        where
+            H: IntoIterator<Item = (&'a str, &'a str)>,
+        {
+            let mut accepted = None;
+
+            for extension in response_headers
+                .into_iter()
+                .filter(|(name, _)| name.eq_ignore_ascii_case("Sec-WebSocket-Extensions"))
+                .flat_map(|(_, value)| value.split(','))
+            {
+                let params = Self::parse(extension)?.ok_or(UpgradeError::InvalidExtensions)?;
+
+                let server_window_ok = match self.server_max_window_bits {
+                    Some(offered) => params
+                        .server_max_window_bits
+                        .map(|bits| bits <= offered)
+                        .unwrap_or(false),
+                    None => true,
+                };
+
+                let client_window_ok = match params.client_max_window_bits {
+                    Some(bits) => self
+                        .client_max_window_bits
+                        .map(|offered| bits <= offered)
+                        .unwrap_or(false),
+                    None => true,
+                };
+
+                if accepted.is_some() || !server_window_ok || !client_window_ok {
+                    return Err(UpgradeError::InvalidExtensions);
+                }
+
+                accepted = Some(params);
+            }
+
+            Ok(accepted)
+        }
+
+        /// The base-2 logarithm of the LZ77 window used by the server.
+        pub fn server_window_bits(&self) -> u8 {
+            self.server_max_window_bits.unwrap_or(Self::MAX_WINDOW_BITS)
+        }
+
+        /// The base-2 logarithm of the LZ77 window used by the client.
+        pub fn client_window_bits(&self) -> u8 {
+            self.client_max_window_bits.unwrap_or(Self::MAX_WINDOW_BITS)
+        }
+
+        /// Format the parameters as a `Sec-WebSocket-Extensions` header value.
+        pub fn format<'a>(&self, buf: &'a mut heapless::String<MAX_DEFLATE_PARAMS_LEN>) -> &'a str {
+            use core::fmt::Write as _;
+
+            buf.clear();
+
+            // Cannot fail, as `MAX_DEFLATE_PARAMS_LEN` fits all parameters
+            let _ = buf.push_str(Self::NAME);
+
+            if self.server_no_context_takeover {
+                let _ = buf.push_str("; server_no_context_takeover");
+            }
+
+            if self.client_no_context_takeover {
+                let _ = buf.push_str("; client_no_context_takeover");
+            }
+
+            if let Some(bits) = self.server_max_window_bits {
+                let _ = write!(buf, "; server_max_window_bits={bits}");
+            }
+
+            if let Some(bits) = self.client_max_window_bits {
+                let _ = write!(buf, "; client_max_window_bits={bits}");
+            }
+
+            buf.as_str()
+        }
+
+        fn parse_window_bits(value: &str) -> Result<u8, UpgradeError> {
+            // Leading zeroes are not allowed by the RFC
+            if value.starts_with('0') {
+                return Err(UpgradeError::InvalidExtensions);
+            }
+
+            value
+                .parse::<u8>()
+                .ok()
+                .filter(|bits| (Self::MIN_WINDOW_BITS..=Self::MAX_WINDOW_BITS).contains(bits))
+                .ok_or(UpgradeError::InvalidExtensions)
+        }
+    }
+
     fn sec_key_encode<'a>(nonce: &[u8], buf: &'a mut [u8]) -> &'a str {
         let nonce_base64_len = base64::encode_config_slice(nonce, base64::STANDARD, buf);
 
@@ -703,7 +1190,12 @@ pub mod ws {
 
 #[cfg(test)]
 mod test {
-    use crate::ws::{sec_key_response, MAX_BASE64_KEY_RESPONSE_LEN};
+    use crate::ws::{
+        accepted_protocol, sec_key_response, select_protocol, upgrade_request_headers,
+        upgrade_request_headers_with, upgrade_response_headers, upgrade_response_headers_with,
+        DeflateParams, UpgradeError, WsUpgradeOptions, WsUpgradeResponseOptions,
+        MAX_BASE64_KEY_LEN, MAX_BASE64_KEY_RESPONSE_LEN,
+    };
 
     #[test]
     fn test_resp() {
@@ -712,6 +1204,182 @@ mod test {
 
         assert_eq!(resp, "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
     }
+
+    #[test]
+    fn test_upgrade_request_headers() {
+        let mut buf = [0_u8; MAX_BASE64_KEY_LEN];
+
+        let headers = upgrade_request_headers(Some("host"), None, None, &[0; 16], &mut buf);
+
+        assert!(headers.contains(&("Host", "host")));
+        assert!(headers.contains(&("Sec-WebSocket-Version", "13")));
+        assert!(!headers
+            .iter()
+            .any(|(name, _)| *name == "Sec-WebSocket-Protocol"
+                || *name == "Sec-WebSocket-Extensions"));
+
+        let options = WsUpgradeOptions {
+            protocols: Some("graphql-ws, mqtt"),
+            deflate: Some(DeflateParams {
+                client_no_context_takeover: true,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let mut extensions_buf = heapless::String::new();
+
+        let headers =
+            upgrade_request_headers_with(&options, &[0; 16], &mut buf, &mut extensions_buf);
+
+        assert!(headers.contains(&("Sec-WebSocket-Protocol", "graphql-ws, mqtt")));
+        assert!(headers.contains(&(
+            "Sec-WebSocket-Extensions",
+            "permessage-deflate; client_no_context_takeover"
+        )));
+        assert!(!headers.iter().any(|(name, _)| *name == "Host"));
+    }
+
+    #[test]
+    fn test_upgrade_response_headers() {
+        let request = [
+            ("Sec-WebSocket-Version", "13"),
+            ("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="),
+        ];
+
+        let mut buf = [0_u8; MAX_BASE64_KEY_RESPONSE_LEN];
+
+        let headers = upgrade_response_headers(request, None, &mut buf).unwrap();
+
+        assert_eq!(
+            headers,
+            [
+                ("Content-Length", "0"),
+                ("Connection", "Upgrade"),
+                ("Upgrade", "websocket"),
+                ("Sec-WebSocket-Accept", "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="),
+            ]
+        );
+
+        let options = WsUpgradeResponseOptions {
+            protocol: Some("mqtt"),
+            deflate: Some(DeflateParams {
+                server_no_context_takeover: true,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let mut extensions_buf = heapless::String::new();
+
+        let headers =
+            upgrade_response_headers_with(request, &options, &mut buf, &mut extensions_buf)
+                .unwrap();
+
+        assert!(headers.contains(&("Sec-WebSocket-Accept", "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")));
+        assert!(headers.contains(&("Sec-WebSocket-Protocol", "mqtt")));
+        assert!(headers.contains(&(
+            "Sec-WebSocket-Extensions",
+            "permessage-deflate; server_no_context_takeover"
+        )));
+
+        let mut buf = [0_u8; MAX_BASE64_KEY_RESPONSE_LEN];
+
+        assert_eq!(
+            upgrade_response_headers([("Sec-WebSocket-Version", "13")], None, &mut buf),
+            Err(UpgradeError::NoSecKey)
+        );
+    }
+
+    #[test]
+    fn test_protocols() {
+        let request = [
+            ("Sec-WebSocket-Protocol", "graphql-ws, chat"),
+            ("Sec-WebSocket-Protocol", "mqtt"),
+        ];
+
+        assert_eq!(select_protocol(request, &["mqtt", "chat"]), Some("mqtt"));
+        assert_eq!(select_protocol(request, &["MQTT"]), None);
+        assert_eq!(select_protocol([("Host", "foo.com")], &["mqtt"]), None);
+
+        let offered = Some("graphql-ws, mqtt");
+
+        assert_eq!(
+            accepted_protocol([("Sec-WebSocket-Protocol", "mqtt")], offered),
+            Ok(Some("mqtt"))
+        );
+        assert_eq!(
+            accepted_protocol([("Upgrade", "websocket")], offered),
+            Ok(None)
+        );
+        assert_eq!(
+            accepted_protocol([("Sec-WebSocket-Protocol", "chat")], offered),
+            Err(UpgradeError::InvalidProtocol)
+        );
+        assert_eq!(
+            accepted_protocol([("Sec-WebSocket-Protocol", "mqtt")], None),
+            Err(UpgradeError::InvalidProtocol)
+        );
+    }
+
+    #[test]
+    fn test_deflate_params() {
+        let offer = DeflateParams::offered([
+            ("Host", "foo.com"),
+            (
+                "Sec-WebSocket-Extensions",
+                "x-foo, permessage-deflate; server_max_window_bits=7, permessage-deflate; client_max_window_bits",
+            ),
+        ])
+        .unwrap();
+
+        assert_eq!(offer.client_max_window_bits, Some(15));
+        assert_eq!(offer.server_max_window_bits, None);
+
+        let config = DeflateParams {
+            server_no_context_takeover: true,
+            server_max_window_bits: Some(10),
+            client_max_window_bits: Some(9),
+            ..Default::default()
+        };
+
+        let negotiated = offer.negotiate(&config).unwrap();
+
+        let mut buf = heapless::String::new();
+        let response = negotiated.format(&mut buf);
+
+        assert_eq!(
+            response,
+            "permessage-deflate; server_no_context_takeover; server_max_window_bits=10; client_max_window_bits=9"
+        );
+
+        assert_eq!(
+            offer.accepted([("Sec-WebSocket-Extensions", response)]),
+            Ok(Some(negotiated))
+        );
+        assert_eq!(offer.accepted([("Upgrade", "websocket")]), Ok(None));
+        assert_eq!(
+            offer.accepted([("Sec-WebSocket-Extensions", "x-foo")]),
+            Err(UpgradeError::InvalidExtensions)
+        );
+
+        // The client did not advertise support for `client_max_window_bits`
+        assert_eq!(DeflateParams::default().negotiate(&config), None);
+        assert_eq!(
+            DeflateParams::default().accepted([(
+                "Sec-WebSocket-Extensions",
+                "permessage-deflate; client_max_window_bits=9"
+            )]),
+            Err(UpgradeError::InvalidExtensions)
+        );
+
+        assert!(DeflateParams::parse("permessage-deflate; server_max_window_bits").is_err());
+        assert!(DeflateParams::parse("permessage-deflate; client_max_window_bits=16").is_err());
+        assert!(DeflateParams::parse(
+            "permessage-deflate; server_no_context_takeover; server_no_context_takeover"
+        )
+        .is_err());
+    }
 }
 
 #[cfg(feature = "embedded-svc")]
@@ -0,0 +1,510 @@
+//! Compute-only support for HTTP range requests, as per RFC 7233.
+//!
+//! Used by the server to answer `Range` / `If-Range` requests with `206 Partial Content`
+//! responses, and by the client to resume interrupted downloads.
+
+use core::fmt::{self, Write as _};
+
+/// The maximum number of ranges in a `Range` header which are honored.
+/// Requests with more ranges are answered with the full representation.
+pub const MAX_RANGES: usize = 8;
+
+/// The maximum length of a `Content-Range` header value (`bytes <start>-<end>/<total>`).
+pub const MAX_CONTENT_RANGE_LEN: usize = 68;
+
+/// The maximum length of the validator (an `ETag` or a `Last-Modified` date)
+/// which is kept for resuming downloads.
+pub const MAX_VALIDATOR_LEN: usize = 64;
+
+/// The maximum length of the boundary string of a `multipart/byteranges` response, as per RFC 2046.
+pub const MAX_BOUNDARY_LEN: usize = 70;
+
+/// A single range specification from a `Range: bytes=...` header, before it is
+/// resolved against the length of the representation.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RangeSpec {
+    /// `<first>-<last>`
+    FromTo(u64, u64),
+    /// `<first>-`
+    From(u64),
+    /// `-<suffix-length>`
+    Suffix(u64),
+}
+
+impl RangeSpec {
+    /// Resolve the range specification against a representation of length `len`.
+    ///
+    /// Returns `None` if the range is not satisfiable.
+    pub fn resolve(&self, len: u64) -> Option<ByteRange> {
+        match *self {
+            Self::FromTo(first, last) => (first < len).then(|| ByteRange {
+                start: first,
+                end: last.min(len - 1),
+            }),
+            Self::From(first) => (first < len).then(|| ByteRange {
+                start: first,
+                end: len - 1,
+            }),
+            Self::Suffix(suffix) => (suffix > 0 && len > 0).then(|| ByteRange {
+                start: len.saturating_sub(suffix),
+                end: len - 1,
+            }),
+        }
+    }
+}
+
+/// A satisfiable byte range, with both ends inclusive.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    /// The number of bytes in the range.
+    #[allow(clippy::len_without_is_empty)]
+    pub const fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// Format the range as a `Content-Range` header value.
+    pub fn content_range<'a>(
+        &self,
+        total: Option<u64>,
+        buf: &'a mut heapless::String<MAX_CONTENT_RANGE_LEN>,
+    ) -> &'a str {
+        buf.clear();
+
+        write!(buf, "{}", ContentRange::new(Some(*self), total)).unwrap();
+
+        buf.as_str()
+    }
+}
+
+/// Parse the value of a `Range` header.
+///
+/// Returns `None` if the header is syntactically invalid, uses a unit other than `bytes`
+/// or contains more than `MAX_RANGES` ranges. As per the spec, such headers should be ignored.
+pub fn parse_range(value: &str) -> Option<heapless::Vec<RangeSpec, MAX_RANGES>> {
+    let (unit, specs) = value.trim().split_once('=')?;
+
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+
+    let mut ranges = heapless::Vec::new();
+
+    for spec in specs
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+    {
+        let (first, last) = spec.split_once('-')?;
+        let (first, last) = (first.trim(), last.trim());
+
+        let spec = if first.is_empty() {
+            RangeSpec::Suffix(parse_num(last)?)
+        } else if last.is_empty() {
+            RangeSpec::From(parse_num(first)?)
+        } else {
+            let (first, last) = (parse_num(first)?, parse_num(last)?);
+
+            if first > last {
+                return None;
+            }
+
+            RangeSpec::FromTo(first, last)
+        };
+
+        ranges.push(spec).ok()?;
+    }
+
+    (!ranges.is_empty()).then_some(ranges)
+}
+
+/// Check whether the value of an `If-Range` header matches the current validator of the representation.
+///
+/// The validator is either a strong entity tag (quotes included), or a `Last-Modified` date.
+/// Weak entity tags never match, as per the spec.
+pub fn if_range_matches(if_range: &str, validator: Option<&str>) -> bool {
+    let if_range = if_range.trim();
+
+    validator
+        .map(|validator| !if_range.starts_with("W/") && if_range == validator.trim())
+        .unwrap_or(false)
+}
+
+/// The outcome of evaluating the `Range` and `If-Range` request headers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Ranges {
+    /// The full representation should be sent with a 200 status
+    Full,
+    /// The listed ranges should be sent with a 206 status
+    Partial(heapless::Vec<ByteRange, MAX_RANGES>),
+    /// None of the requested ranges is satisfiable; a 416 status should be sent
+    Unsatisfiable,
+}
+
+impl Ranges {
+    /// Evaluate the `Range` and `If-Range` request headers against a representation
+    /// of length `len` and with an optional validator (see `if_range_matches`).
+    pub fn from_headers<'a, H>(request_headers: H, len: u64, validator: Option<&str>) -> Self
+    where
+        H: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        let mut range = None;
+        let mut if_range = None;
+
+        for (name, value) in request_headers {
+            if name.eq_ignore_ascii_case("Range") {
+                range = Some(value);
+            } else if name.eq_ignore_ascii_case("If-Range") {
+                if_range = Some(value);
+            }
+        }
+
+        if let Some(if_range) = if_range {
+            if !if_range_matches(if_range, validator) {
+                return Self::Full;
+            }
+        }
+
+        let Some(specs) = range.and_then(parse_range) else {
+            return Self::Full;
+        };
+
+        let ranges: heapless::Vec<_, MAX_RANGES> =
+            specs.iter().filter_map(|spec| spec.resolve(len)).collect();
+
+        if ranges.is_empty() {
+            Self::Unsatisfiable
+        } else {
+            Self::Partial(ranges)
+        }
+    }
+
+    /// Coalesce all ranges into a single range which covers all of them.
+    ///
+    /// Useful when the server does not want to reply with a `multipart/byteranges` body.
+    pub fn coalesced(&self) -> Option<ByteRange> {
+        if let Self::Partial(ranges) = self {
+            let start = ranges.iter().map(|range| range.start).min()?;
+            let end = ranges.iter().map(|range| range.end).max()?;
+
+            Some(ByteRange { start, end })
+        } else {
+            None
+        }
+    }
+}
+
+/// A parsed (or to-be-formatted) `Content-Range` header value.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ContentRange {
+    /// The range being sent, or `None` for `*` (used with 416 responses)
+    pub range: Option<ByteRange>,
+    /// The complete length of the representation, or `None` if unknown (`*`)
+    pub total: Option<u64>,
+}
+
+impl ContentRange {
+    pub const fn new(range: Option<ByteRange>, total: Option<u64>) -> Self {
+        Self { range, total }
+    }
+
+    /// Parse a `Content-Range: bytes <start>-<end>/<total>` header value.
+    pub fn parse(value: &str) -> Option<Self> {
+        let (unit, value) = value.trim().split_once(' ')?;
+
+        if !unit.eq_ignore_ascii_case("bytes") {
+            return None;
+        }
+
+        let (range, total) = value.trim().split_once('/')?;
+
+        let range = if range == "*" {
+            None
+        } else {
+            let (start, end) = range.split_once('-')?;
+            let (start, end) = (parse_num(start)?, parse_num(end)?);
+
+            if start > end {
+                return None;
+            }
+
+            Some(ByteRange { start, end })
+        };
+
+        let total = if total == "*" {
+            None
+        } else {
+            Some(parse_num(total)?)
+        };
+
+        if range.is_none() && total.is_none() {
+            return None;
+        }
+
+        if let (Some(range), Some(total)) = (range, total) {
+            if range.end >= total {
+                return None;
+            }
+        }
+
+        Some(Self { range, total })
+    }
+}
+
+impl fmt::Display for ContentRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bytes ")?;
+
+        if let Some(range) = self.range {
+            write!(f, "{}-{}/", range.start, range.end)?;
+        } else {
+            write!(f, "*/")?;
+        }
+
+        if let Some(total) = self.total {
+            write!(f, "{total}")
+        } else {
+            write!(f, "*")
+        }
+    }
+}
+
+/// Return the pieces of the part header which precedes each range in a
+/// `multipart/byteranges` body. Empty pieces should be skipped.
+pub fn multipart_part_header<'a>(
+    boundary: &'a str,
+    content_type: Option<&'a str>,
+    content_range: &'a str,
+) -> [&'a str; 9] {
+    let (ct_name, ct_value, ct_end) = content_type
+        .map(|content_type| ("Content-Type: ", content_type, "\r\n"))
+        .unwrap_or(("", "", ""));
+
+    [
+        "\r\n--",
+        boundary,
+        "\r\n",
+        ct_name,
+        ct_value,
+        ct_end,
+        "Content-Range: ",
+        content_range,
+        "\r\n\r\n",
+    ]
+}
+
+/// Return the pieces of the trailer which ends a `multipart/byteranges` body.
+pub fn multipart_end(boundary: &str) -> [&str; 3] {
+    ["\r\n--", boundary, "--\r\n"]
+}
+
+/// Compute the length of a `multipart/byteranges` body containing the provided ranges.
+pub fn multipart_len(
+    ranges: &[ByteRange],
+    total: u64,
+    boundary: &str,
+    content_type: Option<&str>,
+) -> u64 {
+    let mut buf = heapless::String::new();
+
+    let parts: u64 = ranges
+        .iter()
+        .map(|range| {
+            let content_range = range.content_range(Some(total), &mut buf);
+
+            multipart_part_header(boundary, content_type, content_range)
+                .iter()
+                .map(|piece| piece.len() as u64)
+                .sum::<u64>()
+                + range.len()
+        })
+        .sum();
+
+    parts
+        + multipart_end(boundary)
+            .iter()
+            .map(|piece| piece.len() as u64)
+            .sum::<u64>()
+}
+
+/// The state of a resumable download, as tracked by the client.
+///
+/// The state survives connection drops, so that the download can be continued from
+/// the last received offset with a new `Range` request.
+#[derive(Clone, Debug, Default)]
+pub struct ResumeState {
+    /// The number of bytes received so far
+    pub offset: u64,
+    /// The complete length of the resource, if known
+    pub total: Option<u64>,
+    /// The validator (strong `ETag` or `Last-Modified` date) of the resource, if known.
+    /// Sent as `If-Range` so that the server restarts the download if the resource had changed.
+    pub validator: heapless::String<MAX_VALIDATOR_LEN>,
+}
+
+impl ResumeState {
+    /// Create a new state for a download which is yet to start
+    pub const fn new() -> Self {
+        Self {
+            offset: 0,
+            total: None,
+            validator: heapless::String::new(),
+        }
+    }
+
+    /// Return `true` if the whole resource had been received
+    pub fn is_complete(&self) -> bool {
+        self.total
+            .map(|total| self.offset >= total)
+            .unwrap_or(false)
+    }
+
+    /// Format the `Range` header value which continues the download from the current offset.
+    pub fn range<'a>(&self, buf: &'a mut heapless::String<24>) -> &'a str {
+        buf.clear();
+
+        write!(buf, "bytes={}-", self.offset).unwrap();
+
+        buf.as_str()
+    }
+
+    /// Remember the validator of the resource from its response headers.
+    /// Weak entity tags are not usable with `If-Range` and are thus ignored.
+    pub fn update_validator<'a, H>(&mut self, response_headers: H)
+    where
+        H: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        let mut etag = None;
+        let mut last_modified = None;
+
+        for (name, value) in response_headers {
+            if name.eq_ignore_ascii_case("ETag") && !value.trim().starts_with("W/") {
+                etag = Some(value.trim());
+            } else if name.eq_ignore_ascii_case("Last-Modified") {
+                last_modified = Some(value.trim());
+            }
+        }
+
+        self.validator.clear();
+
+        if let Some(validator) = etag.or(last_modified) {
+            if self.validator.push_str(validator).is_err() {
+                self.validator.clear();
+            }
+        }
+    }
+}
+
+fn parse_num(value: &str) -> Option<u64> {
+    let value = value.trim();
+
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        None
+    } else {
+        value.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(
+            parse_range("bytes=0-499").unwrap().as_slice(),
+            &[RangeSpec::FromTo(0, 499)]
+        );
+        assert_eq!(
+            parse_range("Bytes = 500-, -200 ,, 3-3").unwrap().as_slice(),
+            &[
+                RangeSpec::From(500),
+                RangeSpec::Suffix(200),
+                RangeSpec::FromTo(3, 3)
+            ]
+        );
+
+        assert!(parse_range("bytes=5-1").is_none());
+        assert!(parse_range("bytes=-").is_none());
+        assert!(parse_range("bytes=+1-2").is_none());
+        assert!(parse_range("items=0-1").is_none());
+        assert!(parse_range("bytes=").is_none());
+        assert!(parse_range("bytes=0-0,1-1,2-2,3-3,4-4,5-5,6-6,7-7,8-8").is_none());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let range = |start, end| ByteRange { start, end };
+
+        assert_eq!(RangeSpec::FromTo(0, 99).resolve(50), Some(range(0, 49)));
+        assert_eq!(RangeSpec::From(10).resolve(50), Some(range(10, 49)));
+        assert_eq!(RangeSpec::Suffix(10).resolve(50), Some(range(40, 49)));
+        assert_eq!(RangeSpec::Suffix(100).resolve(50), Some(range(0, 49)));
+        assert_eq!(RangeSpec::From(50).resolve(50), None);
+        assert_eq!(RangeSpec::Suffix(0).resolve(50), None);
+        assert_eq!(RangeSpec::Suffix(1).resolve(0), None);
+
+        let headers = [("Range", "bytes=100-,0-9"), ("If-Range", "\"abc\"")];
+
+        assert_eq!(
+            Ranges::from_headers(headers, 50, Some("\"abc\"")),
+            Ranges::Partial(heapless::Vec::from_slice(&[range(0, 9)]).unwrap())
+        );
+        assert_eq!(
+            Ranges::from_headers(headers, 50, Some("\"def\"")),
+            Ranges::Full
+        );
+        assert_eq!(
+            Ranges::from_headers([("Range", "bytes=100-")], 50, None),
+            Ranges::Unsatisfiable
+        );
+        assert_eq!(
+            Ranges::from_headers([("Range", "bytes=1-2,10-12")], 50, None).coalesced(),
+            Some(range(1, 12))
+        );
+    }
+
+    #[test]
+    fn test_content_range() {
+        let mut buf = heapless::String::new();
+
+        let range = ByteRange { start: 0, end: 499 };
+        assert_eq!(
+            range.content_range(Some(1234), &mut buf),
+            "bytes 0-499/1234"
+        );
+        assert_eq!(range.content_range(None, &mut buf), "bytes 0-499/*");
+
+        assert_eq!(
+            ContentRange::parse("bytes 0-499/1234"),
+            Some(ContentRange::new(Some(range), Some(1234)))
+        );
+        assert_eq!(
+            ContentRange::parse("bytes */1234"),
+            Some(ContentRange::new(None, Some(1234)))
+        );
+        assert_eq!(ContentRange::parse("bytes 0-1234/1234"), None);
+        assert_eq!(ContentRange::parse("bytes */*"), None);
+    }
+
+    #[test]
+    fn test_multipart_len() {
+        let ranges = [
+            ByteRange { start: 0, end: 1 },
+            ByteRange { start: 5, end: 7 },
+        ];
+
+        let expected = "\r\n--XX\r\nContent-Type: a/b\r\nContent-Range: bytes 0-1/10\r\n\r\n01\
+                        \r\n--XX\r\nContent-Type: a/b\r\nContent-Range: bytes 5-7/10\r\n\r\n567\
+                        \r\n--XX--\r\n";
+
+        assert_eq!(
+            multipart_len(&ranges, 10, "XX", Some("a/b")),
+            expected.len() as u64
+        );
+    }
+}
@@ -0,0 +1,1185 @@
+//! Discovery of the instances of a DNS-SD service type on the local network, as per RFC 6762 §5.2 and RFC 6763.
+//!
+//! The `Browser` sends PTR queries for the service type, resolves the SRV, TXT, A and AAAA records of the
+//! discovered instances, and keeps them in a fixed-capacity cache. Cached records are refreshed at 80%, 85%,
+//! 90% and 95% of their TTL, and expire once their TTL elapses.
+//!
+//! The queries are sent by `io::Mdns::browse`, while the answers are processed by passing the `Browser`
+//! to `io::Mdns::run` as a `PeerAnswersMdnsHandler`.
+
+use core::cell::RefCell;
+use core::net::{Ipv4Addr, Ipv6Addr};
+
+use domain::base::iana::Class;
+use domain::base::message_builder::{AnswerBuilder, PushError, QuestionBuilder};
+use domain::base::name::ToLabelIter;
+use domain::base::wire::Composer;
+use domain::base::{MessageBuilder, Question, Record, Rtype, ToName, Ttl};
+use domain::rdata::{AllRecordData, Ptr};
+
+use embassy_sync::blocking_mutex::{self, raw::RawMutex};
+use embassy_sync::signal::Signal;
+
+use embassy_time::{Duration, Instant};
+
+use log::{debug, warn};
+
+use crate::probe::Name;
+use crate::{
+    compose_rdata, set_header, Buf, MdnsError, NameSlice, PeerAnswer, PeerAnswers, MAX_RDATA_LEN,
+};
+
+/// The interval between the first two PTR queries for the service type,
+/// which doubles for every subsequent query.
+pub const QUERY_INTERVAL: Duration = Duration::from_secs(1);
+/// The maximum interval between two PTR queries for the service type, as per spec.
+pub const MAX_QUERY_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// How many times to query for the SRV, TXT, A and AAAA records of an instance until it is resolved.
+pub const RESOLVE_ATTEMPTS: u8 = 3;
+/// The interval between two resolution queries for an instance.
+pub const RESOLVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The maximum length of the TXT data of an instance. Longer TXT data is truncated.
+pub const MAX_TXT_LEN: usize = MAX_RDATA_LEN;
+/// The maximum number of IPv4 and of IPv6 addresses cached for the host of an instance.
+/// Further addresses are ignored.
+pub const MAX_ADDRS: usize = 4;
+
+/// The percentages of the TTL of a cached record at which it is refreshed, as per spec.
+const REFRESH_PERCENTS: [u32; 4] = [80, 85, 90, 95];
+
+const LOCAL: NameSlice = NameSlice::new(&["local"]);
+
+/// An event delivered to the application by the `Browser`.
+#[derive(Debug)]
+pub enum BrowseEvent<'a> {
+    /// A new instance was discovered and resolved, i.e. its port and at least one of its addresses are known
+    Added(&'a Instance),
+    /// The port, the addresses or the TXT data of a resolved instance changed
+    Updated(&'a Instance),
+    /// A resolved instance is gone, i.e. it sent a goodbye announcement, or its records expired
+    Removed(&'a Instance),
+}
+
+/// A discovered instance of the browsed service type.
+#[derive(Debug, Clone)]
+pub struct Instance {
+    name: Name,
+    ptr: Expiry,
+    target: Option<Cached<Target>>,
+    txt: Option<Cached<heapless::Vec<u8, MAX_TXT_LEN>>>,
+    ipv4: heapless::Vec<Cached<Ipv4Addr>, MAX_ADDRS>,
+    ipv6: heapless::Vec<Cached<Ipv6Addr>, MAX_ADDRS>,
+    resolve_at: Instant,
+    resolve_attempts: u8,
+    announced: bool,
+    changed: bool,
+}
+
+impl Instance {
+    fn new(name: Name, ptr: Expiry) -> Self {
+        Self {
+            name,
+            ptr,
+            target: None,
+            txt: None,
+            ipv4: heapless::Vec::new(),
+            ipv6: heapless::Vec::new(),
+            resolve_at: ptr.received,
+            resolve_attempts: 0,
+            announced: false,
+            changed: false,
+        }
+    }
+
+    /// The name of the instance, i.e. "My Sensor" for "My Sensor._http._tcp.local".
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The host name of the instance, without the `.local` suffix.
+    pub fn hostname(&self) -> Option<&str> {
+        self.target
+            .as_ref()
+            .map(|target| target.value.hostname.as_str())
+    }
+
+    /// The port on which the instance listens.
+    pub fn port(&self) -> Option<u16> {
+        self.target.as_ref().map(|target| target.value.port)
+    }
+
+    /// The first IPv4 address of the host of the instance.
+    pub fn ipv4(&self) -> Option<Ipv4Addr> {
+        self.ipv4_addrs().next()
+    }
+
+    /// The first IPv6 address of the host of the instance.
+    pub fn ipv6(&self) -> Option<Ipv6Addr> {
+        self.ipv6_addrs().next()
+    }
+
+    /// The IPv4 addresses of the host of the instance.
+    pub fn ipv4_addrs(&self) -> impl Iterator<Item = Ipv4Addr> + '_ {
+        self.ipv4.iter().map(|ipv4| ipv4.value)
+    }
+
+    /// The IPv6 addresses of the host of the instance.
+    pub fn ipv6_addrs(&self) -> impl Iterator<Item = Ipv6Addr> + '_ {
+        self.ipv6.iter().map(|ipv6| ipv6.value)
+    }
+
+    /// The key-value pairs of the TXT record of the instance, as per the DNS-SD spec.
+    ///
+    /// Entries which are not valid UTF-8 are skipped. Keys without a value are returned with an empty value.
+    pub fn txt(&self) -> impl Iterator<Item = (&str, &str)> {
+        let mut data = self
+            .txt
+            .as_ref()
+            .map(|txt| txt.value.as_slice())
+            .unwrap_or(&[]);
+
+        core::iter::from_fn(move || loop {
+            let (&len, rest) = data.split_first()?;
+            let (entry, rest) = rest.split_at((len as usize).min(rest.len()));
+
+            data = rest;
+
+            if let Ok(entry) = core::str::from_utf8(entry) {
+                if !entry.is_empty() {
+                    break Some(entry.split_once('=').unwrap_or((entry, "")));
+                }
+            }
+        })
+    }
+
+    /// Return `true` if the port and at least one of the addresses of the instance are known.
+    pub fn is_resolved(&self) -> bool {
+        self.target.is_some() && !(self.ipv4.is_empty() && self.ipv6.is_empty())
+    }
+
+    fn needs_resolving(&self) -> bool {
+        (self.target.is_none() || self.txt.is_none() || !self.is_resolved())
+            && self.resolve_attempts < RESOLVE_ATTEMPTS
+    }
+
+    /// Remove the expired records, returning `false` if the instance itself expired.
+    fn expire(&mut self, now: Instant) -> bool {
+        if self.ptr.is_expired(now) || expire(&mut self.target, now) {
+            return false;
+        }
+
+        self.changed |= expire(&mut self.txt, now);
+        self.changed |= expire_addrs(&mut self.ipv4, now);
+        self.changed |= expire_addrs(&mut self.ipv6, now);
+
+        true
+    }
+
+    fn deadline(&self) -> Instant {
+        let mut deadline = self.ptr.deadline();
+
+        if let Some(target) = &self.target {
+            deadline = deadline.min(target.expiry.deadline());
+        }
+
+        if let Some(txt) = &self.txt {
+            deadline = deadline.min(txt.expiry.deadline());
+        }
+
+        for ipv4 in &self.ipv4 {
+            deadline = deadline.min(ipv4.expiry.deadline());
+        }
+
+        for ipv6 in &self.ipv6 {
+            deadline = deadline.min(ipv6.expiry.deadline());
+        }
+
+        if self.needs_resolving() {
+            deadline = deadline.min(self.resolve_at);
+        }
+
+        deadline
+    }
+
+    fn notify<F>(&mut self, events: &mut F)
+    where
+        F: FnMut(BrowseEvent<'_>),
+    {
+        let resolved = self.is_resolved();
+
+        if resolved && !self.announced {
+            debug!("Instance {} added", self.name);
+            events(BrowseEvent::Added(self));
+        } else if !resolved && self.announced {
+            debug!("Instance {} removed", self.name);
+            events(BrowseEvent::Removed(self));
+        } else if resolved && self.changed {
+            debug!("Instance {} updated", self.name);
+            events(BrowseEvent::Updated(self));
+        }
+
+        self.announced = resolved;
+        self.changed = false;
+    }
+}
+
+/// Browses the instances of a DNS-SD service type and keeps up to `N` of them in a cache.
+///
+/// Add, update and remove events are delivered to the `events` callback, which should not call back
+/// into the `Browser`, as the cache is locked while the callback runs.
+pub struct Browser<'a, M, F, const N: usize>
+where
+    M: RawMutex,
+{
+    service: &'a str,
+    protocol: &'a str,
+    state: blocking_mutex::Mutex<M, RefCell<State<F, N>>>,
+    signal: Signal<M, ()>,
+}
+
+impl<'a, M, F, const N: usize> Browser<'a, M, F, N>
+where
+    M: RawMutex,
+{
+    /// Create a new `Browser` for the provided service type (i.e. "_http") and protocol (i.e. "_tcp" or "_udp").
+    pub const fn new(service: &'a str, protocol: &'a str, events: F) -> Self {
+        Self {
+            service,
+            protocol,
+            state: blocking_mutex::Mutex::new(RefCell::new(State {
+                instances: heapless::Vec::new(),
+                events,
+                query_at: Instant::from_ticks(0),
+                query_interval: QUERY_INTERVAL,
+                known_answers: None,
+            })),
+            signal: Signal::new(),
+        }
+    }
+}
+
+impl<'a, M, F, const N: usize> Browser<'a, M, F, N>
+where
+    M: RawMutex,
+    F: FnMut(BrowseEvent<'_>),
+{
+    /// Visit the currently cached instances, including the ones which are not resolved yet.
+    pub fn instances<R>(&self, f: impl FnOnce(&[Instance]) -> R) -> R {
+        self.state.lock(|state| f(&state.borrow().instances))
+    }
+
+    /// Remove the expired instances and records, and construct in `buf` an mDNS query
+    /// for the service type, the unresolved instances and the records due for a refresh.
+    ///
+    /// Return the length of the query, or 0 if there is nothing to query for.
+    ///
+    /// If not all questions fit in `buf`, the rest of them are sent by the next query,
+    /// i.e. `deadline` returns a time which is already due.
+    pub fn query(&self, buf: &mut [u8]) -> Result<usize, MdnsError> {
+        let now = Instant::now();
+
+        let service = [self.service, self.protocol, "local"];
+        let service = NameSlice::new(&service);
+
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            let state = &mut *state;
+
+            state.expire(now);
+
+            let buf = Buf(buf, 0);
+
+            let mut mb = MessageBuilder::from_target(buf)?;
+
+            set_header(&mut mb, 0, false);
+
+            let mut qb = mb.question();
+
+            let mut pushed = false;
+            let mut full = false;
+
+            let query_ptr = (now >= state.query_at
+                || state
+                    .instances
+                    .iter()
+                    .any(|instance| instance.ptr.is_due(now)))
+                && push_question(
+                    &mut qb,
+                    Question::new(service.clone(), Rtype::PTR, Class::IN),
+                    &mut pushed,
+                    &mut full,
+                )?;
+
+            if query_ptr {
+                if now >= state.query_at {
+                    state.query_at = now + state.query_interval;
+                    state.query_interval = (state.query_interval * 2).min(MAX_QUERY_INTERVAL);
+                }
+
+                for instance in &mut state.instances {
+                    instance.ptr.refresh(now);
+                }
+            }
+
+            // The questions which do not fit are left for the next query, which is then due at once,
+            // as their records are still due for a refresh or a resolution
+            for instance in &mut state.instances {
+                if full {
+                    break;
+                }
+
+                let labels = [instance.name.as_str(), self.service, self.protocol, "local"];
+                let name = NameSlice::new(&labels);
+
+                let resolve = instance.needs_resolving() && now >= instance.resolve_at;
+
+                let mut sent = false;
+
+                if (is_due(instance.target.as_slice(), now)
+                    || (resolve && instance.target.is_none()))
+                    && push_question(
+                        &mut qb,
+                        Question::new(name.clone(), Rtype::SRV, Class::IN),
+                        &mut pushed,
+                        &mut full,
+                    )?
+                {
+                    refresh(instance.target.as_mut_slice(), now);
+                    sent = true;
+                }
+
+                if (is_due(instance.txt.as_slice(), now) || (resolve && instance.txt.is_none()))
+                    && push_question(
+                        &mut qb,
+                        Question::new(name, Rtype::TXT, Class::IN),
+                        &mut pushed,
+                        &mut full,
+                    )?
+                {
+                    refresh(instance.txt.as_mut_slice(), now);
+                    sent = true;
+                }
+
+                if let Some(target) = &instance.target {
+                    let labels = [target.value.hostname.as_str(), "local"];
+                    let hostname = NameSlice::new(&labels);
+
+                    let resolve_addrs = resolve && !instance.is_resolved();
+
+                    if (is_due(&instance.ipv4, now) || resolve_addrs)
+                        && push_question(
+                            &mut qb,
+                            Question::new(hostname.clone(), Rtype::A, Class::IN),
+                            &mut pushed,
+                            &mut full,
+                        )?
+                    {
+                        refresh(&mut instance.ipv4, now);
+                        sent = true;
+                    }
+
+                    if (is_due(&instance.ipv6, now) || resolve_addrs)
+                        && push_question(
+                            &mut qb,
+                            Question::new(hostname, Rtype::AAAA, Class::IN),
+                            &mut pushed,
+                            &mut full,
+                        )?
+                    {
+                        refresh(&mut instance.ipv6, now);
+                        sent = true;
+                    }
+                }
+
+                if resolve && sent {
+                    instance.resolve_attempts += 1;
+                    instance.resolve_at = now + RESOLVE_INTERVAL;
+                }
+            }
+
+            let mut ab = qb.answer();
+
+            state.known_answers = if query_ptr {
+                // As per spec, list the instances we already know in the Known-Answer section,
+                // unless their remaining TTL is less than half of their original TTL
+                self.push_known_answers(&state.instances, 0, now, &mut ab, pushed)?
+            } else {
+                None
+            };
+
+            let buf = ab.finish();
+
+            if pushed {
+                Ok(buf.1)
+            } else {
+                Ok(0)
+            }
+        })
+    }
+
+    /// Return `true` if the Known-Answer records of the last query did not fit in it, i.e.
+    /// `known_answers` should be called to send the rest of them.
+    pub fn has_known_answers(&self) -> bool {
+        self.state
+            .lock(|state| state.borrow().known_answers.is_some())
+    }
+
+    /// Construct in `buf` an mDNS packet with the Known-Answer records which did not fit in the last query
+    /// (or in the last such packet), as per RFC 6762 §7.2.
+    ///
+    /// Return the length of the packet, or 0 if there are no such records.
+    pub fn known_answers(&self, buf: &mut [u8]) -> Result<usize, MdnsError> {
+        let now = Instant::now();
+
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            let state = &mut *state;
+
+            let Some(first) = state.known_answers.take() else {
+                return Ok(0);
+            };
+
+            let buf = Buf(buf, 0);
+
+            let mut mb = MessageBuilder::from_target(buf)?;
+
+            set_header(&mut mb, 0, false);
+
+            let mut ab = mb.answer();
+
+            state.known_answers =
+                self.push_known_answers(&state.instances, first, now, &mut ab, false)?;
+
+            let buf = ab.finish();
+
+            Ok(buf.1)
+        })
+    }
+
+    /// Push the PTR records of the instances - starting from the instance at `first` - as Known-Answer records.
+    ///
+    /// If the records do not fit, set the TC bit and return the index of the first instance whose record
+    /// did not fit, so that the rest of the records are sent in subsequent packets.
+    fn push_known_answers<T>(
+        &self,
+        instances: &[Instance],
+        first: usize,
+        now: Instant,
+        ab: &mut AnswerBuilder<T>,
+        mut pushed: bool,
+    ) -> Result<Option<usize>, MdnsError>
+    where
+        T: Composer,
+    {
+        let service = [self.service, self.protocol, "local"];
+        let service = NameSlice::new(&service);
+
+        for (index, instance) in instances.iter().enumerate().skip(first) {
+            let Some(remaining) = instance.ptr.known_answer_ttl(now) else {
+                continue;
+            };
+
+            let labels = [instance.name.as_str(), self.service, self.protocol, "local"];
+
+            match ab.push(Record::new(
+                service.clone(),
+                Class::IN,
+                remaining,
+                AllRecordData::<&[u8], _>::Ptr(Ptr::new(NameSlice::new(&labels))),
+            )) {
+                Ok(()) => pushed = true,
+                Err(PushError::ShortBuf) if pushed => {
+                    ab.header_mut().set_tc(true);
+
+                    return Ok(Some(index));
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The time at which `query` should be called next.
+    pub fn deadline(&self) -> Instant {
+        self.state.lock(|state| {
+            let state = state.borrow();
+
+            state
+                .instances
+                .iter()
+                .map(Instance::deadline)
+                .fold(state.query_at, Instant::min)
+        })
+    }
+
+    /// Wait until new instances or new SRV targets need to be resolved, i.e. `query` should be called
+    /// before the `deadline`.
+    pub async fn wait(&self) {
+        self.signal.wait().await
+    }
+}
+
+impl<'a, M, F, const N: usize> PeerAnswers for Browser<'a, M, F, N>
+where
+    M: RawMutex,
+    F: FnMut(BrowseEvent<'_>),
+{
+    fn answers<'b, T, A>(&self, answers: T, additional: A) -> Result<(), MdnsError>
+    where
+        T: IntoIterator<Item = Result<PeerAnswer<'b>, MdnsError>> + Clone + 'b,
+        A: IntoIterator<Item = Result<PeerAnswer<'b>, MdnsError>> + Clone + 'b,
+    {
+        let now = Instant::now();
+
+        let service = [self.service, self.protocol, "local"];
+        let service = NameSlice::new(&service);
+
+        let resolve = self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            let state = &mut *state;
+
+            let mut resolve = false;
+
+            // PTR records first, so that the SRV and TXT records in the same message are attributed
+            // to the new instances, and SRV records before A and AAAA records, so that the host names
+            // of the instances are known
+            for rtypes in [
+                &[Rtype::PTR][..],
+                &[Rtype::SRV, Rtype::TXT],
+                &[Rtype::A, Rtype::AAAA],
+            ] {
+                for record in answers.clone().into_iter().chain(additional.clone()) {
+                    let record = record?;
+
+                    if rtypes.contains(&record.rtype()) {
+                        resolve |= state.update(&service, now, &record);
+                    }
+                }
+            }
+
+            // Also notifies the application about the changes
+            state.expire(now);
+
+            Ok::<_, MdnsError>(resolve)
+        })?;
+
+        if resolve {
+            self.signal.signal(());
+        }
+
+        Ok(())
+    }
+}
+
+struct State<F, const N: usize> {
+    instances: heapless::Vec<Instance, N>,
+    events: F,
+    query_at: Instant,
+    query_interval: Duration,
+    /// The index of the first instance whose Known-Answer record did not fit in the last query
+    known_answers: Option<usize>,
+}
+
+impl<F, const N: usize> State<F, N>
+where
+    F: FnMut(BrowseEvent<'_>),
+{
+    /// Remove the expired instances, notifying the application about the changes.
+    fn expire(&mut self, now: Instant) {
+        let mut index = 0;
+
+        while index < self.instances.len() {
+            let instance = &mut self.instances[index];
+
+            if instance.expire(now) {
+                instance.notify(&mut self.events);
+
+                index += 1;
+            } else {
+                let instance = self.instances.swap_remove(index);
+
+                if instance.announced {
+                    debug!("Instance {} expired", instance.name);
+                    (self.events)(BrowseEvent::Removed(&instance));
+                }
+            }
+        }
+    }
+
+    /// Update the cache with `record`, returning `true` if a new instance or a new SRV target needs to be resolved.
+    fn update(&mut self, service: &NameSlice, now: Instant, record: &PeerAnswer) -> bool {
+        let expiry = Expiry::new(now, record.ttl());
+
+        match record.data() {
+            AllRecordData::Ptr(ptr) => {
+                if !record.owner().name_eq(service) {
+                    return false;
+                }
+
+                let Some(name) = first_label(ptr.ptrdname(), service) else {
+                    return false;
+                };
+
+                if let Some(instance) = self.instance(&name) {
+                    instance.ptr = expiry;
+
+                    false
+                } else if record.ttl().as_secs() > 0 {
+                    debug!("Discovered instance {name}");
+
+                    if let Err(instance) = self.instances.push(Instance::new(name, expiry)) {
+                        warn!("Browser cache is full, ignoring instance {}", instance.name);
+
+                        return false;
+                    }
+
+                    true
+                } else {
+                    false
+                }
+            }
+            AllRecordData::Srv(srv) => {
+                let Some(instance) =
+                    first_label(record.owner(), service).and_then(|name| self.instance(&name))
+                else {
+                    return false;
+                };
+
+                let Some(hostname) = first_label(srv.target(), &LOCAL) else {
+                    return false;
+                };
+
+                let target = Target {
+                    hostname,
+                    port: srv.port(),
+                };
+
+                let resolve = match &instance.target {
+                    Some(old) if old.value.hostname.eq_ignore_ascii_case(&target.hostname) => {
+                        instance.changed |= old.value.port != target.port;
+
+                        false
+                    }
+                    old => {
+                        instance.changed |= old.is_some();
+
+                        // The addresses belong to the old host
+                        instance.ipv4.clear();
+                        instance.ipv6.clear();
+                        instance.resolve_attempts = 0;
+
+                        true
+                    }
+                };
+
+                instance.target = Some(Cached::new(target, expiry));
+
+                resolve
+            }
+            AllRecordData::Txt(_) => {
+                let Some(instance) =
+                    first_label(record.owner(), service).and_then(|name| self.instance(&name))
+                else {
+                    return false;
+                };
+
+                let mut buf = [0; MAX_TXT_LEN];
+                let txt = compose_rdata(record.data(), &mut buf);
+
+                update(
+                    &mut instance.txt,
+                    &mut instance.changed,
+                    txt,
+                    expiry,
+                    |txt| heapless::Vec::from_slice(txt).unwrap_or_default(),
+                );
+
+                false
+            }
+            AllRecordData::A(a) => {
+                let ipv4 = Ipv4Addr::from(a.addr().octets());
+
+                for instance in self.host_instances(record.owner()) {
+                    update_addr(&mut instance.ipv4, &mut instance.changed, ipv4, expiry);
+                }
+
+                false
+            }
+            AllRecordData::Aaaa(aaaa) => {
+                let ipv6 = Ipv6Addr::from(aaaa.addr().octets());
+
+                for instance in self.host_instances(record.owner()) {
+                    update_addr(&mut instance.ipv6, &mut instance.changed, ipv6, expiry);
+                }
+
+                false
+            }
+            _ => false,
+        }
+    }
+
+    fn instance(&mut self, name: &str) -> Option<&mut Instance> {
+        self.instances
+            .iter_mut()
+            .find(|instance| instance.name.eq_ignore_ascii_case(name))
+    }
+
+    fn host_instances<'s, O>(&'s mut self, owner: &O) -> impl Iterator<Item = &'s mut Instance>
+    where
+        O: ToLabelIter,
+    {
+        let hostname = first_label(owner, &LOCAL);
+
+        self.instances.iter_mut().filter(move |instance| {
+            matches!(
+                (&hostname, &instance.target),
+                (Some(hostname), Some(target)) if target.value.hostname.eq_ignore_ascii_case(hostname)
+            )
+        })
+    }
+}
+
+/// The host name and the port of an instance, from its SRV record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Target {
+    hostname: Name,
+    port: u16,
+}
+
+#[derive(Debug, Clone)]
+struct Cached<T> {
+    value: T,
+    expiry: Expiry,
+}
+
+impl<T> Cached<T> {
+    const fn new(value: T, expiry: Expiry) -> Self {
+        Self { value, expiry }
+    }
+}
+
+/// The time at which a cached record was received, its TTL, and how many times it was refreshed.
+#[derive(Debug, Clone, Copy)]
+struct Expiry {
+    received: Instant,
+    ttl: Duration,
+    refreshes: usize,
+}
+
+impl Expiry {
+    fn new(now: Instant, ttl: Ttl) -> Self {
+        if ttl.as_secs() == 0 {
+            // A goodbye announcement. As per spec, expire the record in one second
+            Self {
+                received: now,
+                ttl: Duration::from_secs(1),
+                refreshes: REFRESH_PERCENTS.len(),
+            }
+        } else {
+            Self {
+                received: now,
+                ttl: Duration::from_secs(ttl.as_secs() as u64),
+                refreshes: 0,
+            }
+        }
+    }
+
+    fn expires(&self) -> Instant {
+        self.received + self.ttl
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        now >= self.expires()
+    }
+
+    fn refresh_at(&self) -> Option<Instant> {
+        REFRESH_PERCENTS
+            .get(self.refreshes)
+            .map(|percent| self.received + self.ttl * *percent / 100)
+    }
+
+    /// Return `true` if the record is due for a refresh.
+    fn is_due(&self, now: Instant) -> bool {
+        self.refresh_at().map(|at| now >= at).unwrap_or(false)
+    }
+
+    /// Count the refresh of the record, skipping the refreshes which are overdue as well.
+    fn refresh(&mut self, now: Instant) {
+        while self.is_due(now) {
+            self.refreshes += 1;
+        }
+    }
+
+    fn deadline(&self) -> Instant {
+        self.refresh_at().unwrap_or(self.expires())
+    }
+
+    fn known_answer_ttl(&self, now: Instant) -> Option<Ttl> {
+        let remaining = self.expires().checked_duration_since(now)?;
+
+        (remaining * 2 > self.ttl).then(|| Ttl::from_secs(remaining.as_secs() as u32))
+    }
+}
+
+fn expire<T>(cached: &mut Option<Cached<T>>, now: Instant) -> bool {
+    let expired = cached
+        .as_ref()
+        .map(|cached| cached.expiry.is_expired(now))
+        .unwrap_or(false);
+
+    if expired {
+        *cached = None;
+    }
+
+    expired
+}
+
+/// Remove the expired addresses, returning `true` if any of them expired.
+fn expire_addrs<T>(cached: &mut heapless::Vec<Cached<T>, MAX_ADDRS>, now: Instant) -> bool {
+    let len = cached.len();
+
+    cached.retain(|cached| !cached.expiry.is_expired(now));
+
+    cached.len() != len
+}
+
+/// Return `true` if one of the cached records is due for a refresh.
+fn is_due<T>(cached: &[Cached<T>], now: Instant) -> bool {
+    cached.iter().any(|cached| cached.expiry.is_due(now))
+}
+
+/// Count the refreshes of the cached records which are due for a refresh.
+fn refresh<T>(cached: &mut [Cached<T>], now: Instant) {
+    for cached in cached {
+        cached.expiry.refresh(now);
+    }
+}
+
+/// Push `question`, unless the query is already full. Return `true` if the question was pushed.
+///
+/// Only a question which does not fit in an empty query is an error.
+fn push_question<T>(
+    qb: &mut QuestionBuilder<T>,
+    question: Question<NameSlice>,
+    pushed: &mut bool,
+    full: &mut bool,
+) -> Result<bool, MdnsError>
+where
+    T: Composer,
+{
+    if *full {
+        return Ok(false);
+    }
+
+    match qb.push(question) {
+        Ok(()) => {
+            *pushed = true;
+
+            Ok(true)
+        }
+        Err(PushError::ShortBuf) if *pushed => {
+            *full = true;
+
+            Ok(false)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn update<T, V>(
+    cached: &mut Option<Cached<T>>,
+    changed: &mut bool,
+    value: &V,
+    expiry: Expiry,
+    to_owned: impl FnOnce(&V) -> T,
+) where
+    T: PartialEq<V>,
+    V: ?Sized,
+{
+    *changed |= cached
+        .as_ref()
+        .map(|cached| cached.value != *value)
+        .unwrap_or(true);
+
+    *cached = Some(Cached::new(to_owned(value), expiry));
+}
+
+/// Add `addr` to the addresses of a host, or update its expiry if it is already known.
+///
+/// A host usually announces several addresses, so only new addresses are a change.
+fn update_addr<T>(
+    cached: &mut heapless::Vec<Cached<T>, MAX_ADDRS>,
+    changed: &mut bool,
+    addr: T,
+    expiry: Expiry,
+) where
+    T: PartialEq,
+{
+    if let Some(cached) = cached.iter_mut().find(|cached| cached.value == addr) {
+        cached.expiry = expiry;
+    } else if cached.push(Cached::new(addr, expiry)).is_ok() {
+        *changed = true;
+    }
+}
+
+/// Return the first label of `name` if `name` is that label followed by `suffix`.
+fn first_label<N>(name: &N, suffix: &NameSlice) -> Option<Name>
+where
+    N: ToLabelIter + ?Sized,
+{
+    // The label, the suffix labels and the root label
+    if name.iter_labels().count() != suffix.0.len() + 2 || !name.ends_with(suffix) {
+        return None;
+    }
+
+    let label = name.iter_labels().next()?;
+
+    core::str::from_utf8(label.as_slice()).ok()?.try_into().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use domain::base::Message;
+
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use crate::fixtures::{announce, myhost, sensor};
+    use crate::host::{Service, ServiceAnswers};
+    use crate::{
+        ChainedHostAnswers, GoodbyeHostAnswers, HostAnswer, HostAnswers, MdnsHandler, MdnsRequest,
+        PeerAnswersMdnsHandler, RecordDataChain,
+    };
+
+    use super::*;
+
+    const IP1: Ipv4Addr = Ipv4Addr::new(192, 168, 0, 1);
+    const IP2: Ipv4Addr = Ipv4Addr::new(192, 168, 0, 2);
+
+    type Events = RefCell<heapless::Vec<&'static str, 16>>;
+
+    fn browser(
+        events: &Events,
+    ) -> Browser<'static, NoopRawMutex, impl FnMut(BrowseEvent<'_>) + '_, 8> {
+        Browser::new("_http", "_tcp", move |event: BrowseEvent<'_>| {
+            let event = match event {
+                BrowseEvent::Added(_) => "added",
+                BrowseEvent::Updated(_) => "updated",
+                BrowseEvent::Removed(_) => "removed",
+            };
+
+            events.borrow_mut().push(event).unwrap();
+        })
+    }
+
+    /// The PTR records of instances which are not resolved yet
+    struct Instances(&'static [&'static str]);
+
+    impl HostAnswers for Instances {
+        fn visit<F, E>(&self, mut f: F) -> Result<(), E>
+        where
+            F: FnMut(HostAnswer) -> Result<(), E>,
+            E: From<MdnsError>,
+        {
+            for name in self.0 {
+                let owner = [*name, "_http", "_tcp", "local"];
+
+                f(Record::new(
+                    NameSlice::new(&["_http", "_tcp", "local"]),
+                    Class::IN,
+                    Ttl::from_secs(120),
+                    RecordDataChain::Next(AllRecordData::Ptr(Ptr::new(NameSlice::new(&owner)))),
+                ))?;
+            }
+
+            Ok(())
+        }
+    }
+
+    /// Pass the announcement of `answers` to `browser`
+    fn announce_to<T, F, const N: usize>(browser: &Browser<'_, NoopRawMutex, F, N>, answers: T)
+    where
+        T: HostAnswers,
+        F: FnMut(BrowseEvent<'_>),
+    {
+        let mut buf = [0; 1500];
+
+        let data = announce(answers, &mut buf);
+
+        let request = MdnsRequest::Request {
+            legacy: false,
+            multicast: true,
+            data,
+            known_answers: &[],
+        };
+
+        PeerAnswersMdnsHandler::new(browser)
+            .handle(request, &mut [])
+            .unwrap();
+    }
+
+    fn expire<F, const N: usize>(browser: &Browser<'_, NoopRawMutex, F, N>, after: Duration)
+    where
+        F: FnMut(BrowseEvent<'_>),
+    {
+        browser
+            .state
+            .lock(|state| state.borrow_mut().expire(Instant::now() + after));
+    }
+
+    #[test]
+    fn test_browse() {
+        let events = Events::default();
+        let browser = browser(&events);
+
+        let host = myhost(&[IP1, IP2]);
+        let service = sensor();
+
+        announce_to(
+            &browser,
+            ChainedHostAnswers::new(&host, ServiceAnswers::new(&host, &service)),
+        );
+        assert_eq!(*events.borrow(), ["added"]);
+
+        browser.instances(|instances| {
+            let [instance] = instances else {
+                panic!("Expected one instance");
+            };
+
+            assert_eq!(instance.name(), "mysensor");
+            assert_eq!(instance.hostname(), Some("myhost"));
+            assert_eq!(instance.port(), Some(80));
+            assert!(instance.ipv4_addrs().eq([IP1, IP2]));
+            assert_eq!(instance.ipv6(), None);
+            assert!(instance.txt().eq([("version", "1")]));
+        });
+
+        // Repeated announcements of a host with several addresses are not a change
+        announce_to(
+            &browser,
+            ChainedHostAnswers::new(&host, ServiceAnswers::new(&host, &service)),
+        );
+        announce_to(&browser, &host);
+        assert_eq!(*events.borrow(), ["added"]);
+
+        // One of the addresses is gone
+        announce_to(&browser, GoodbyeHostAnswers::new(myhost(&[IP2])));
+        expire(&browser, Duration::from_secs(2));
+        assert_eq!(*events.borrow(), ["added", "updated"]);
+
+        browser.instances(|instances| assert!(instances[0].ipv4_addrs().eq([IP1])));
+
+        // The port changed
+        let service = Service {
+            port: 8080,
+            ..sensor()
+        };
+
+        announce_to(&browser, ServiceAnswers::new(&host, &service));
+        assert_eq!(*events.borrow(), ["added", "updated", "updated"]);
+
+        // The records expired
+        expire(&browser, Duration::from_secs(121));
+        assert_eq!(*events.borrow(), ["added", "updated", "updated", "removed"]);
+
+        browser.instances(|instances| assert!(instances.is_empty()));
+    }
+
+    #[test]
+    fn test_query_overflow() {
+        let events = Events::default();
+        let browser = browser(&events);
+
+        announce_to(
+            &browser,
+            Instances(&[
+                "sensor-1", "sensor-2", "sensor-3", "sensor-4", "sensor-5", "sensor-6", "sensor-7",
+                "sensor-8",
+            ]),
+        );
+
+        browser.instances(|instances| assert_eq!(instances.len(), 8));
+
+        // Too small for the PTR question and the SRV and TXT questions of all instances
+        let mut buf = [0; 220];
+
+        let qdcount = |data: &[u8]| {
+            Message::from_octets(data)
+                .unwrap()
+                .header_counts()
+                .qdcount()
+        };
+
+        let len = browser.query(&mut buf).unwrap();
+
+        let mut questions = qdcount(&buf[..len]);
+        assert!(questions < 1 + 8 * 2);
+
+        // The rest of the questions are due at once
+        assert!(browser.deadline() <= Instant::now());
+
+        for _ in 0..8 {
+            let len = browser.query(&mut buf).unwrap();
+
+            if len == 0 {
+                break;
+            }
+
+            questions += qdcount(&buf[..len]);
+        }
+
+        assert_eq!(questions, 1 + 8 * 2);
+
+        // Each instance was asked for exactly once
+        browser.instances(|instances| {
+            assert!(instances
+                .iter()
+                .all(|instance| instance.resolve_attempts == 1))
+        });
+    }
+
+    #[test]
+    fn test_known_answers_overflow() {
+        let events = Events::default();
+        let browser = browser(&events);
+
+        announce_to(
+            &browser,
+            Instances(&[
+                "sensor-1", "sensor-2", "sensor-3", "sensor-4", "sensor-5", "sensor-6", "sensor-7",
+                "sensor-8",
+            ]),
+        );
+
+        // Enough for all questions, but not for the Known-Answer records of all instances
+        let mut buf = [0; 600];
+
+        let header = |data: &[u8]| {
+            let message = Message::from_octets(data).unwrap();
+
+            (message.header().tc(), message.header_counts().ancount())
+        };
+
+        let len = browser.query(&mut buf).unwrap();
+
+        let (tc, mut known) = header(&buf[..len]);
+        assert!(tc);
+        assert!(known < 8);
+        assert!(browser.has_known_answers());
+
+        // The rest of the Known-Answer records follow in separate packets, the last one without the TC bit
+        while browser.has_known_answers() {
+            let len = browser.known_answers(&mut buf).unwrap();
+
+            let (tc, count) = header(&buf[..len]);
+            assert_eq!(tc, browser.has_known_answers());
+
+            known += count;
+        }
+
+        assert_eq!(known, 8);
+        assert_eq!(browser.known_answers(&mut buf).unwrap(), 0);
+    }
+}
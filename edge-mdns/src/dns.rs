@@ -0,0 +1,678 @@
+//! An `edge_nal::Dns` implementation which resolves `.local` names and reverse lookups via mDNS,
+//! as well as a `Dns` combinator which routes `.local` names to mDNS and all other names to another `Dns`.
+
+use core::cell::RefCell;
+use core::fmt::{self, Write};
+use core::net::IpAddr;
+
+use domain::base::iana::Class;
+use domain::base::{Question, Rtype, ToName};
+use domain::rdata::AllRecordData;
+
+use edge_nal::{AddrType, Dns, Readable, UdpReceive, UdpSend};
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::{self, raw::RawMutex};
+use embassy_sync::mutex::Mutex;
+use embassy_sync::signal::Signal;
+
+use embassy_time::{Duration, Instant, Timer};
+
+use embedded_io_async::ErrorKind;
+
+use log::debug;
+
+use crate::buf::BufferAccess;
+use crate::io::{Mdns, MdnsIoError};
+use crate::probe::MAX_LABEL_LEN;
+use crate::{HostQuestion, HostQuestions, MdnsError, NameSlice, PeerAnswer, PeerAnswers};
+
+/// The maximum length of a domain name, as per RFC 1035.
+pub const MAX_NAME_LEN: usize = 255;
+
+/// The interval between the first two queries of a lookup, which doubles for every subsequent query.
+pub const QUERY_INTERVAL: Duration = Duration::from_secs(1);
+
+const MAX_LABELS: usize = 128;
+
+type NameBuf = heapless::String<MAX_NAME_LEN>;
+
+/// An error returned by `MdnsDns` and `RoutedDns`.
+#[derive(Debug)]
+pub enum DnsError<E> {
+    /// No answer arrived before the timeout
+    NotFound,
+    /// The host name cannot be used in an mDNS query
+    InvalidName,
+    /// Sending the query failed
+    Io(MdnsIoError<E>),
+}
+
+impl<E> From<MdnsIoError<E>> for DnsError<E> {
+    fn from(err: MdnsIoError<E>) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl<E> From<MdnsError> for DnsError<E> {
+    fn from(err: MdnsError) -> Self {
+        Self::Io(MdnsIoError::MdnsError(err))
+    }
+}
+
+impl<E> fmt::Display for DnsError<E>
+where
+    E: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "Not found"),
+            Self::InvalidName => write!(f, "Invalid name"),
+            Self::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E> std::error::Error for DnsError<E> where E: std::error::Error {}
+
+impl<E> embedded_io_async::Error for DnsError<E>
+where
+    E: embedded_io_async::Error,
+{
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::NotFound => ErrorKind::NotFound,
+            Self::InvalidName => ErrorKind::InvalidInput,
+            Self::Io(MdnsIoError::IoError(err)) => err.kind(),
+            Self::Io(_) => ErrorKind::Other,
+        }
+    }
+}
+
+/// Collects the answers to the queries of `MdnsDns`.
+///
+/// Should be passed to `io::Mdns::run` as a `PeerAnswersMdnsHandler` (or chained with the other handlers),
+/// so that the answers are processed.
+pub struct Resolver<M>
+where
+    M: RawMutex,
+{
+    lookup: Mutex<M, ()>,
+    pending: blocking_mutex::Mutex<M, RefCell<Option<Lookup>>>,
+    answer: Signal<M, Answer>,
+}
+
+impl<M> Resolver<M>
+where
+    M: RawMutex,
+{
+    /// Create a new `Resolver`.
+    pub const fn new() -> Self {
+        Self {
+            lookup: Mutex::new(()),
+            pending: blocking_mutex::Mutex::new(RefCell::new(None)),
+            answer: Signal::new(),
+        }
+    }
+}
+
+impl<M> Default for Resolver<M>
+where
+    M: RawMutex,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M> PeerAnswers for Resolver<M>
+where
+    M: RawMutex,
+{
+    fn answers<'a, T, A>(&self, answers: T, additional: A) -> Result<(), MdnsError>
+    where
+        T: IntoIterator<Item = Result<PeerAnswer<'a>, MdnsError>> + Clone + 'a,
+        A: IntoIterator<Item = Result<PeerAnswer<'a>, MdnsError>> + Clone + 'a,
+    {
+        let answer = self.pending.lock(|pending| {
+            let mut pending = pending.borrow_mut();
+
+            let Some(lookup) = pending.as_ref() else {
+                return Ok(None);
+            };
+
+            for record in answers.into_iter().chain(additional) {
+                let record = record?;
+
+                if let Some(answer) = lookup.answer(&record)? {
+                    *pending = None;
+
+                    return Ok(Some(answer));
+                }
+            }
+
+            Ok::<_, MdnsError>(None)
+        })?;
+
+        if let Some(answer) = answer {
+            self.answer.signal(answer);
+        }
+
+        Ok(())
+    }
+}
+
+/// An `edge_nal::Dns` implementation which resolves host names and addresses via mDNS.
+///
+/// `get_host_by_name` sends A and/or AAAA queries for the host name, while `get_host_by_address`
+/// sends a reverse PTR query for the address. Both wait for the first matching answer, re-sending
+/// the query with doubling intervals, until `timeout` elapses.
+pub struct MdnsDns<'a, 'm, M, R, S, RB, SB>
+where
+    M: RawMutex,
+{
+    mdns: &'a Mdns<'m, M, R, S, RB, SB>,
+    resolver: &'a Resolver<M>,
+    timeout: Duration,
+}
+
+impl<'a, 'm, M, R, S, RB, SB> MdnsDns<'a, 'm, M, R, S, RB, SB>
+where
+    M: RawMutex,
+    R: UdpReceive + Readable,
+    S: UdpSend<Error = R::Error>,
+    RB: BufferAccess<[u8]>,
+    SB: BufferAccess<[u8]>,
+{
+    /// Create a new `MdnsDns` instance, which sends its queries with `mdns`, and receives the answers
+    /// with `resolver`.
+    pub const fn new(
+        mdns: &'a Mdns<'m, M, R, S, RB, SB>,
+        resolver: &'a Resolver<M>,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            mdns,
+            resolver,
+            timeout,
+        }
+    }
+
+    async fn resolve(&self, lookup: Lookup) -> Result<Answer, DnsError<S::Error>> {
+        // One lookup at a time
+        let _guard = self.resolver.lookup.lock().await;
+
+        let rtypes: &[Rtype] = match lookup.kind {
+            Kind::Host(AddrType::IPv4) => &[Rtype::A],
+            Kind::Host(AddrType::IPv6) => &[Rtype::AAAA],
+            Kind::Host(AddrType::Either) => &[Rtype::A, Rtype::AAAA],
+            Kind::Address => &[Rtype::PTR],
+        };
+
+        let mut labels = heapless::Vec::new();
+        let name = lookup
+            .name(&mut labels)
+            .map_err(|_| DnsError::InvalidName)?;
+
+        self.resolver.answer.reset();
+        self.resolver
+            .pending
+            .lock(|pending| *pending.borrow_mut() = Some(lookup.clone()));
+
+        let result = self.query(&name, rtypes).await;
+
+        self.resolver
+            .pending
+            .lock(|pending| *pending.borrow_mut() = None);
+
+        result
+    }
+
+    async fn query(
+        &self,
+        name: &NameSlice<'_>,
+        rtypes: &[Rtype],
+    ) -> Result<Answer, DnsError<S::Error>> {
+        let deadline = Instant::now() + self.timeout;
+        let mut interval = QUERY_INTERVAL;
+
+        loop {
+            debug!("Querying {name} via mDNS");
+
+            self.mdns
+                .query(|buf| Questions { name, rtypes }.query(0, buf))
+                .await?;
+
+            let wait = (Instant::now() + interval).min(deadline);
+
+            if let Either::First(answer) =
+                select(self.resolver.answer.wait(), Timer::at(wait)).await
+            {
+                return Ok(answer);
+            }
+
+            if wait >= deadline {
+                return Err(DnsError::NotFound);
+            }
+
+            interval = interval * 2;
+        }
+    }
+}
+
+impl<'a, 'm, M, R, S, RB, SB> Dns for MdnsDns<'a, 'm, M, R, S, RB, SB>
+where
+    M: RawMutex,
+    R: UdpReceive + Readable,
+    S: UdpSend<Error = R::Error>,
+    RB: BufferAccess<[u8]>,
+    SB: BufferAccess<[u8]>,
+{
+    type Error = DnsError<S::Error>;
+
+    async fn get_host_by_name(
+        &self,
+        host: &str,
+        addr_type: AddrType,
+    ) -> Result<IpAddr, Self::Error> {
+        let name = host
+            .trim_end_matches('.')
+            .try_into()
+            .map_err(|_| DnsError::InvalidName)?;
+
+        match self
+            .resolve(Lookup {
+                name,
+                kind: Kind::Host(addr_type),
+            })
+            .await?
+        {
+            Answer::Addr(addr) => Ok(addr),
+            Answer::Name(_) => Err(DnsError::NotFound),
+        }
+    }
+
+    async fn get_host_by_address(
+        &self,
+        addr: IpAddr,
+        result: &mut [u8],
+    ) -> Result<usize, Self::Error> {
+        let name = reverse_name(addr).map_err(|_| DnsError::InvalidName)?;
+
+        match self
+            .resolve(Lookup {
+                name,
+                kind: Kind::Address,
+            })
+            .await?
+        {
+            Answer::Name(name) => {
+                let name = name.as_bytes();
+
+                if name.len() > result.len() {
+                    return Err(MdnsError::ShortBuf.into());
+                }
+
+                result[..name.len()].copy_from_slice(name);
+
+                Ok(name.len())
+            }
+            Answer::Addr(_) => Err(DnsError::NotFound),
+        }
+    }
+}
+
+/// A `Dns` combinator which resolves `.local` host names and link-local addresses with the `local` `Dns`
+/// (i.e. `MdnsDns`), and all other host names and addresses with the `fallback` `Dns`.
+pub struct RoutedDns<L, F> {
+    local: L,
+    fallback: F,
+}
+
+impl<L, F> RoutedDns<L, F> {
+    /// Create a new `RoutedDns` instance.
+    pub const fn new(local: L, fallback: F) -> Self {
+        Self { local, fallback }
+    }
+}
+
+impl<L, F> Dns for RoutedDns<L, F>
+where
+    L: Dns,
+    F: Dns,
+{
+    type Error = RoutedDnsError<L::Error, F::Error>;
+
+    async fn get_host_by_name(
+        &self,
+        host: &str,
+        addr_type: AddrType,
+    ) -> Result<IpAddr, Self::Error> {
+        if is_local_name(host) {
+            self.local
+                .get_host_by_name(host, addr_type)
+                .await
+                .map_err(RoutedDnsError::Local)
+        } else {
+            self.fallback
+                .get_host_by_name(host, addr_type)
+                .await
+                .map_err(RoutedDnsError::Fallback)
+        }
+    }
+
+    async fn get_host_by_address(
+        &self,
+        addr: IpAddr,
+        result: &mut [u8],
+    ) -> Result<usize, Self::Error> {
+        if is_link_local_addr(addr) {
+            self.local
+                .get_host_by_address(addr, result)
+                .await
+                .map_err(RoutedDnsError::Local)
+        } else {
+            self.fallback
+                .get_host_by_address(addr, result)
+                .await
+                .map_err(RoutedDnsError::Fallback)
+        }
+    }
+}
+
+/// An error returned by `RoutedDns`.
+#[derive(Debug)]
+pub enum RoutedDnsError<L, F> {
+    /// An error returned by the `local` `Dns`
+    Local(L),
+    /// An error returned by the `fallback` `Dns`
+    Fallback(F),
+}
+
+impl<L, F> fmt::Display for RoutedDnsError<L, F>
+where
+    L: fmt::Display,
+    F: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Local(err) => write!(f, "Local DNS error: {}", err),
+            Self::Fallback(err) => write!(f, "Fallback DNS error: {}", err),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<L, F> std::error::Error for RoutedDnsError<L, F>
+where
+    L: std::error::Error,
+    F: std::error::Error,
+{
+}
+
+impl<L, F> embedded_io_async::Error for RoutedDnsError<L, F>
+where
+    L: embedded_io_async::Error,
+    F: embedded_io_async::Error,
+{
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Local(err) => err.kind(),
+            Self::Fallback(err) => err.kind(),
+        }
+    }
+}
+
+/// Return `true` if `host` is in the `.local` domain, which is resolved via mDNS as per spec.
+fn is_local_name(host: &str) -> bool {
+    let host = host.trim_end_matches('.').as_bytes();
+
+    host.len() >= 5
+        && host[host.len() - 5..].eq_ignore_ascii_case(b"local")
+        && (host.len() == 5 || host[host.len() - 6] == b'.')
+}
+
+/// Return the reverse-mapping name of `addr`, i.e. in the `in-addr.arpa` or `ip6.arpa` domain.
+fn reverse_name(addr: IpAddr) -> Result<NameBuf, fmt::Error> {
+    let mut name = NameBuf::new();
+
+    match addr {
+        IpAddr::V4(addr) => {
+            let [a, b, c, d] = addr.octets();
+
+            write!(name, "{d}.{c}.{b}.{a}.in-addr.arpa")?;
+        }
+        IpAddr::V6(addr) => {
+            for octet in addr.octets().iter().rev() {
+                write!(name, "{:x}.{:x}.", octet & 0xf, octet >> 4)?;
+            }
+
+            write!(name, "ip6.arpa")?;
+        }
+    }
+
+    Ok(name)
+}
+
+/// Return `true` if `addr` is a link-local address, which is resolved via mDNS as per spec.
+fn is_link_local_addr(addr: IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(addr) => addr.is_link_local(),
+        IpAddr::V6(addr) => addr.segments()[0] & 0xffc0 == 0xfe80,
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Kind {
+    Host(AddrType),
+    Address,
+}
+
+/// A pending lookup, i.e. the name to query for and the expected answer.
+#[derive(Debug, Clone)]
+struct Lookup {
+    name: NameBuf,
+    kind: Kind,
+}
+
+impl Lookup {
+    fn name<'s>(
+        &'s self,
+        labels: &'s mut heapless::Vec<&'s str, MAX_LABELS>,
+    ) -> Result<NameSlice<'s>, MdnsError> {
+        for label in self.name.split('.') {
+            if label.is_empty() || label.len() > MAX_LABEL_LEN {
+                return Err(MdnsError::InvalidMessage);
+            }
+
+            labels.push(label).map_err(|_| MdnsError::InvalidMessage)?;
+        }
+
+        Ok(NameSlice::new(labels))
+    }
+
+    fn answer(&self, record: &PeerAnswer) -> Result<Option<Answer>, MdnsError> {
+        let mut labels = heapless::Vec::new();
+
+        if !record.owner().name_eq(&self.name(&mut labels)?) {
+            return Ok(None);
+        }
+
+        let answer = match (&self.kind, record.data()) {
+            (Kind::Host(AddrType::IPv4 | AddrType::Either), AllRecordData::A(a)) => {
+                Some(Answer::Addr(IpAddr::from(a.addr().octets())))
+            }
+            (Kind::Host(AddrType::IPv6 | AddrType::Either), AllRecordData::Aaaa(aaaa)) => {
+                Some(Answer::Addr(IpAddr::from(aaaa.addr().octets())))
+            }
+            (Kind::Address, AllRecordData::Ptr(ptr)) => {
+                let mut name = NameBuf::new();
+                write!(name, "{}", ptr.ptrdname()).map_err(|_| MdnsError::ShortBuf)?;
+
+                while name.ends_with('.') {
+                    name.pop();
+                }
+
+                Some(Answer::Name(name))
+            }
+            _ => None,
+        };
+
+        Ok(answer)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Answer {
+    Addr(IpAddr),
+    Name(NameBuf),
+}
+
+/// The questions of a lookup.
+struct Questions<'a> {
+    name: &'a NameSlice<'a>,
+    rtypes: &'a [Rtype],
+}
+
+impl HostQuestions for Questions<'_> {
+    fn visit<F, E>(&self, mut f: F) -> Result<(), E>
+    where
+        F: FnMut(HostQuestion) -> Result<(), E>,
+        E: From<MdnsError>,
+    {
+        for rtype in self.rtypes {
+            f(Question::new(self.name.clone(), *rtype, Class::IN))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::net::{Ipv4Addr, Ipv6Addr};
+
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use crate::fixtures::{announce, host};
+    use crate::host::Host;
+    use crate::{MdnsHandler, MdnsRequest, PeerAnswersMdnsHandler};
+
+    use super::*;
+
+    const IPV4: Ipv4Addr = Ipv4Addr::new(169, 254, 0, 1);
+    const IPV6: Ipv6Addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 0x1234, 0, 0, 1);
+
+    /// Look up `name` with `resolver`, passing to it the announcement of our host
+    fn lookup(resolver: &Resolver<NoopRawMutex>, name: &str, kind: Kind) -> Option<Answer> {
+        // Our host, with link-local addresses
+        let host = Host {
+            ipv4: &[IPV4],
+            ipv6: &[IPV6],
+            ..host()
+        };
+
+        let mut buf = [0; 1500];
+
+        let data = announce(&host, &mut buf);
+
+        resolver.pending.lock(|pending| {
+            *pending.borrow_mut() = Some(Lookup {
+                name: name.try_into().unwrap(),
+                kind,
+            })
+        });
+
+        let request = MdnsRequest::Request {
+            legacy: false,
+            multicast: true,
+            data,
+            known_answers: &[],
+        };
+
+        PeerAnswersMdnsHandler::new(resolver)
+            .handle(request, &mut [])
+            .unwrap();
+
+        resolver.answer.try_take()
+    }
+
+    #[test]
+    fn test_lookup() {
+        let resolver = Resolver::new();
+
+        let answer = lookup(&resolver, "myhost.local", Kind::Host(AddrType::IPv4));
+        assert!(matches!(answer, Some(Answer::Addr(addr)) if addr == IPV4));
+
+        let answer = lookup(&resolver, "MyHost.local", Kind::Host(AddrType::IPv6));
+        assert!(matches!(answer, Some(Answer::Addr(addr)) if addr == IPV6));
+
+        let answer = lookup(&resolver, "myhost.local", Kind::Host(AddrType::Either));
+        assert!(matches!(answer, Some(Answer::Addr(addr)) if addr == IPV4));
+
+        for addr in [IpAddr::V4(IPV4), IpAddr::V6(IPV6)] {
+            let name = reverse_name(addr).unwrap();
+
+            let answer = lookup(&resolver, &name, Kind::Address);
+            assert!(matches!(answer, Some(Answer::Name(name)) if name == "myhost.local"));
+        }
+
+        // Other names are not answered
+        assert!(lookup(&resolver, "other.local", Kind::Host(AddrType::Either)).is_none());
+        assert!(lookup(&resolver, "myhost.local", Kind::Address).is_none());
+    }
+
+    #[test]
+    fn test_lookup_name() {
+        let lookup = Lookup {
+            name: "myhost.local".try_into().unwrap(),
+            kind: Kind::Address,
+        };
+
+        let mut labels = heapless::Vec::new();
+
+        let mut name = NameBuf::new();
+        write!(name, "{}", lookup.name(&mut labels).unwrap()).unwrap();
+        assert_eq!(name, "myhost.local.");
+
+        for name in ["myhost..local", ".local", "myhost.local."] {
+            let lookup = Lookup {
+                name: name.try_into().unwrap(),
+                kind: Kind::Address,
+            };
+
+            let mut labels = heapless::Vec::new();
+            assert!(lookup.name(&mut labels).is_err());
+        }
+    }
+
+    #[test]
+    fn test_reverse_name() {
+        assert_eq!(
+            reverse_name(IpAddr::V4(IPV4)).unwrap(),
+            "1.0.254.169.in-addr.arpa"
+        );
+        assert_eq!(
+            reverse_name(IpAddr::V6(IPV6)).unwrap(),
+            "1.0.0.0.0.0.0.0.0.0.0.0.4.3.2.1.0.0.0.0.0.0.0.0.0.0.0.0.0.8.e.f.ip6.arpa"
+        );
+    }
+
+    #[test]
+    fn test_routing() {
+        assert!(is_local_name("myhost.local"));
+        assert!(is_local_name("myhost.LOCAL."));
+        assert!(is_local_name("local"));
+        assert!(!is_local_name("mylocal"));
+        assert!(!is_local_name("local.example.com"));
+        assert!(!is_local_name(""));
+
+        assert!(is_link_local_addr(IpAddr::V4(IPV4)));
+        assert!(is_link_local_addr(IpAddr::V6(IPV6)));
+        assert!(!is_link_local_addr(IpAddr::V4(Ipv4Addr::new(
+            192, 168, 0, 1
+        ))));
+        assert!(!is_link_local_addr(IpAddr::V6(Ipv6Addr::LOCALHOST)));
+    }
+}
@@ -0,0 +1,99 @@
+//! Hosts, services and helpers shared by the tests of the crate.
+
+use core::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::domain::base::iana::Class;
+use crate::domain::base::{MessageBuilder, Record, Ttl};
+use crate::domain::rdata::{AllRecordData, A};
+use crate::host::{Host, Service};
+use crate::{
+    set_header, Buf, HostAnswer, HostAnswers, HostAnswersMdnsHandler, HostQuestion, MdnsHandler,
+    MdnsRequest, MdnsResponse, NameSlice, RecordDataChain,
+};
+
+pub const HOSTNAME: NameSlice = NameSlice::new(&["myhost", "local"]);
+
+pub const IPV4: Ipv4Addr = Ipv4Addr::new(192, 168, 0, 1);
+pub const IPV6: Ipv6Addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+
+/// `myhost`, with the `IPV4` and `IPV6` addresses
+pub fn host() -> Host<'static> {
+    Host {
+        ipv6: &[IPV6],
+        ..myhost(&[IPV4])
+    }
+}
+
+/// `myhost`, with the provided IPv4 addresses only
+pub fn myhost(ipv4: &[Ipv4Addr]) -> Host<'_> {
+    Host {
+        hostname: "myhost",
+        ipv4,
+        ipv6: &[],
+        ttl: Ttl::from_secs(60),
+    }
+}
+
+/// The `mysensor._http._tcp.local` instance, on port 80
+pub fn sensor() -> Service<'static> {
+    Service {
+        name: "mysensor",
+        priority: 0,
+        weight: 0,
+        service: "_http",
+        protocol: "_tcp",
+        port: 80,
+        service_subtypes: &[],
+        txt_kvs: &[("version", "1")],
+    }
+}
+
+/// Compose into `buf` the unsolicited announcement of `answers`
+pub fn announce<T>(answers: T, buf: &mut [u8]) -> &[u8]
+where
+    T: HostAnswers,
+{
+    let MdnsResponse::Reply { data, .. } = HostAnswersMdnsHandler::new(answers)
+        .handle(MdnsRequest::None, buf)
+        .unwrap()
+    else {
+        panic!("No announcement");
+    };
+
+    data
+}
+
+/// The A record of `myhost` with the `IPV4` address, with the provided TTL
+pub fn a(ttl: u32) -> HostAnswer<'static> {
+    Record::new(
+        HOSTNAME,
+        Class::IN,
+        Ttl::from_secs(ttl),
+        RecordDataChain::Next(AllRecordData::A(A::new(
+            crate::domain::base::net::Ipv4Addr::from(IPV4.octets()),
+        ))),
+    )
+}
+
+/// Compose into `buf` a query with the provided questions and known answers
+pub fn query<'a>(questions: &[HostQuestion], known: &[HostAnswer], buf: &'a mut [u8]) -> &'a [u8] {
+    let mut mb = MessageBuilder::from_target(Buf(&mut *buf, 0)).unwrap();
+
+    set_header(&mut mb, 0, false);
+
+    let mut qb = mb.question();
+
+    for question in questions {
+        qb.push(question.clone()).unwrap();
+    }
+
+    let mut ab = qb.answer();
+
+    for answer in known {
+        ab.push(answer.clone()).unwrap();
+    }
+
+    let len = ab.finish().1;
+
+    &buf[..len]
+}
@@ -1,3 +1,4 @@
+use core::fmt::Write;
 use core::net::{Ipv4Addr, Ipv6Addr};
 
 use crate::domain::base::{iana::Class, Record, Ttl};
@@ -9,16 +10,19 @@ use crate::{HostAnswer, HostAnswers, MdnsError, NameSlice, RecordDataChain, Txt,
 ///
 /// This structure implements the `HostAnswers` trait, which allows it to be used
 /// as a responder for mDNS queries coming from other network peers.
+///
+/// Besides the A and AAAA records of its addresses, the host answers reverse-mapping
+/// (`in-addr.arpa` and `ip6.arpa`) PTR queries for its addresses.
 #[derive(Debug, Clone)]
 pub struct Host<'a> {
     /// The name of the host. I.e. a name "foo" will be pingable as "foo.local"
     pub hostname: &'a str,
-    /// The IPv4 address of the host.
-    /// Leaving it as `Ipv4Addr::UNSPECIFIED` means that the host will not aswer it to A queries.
-    pub ipv4: Ipv4Addr,
-    /// The IPv6 address of the host.
-    /// Leaving it as `Ipv6Addr::UNSPECIFIED` means that the host will not aswer it to AAAA queries.
-    pub ipv6: Ipv6Addr,
+    /// The IPv4 addresses of the host.
+    /// Unspecified addresses (`Ipv4Addr::UNSPECIFIED`) are skipped.
+    pub ipv4: &'a [Ipv4Addr],
+    /// The IPv6 addresses of the host, i.e. its link-local and its global addresses.
+    /// Unspecified addresses (`Ipv6Addr::UNSPECIFIED`) are skipped.
+    pub ipv6: &'a [Ipv6Addr],
     /// The time-to-live of the mDNS answers.
     pub ttl: Ttl,
 }
@@ -31,32 +35,81 @@ impl<'a> Host<'a> {
     {
         let owner = &[self.hostname, "local"];
 
-        if !self.ipv4.is_unspecified() {
+        for ipv4 in self.ipv4.iter().filter(|ipv4| !ipv4.is_unspecified()) {
             f(Record::new(
                 NameSlice::new(owner),
                 Class::IN,
                 self.ttl,
                 RecordDataChain::Next(AllRecordData::A(A::new(domain::base::net::Ipv4Addr::from(
-                    self.ipv4.octets(),
+                    ipv4.octets(),
                 )))),
             ))?;
         }
 
-        if !self.ipv6.is_unspecified() {
+        for ipv6 in self.ipv6.iter().filter(|ipv6| !ipv6.is_unspecified()) {
             f(Record::new(
                 NameSlice::new(owner),
                 Class::IN,
                 self.ttl,
                 RecordDataChain::Next(AllRecordData::Aaaa(Aaaa::new(
-                    domain::base::net::Ipv6Addr::from(self.ipv6.octets()),
+                    domain::base::net::Ipv6Addr::from(ipv6.octets()),
                 ))),
             ))?;
         }
 
+        for ipv4 in self.ipv4.iter().filter(|ipv4| !ipv4.is_unspecified()) {
+            let octets = ipv4.octets().map(|octet| {
+                let mut label = heapless::String::<3>::new();
+                let _ = write!(label, "{octet}");
+
+                label
+            });
+
+            let reverse = &[
+                octets[3].as_str(),
+                octets[2].as_str(),
+                octets[1].as_str(),
+                octets[0].as_str(),
+                "in-addr",
+                "arpa",
+            ];
+
+            f(Record::new(
+                NameSlice::new(reverse),
+                Class::IN,
+                self.ttl,
+                RecordDataChain::Next(AllRecordData::Ptr(Ptr::new(NameSlice::new(owner)))),
+            ))?;
+        }
+
+        for ipv6 in self.ipv6.iter().filter(|ipv6| !ipv6.is_unspecified()) {
+            let mut reverse = [""; 34];
+
+            for (index, octet) in ipv6.octets().iter().rev().enumerate() {
+                reverse[index * 2] = NIBBLES[(octet & 0xf) as usize];
+                reverse[index * 2 + 1] = NIBBLES[(octet >> 4) as usize];
+            }
+
+            reverse[32] = "ip6";
+            reverse[33] = "arpa";
+
+            f(Record::new(
+                NameSlice::new(&reverse),
+                Class::IN,
+                self.ttl,
+                RecordDataChain::Next(AllRecordData::Ptr(Ptr::new(NameSlice::new(owner)))),
+            ))?;
+        }
+
         Ok(())
     }
 }
 
+/// The labels of the nibbles of an `ip6.arpa` reverse-mapping name.
+const NIBBLES: [&str; 16] = [
+    "0", "1", "2", "3", "4", "5", "6", "7", "8", "9", "a", "b", "c", "d", "e", "f",
+];
+
 impl<'a> HostAnswers for Host<'a> {
     fn visit<F, E>(&self, mut f: F) -> Result<(), E>
     where
@@ -188,3 +241,101 @@ impl<'a> HostAnswers for ServiceAnswers<'a> {
         self.service.visit_answers(self.host, &mut f)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::compose_rdata;
+    use crate::domain::base::Rtype;
+
+    use super::*;
+
+    type Owner = heapless::String<128>;
+    type Rdata = heapless::Vec<u8, 64>;
+
+    /// The owner, the type and the data of the answers
+    fn answers<T>(answers: &T) -> heapless::Vec<(Owner, Rtype, Rdata), 16>
+    where
+        T: HostAnswers,
+    {
+        let mut result = heapless::Vec::new();
+
+        answers
+            .visit(|answer| {
+                let mut owner = Owner::new();
+                write!(owner, "{}", answer.owner()).unwrap();
+
+                let mut buf = [0; 64];
+                let rdata = Rdata::from_slice(compose_rdata(answer.data(), &mut buf)).unwrap();
+
+                result.push((owner, answer.rtype(), rdata)).unwrap();
+
+                Ok::<_, MdnsError>(())
+            })
+            .unwrap();
+
+        result
+    }
+
+    /// `myhost.local` in wire format
+    const HOSTNAME: &[u8] = b"\x06myhost\x05local\x00";
+
+    #[test]
+    fn test_host() {
+        let host = Host {
+            hostname: "myhost",
+            ipv4: &[
+                Ipv4Addr::new(192, 168, 0, 1),
+                Ipv4Addr::UNSPECIFIED,
+                Ipv4Addr::new(10, 0, 0, 17),
+            ],
+            ipv6: &[
+                Ipv6Addr::UNSPECIFIED,
+                Ipv6Addr::new(0xfe80, 0, 0, 0, 0x1234, 0, 0, 0xab),
+            ],
+            ttl: Ttl::from_secs(60),
+        };
+
+        let answers = answers(&host);
+
+        let expected: [(&str, Rtype, &[u8]); 6] = [
+            ("myhost.local.", Rtype::A, &[192, 168, 0, 1]),
+            ("myhost.local.", Rtype::A, &[10, 0, 0, 17]),
+            (
+                "myhost.local.",
+                Rtype::AAAA,
+                &[
+                    0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0x12, 0x34, 0, 0, 0, 0, 0, 0xab,
+                ],
+            ),
+            ("1.0.168.192.in-addr.arpa.", Rtype::PTR, HOSTNAME),
+            ("17.0.0.10.in-addr.arpa.", Rtype::PTR, HOSTNAME),
+            (
+                "b.a.0.0.0.0.0.0.0.0.0.0.4.3.2.1.0.0.0.0.0.0.0.0.0.0.0.0.0.8.e.f.ip6.arpa.",
+                Rtype::PTR,
+                HOSTNAME,
+            ),
+        ];
+
+        assert_eq!(answers.len(), expected.len());
+
+        for ((owner, rtype, rdata), (expected_owner, expected_rtype, expected_rdata)) in
+            answers.iter().zip(expected)
+        {
+            assert_eq!(owner, expected_owner);
+            assert_eq!(*rtype, expected_rtype);
+            assert_eq!(rdata, expected_rdata);
+        }
+    }
+
+    #[test]
+    fn test_host_without_addresses() {
+        let host = Host {
+            hostname: "myhost",
+            ipv4: &[Ipv4Addr::UNSPECIFIED],
+            ipv6: &[],
+            ttl: Ttl::from_secs(60),
+        };
+
+        assert!(answers(&host).is_empty());
+    }
+}
@@ -1,4 +1,4 @@
-use core::cell::RefCell;
+use core::cell::{Cell, RefCell};
 use core::fmt;
 use core::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
 
@@ -13,11 +13,15 @@ use embassy_sync::signal::Signal;
 
 use edge_nal::{MulticastV4, MulticastV6, Readable, UdpBind, UdpReceive, UdpSend};
 
-use embassy_time::{Duration, Timer};
+use embassy_time::{Duration, Instant, Timer};
 use log::{info, warn};
 
 use super::*;
 
+use browse::{BrowseEvent, Browser};
+use host::{Host, Service, ServiceAnswers};
+use probe::{Conflict, Name, ProbeCheck, PROBE_COUNT, PROBE_DEFER_MS, PROBE_INTERVAL_MS};
+
 /// A quick-and-dirty socket address that binds to a "default" interface.
 /// Don't use in production code.
 pub const DEFAULT_SOCKET: SocketAddr = SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), PORT);
@@ -30,6 +34,20 @@ pub const IPV6_BROADCAST_ADDR: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0
 /// The mDNS port, as per spec.
 pub const PORT: u16 = 5353;
 
+/// The maximum size of an outgoing mDNS packet, i.e. the Ethernet MTU of 1500 bytes minus the IPv6 and UDP headers.
+///
+/// Replies which do not fit are split across several packets.
+pub const MAX_PACKET_LEN: usize = 1452;
+
+/// The number of unsolicited announcements sent on start and whenever the answers change.
+/// The RFC requires at least two.
+pub const ANNOUNCE_COUNT: usize = 3;
+/// The interval between the first two announcements, which doubles for every subsequent announcement.
+pub const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The maximum number of services which can be probed and announced by `Mdns::run_probed`.
+pub const MAX_PROBED_SERVICES: usize = 8;
+
 /// A wrapper for mDNS and IO errors.
 #[derive(Debug)]
 pub enum MdnsIoError<E> {
@@ -115,6 +133,10 @@ where
     send_buf: SB,
     rand: fn(&mut [u8]),
     broadcast_signal: &'a Signal<M, ()>,
+    /// The number of `probe` calls in progress, which take over the receiver from `run`
+    probing: blocking_mutex::Mutex<M, Cell<usize>>,
+    /// Signalled when a `probe` call starts or ends
+    probe_signal: Signal<M, ()>,
 }
 
 impl<'a, M, R, S, RB, SB> Mdns<'a, M, R, S, RB, SB>
@@ -146,6 +168,8 @@ where
             send_buf,
             rand,
             broadcast_signal,
+            probing: blocking_mutex::Mutex::new(Cell::new(0)),
+            probe_signal: Signal::new(),
         }
     }
 
@@ -174,6 +198,184 @@ where
         }
     }
 
+    /// Probes the host name and the instance names of `services`, then runs the mDNS service with
+    /// their answers, as per RFC 6762 §8 and §9.
+    ///
+    /// Names which are already used by another host on the network are renamed (i.e. "device" to "device-2",
+    /// see `Conflict::rename`) and probed again, and `renamed` is called with the conflict and the new name.
+    /// The names are probed again as well when another host starts using one of them while running.
+    ///
+    /// Fails if there are more than `MAX_PROBED_SERVICES` services.
+    pub async fn run_probed<F>(
+        &self,
+        host: &Host<'_>,
+        services: &[Service<'_>],
+        mut renamed: F,
+    ) -> Result<(), MdnsIoError<S::Error>>
+    where
+        F: FnMut(&Conflict, &str),
+    {
+        let mut hostname = Name::try_from(host.hostname).map_err(|_| MdnsError::ShortBuf)?;
+        let mut names = heapless::Vec::<Name, MAX_PROBED_SERVICES>::new();
+
+        for service in services {
+            names
+                .push(Name::try_from(service.name).map_err(|_| MdnsError::ShortBuf)?)
+                .map_err(|_| MdnsError::ShortBuf)?;
+        }
+
+        loop {
+            let conflict = {
+                let host = Host {
+                    hostname: hostname.as_str(),
+                    ..host.clone()
+                };
+
+                let services = services
+                    .iter()
+                    .zip(&names)
+                    .map(|(service, name)| Service {
+                        name: name.as_str(),
+                        ..service.clone()
+                    })
+                    .collect::<heapless::Vec<_, MAX_PROBED_SERVICES>>();
+
+                let answers = ProbedAnswers {
+                    host: &host,
+                    services: &services,
+                };
+
+                match self.probe(&answers).await? {
+                    Some(conflict) => conflict,
+                    None => match self.run(HostAnswersMdnsHandler::new(&answers)).await {
+                        // Another host started using one of our names, so probe them again
+                        Err(MdnsIoError::MdnsError(MdnsError::Conflict(conflict))) => {
+                            warn!("{conflict}, probing again");
+
+                            continue;
+                        }
+                        other => return other,
+                    },
+                }
+            };
+
+            if conflict.name().eq_ignore_ascii_case(&hostname) {
+                hostname = conflict.rename()?;
+
+                info!("Host name is already taken, trying {hostname}.local instead");
+
+                renamed(&conflict, hostname.as_str());
+            } else if let Some(name) = names
+                .iter_mut()
+                .find(|name| conflict.name().eq_ignore_ascii_case(name))
+            {
+                *name = conflict.rename()?;
+
+                info!("Service name is already taken, trying {name} instead");
+
+                renamed(&conflict, name.as_str());
+            }
+        }
+    }
+
+    /// Probes the unique names of the provided answers (i.e. the host name and the service instance names),
+    /// as per RFC 6762 §8.
+    ///
+    /// Should be called before `run`, which announces the answers, as well as after `run` had
+    /// returned with an `MdnsError::Conflict` error, which carries the name claimed by the other host.
+    ///
+    /// Can also be called while `run` is running, e.g. to probe the name of a service before adding it
+    /// to a `registry::ServiceRegistry`. `run` then hands the receiver over until probing is done, so
+    /// the queries arriving in the meantime (i.e. for about a second) remain unanswered.
+    ///
+    /// Returns the conflicting name if another host on the network already uses it. In that case, the
+    /// conflicting entity should be renamed (e.g. with `Conflict::rename`), and the new names probed again.
+    /// `run_probed` does all of this.
+    pub async fn probe<A>(&self, answers: A) -> Result<Option<Conflict>, MdnsIoError<S::Error>>
+    where
+        A: HostAnswers,
+    {
+        let _probing = Probing::new(&self.probing, &self.probe_signal);
+
+        let mut recv = self.recv.lock().await;
+
+        // Wait a random 0-250ms before the first probe, as per spec
+        let mut b = [0];
+        (self.rand)(&mut b);
+
+        Timer::after(Duration::from_millis(b[0] as u64 * PROBE_INTERVAL_MS / 256)).await;
+
+        let mut sent = 0;
+
+        while sent < PROBE_COUNT {
+            {
+                let mut send_buf = self
+                    .send_buf
+                    .get()
+                    .await
+                    .ok_or(MdnsIoError::NoSendBufError)?;
+
+                let mut send_guard = self.send.lock().await;
+                let send = &mut *send_guard;
+
+                let len = probe::probe_query(&answers, 0, sent == 0, send_buf.as_mut())?;
+
+                if len == 0 {
+                    // Nothing to probe
+                    return Ok(None);
+                }
+
+                self.broadcast_once(send, &send_buf.as_mut()[..len], true, true)
+                    .await?;
+            }
+
+            sent += 1;
+
+            let deadline = Instant::now() + Duration::from_millis(PROBE_INTERVAL_MS);
+
+            loop {
+                let mut recv_buf = self
+                    .recv_buf
+                    .get()
+                    .await
+                    .ok_or(MdnsIoError::NoRecvBufError)?;
+
+                let received =
+                    select(pin!(recv.receive(recv_buf.as_mut())), Timer::at(deadline)).await;
+
+                let Either::First(received) = received else {
+                    break;
+                };
+
+                let (len, remote) = received.map_err(MdnsIoError::IoError)?;
+
+                match probe::check(&answers, &recv_buf.as_mut()[..len], true) {
+                    Ok(ProbeCheck::None) => (),
+                    Ok(ProbeCheck::Conflict(conflict)) => {
+                        warn!("{conflict} with {remote}");
+
+                        return Ok(Some(conflict));
+                    }
+                    Ok(ProbeCheck::Lost) => {
+                        info!("Lost a simultaneous probe tie-break to {remote}, probing again");
+
+                        Timer::after(Duration::from_millis(PROBE_DEFER_MS)).await;
+
+                        sent = 0;
+
+                        break;
+                    }
+                    Err(MdnsError::InvalidMessage) => {
+                        warn!("Got invalid message from {remote}, skipping");
+                    }
+                    Err(other) => Err(other)?,
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
     /// Sends a multicast query with the provided payload.
     /// It is assumed that the payload represents a valid mDNS query message.
     ///
@@ -195,7 +397,7 @@ where
         let mut send_guard = self.send.lock().await;
         let send = &mut *send_guard;
 
-        let len = q(send_buf.as_mut())?;
+        let len = q(packet_buf(send_buf.as_mut()))?;
 
         if len > 0 {
             self.broadcast_once(send, &send_buf.as_mut()[..len], true, true)
@@ -205,6 +407,30 @@ where
         Ok(())
     }
 
+    /// Sends the queries of the provided `browser`, i.e. the PTR queries for its service type,
+    /// the queries resolving the discovered instances, and the queries refreshing the cached records.
+    ///
+    /// Should run concurrently with `run`, where the handler passed to `run` should be - or should
+    /// chain - a `PeerAnswersMdnsHandler` for the same `browser`, so that the answers are processed.
+    pub async fn browse<F, const N: usize>(
+        &self,
+        browser: &Browser<'_, M, F, N>,
+    ) -> Result<(), MdnsIoError<S::Error>>
+    where
+        F: FnMut(BrowseEvent<'_>),
+    {
+        loop {
+            self.query(|buf| browser.query(buf)).await?;
+
+            // The Known-Answer packets following a query with the TC bit set
+            while browser.has_known_answers() {
+                self.query(|buf| browser.known_answers(buf)).await?;
+            }
+
+            select(Timer::at(browser.deadline()), browser.wait()).await;
+        }
+    }
+
     async fn broadcast<T>(
         &self,
         handler: &blocking_mutex::Mutex<M, RefCell<T>>,
@@ -212,6 +438,9 @@ where
     where
         T: MdnsHandler,
     {
+        let mut remaining = ANNOUNCE_COUNT;
+        let mut interval = ANNOUNCE_INTERVAL;
+
         loop {
             {
                 let mut send_buf = self
@@ -223,26 +452,90 @@ where
                 let mut send_guard = self.send.lock().await;
                 let send = &mut *send_guard;
 
-                let response = handler.lock(|handler| {
-                    handler
-                        .borrow_mut()
-                        .handle(MdnsRequest::None, send_buf.as_mut())
-                })?;
+                let mut first = true;
+
+                loop {
+                    let response = handler.lock(|handler| {
+                        handler
+                            .borrow_mut()
+                            .handle(MdnsRequest::None, packet_buf(send_buf.as_mut()))
+                    })?;
+
+                    let MdnsResponse::Reply { data, delay, more } = response else {
+                        break;
+                    };
 
-                if let MdnsResponse::Reply { data, delay } = response {
-                    if delay {
+                    if delay && first {
                         // TODO: Not ideal, as we hold the lock during the delay
                         self.delay().await;
                     }
 
                     self.broadcast_once(send, data, true, true).await?;
+
+                    if !more {
+                        break;
+                    }
+
+                    first = false;
+                }
+            }
+
+            remaining -= 1;
+
+            if remaining > 0 {
+                // Repeat the announcement with doubling intervals, as per spec,
+                // unless the answers change in the meantime
+                if let Either::First(_) =
+                    select(Timer::after(interval), self.broadcast_signal.wait()).await
+                {
+                    interval = interval * 2;
+
+                    continue;
                 }
+            } else {
+                self.broadcast_signal.wait().await;
             }
 
-            self.broadcast_signal.wait().await;
+            remaining = ANNOUNCE_COUNT;
+            interval = ANNOUNCE_INTERVAL;
         }
     }
 
+    /// Sends goodbye announcements, i.e. the provided answers with a TTL of 0, so that peers
+    /// remove them from their caches immediately, rather than when they expire.
+    ///
+    /// Should be called with all answers before shutting down, or with the answers of a service
+    /// which is about to be removed.
+    pub async fn goodbye<A>(&self, answers: A) -> Result<(), MdnsIoError<S::Error>>
+    where
+        A: HostAnswers,
+    {
+        let mut send_buf = self
+            .send_buf
+            .get()
+            .await
+            .ok_or(MdnsIoError::NoSendBufError)?;
+
+        let mut send_guard = self.send.lock().await;
+        let send = &mut *send_guard;
+
+        let mut handler = HostAnswersMdnsHandler::new(GoodbyeHostAnswers::new(answers));
+
+        while let MdnsResponse::Reply { data, more, .. } =
+            handler.handle(MdnsRequest::None, packet_buf(send_buf.as_mut()))?
+        {
+            info!("Sending mDNS goodbye");
+
+            self.broadcast_once(send, data, true, true).await?;
+
+            if !more {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
     async fn respond<T>(
         &self,
         handler: &blocking_mutex::Mutex<M, RefCell<T>>,
@@ -250,10 +543,18 @@ where
     where
         T: MdnsHandler,
     {
-        let mut recv = self.recv.lock().await;
-
         loop {
-            recv.readable().await.map_err(MdnsIoError::IoError)?;
+            // Hand the receiver over to the `probe` calls in progress, until they are done
+            while self.probing.lock(Cell::get) > 0 {
+                self.probe_signal.wait().await;
+            }
+
+            let mut recv = self.recv.lock().await;
+
+            match select(pin!(recv.readable()), self.probe_signal.wait()).await {
+                Either::First(readable) => readable.map_err(MdnsIoError::IoError)?,
+                Either::Second(_) => continue,
+            }
 
             {
                 let mut recv_buf = self
@@ -267,41 +568,157 @@ where
                     .await
                     .ok_or(MdnsIoError::NoSendBufError)?;
 
-                let (len, remote) = recv
-                    .receive(recv_buf.as_mut())
+                let (len, remote, destination) = recv
+                    .receive_with_destination(recv_buf.as_mut())
                     .await
                     .map_err(MdnsIoError::IoError)?;
 
-                debug!("Got mDNS query from {remote}");
+                // The packets received while waiting before replying to the first one
+                // are stored after it in `recv_buf`, and handled after it
+                let mut packets = Packets::new();
+                let _ = packets.push(Packet::new(0, len, remote, destination));
 
-                let mut send_guard = self.send.lock().await;
-                let send = &mut *send_guard;
+                let mut index = 0;
 
-                let response = match handler.lock(|handler| {
-                    handler.borrow_mut().handle(
-                        MdnsRequest::Request {
-                            data: &recv_buf.as_mut()[..len],
-                            legacy: remote.port() != PORT,
-                            multicast: true, // TODO: Cannot determine this
-                        },
+                while index < packets.len() {
+                    let packet = packets[index];
+
+                    index += 1;
+
+                    self.respond_once(
+                        &mut recv,
+                        recv_buf.as_mut(),
                         send_buf.as_mut(),
+                        handler,
+                        packet,
+                        (index == 1).then_some(&mut packets),
                     )
+                    .await?;
+                }
+            }
+        }
+    }
+
+    async fn respond_once<T>(
+        &self,
+        recv: &mut R,
+        recv_buf: &mut [u8],
+        send_buf: &mut [u8],
+        handler: &blocking_mutex::Mutex<M, RefCell<T>>,
+        packet: Packet,
+        mut packets: Option<&mut Packets>,
+    ) -> Result<(), MdnsIoError<S::Error>>
+    where
+        T: MdnsHandler,
+    {
+        let remote = packet.remote;
+
+        debug!("Got mDNS query from {remote}");
+
+        if let Some(packets) = packets.as_deref_mut() {
+            let truncated = Message::from_octets(&recv_buf[packet.range()])
+                .map(|message| !message.header().qr() && message.header().tc())
+                .unwrap_or(false);
+
+            if truncated {
+                // As per spec, the rest of the Known-Answer list follows in subsequent packets,
+                // so wait 400-500ms for them
+                let deadline = Instant::now() + self.random_duration(400, 100);
+
+                self.receive_until(recv, recv_buf, packets, deadline)
+                    .await?;
+            }
+        }
+
+        // The Known-Answer packets of the query, if any
+        let mut known_packets = Packets::new();
+
+        if let Some(packets) = packets.as_deref() {
+            for other in packets[1..].iter().filter(|other| other.remote == remote) {
+                let _ = known_packets.push(*other);
+            }
+        }
+
+        let mut send_guard = self.send.lock().await;
+        let send = &mut *send_guard;
+
+        // The index of the first packet received while delaying the reply, if the reply was delayed
+        let mut others = None;
+
+        loop {
+            let (unicast, response) = {
+                let mut known_answers = heapless::Vec::<&[u8], MAX_PACKETS>::new();
+
+                for other in &known_packets {
+                    let _ = known_answers.push(&recv_buf[other.range()]);
+                }
+
+                let request = MdnsRequest::Request {
+                    data: &recv_buf[packet.range()],
+                    legacy: remote.port() != PORT,
+                    multicast: packet.multicast,
+                    known_answers: &known_answers,
+                };
+
+                let unicast = request.unicast_reply();
+
+                let response = match handler.lock(|handler| {
+                    handler
+                        .borrow_mut()
+                        .handle(request, packet_buf(&mut *send_buf))
                 }) {
-                    Ok(len) => len,
+                    Ok(response) => response,
                     Err(err) => match err {
                         MdnsError::InvalidMessage => {
                             warn!("Got invalid message from {remote}, skipping");
-                            continue;
+                            return Ok(());
                         }
                         other => Err(other)?,
                     },
                 };
 
-                if let MdnsResponse::Reply { data, delay } = response {
-                    if delay {
-                        self.delay().await;
+                (unicast, response)
+            };
+
+            let MdnsResponse::Reply { data, delay, more } = response else {
+                break;
+            };
+
+            if unicast {
+                // Legacy, direct unicast or QU queries are answered directly to the querier,
+                // without a delay, as per spec
+                info!("Replying to mDNS query from {remote} via unicast");
+
+                let fut = pin!(send.send(remote, data));
+
+                fut.await.map_err(MdnsIoError::IoError)?;
+            } else {
+                if delay && others.is_none() {
+                    let deadline = Instant::now() + self.random_duration(20, 100);
+
+                    if let Some(packets) = packets.as_deref_mut() {
+                        others = Some(packets.len());
+
+                        self.receive_until(recv, recv_buf, packets, deadline)
+                            .await?;
+                    } else {
+                        others = Some(0);
+
+                        Timer::at(deadline).await;
                     }
+                }
 
+                // Do not repeat the answers another responder already sent in the meantime
+                let duplicate = match (packets.as_deref(), others) {
+                    (Some(packets), Some(others)) => packets[others..].iter().any(|other| {
+                        is_duplicate_reply(data, &recv_buf[other.range()]).unwrap_or(false)
+                    }),
+                    _ => false,
+                };
+
+                if duplicate {
+                    info!("Another responder already replied to mDNS query from {remote}");
+                } else {
                     info!("Replying to mDNS query from {remote}");
 
                     self.broadcast_once(
@@ -313,7 +730,51 @@ where
                     .await?;
                 }
             }
+
+            if !more {
+                break;
+            }
         }
+
+        Ok(())
+    }
+
+    /// Receives packets into the unused part of `recv_buf` until `deadline`.
+    async fn receive_until(
+        &self,
+        recv: &mut R,
+        recv_buf: &mut [u8],
+        packets: &mut Packets,
+        deadline: Instant,
+    ) -> Result<(), MdnsIoError<S::Error>> {
+        while !packets.is_full() {
+            let offset = packets
+                .last()
+                .map(|packet| packet.offset + packet.len)
+                .unwrap_or(0);
+
+            if offset >= recv_buf.len() {
+                break;
+            }
+
+            match select(
+                pin!(recv.receive_with_destination(&mut recv_buf[offset..])),
+                Timer::at(deadline),
+            )
+            .await
+            {
+                Either::First(received) => {
+                    let (len, remote, destination) = received.map_err(MdnsIoError::IoError)?;
+
+                    let _ = packets.push(Packet::new(offset, len, remote, destination));
+                }
+                Either::Second(_) => return Ok(()),
+            }
+        }
+
+        Timer::at(deadline).await;
+
+        Ok(())
     }
 
     async fn broadcast_once(
@@ -353,12 +814,643 @@ where
     }
 
     async fn delay(&self) {
+        // Generate a delay between 20 and 120 ms, as per spec
+        Timer::after(self.random_duration(20, 100)).await;
+    }
+
+    fn random_duration(&self, min_ms: u64, range_ms: u64) -> Duration {
         let mut b = [0];
         (self.rand)(&mut b);
 
-        // Generate a delay between 20 and 120 ms, as per spec
-        let delay_ms = 20 + (b[0] as u32 * 100 / 256);
+        Duration::from_millis(min_ms + b[0] as u64 * range_ms / 256)
+    }
+}
+
+/// Marks a `probe` call in progress for as long as it is alive, including when the call is cancelled.
+struct Probing<'a, M>
+where
+    M: RawMutex,
+{
+    probing: &'a blocking_mutex::Mutex<M, Cell<usize>>,
+    signal: &'a Signal<M, ()>,
+}
+
+impl<'a, M> Probing<'a, M>
+where
+    M: RawMutex,
+{
+    fn new(probing: &'a blocking_mutex::Mutex<M, Cell<usize>>, signal: &'a Signal<M, ()>) -> Self {
+        probing.lock(|probing| probing.set(probing.get() + 1));
+        signal.signal(());
+
+        Self { probing, signal }
+    }
+}
+
+impl<M> Drop for Probing<'_, M>
+where
+    M: RawMutex,
+{
+    fn drop(&mut self) {
+        self.probing.lock(|probing| probing.set(probing.get() - 1));
+        self.signal.signal(());
+    }
+}
+
+/// The answers of a host and of its services, as probed and announced by `Mdns::run_probed`.
+struct ProbedAnswers<'a> {
+    host: &'a Host<'a>,
+    services: &'a [Service<'a>],
+}
+
+impl HostAnswers for ProbedAnswers<'_> {
+    fn visit<F, E>(&self, mut f: F) -> Result<(), E>
+    where
+        F: FnMut(HostAnswer) -> Result<(), E>,
+        E: From<MdnsError>,
+    {
+        self.host.visit(&mut f)?;
+
+        for service in self.services {
+            ServiceAnswers::new(self.host, service).visit(&mut f)?;
+        }
+
+        Ok(())
+    }
+}
+
+const MAX_PACKETS: usize = 8;
+
+/// The part of `buf` which fits in a single packet.
+fn packet_buf(buf: &mut [u8]) -> &mut [u8] {
+    let len = buf.len().min(MAX_PACKET_LEN);
+
+    &mut buf[..len]
+}
+
+type Packets = heapless::Vec<Packet, MAX_PACKETS>;
+
+#[derive(Copy, Clone)]
+struct Packet {
+    offset: usize,
+    len: usize,
+    remote: SocketAddr,
+    multicast: bool,
+}
+
+impl Packet {
+    fn new(offset: usize, len: usize, remote: SocketAddr, destination: Option<IpAddr>) -> Self {
+        Self {
+            offset,
+            len,
+            remote,
+            // Assume multicast if the socket cannot report the destination address
+            multicast: destination.map(|addr| addr.is_multicast()).unwrap_or(true),
+        }
+    }
+
+    fn range(&self) -> core::ops::Range<usize> {
+        self.offset..self.offset + self.len
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::convert::Infallible;
+
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use embedded_io_async::ErrorType;
+
+    use crate::buf::VecBufAccess;
+    use crate::fixtures::{a, announce, host, myhost, query, sensor, HOSTNAME, IPV4};
+
+    use super::*;
+
+    const QUERIER: SocketAddr =
+        SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(192, 168, 0, 2), PORT));
+    const OTHER: SocketAddr =
+        SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(192, 168, 0, 3), PORT));
+    const MULTICAST: SocketAddr = SocketAddr::V4(SocketAddrV4::new(IP_BROADCAST_ADDR, PORT));
+
+    /// A packet sent through the `Socket`: the time it was sent at (in ms), its destination and its data
+    type Sent = (u64, SocketAddr, heapless::Vec<u8, MAX_PACKET_LEN>);
+
+    /// A UDP socket receiving the `received` packets - each one at its time (in ms) since the socket was created -
+    /// and recording the packets sent through it
+    struct Socket<'a> {
+        start: Instant,
+        received: &'a [(u64, SocketAddr, &'a [u8])],
+        index: Cell<usize>,
+        sent: RefCell<heapless::Vec<Sent, 8>>,
+    }
+
+    impl<'a> Socket<'a> {
+        fn new(received: &'a [(u64, SocketAddr, &'a [u8])]) -> Self {
+            Self {
+                start: Instant::now(),
+                received,
+                index: Cell::new(0),
+                sent: RefCell::new(heapless::Vec::new()),
+            }
+        }
+
+        /// Wait for the next packet to arrive, or forever if all packets were received
+        async fn arrival(&self) -> (SocketAddr, &'a [u8]) {
+            let Some((at, remote, data)) = self.received.get(self.index.get()) else {
+                return core::future::pending().await;
+            };
+
+            Timer::at(self.start + Duration::from_millis(*at)).await;
+
+            (*remote, data)
+        }
+    }
+
+    impl ErrorType for &Socket<'_> {
+        type Error = Infallible;
+    }
+
+    impl UdpReceive for &Socket<'_> {
+        async fn receive(&mut self, buffer: &mut [u8]) -> Result<(usize, SocketAddr), Self::Error> {
+            let (remote, data) = self.arrival().await;
+
+            self.index.set(self.index.get() + 1);
+            buffer[..data.len()].copy_from_slice(data);
+
+            Ok((data.len(), remote))
+        }
+    }
+
+    impl Readable for &Socket<'_> {
+        async fn readable(&mut self) -> Result<(), Self::Error> {
+            self.arrival().await;
+
+            Ok(())
+        }
+    }
+
+    impl UdpSend for &Socket<'_> {
+        async fn send(&mut self, remote: SocketAddr, data: &[u8]) -> Result<(), Self::Error> {
+            let at = self.start.elapsed().as_millis();
+
+            self.sent
+                .borrow_mut()
+                .push((at, remote, heapless::Vec::from_slice(data).unwrap()))
+                .unwrap();
+
+            Ok(())
+        }
+    }
+
+    /// The shortest random delays
+    fn rand_min(buf: &mut [u8]) {
+        buf.fill(0);
+    }
+
+    /// The longest random delays
+    fn rand_max(buf: &mut [u8]) {
+        buf.fill(0xff);
+    }
+
+    /// An `MdnsHandler` preparing packets of at most `len` bytes, so that its replies are split sooner
+    struct Split<T> {
+        handler: T,
+        len: usize,
+    }
+
+    impl<T> MdnsHandler for Split<T>
+    where
+        T: MdnsHandler,
+    {
+        fn handle<'a>(
+            &mut self,
+            request: MdnsRequest<'_>,
+            response_buf: &'a mut [u8],
+        ) -> Result<MdnsResponse<'a>, MdnsError> {
+            let len = response_buf.len().min(self.len);
+
+            self.handler.handle(request, &mut response_buf[..len])
+        }
+    }
+
+    /// Answer the queries received by `socket` with `answers` for `duration_ms`
+    fn respond<T>(socket: &Socket<'_>, answers: T, rand: fn(&mut [u8]), duration_ms: u64)
+    where
+        T: HostAnswers,
+    {
+        run(
+            socket,
+            HostAnswersMdnsHandler::new(answers),
+            rand,
+            duration_ms,
+            false,
+        );
+    }
+
+    /// Answer the queries received by `socket` with `handler` - or broadcast its answers, if `broadcast` is `true` -
+    /// for `duration_ms`
+    fn run<T>(
+        socket: &Socket<'_>,
+        handler: T,
+        rand: fn(&mut [u8]),
+        duration_ms: u64,
+        broadcast: bool,
+    ) where
+        T: MdnsHandler,
+    {
+        let handler = blocking_mutex::Mutex::<NoopRawMutex, _>::new(RefCell::new(handler));
+
+        let result = with_mdns(socket, rand, |mdns| {
+            let timer = Timer::after(Duration::from_millis(duration_ms));
+
+            if broadcast {
+                embassy_futures::block_on(select(pin!(mdns.broadcast(&handler)), timer))
+            } else {
+                embassy_futures::block_on(select(pin!(mdns.respond(&handler)), timer))
+            }
+        });
+
+        if let Either::First(result) = result {
+            result.unwrap();
+        }
+    }
+
+    type TestMdns<'a> = Mdns<
+        'a,
+        NoopRawMutex,
+        &'a Socket<'a>,
+        &'a Socket<'a>,
+        &'a VecBufAccess<NoopRawMutex, 3000>,
+        &'a VecBufAccess<NoopRawMutex, MAX_PACKET_LEN>,
+    >;
+
+    /// Call `f` with an `Mdns` instance receiving and sending through `socket`
+    fn with_mdns<F, R>(socket: &Socket<'_>, rand: fn(&mut [u8]), f: F) -> R
+    where
+        F: FnOnce(&TestMdns<'_>) -> R,
+    {
+        let signal = Signal::<NoopRawMutex, _>::new();
+        let recv_buf = VecBufAccess::<NoopRawMutex, 3000>::new();
+        let send_buf = VecBufAccess::<NoopRawMutex, MAX_PACKET_LEN>::new();
+
+        let mdns = Mdns::new(
+            Some(IPV4),
+            None,
+            socket,
+            socket,
+            &recv_buf,
+            &send_buf,
+            rand,
+            &signal,
+        );
+
+        f(&mdns)
+    }
+
+    fn ptr_question() -> HostQuestion<'static> {
+        Question::new(
+            NameSlice::new(&["_http", "_tcp", "local"]),
+            Rtype::PTR,
+            Class::IN,
+        )
+    }
+
+    #[test]
+    fn test_respond_delay() {
+        let host = host();
+        let service = sensor();
+        let answers = ChainedHostAnswers::new(&host, ServiceAnswers::new(&host, &service));
+
+        let mut query_buf = [0; 512];
+        let data = query(&[ptr_question()], &[], &mut query_buf);
+
+        let received = [(0, QUERIER, data)];
+
+        // Replies with shared records are delayed by 20-120ms
+        let socket = Socket::new(&received);
+        respond(&socket, &answers, rand_min, 300);
+
+        let sent = socket.sent.borrow();
+        let [(at, remote, _)] = sent.as_slice() else {
+            panic!("Expected one reply");
+        };
+        assert_eq!(*remote, MULTICAST);
+        assert!((20..120).contains(at));
+
+        let socket = Socket::new(&received);
+        respond(&socket, &answers, rand_max, 300);
+
+        let sent = socket.sent.borrow();
+        let [(at, _, _)] = sent.as_slice() else {
+            panic!("Expected one reply");
+        };
+        assert!(*at >= 119);
+
+        // ... unlike replies with unique records only
+        let mut a_query_buf = [0; 512];
+        let data = query(
+            &[Question::new(HOSTNAME, Rtype::A, Class::IN)],
+            &[],
+            &mut a_query_buf,
+        );
+
+        let received = [(0, QUERIER, data)];
+
+        let socket = Socket::new(&received);
+        respond(&socket, &answers, rand_max, 300);
+
+        let sent = socket.sent.borrow();
+        let [(at, remote, _)] = sent.as_slice() else {
+            panic!("Expected one reply");
+        };
+        assert_eq!(*remote, MULTICAST);
+        assert!(*at < 20);
+    }
+
+    #[test]
+    fn test_respond_known_answers() {
+        let host = myhost(&[IPV4]);
+
+        let mut query_buf = [0; 512];
+        let len = query(
+            &[Question::new(HOSTNAME, Rtype::A, Class::IN)],
+            &[],
+            &mut query_buf,
+        )
+        .len();
+
+        // The TC bit: the Known-Answer list continues in the next packets
+        query_buf[2] |= 0x02;
+
+        let data = &query_buf[..len];
+
+        let mut known_buf = [0; 512];
+        let known = query(&[], &[a(60)], &mut known_buf);
+
+        // The reply waits 400-500ms for the Known-Answer packets
+        let received = [(0, QUERIER, data)];
+
+        let socket = Socket::new(&received);
+        respond(&socket, &host, rand_min, 700);
+
+        let sent = socket.sent.borrow();
+        let [(at, _, _)] = sent.as_slice() else {
+            panic!("Expected one reply");
+        };
+        assert!((400..500).contains(at));
+
+        // The querier knows the answer already
+        let received = [(0, QUERIER, data), (300, QUERIER, known)];
+
+        let socket = Socket::new(&received);
+        respond(&socket, &host, rand_min, 700);
+
+        assert!(socket.sent.borrow().is_empty());
+
+        // Known-Answer packets of other queriers do not matter
+        let received = [(0, QUERIER, data), (300, OTHER, known)];
+
+        let socket = Socket::new(&received);
+        respond(&socket, &host, rand_min, 700);
+
+        assert_eq!(socket.sent.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_respond_duplicate() {
+        let host = host();
+        let service = sensor();
+        let answers = ChainedHostAnswers::new(&host, ServiceAnswers::new(&host, &service));
+
+        let mut query_buf = [0; 512];
+        let data = query(&[ptr_question()], &[], &mut query_buf);
+
+        // Another responder sends our answers while we delay our reply
+        let mut other_buf = [0; 1500];
+        let other = announce(&answers, &mut other_buf);
+
+        let received = [(0, QUERIER, data), (10, OTHER, other)];
+
+        let socket = Socket::new(&received);
+        respond(&socket, &answers, rand_max, 300);
+
+        assert!(socket.sent.borrow().is_empty());
+
+        // ... or other answers
+        let other_host = Host {
+            hostname: "other",
+            ..myhost(&[Ipv4Addr::new(192, 168, 0, 3)])
+        };
+        let other_service = Service {
+            name: "othersensor",
+            ..sensor()
+        };
+
+        let mut other_service_buf = [0; 1500];
+        let other = announce(
+            ServiceAnswers::new(&other_host, &other_service),
+            &mut other_service_buf,
+        );
+
+        let received = [(0, QUERIER, data), (10, OTHER, other)];
+
+        let socket = Socket::new(&received);
+        respond(&socket, &answers, rand_max, 300);
+
+        assert_eq!(socket.sent.borrow().len(), 1);
+    }
+
+    /// The number of answers in each of the `sent` packets
+    fn ancounts(sent: &[Sent]) -> heapless::Vec<u16, 8> {
+        sent.iter()
+            .map(|(_, _, data)| {
+                Message::from_octets(data.as_slice())
+                    .unwrap()
+                    .header_counts()
+                    .ancount()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_broadcast_split() {
+        let host = host();
+        let service = sensor();
+        let answers = ChainedHostAnswers::new(&host, ServiceAnswers::new(&host, &service));
+
+        let socket = Socket::new(&[]);
+        run(
+            &socket,
+            Split {
+                handler: HostAnswersMdnsHandler::new(&answers),
+                len: 150,
+            },
+            rand_min,
+            500,
+            true,
+        );
+
+        // The announcement is sent in several packets at once, with each record sent exactly once
+        let sent = socket.sent.borrow();
+        assert!(sent.len() > 1);
+        assert!(sent
+            .iter()
+            .all(|(at, remote, _)| *at < 20 && *remote == MULTICAST));
+        assert_eq!(ancounts(&sent).iter().sum::<u16>(), 8);
+    }
+
+    #[test]
+    fn test_respond_split() {
+        let host = host();
+        let service = sensor();
+        let answers = ChainedHostAnswers::new(&host, ServiceAnswers::new(&host, &service));
+
+        let mut query_buf = [0; 512];
+        let data = query(&[ptr_question()], &[], &mut query_buf);
+
+        let received = [(0, QUERIER, data)];
+
+        let socket = Socket::new(&received);
+        run(
+            &socket,
+            Split {
+                handler: HostAnswersMdnsHandler::new(&answers),
+                len: 150,
+            },
+            rand_max,
+            300,
+            false,
+        );
+
+        // The reply is delayed once, and its additional records follow the answer in a second packet
+        let sent = socket.sent.borrow();
+        let [(first, _, _), (second, remote, _)] = sent.as_slice() else {
+            panic!("Expected two reply packets");
+        };
+        assert_eq!(*remote, MULTICAST);
+        assert!(*first >= 119 && *second - *first < 20);
+        assert_eq!(ancounts(&sent), [1, 0]);
+    }
+
+    /// Return `true` if the `sent` packet is a probe query rather than a reply
+    fn is_probe(sent: &Sent) -> bool {
+        let message = Message::from_octets(sent.2.as_slice()).unwrap();
+
+        !message.header().qr() && message.header_counts().nscount() > 0
+    }
+
+    #[test]
+    fn test_probe_while_running() {
+        let host = myhost(&[IPV4]);
+
+        let other_host = myhost(&[Ipv4Addr::new(192, 168, 0, 3)]);
+
+        let mut other_buf = [0; 1500];
+        let other = announce(&other_host, &mut other_buf);
+
+        // The responder hands over the announcement of the other host to the probe
+        let received = [(100, OTHER, other)];
+
+        let socket = Socket::new(&received);
+
+        let result = with_mdns(&socket, rand_min, |mdns| {
+            let handler = blocking_mutex::Mutex::<NoopRawMutex, _>::new(RefCell::new(
+                HostAnswersMdnsHandler::new(&host),
+            ));
+
+            embassy_futures::block_on(select(
+                pin!(mdns.respond(&handler)),
+                pin!(mdns.probe(&host)),
+            ))
+        });
+
+        let Either::Second(Ok(Some(conflict))) = result else {
+            panic!("Expected a conflict");
+        };
+
+        assert_eq!(conflict.name(), "myhost");
+    }
+
+    #[test]
+    fn test_respond_after_probe() {
+        let host = myhost(&[IPV4]);
+
+        let mut query_buf = [0; 512];
+        let data = query(
+            &[Question::new(HOSTNAME, Rtype::A, Class::IN)],
+            &[],
+            &mut query_buf,
+        );
+
+        let received = [(1000, QUERIER, data)];
+
+        let socket = Socket::new(&received);
+
+        let result = with_mdns(&socket, rand_min, |mdns| {
+            let handler = blocking_mutex::Mutex::<NoopRawMutex, _>::new(RefCell::new(
+                HostAnswersMdnsHandler::new(&host),
+            ));
+
+            embassy_futures::block_on(select(pin!(mdns.respond(&handler)), async {
+                let result = mdns.probe(&host).await;
+
+                Timer::after(Duration::from_millis(1500)).await;
+
+                result
+            }))
+        });
+
+        let Either::Second(Ok(None)) = result else {
+            panic!("Expected no conflict");
+        };
+
+        // The responder takes the receiver back once probing is done
+        let sent = socket.sent.borrow();
+        let [probes @ .., reply] = sent.as_slice() else {
+            panic!("Expected the probes and a reply");
+        };
+        assert_eq!(probes.len(), PROBE_COUNT);
+        assert!(probes.iter().all(is_probe));
+        assert!(!is_probe(reply) && reply.0 >= 1000);
+    }
+
+    #[test]
+    fn test_run_probed() {
+        let host = myhost(&[IPV4]);
+
+        let other_host = myhost(&[Ipv4Addr::new(192, 168, 0, 3)]);
+
+        let mut other_buf = [0; 1500];
+        let other = announce(&other_host, &mut other_buf);
+
+        let received = [(100, OTHER, other)];
+
+        let socket = Socket::new(&received);
+
+        let mut renamed = heapless::Vec::<Name, 2>::new();
+
+        with_mdns(&socket, rand_min, |mdns| {
+            embassy_futures::block_on(select(
+                pin!(mdns.run_probed(&host, &[sensor()], |conflict, name| {
+                    assert_eq!(conflict.name(), "myhost");
+
+                    renamed.push(name.try_into().unwrap()).unwrap();
+                })),
+                Timer::after(Duration::from_millis(2000)),
+            ))
+        });
+
+        assert_eq!(renamed, ["myhost-2"]);
+
+        // The renamed host is probed, and then announced
+        let sent = socket.sent.borrow();
+        let announcement = sent.iter().find(|sent| !is_probe(sent)).unwrap();
 
-        Timer::after(Duration::from_millis(delay_ms as _)).await;
+        let message = Message::from_octets(announcement.2.as_slice()).unwrap();
+        let record = message.answer().unwrap().next().unwrap().unwrap();
+        assert!(record
+            .owner()
+            .name_eq(&NameSlice::new(&["myhost-2", "local"])));
     }
 }
@@ -7,22 +7,27 @@ use core::fmt::{self, Display};
 use core::ops::RangeBounds;
 
 use domain::base::header::Flags;
-use domain::base::iana::{Opcode, Rcode};
+use domain::base::iana::{Class, Opcode, Rcode};
 use domain::base::message::ShortMessage;
 use domain::base::message_builder::PushError;
 use domain::base::name::{FromStrError, Label, ToLabelIter};
 use domain::base::rdata::ComposeRecordData;
 use domain::base::wire::{Composer, ParseError};
 use domain::base::{
-    Message, MessageBuilder, ParsedName, Question, Record, RecordData, Rtype, ToName,
+    Message, MessageBuilder, ParsedName, Question, Record, RecordData, Rtype, ToName, Ttl,
 };
 use domain::dep::octseq::{FreezeBuilder, FromBuilder, Octets, OctetsBuilder, ShortBuf, Truncate};
+use domain::rdata::dnssec::{Nsec, RtypeBitmap};
 use domain::rdata::AllRecordData;
 
-use log::debug;
+use log::{debug, warn};
 
+#[cfg(feature = "io")]
+pub mod browse;
 #[cfg(feature = "io")]
 pub mod buf; // TODO: Maybe move to a generic `edge-buf` crate in future
+#[cfg(feature = "io")]
+pub mod dns;
 /// Re-export the domain lib if the user would like to directly
 /// assemble / parse mDNS messages.
 pub mod domain {
@@ -31,16 +36,28 @@ pub mod domain {
 pub mod host;
 #[cfg(feature = "io")]
 pub mod io;
+pub mod probe;
+#[cfg(feature = "io")]
+pub mod registry;
+
+#[cfg(test)]
+mod fixtures;
 
 /// The DNS-SD owner name.
 pub const DNS_SD_OWNER: NameSlice = NameSlice::new(&["_services", "_dns-sd", "_udp", "local"]);
 
 /// A wrapper type for the errors returned by the `domain` library during parsing and
 /// constructing mDNS messages.
+///
+/// New variants might be added in the future (as `Conflict` was), so matches on it need a wildcard arm.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum MdnsError {
     ShortBuf,
     InvalidMessage,
+    /// Another host on the network claims one of our unique names.
+    /// The names should be probed again, see the `probe` module.
+    Conflict(probe::Conflict),
 }
 
 impl Display for MdnsError {
@@ -48,6 +65,7 @@ impl Display for MdnsError {
         match self {
             Self::ShortBuf => write!(f, "ShortBuf"),
             Self::InvalidMessage => write!(f, "InvalidMessage"),
+            Self::Conflict(conflict) => write!(f, "{conflict}"),
         }
     }
 }
@@ -97,6 +115,11 @@ impl<'a> NameSlice<'a> {
     pub const fn new(labels: &'a [&'a str]) -> Self {
         Self(labels)
     }
+
+    /// Return `true` if this is a reverse-mapping name, i.e. in the `in-addr.arpa` or `ip6.arpa` domain.
+    pub fn is_reverse_mapping(&self) -> bool {
+        matches!(self.0.last(), Some(label) if label.eq_ignore_ascii_case("arpa"))
+    }
 }
 
 impl<'a> fmt::Display for NameSlice<'a> {
@@ -386,14 +409,113 @@ pub enum MdnsRequest<'a> {
         multicast: bool,
         /// The data of the request
         data: &'a [u8],
+        /// The data of the Known-Answer packets which followed the request, if it had the TC bit set
+        known_answers: &'a [&'a [u8]],
     },
 }
 
+impl MdnsRequest<'_> {
+    /// Return `true` if the reply to this request should be sent directly to the source address
+    /// of the request, rather than to the multicast address, as per RFC 6762 §5.4 and §6.7.
+    ///
+    /// This is the case for legacy requests, for requests which did not arrive on the multicast
+    /// address, and for requests in which all questions have the "unicast response" (QU) bit set.
+    ///
+    /// Note that QU requests are always answered via unicast, whereas RFC 6762 §5.4 says they should
+    /// rather be answered via multicast if the records were not multicast within the last quarter of
+    /// their TTL, as that requires tracking when each record was last multicast.
+    pub fn unicast_reply(&self) -> bool {
+        let Self::Request {
+            legacy,
+            multicast,
+            data,
+            ..
+        } = self
+        else {
+            return false;
+        };
+
+        if *legacy || !*multicast {
+            return true;
+        }
+
+        let Ok(message) = Message::from_octets(*data) else {
+            return false;
+        };
+
+        if message.header().qr() {
+            return false;
+        }
+
+        let mut questions = message.question().peekable();
+
+        questions.peek().is_some()
+            && questions.all(|question| {
+                question
+                    .map(|question| question.qclass().to_int() & probe::QU_BIT != 0)
+                    .unwrap_or(false)
+            })
+    }
+
+    /// A digest of the request, which tells apart the requests whose replies span several packets.
+    fn digest(&self) -> u32 {
+        // FNV-1a
+        const PRIME: u32 = 0x0100_0193;
+
+        let mut digest: u32 = 0x811c_9dc5;
+
+        let mut update = |bytes: &[u8]| {
+            for byte in bytes {
+                digest = (digest ^ *byte as u32).wrapping_mul(PRIME);
+            }
+        };
+
+        if let Self::Request {
+            legacy,
+            multicast,
+            data,
+            known_answers,
+        } = self
+        {
+            update(&[1, *legacy as u8, *multicast as u8]);
+            update(data);
+
+            for known in known_answers.iter() {
+                update(known);
+            }
+        }
+
+        digest
+    }
+}
+
 /// Return type for `MdnsHandler::handle`.
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum MdnsResponse<'a> {
     None,
-    Reply { data: &'a [u8], delay: bool },
+    /// A reply to be sent.
+    ///
+    /// If `more` is `true`, the reply did not fit in `response_buf`, and the handler should be
+    /// called again with the same request, to prepare the next packet of the reply.
+    ///
+    /// Note that `more` is a breaking addition for handlers constructing replies with a struct literal;
+    /// such handlers should either set it to `false`, or use `MdnsResponse::reply`.
+    Reply {
+        data: &'a [u8],
+        delay: bool,
+        more: bool,
+    },
+}
+
+impl<'a> MdnsResponse<'a> {
+    /// A reply which fits in a single packet.
+    pub const fn reply(data: &'a [u8], delay: bool) -> Self {
+        Self::Reply {
+            data,
+            delay,
+            more: false,
+        }
+    }
 }
 
 /// A trait that abstracts the processing logic for an incoming mDNS message.
@@ -485,12 +607,13 @@ where
     ) -> Result<MdnsResponse<'a>, MdnsError> {
         match self.first.handle(request.clone(), response_buf)? {
             MdnsResponse::None => self.second.handle(request, response_buf),
-            MdnsResponse::Reply { data, delay } => {
+            MdnsResponse::Reply { data, delay, more } => {
                 let len = data.len();
 
                 Ok(MdnsResponse::Reply {
                     data: &response_buf[..len],
                     delay,
+                    more,
                 })
             }
         }
@@ -735,19 +858,67 @@ where
     }
 }
 
+/// A `HostAnswers` wrapper which provides the answers of the wrapped instance with a TTL of 0.
+///
+/// Broadcasting these answers is a goodbye announcement, i.e. it tells peers that the
+/// answers are no longer valid.
+pub struct GoodbyeHostAnswers<T> {
+    answers: T,
+}
+
+impl<T> GoodbyeHostAnswers<T> {
+    /// Create a new `GoodbyeHostAnswers` instance from the answers which are going away.
+    pub const fn new(answers: T) -> Self {
+        Self { answers }
+    }
+}
+
+impl<T> HostAnswers for GoodbyeHostAnswers<T>
+where
+    T: HostAnswers,
+{
+    fn visit<F, E>(&self, mut f: F) -> Result<(), E>
+    where
+        F: FnMut(HostAnswer) -> Result<(), E>,
+        E: From<MdnsError>,
+    {
+        self.answers.visit(|mut answer| {
+            answer.set_ttl(Ttl::from_secs(0));
+
+            f(answer)
+        })
+    }
+}
+
 /// An `MdnsHandler` implementation that answers mDNS queries with the answers
 /// provided by an entity implementing the `HostAnswers` trait.
 ///
 /// Typically, this structure will be used to provide answers to other peers that broadcast
 /// mDNS queries - i.e. this is the "responder" aspect of the mDNS protocol.
+///
+/// Replies which do not fit in the response buffer are split across several packets, with the
+/// answers to the asked questions coming first, followed by the additional records.
 pub struct HostAnswersMdnsHandler<T> {
     answers: T,
+    /// The number of records sent in the previous packets of a multi-packet reply
+    next: usize,
+    /// The digest of the request whose reply is in progress
+    request: u32,
 }
 
 impl<T> HostAnswersMdnsHandler<T> {
     /// Create a new `HostAnswersMdnsHandler` instance from an entity that provides answers.
     pub const fn new(answers: T) -> Self {
-        Self { answers }
+        Self {
+            answers,
+            next: 0,
+            request: 0,
+        }
+    }
+
+    /// Return `true` if the handler is in the middle of a multi-packet reply to `request`.
+    pub(crate) fn is_replying(&self, request: &MdnsRequest<'_>) -> bool {
+        self.next > 0 && self.request == request.digest()
     }
 }
 
@@ -764,19 +935,50 @@ where
 
         let mut mb = MessageBuilder::from_target(buf)?;
 
-        let mut pushed = false;
+        // Skip the records which were sent in the previous packets of this reply, unless
+        // the reply was abandoned, i.e. the previous packets were for another request
+        let skip = if self.is_replying(&request) {
+            self.next
+        } else {
+            0
+        };
 
-        let buf = if let MdnsRequest::Request { legacy, data, .. } = request {
+        self.next = 0;
+        self.request = request.digest();
+
+        let mut pager = Pager::new(skip);
+
+        let mut shared = false;
+        let mut delay = false;
+
+        let buf = if let MdnsRequest::Request {
+            legacy,
+            data,
+            known_answers,
+            ..
+        } = request
+        {
             let message = Message::from_octets(data)?;
 
             if !matches!(message.header().opcode(), Opcode::QUERY)
                 || !matches!(message.header().rcode(), Rcode::NOERROR)
-                || message.header().qr()
-            // Not a query but a response
             {
                 return Ok(MdnsResponse::None);
             }
 
+            if message.header().qr() {
+                // Not a query but a response, which might conflict with our unique records
+                if let probe::ProbeCheck::Conflict(conflict) =
+                    probe::check(&self.answers, data, false)?
+                {
+                    warn!("{conflict}");
+
+                    return Err(MdnsError::Conflict(conflict));
+                }
+
+                return Ok(MdnsResponse::None);
+            }
+
             let mut ab = if legacy {
                 set_header(&mut mb, message.header().id(), true);
 
@@ -794,76 +996,117 @@ where
                 mb.answer()
             };
 
-            let mut additional_a = false;
-            let mut additional_srv_txt = false;
-
             for question in message.question() {
                 let question = question?;
 
-                self.answers.visit(|answer| {
-                    if matches!(answer.data(), RecordDataChain::Next(AllRecordData::Srv(_))) {
-                        additional_a = true;
-                    }
+                self.answers.visit(|mut answer| {
+                    if answers_question(&question, &answer) {
+                        if is_known_answer(&message, known_answers, &answer)? {
+                            debug!("Suppressing known answer [{answer}]");
+                        } else if !pager.skip() {
+                            if legacy {
+                                cap_legacy_ttl(&mut answer);
+                            }
 
-                    if !answer.owner().name_eq(&DNS_SD_OWNER)
-                        && matches!(answer.data(), RecordDataChain::Next(AllRecordData::Ptr(_)))
-                    {
-                        additional_a = true;
+                            debug!("Answering question [{question}] with: [{answer}]");
 
-                        // Over-simplifying here in that we'll send all our SRV and TXT records, however
-                        // sending only some SRV and PTR records is too complex to implement.
-                        additional_srv_txt = true;
-                    }
-
-                    if question.qname().name_eq(&answer.owner()) {
-                        debug!("Answering question [{question}] with: [{answer}]");
-
-                        ab.push(answer)?;
+                            if matches!(answer.data(), RecordDataChain::Next(AllRecordData::Ptr(_)))
+                            {
+                                shared = true;
+                            }
 
-                        pushed = true;
+                            pager.push(ab.push(answer))?;
+                        }
                     }
 
                     Ok::<_, MdnsError>(())
                 })?;
             }
 
-            if additional_a || additional_srv_txt {
-                // Fill-in the additional section as well
+            // As per spec, responses containing shared records should be delayed,
+            // because other responders might answer with the same records
+            delay = shared && !legacy;
+
+            let mut aa = ab.additional();
+
+            // The additional records recommended by RFC 6763 §12, which save the querier a few round trips
+            self.answers.visit(|mut answer| {
+                if !is_answer(&message, &answer)?
+                    && is_additional(&self.answers, &message, &answer)?
+                    && !is_known_answer(&message, known_answers, &answer)?
+                    && !pager.skip()
+                {
+                    if legacy {
+                        cap_legacy_ttl(&mut answer);
+                    }
 
-                let mut aa = ab.additional();
+                    debug!("Additional answer: [{answer}]");
 
-                self.answers.visit(|answer| {
-                    if matches!(
-                        answer.data(),
-                        RecordDataChain::Next(AllRecordData::A(_))
-                            | RecordDataChain::Next(AllRecordData::Aaaa(_))
-                            | RecordDataChain::Next(AllRecordData::Srv(_))
-                            | RecordDataChain::Next(AllRecordData::Txt(_))
-                            | RecordDataChain::This(Txt(_))
-                    ) {
-                        debug!("Additional answer: [{answer}]");
+                    pager.push(aa.push(answer))?;
+                }
 
-                        aa.push(answer)?;
+                Ok::<_, MdnsError>(())
+            })?;
+
+            // As per RFC 6762 §6.1, assert the non-existence of the record types we do not have
+            // for our names with NSEC records, so that queriers do not need to wait for them
+            let mut index = 0;
 
-                        pushed = true;
+            self.answers.visit(|answer| {
+                let owner = answer.owner();
+
+                if is_owned(&answer)
+                    && is_first_of_owner(&self.answers, index, owner)?
+                    && needs_nsec(&self.answers, &message, owner)?
+                    && !pager.skip()
+                {
+                    let mut buf = [0; NSEC_BITMAP_LEN];
+                    let bitmap =
+                        RtypeBitmap::from_octets(nsec_bitmap(&self.answers, owner, &mut buf)?)
+                            .map_err(|_| MdnsError::InvalidMessage)?;
+
+                    let mut nsec: HostAnswer = Record::new(
+                        owner.clone(),
+                        Class::IN,
+                        answer.ttl(),
+                        RecordDataChain::Next(AllRecordData::Nsec(Nsec::new(
+                            owner.clone(),
+                            bitmap,
+                        ))),
+                    );
+
+                    if legacy {
+                        cap_legacy_ttl(&mut nsec);
                     }
 
-                    Ok::<_, MdnsError>(())
-                })?;
+                    debug!("Negative answer: [{nsec}]");
 
-                aa.finish()
-            } else {
-                ab.finish()
+                    pager.push(aa.push(nsec))?;
+                }
+
+                index += 1;
+
+                Ok::<_, MdnsError>(())
+            })?;
+
+            if legacy && pager.next.is_some() {
+                // Legacy queriers do not expect multi-packet replies, so - as per spec - just
+                // indicate that the reply is truncated
+                aa.header_mut().set_tc(true);
+
+                pager.next = None;
             }
+
+            aa.finish()
         } else {
             set_header(&mut mb, 0, true);
 
             let mut ab = mb.answer();
 
             self.answers.visit(|answer| {
-                ab.push(answer)?;
-
-                pushed = true;
+                if !pager.skip() {
+                    pager.push(ab.push(answer))?;
+                }
 
                 Ok::<_, MdnsError>(())
             })?;
@@ -871,10 +1114,15 @@ where
             ab.finish()
         };
 
-        if pushed {
+        if let Some(next) = pager.next {
+            self.next = next;
+        }
+
+        if pager.pushed {
             Ok(MdnsResponse::Reply {
                 data: &buf.0[..buf.1],
-                delay: false,
+                delay,
+                more: pager.next.is_some(),
             })
         } else {
             Ok(MdnsResponse::None)
@@ -882,6 +1130,379 @@ where
     }
 }
 
+/// Splits the records of a reply across several packets.
+struct Pager {
+    /// The number of records sent in the previous packets
+    skip: usize,
+    /// The index of the current record
+    index: usize,
+    /// The index of the first record which did not fit in this packet, if any
+    next: Option<usize>,
+    pushed: bool,
+}
+
+impl Pager {
+    const fn new(skip: usize) -> Self {
+        Self {
+            skip,
+            index: 0,
+            next: None,
+            pushed: false,
+        }
+    }
+
+    /// Return `true` if the current record should not be pushed, because it was sent in
+    /// a previous packet, or because this packet is already full.
+    fn skip(&mut self) -> bool {
+        let index = self.index;
+
+        self.index += 1;
+
+        index < self.skip || self.next.is_some()
+    }
+
+    /// Process the result of pushing the current record.
+    ///
+    /// If the record did not fit, it will be the first record of the next packet,
+    /// unless this packet is empty, in which case the record does not fit in any packet.
+    fn push(&mut self, result: Result<(), PushError>) -> Result<(), MdnsError> {
+        match result {
+            Ok(()) => {
+                self.pushed = true;
+
+                Ok(())
+            }
+            Err(PushError::ShortBuf) if self.pushed => {
+                self.next = Some(self.index - 1);
+
+                Ok(())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// Return `true` if the response of another responder contains all answers of our `reply`,
+/// with at least half of their TTL.
+///
+/// In that case our reply is redundant and should not be sent, as per RFC 6762 §7.4.
+pub fn is_duplicate_reply(reply: &[u8], response: &[u8]) -> Result<bool, MdnsError> {
+    let response = Message::from_octets(response)?;
+
+    if !response.header().qr() {
+        return Ok(false);
+    }
+
+    let reply = Message::from_octets(reply)?;
+
+    let mut duplicate = false;
+
+    for ours in reply.answer()? {
+        let Some(ours) = ours?.into_record::<AllRecordData<_, _>>()? else {
+            continue;
+        };
+
+        if !has_known_answer(&response, &ours)? {
+            return Ok(false);
+        }
+
+        duplicate = true;
+    }
+
+    Ok(duplicate)
+}
+
+/// Return `true` if `answer` answers `question`.
+fn answers_question<N>(question: &Question<N>, answer: &HostAnswer) -> bool
+where
+    N: ToName,
+{
+    let class = class_of(question.qclass());
+
+    question.qname().name_eq(answer.owner())
+        && (question.qtype() == Rtype::ANY || question.qtype() == answer.rtype())
+        && (class == Class::IN.to_int() || class == Class::ANY.to_int())
+}
+
+/// Return `true` if `answer` answers one of the questions of `message`.
+fn is_answer(message: &Message<&[u8]>, answer: &HostAnswer) -> Result<bool, MdnsError> {
+    for question in message.question() {
+        if answers_question(&question?, answer) {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Return `true` if `answer` is an additional record recommended by RFC 6763 §12 for the answers
+/// to `message`, i.e.:
+/// - the SRV and TXT records of the service instances in PTR answers
+/// - the A and AAAA records of the targets of SRV answers and SRV additional records
+fn is_additional<T>(
+    answers: &T,
+    message: &Message<&[u8]>,
+    answer: &HostAnswer,
+) -> Result<bool, MdnsError>
+where
+    T: HostAnswers,
+{
+    let mut additional = false;
+
+    match answer.data() {
+        RecordDataChain::This(Txt(_))
+        | RecordDataChain::Next(AllRecordData::Srv(_) | AllRecordData::Txt(_)) => {
+            answers.visit(|other| {
+                if !additional
+                    && matches!(
+                        other.data(),
+                        RecordDataChain::Next(AllRecordData::Ptr(ptr)) if ptr.ptrdname().name_eq(answer.owner())
+                    )
+                    && is_answer(message, &other)?
+                {
+                    additional = true;
+                }
+
+                Ok::<_, MdnsError>(())
+            })?;
+        }
+        RecordDataChain::Next(AllRecordData::A(_) | AllRecordData::Aaaa(_)) => {
+            answers.visit(|other| {
+                if !additional
+                    && matches!(
+                        other.data(),
+                        RecordDataChain::Next(AllRecordData::Srv(srv)) if srv.target().name_eq(answer.owner())
+                    )
+                    && (is_answer(message, &other)? || is_additional(answers, message, &other)?)
+                {
+                    additional = true;
+                }
+
+                Ok::<_, MdnsError>(())
+            })?;
+        }
+        _ => (),
+    }
+
+    Ok(additional)
+}
+
+/// Return `true` if the owner of `answer` is one of our names, i.e. it is not the (shared) name of
+/// a service type, but the host name, a service instance name, or a reverse-mapping name.
+fn is_owned(answer: &HostAnswer) -> bool {
+    !matches!(answer.data(), RecordDataChain::Next(AllRecordData::Ptr(_)))
+        || answer.owner().is_reverse_mapping()
+}
+
+/// Return `true` if the owned answer at `index` is the first one with that owner name.
+fn is_first_of_owner<T>(answers: &T, index: usize, owner: &NameSlice) -> Result<bool, MdnsError>
+where
+    T: HostAnswers,
+{
+    let mut first = true;
+    let mut current = 0;
+
+    answers.visit(|answer| {
+        if current < index && is_owned(&answer) && answer.owner().name_eq(owner) {
+            first = false;
+        }
+
+        current += 1;
+
+        Ok::<_, MdnsError>(())
+    })?;
+
+    Ok(first)
+}
+
+/// Return `true` if the response to `message` should assert the non-existence of the record types
+/// we do not have for `owner`, i.e. if `message` asks for such a type, or if the response contains
+/// only the IPv4 or only the IPv6 addresses of `owner`, because it has no addresses of the other kind.
+fn needs_nsec<T>(
+    answers: &T,
+    message: &Message<&[u8]>,
+    owner: &NameSlice,
+) -> Result<bool, MdnsError>
+where
+    T: HostAnswers,
+{
+    let mut asked = false;
+
+    for question in message.question() {
+        let question = question?;
+
+        if question.qname().name_eq(owner) && question.qtype() != Rtype::ANY {
+            let mut found = false;
+
+            answers.visit(|answer| {
+                if answers_question(&question, &answer) {
+                    found = true;
+                }
+
+                Ok::<_, MdnsError>(())
+            })?;
+
+            asked |= !found;
+        }
+    }
+
+    let mut ipv4 = false;
+    let mut ipv6 = false;
+    let mut included = false;
+
+    answers.visit(|answer| {
+        if answer.owner().name_eq(owner) {
+            match answer.rtype() {
+                Rtype::A => ipv4 = true,
+                Rtype::AAAA => ipv6 = true,
+                _ => return Ok(()),
+            }
+
+            included |= is_answer(message, &answer)? || is_additional(answers, message, &answer)?;
+        }
+
+        Ok::<_, MdnsError>(())
+    })?;
+
+    Ok(asked || (included && !(ipv4 && ipv6)))
+}
+
+/// The length of an mDNS NSEC type bitmap, which has a single window block for the types 0-255,
+/// as per RFC 6762 §6.1.
+const NSEC_BITMAP_LEN: usize = 34;
+
+/// Compose in `buf` the NSEC type bitmap of the types of our records with `owner`.
+fn nsec_bitmap<'b, T>(
+    answers: &T,
+    owner: &NameSlice,
+    buf: &'b mut [u8; NSEC_BITMAP_LEN],
+) -> Result<&'b [u8], MdnsError>
+where
+    T: HostAnswers,
+{
+    let mut len = 0;
+
+    answers.visit(|answer| {
+        let rtype = answer.rtype().to_int() as usize;
+
+        if answer.owner().name_eq(owner) && rtype < 256 {
+            buf[2 + rtype / 8] |= 0x80 >> (rtype % 8);
+            len = len.max(rtype / 8 + 1);
+        }
+
+        Ok::<_, MdnsError>(())
+    })?;
+
+    if len == 0 {
+        return Ok(&[]);
+    }
+
+    // Window block 0
+    buf[0] = 0;
+    buf[1] = len as u8;
+
+    Ok(&buf[..2 + len])
+}
+
+const LEGACY_TTL_SECS: u32 = 10;
+
+/// As per spec, the TTL of answers to legacy requests should not exceed 10 seconds.
+fn cap_legacy_ttl(answer: &mut HostAnswer) {
+    if answer.ttl().as_secs() > LEGACY_TTL_SECS {
+        answer.set_ttl(Ttl::from_secs(LEGACY_TTL_SECS));
+    }
+}
+
+/// Return `true` if the querier listed `answer` in the Known-Answer section of its query,
+/// or of the Known-Answer packets which followed the query (RFC 6762 §7.1 and §7.2).
+fn is_known_answer(
+    message: &Message<&[u8]>,
+    known_answers: &[&[u8]],
+    answer: &HostAnswer,
+) -> Result<bool, MdnsError> {
+    if has_known_answer(message, answer)? {
+        return Ok(true);
+    }
+
+    for data in known_answers {
+        // Invalid Known-Answer packets are not a reason to drop the query
+        let known = Message::from_octets(*data)
+            .map_err(MdnsError::from)
+            .and_then(|message| has_known_answer(&message, answer));
+
+        if matches!(known, Ok(true)) {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+fn has_known_answer<N, D>(
+    message: &Message<&[u8]>,
+    record: &Record<N, D>,
+) -> Result<bool, MdnsError>
+where
+    N: ToName,
+    D: RecordData + ComposeRecordData,
+{
+    for known in message.answer()? {
+        let Some(known) = known?.into_record::<AllRecordData<_, _>>()? else {
+            continue;
+        };
+
+        if known.ttl().as_secs() >= record.ttl().as_secs() / 2 && record_eq(&known, record) {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Return `true` if the two records have the same owner, type, class and data.
+pub(crate) fn record_eq<N, D, O, E>(record: &Record<N, D>, other: &Record<O, E>) -> bool
+where
+    N: ToName,
+    D: RecordData + ComposeRecordData,
+    O: ToName,
+    E: RecordData + ComposeRecordData,
+{
+    if record.rtype() != other.rtype()
+        || class_of(record.class()) != class_of(other.class())
+        || !record.owner().name_eq(other.owner())
+    {
+        return false;
+    }
+
+    let mut buf = [0; MAX_RDATA_LEN];
+    let mut other_buf = [0; MAX_RDATA_LEN];
+
+    compose_rdata(record.data(), &mut buf) == compose_rdata(other.data(), &mut other_buf)
+}
+
+const MAX_RDATA_LEN: usize = 256;
+
+/// Compose the canonical form of `data` into `buf`.
+///
+/// If `buf` is too short, only the prefix which fits is composed. This is good enough for comparisons.
+pub(crate) fn compose_rdata<'b, D>(data: &D, buf: &'b mut [u8]) -> &'b [u8]
+where
+    D: ComposeRecordData,
+{
+    let mut target = Buf(&mut *buf, 0);
+
+    let _ = data.compose_canonical_rdata(&mut target);
+
+    let len = target.1;
+
+    &buf[..len]
+}
+
+/// The class without the mDNS cache-flush (or unicast-response) bit.
+pub(crate) fn class_of(class: Class) -> u16 {
+    class.to_int() & 0x7fff
+}
+
 /// A type alias for the answer which is expected to be returned by instances
 /// implementing the `PeerAnswers` trait.
 pub type PeerAnswer<'a> =
@@ -1016,3 +1637,528 @@ pub fn set_header<T: Composer>(answer: &mut MessageBuilder<T>, id: u16, response
     flags.aa = response;
     header.set_flags(flags);
 }
+
+#[cfg(test)]
+mod test {
+    use crate::fixtures::{a, host, query, sensor, HOSTNAME};
+    use crate::host::{Host, ServiceAnswers};
+
+    use super::*;
+
+    fn request<'a>(data: &'a [u8], known_answers: &'a [&'a [u8]]) -> MdnsRequest<'a> {
+        MdnsRequest::Request {
+            legacy: false,
+            multicast: true,
+            data,
+            known_answers,
+        }
+    }
+
+    /// Handle `request` with the provided answers, returning the reply, if any
+    fn handle<'a, T>(answers: T, request: MdnsRequest<'_>, buf: &'a mut [u8]) -> &'a [u8]
+    where
+        T: HostAnswers,
+    {
+        match HostAnswersMdnsHandler::new(answers)
+            .handle(request, buf)
+            .unwrap()
+        {
+            MdnsResponse::Reply { data, .. } => data,
+            MdnsResponse::None => &[],
+        }
+    }
+
+    /// The TTLs of the records in the answer section of `data`
+    fn ttls(data: &[u8]) -> heapless::Vec<u32, 16> {
+        Message::from_octets(data)
+            .unwrap()
+            .answer()
+            .unwrap()
+            .map(|record| record.unwrap().ttl().as_secs())
+            .collect()
+    }
+
+    /// The types of the records in the answer and the additional sections of `data`
+    fn rtypes(data: &[u8]) -> (heapless::Vec<Rtype, 16>, heapless::Vec<Rtype, 16>) {
+        let message = Message::from_octets(data).unwrap();
+
+        let answer = message
+            .answer()
+            .unwrap()
+            .map(|record| record.unwrap().rtype())
+            .collect();
+
+        let additional = message
+            .additional()
+            .unwrap()
+            .map(|record| record.unwrap().rtype())
+            .collect();
+
+        (answer, additional)
+    }
+
+    #[test]
+    fn test_announce() {
+        let mut buf = [0; 1500];
+        let data = handle(host(), MdnsRequest::None, &mut buf);
+
+        let message = Message::from_octets(data).unwrap();
+        assert!(message.header().qr());
+        assert_eq!(message.header().id(), 0);
+
+        // The A and AAAA records, and their reverse-mapping PTR records
+        assert_eq!(ttls(data), [60, 60, 60, 60]);
+    }
+
+    #[test]
+    fn test_goodbye() {
+        let mut buf = [0; 1500];
+        let data = handle(GoodbyeHostAnswers::new(host()), MdnsRequest::None, &mut buf);
+
+        assert_eq!(ttls(data), [0, 0, 0, 0]);
+
+        // Goodbyes are sent for the chained answers as well
+        let answers = NoHostAnswers
+            .chain(host())
+            .chain(GoodbyeHostAnswers::new(host()));
+        let data = handle(answers, MdnsRequest::None, &mut buf);
+
+        assert_eq!(ttls(data), [0, 0, 0, 0, 60, 60, 60, 60]);
+    }
+
+    #[test]
+    fn test_known_answers() {
+        let questions = [Question::new(HOSTNAME, Rtype::A, Class::IN)];
+
+        let mut query_buf = [0; 1500];
+        let mut buf = [0; 1500];
+
+        let data = query(&questions, &[], &mut query_buf);
+        assert_eq!(ttls(handle(host(), request(data, &[]), &mut buf)), [60]);
+
+        // The querier already knows our answer
+        let data = query(&questions, &[a(60)], &mut query_buf);
+        assert!(handle(host(), request(data, &[]), &mut buf).is_empty());
+
+        // ... but it is about to expire
+        let data = query(&questions, &[a(29)], &mut query_buf);
+        assert_eq!(ttls(handle(host(), request(data, &[]), &mut buf)), [60]);
+
+        // The known answer is in a Known-Answer packet following the query
+        let mut known_buf = [0; 1500];
+        let known = query(&[], &[a(30)], &mut known_buf);
+
+        let data = query(&questions, &[], &mut query_buf);
+        assert!(handle(host(), request(data, &[known]), &mut buf).is_empty());
+
+        // Invalid Known-Answer packets are ignored
+        assert_eq!(
+            ttls(handle(host(), request(data, &[&[0xff; 5]]), &mut buf)),
+            [60]
+        );
+    }
+
+    #[test]
+    fn test_duplicate_reply() {
+        let mut query_buf = [0; 1500];
+        let mut buf = [0; 1500];
+        let mut other_buf = [0; 1500];
+
+        let data = query(
+            &[Question::new(HOSTNAME, Rtype::A, Class::IN)],
+            &[],
+            &mut query_buf,
+        );
+        let reply = handle(host(), request(data, &[]), &mut buf);
+
+        // Another responder announcing the same records
+        let response = handle(host(), MdnsRequest::None, &mut other_buf);
+        assert!(is_duplicate_reply(reply, response).unwrap());
+
+        // ... which are about to expire
+        let mut other = host();
+        other.ttl = Ttl::from_secs(20);
+
+        let response = handle(other, MdnsRequest::None, &mut other_buf);
+        assert!(!is_duplicate_reply(reply, response).unwrap());
+
+        // ... or other records
+        let mut other = host();
+        other.ipv4 = &[];
+
+        let response = handle(other, MdnsRequest::None, &mut other_buf);
+        assert!(!is_duplicate_reply(reply, response).unwrap());
+
+        // A query with our records as known answers is not a reply
+        let response = query(&[], &[a(60)], &mut other_buf);
+        assert!(!is_duplicate_reply(reply, response).unwrap());
+    }
+
+    #[test]
+    fn test_unicast_reply() {
+        let qm = Question::new(HOSTNAME, Rtype::A, Class::IN);
+        let qu = Question::new(HOSTNAME, Rtype::AAAA, Class::from_int(1 | probe::QU_BIT));
+
+        let mut buf = [0; 1500];
+
+        assert!(!MdnsRequest::None.unicast_reply());
+
+        let data = query(&[qm.clone()], &[], &mut buf);
+        assert!(!request(data, &[]).unicast_reply());
+
+        // Legacy and direct unicast queries
+        let legacy = MdnsRequest::Request {
+            legacy: true,
+            multicast: true,
+            data,
+            known_answers: &[],
+        };
+        assert!(legacy.unicast_reply());
+
+        let direct = MdnsRequest::Request {
+            legacy: false,
+            multicast: false,
+            data,
+            known_answers: &[],
+        };
+        assert!(direct.unicast_reply());
+
+        // QU queries, unless only some of the questions have the QU bit set
+        let data = query(&[qu.clone()], &[], &mut buf);
+        assert!(request(data, &[]).unicast_reply());
+
+        let data = query(&[qu, qm], &[], &mut buf);
+        assert!(!request(data, &[]).unicast_reply());
+
+        // Responses are never replied to
+        let data = handle(host(), MdnsRequest::None, &mut buf);
+        assert!(!request(data, &[]).unicast_reply());
+    }
+
+    #[test]
+    fn test_legacy_reply() {
+        let mut query_buf = [0; 1500];
+        let mut buf = [0; 1500];
+
+        let len = query(
+            &[Question::new(HOSTNAME, Rtype::A, Class::IN)],
+            &[],
+            &mut query_buf,
+        )
+        .len();
+
+        // A legacy querier uses its own query IDs
+        query_buf[..2].copy_from_slice(&0x1234_u16.to_be_bytes());
+
+        let request = MdnsRequest::Request {
+            legacy: true,
+            multicast: true,
+            data: &query_buf[..len],
+            known_answers: &[],
+        };
+
+        let data = handle(host(), request, &mut buf);
+
+        let message = Message::from_octets(data).unwrap();
+        assert_eq!(message.header().id(), 0x1234);
+        assert_eq!(message.header_counts().qdcount(), 1);
+
+        // ... and does not expect TTLs above 10 seconds
+        assert_eq!(ttls(data), [10]);
+    }
+
+    #[test]
+    fn test_nsec_bitmap() {
+        let mut buf = [0; NSEC_BITMAP_LEN];
+        assert_eq!(
+            nsec_bitmap(&host(), &HOSTNAME, &mut buf).unwrap(),
+            [0, 4, 0x40, 0, 0, 0x08]
+        );
+
+        let mut buf = [0; NSEC_BITMAP_LEN];
+        let ipv4_host = Host {
+            ipv6: &[],
+            ..host()
+        };
+        assert_eq!(
+            nsec_bitmap(&ipv4_host, &HOSTNAME, &mut buf).unwrap(),
+            [0, 1, 0x40]
+        );
+
+        let mut buf = [0; NSEC_BITMAP_LEN];
+        let reverse = NameSlice::new(&["1", "0", "168", "192", "in-addr", "arpa"]);
+        assert_eq!(
+            nsec_bitmap(&ipv4_host, &reverse, &mut buf).unwrap(),
+            [0, 2, 0, 0x08]
+        );
+
+        let mut buf = [0; NSEC_BITMAP_LEN];
+        let other = NameSlice::new(&["other", "local"]);
+        assert!(nsec_bitmap(&ipv4_host, &other, &mut buf)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_nsec() {
+        let mut query_buf = [0; 1500];
+        let mut buf = [0; 1500];
+
+        let qa = [Question::new(HOSTNAME, Rtype::A, Class::IN)];
+        let qaaaa = [Question::new(HOSTNAME, Rtype::AAAA, Class::IN)];
+
+        // We have both kinds of addresses
+        let data = query(&qa, &[], &mut query_buf);
+        let (answer, additional) = rtypes(handle(host(), request(data, &[]), &mut buf));
+        assert_eq!(answer, [Rtype::A]);
+        assert!(additional.is_empty());
+
+        // We have IPv4 addresses only
+        let ipv4_host = Host {
+            ipv6: &[],
+            ..host()
+        };
+
+        let data = query(&qa, &[], &mut query_buf);
+        let (answer, additional) = rtypes(handle(&ipv4_host, request(data, &[]), &mut buf));
+        assert_eq!(answer, [Rtype::A]);
+        assert_eq!(additional, [Rtype::NSEC]);
+
+        // ... so the absence of the AAAA records is asserted with an NSEC record
+        let data = query(&qaaaa, &[], &mut query_buf);
+        let (answer, additional) = rtypes(handle(&ipv4_host, request(data, &[]), &mut buf));
+        assert!(answer.is_empty());
+        assert_eq!(additional, [Rtype::NSEC]);
+    }
+
+    #[test]
+    fn test_additional() {
+        let host = host();
+        let service = sensor();
+
+        let answers = ChainedHostAnswers::new(&host, ServiceAnswers::new(&host, &service));
+
+        let mut query_buf = [0; 1500];
+        let mut buf = [0; 1500];
+
+        let questions = [Question::new(
+            NameSlice::new(&["_http", "_tcp", "local"]),
+            Rtype::PTR,
+            Class::IN,
+        )];
+
+        // The SRV and TXT records of the instance, and the addresses of its host
+        let data = query(&questions, &[], &mut query_buf);
+        let (answer, additional) = rtypes(handle(&answers, request(data, &[]), &mut buf));
+        assert_eq!(answer, [Rtype::PTR]);
+        assert_eq!(additional, [Rtype::A, Rtype::AAAA, Rtype::SRV, Rtype::TXT]);
+
+        // ... unless the querier knows them already
+        let data = query(&questions, &[a(60)], &mut query_buf);
+        let (answer, additional) = rtypes(handle(&answers, request(data, &[]), &mut buf));
+        assert_eq!(answer, [Rtype::PTR]);
+        assert_eq!(additional, [Rtype::AAAA, Rtype::SRV, Rtype::TXT]);
+    }
+
+    #[test]
+    fn test_split() {
+        let host = host();
+        let service = sensor();
+
+        let mut handler = HostAnswersMdnsHandler::new(ChainedHostAnswers::new(
+            &host,
+            ServiceAnswers::new(&host, &service),
+        ));
+
+        // Too small for all records of the host and the service
+        let mut buf = [0; 150];
+
+        let mut packets = 0;
+        let mut records = 0;
+
+        loop {
+            let MdnsResponse::Reply { data, more, .. } =
+                handler.handle(MdnsRequest::None, &mut buf).unwrap()
+            else {
+                panic!("Expected a reply");
+            };
+
+            packets += 1;
+            records += ttls(data).len();
+
+            if !more {
+                break;
+            }
+        }
+
+        // Each record was sent exactly once
+        assert!(packets > 1);
+        assert_eq!(records, 8);
+
+        // The next reply starts from the first record again
+        let MdnsResponse::Reply { data, .. } = handler.handle(MdnsRequest::None, &mut buf).unwrap()
+        else {
+            panic!("Expected a reply");
+        };
+
+        assert_eq!(rtypes(data).0[0], Rtype::A);
+    }
+
+    #[test]
+    fn test_split_reply() {
+        let host = host();
+        let service = sensor();
+
+        let mut handler = HostAnswersMdnsHandler::new(ChainedHostAnswers::new(
+            &host,
+            ServiceAnswers::new(&host, &service),
+        ));
+
+        let mut query_buf = [0; 1500];
+        let mut buf = [0; 150];
+
+        let data = query(
+            &[Question::new(
+                NameSlice::new(&["_http", "_tcp", "local"]),
+                Rtype::PTR,
+                Class::IN,
+            )],
+            &[],
+            &mut query_buf,
+        );
+
+        // The answers come first, followed by the additional records
+        let MdnsResponse::Reply {
+            data: reply, more, ..
+        } = handler.handle(request(data, &[]), &mut buf).unwrap()
+        else {
+            panic!("Expected a reply");
+        };
+
+        assert!(more);
+
+        let (answer, additional) = rtypes(reply);
+        assert_eq!(answer, [Rtype::PTR]);
+        assert_eq!(additional, [Rtype::A, Rtype::AAAA]);
+
+        let MdnsResponse::Reply {
+            data: reply, more, ..
+        } = handler.handle(request(data, &[]), &mut buf).unwrap()
+        else {
+            panic!("Expected a reply");
+        };
+
+        assert!(!more);
+
+        let (answer, additional) = rtypes(reply);
+        assert!(answer.is_empty());
+        assert_eq!(additional, [Rtype::SRV, Rtype::TXT]);
+    }
+
+    #[test]
+    fn test_split_abandoned() {
+        let host = host();
+        let service = sensor();
+
+        let mut handler = HostAnswersMdnsHandler::new(ChainedHostAnswers::new(
+            &host,
+            ServiceAnswers::new(&host, &service),
+        ));
+
+        let mut ptr_query_buf = [0; 1500];
+        let mut a_query_buf = [0; 1500];
+        let mut buf = [0; 150];
+
+        let ptr_query = query(
+            &[Question::new(
+                NameSlice::new(&["_http", "_tcp", "local"]),
+                Rtype::PTR,
+                Class::IN,
+            )],
+            &[],
+            &mut ptr_query_buf,
+        );
+
+        let a_query = query(
+            &[Question::new(HOSTNAME, Rtype::A, Class::IN)],
+            &[],
+            &mut a_query_buf,
+        );
+
+        let MdnsResponse::Reply { more, .. } =
+            handler.handle(request(ptr_query, &[]), &mut buf).unwrap()
+        else {
+            panic!("Expected a reply");
+        };
+
+        assert!(more);
+
+        // The rest of the reply is never asked for, so another request is answered in full
+        let MdnsResponse::Reply {
+            data: reply, more, ..
+        } = handler.handle(request(a_query, &[]), &mut buf).unwrap()
+        else {
+            panic!("Expected a reply");
+        };
+
+        assert!(!more);
+        assert_eq!(rtypes(reply).0, [Rtype::A]);
+
+        // ... and so is the abandoned request when it comes again
+        let MdnsResponse::Reply {
+            data: reply, more, ..
+        } = handler.handle(request(ptr_query, &[]), &mut buf).unwrap()
+        else {
+            panic!("Expected a reply");
+        };
+
+        assert!(more);
+        assert_eq!(rtypes(reply).0, [Rtype::PTR]);
+    }
+
+    #[test]
+    fn test_legacy_truncated() {
+        let host = host();
+        let service = sensor();
+
+        let mut handler = HostAnswersMdnsHandler::new(ChainedHostAnswers::new(
+            &host,
+            ServiceAnswers::new(&host, &service),
+        ));
+
+        let mut query_buf = [0; 1500];
+        let mut buf = [0; 150];
+
+        let data = query(
+            &[Question::new(
+                NameSlice::new(&["_http", "_tcp", "local"]),
+                Rtype::PTR,
+                Class::IN,
+            )],
+            &[],
+            &mut query_buf,
+        );
+
+        let request = MdnsRequest::Request {
+            legacy: true,
+            multicast: true,
+            data,
+            known_answers: &[],
+        };
+
+        // Legacy queriers get a single, truncated packet
+        let MdnsResponse::Reply {
+            data: reply, more, ..
+        } = handler.handle(request, &mut buf).unwrap()
+        else {
+            panic!("Expected a reply");
+        };
+
+        assert!(!more);
+        assert!(Message::from_octets(reply).unwrap().header().tc());
+
+        let (answer, additional) = rtypes(reply);
+        assert_eq!(answer, [Rtype::PTR]);
+        assert_eq!(additional, [Rtype::A]);
+    }
+}
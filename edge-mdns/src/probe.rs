@@ -0,0 +1,508 @@
+//! Probing and conflict resolution for the unique names of a host and its services,
+//! as per RFC 6762 §8 and §9.
+//!
+//! The unique records are all records of a `HostAnswers` entity except the (shared) PTR records,
+//! i.e. the A and AAAA records of the host name, and the SRV and TXT records of the service instance names.
+
+use core::cmp::Ordering;
+use core::fmt::{self, Write};
+
+use domain::base::iana::{Class, Opcode, Rcode};
+use domain::base::rdata::ComposeRecordData;
+use domain::base::{Message, MessageBuilder, Question, Record, RecordData, Rtype, ToName};
+use domain::rdata::AllRecordData;
+
+use crate::{
+    class_of, compose_rdata, record_eq, set_header, Buf, HostAnswer, HostAnswers, MdnsError,
+    NameSlice, RecordDataChain, MAX_RDATA_LEN,
+};
+
+/// The number of probe queries to send before a name is considered unique.
+pub const PROBE_COUNT: usize = 3;
+/// The interval between two probe queries, in milliseconds.
+pub const PROBE_INTERVAL_MS: u64 = 250;
+/// How long to wait before probing again after losing a simultaneous probe tie-break, in milliseconds.
+pub const PROBE_DEFER_MS: u64 = 1000;
+
+/// The maximum length of a DNS label.
+pub const MAX_LABEL_LEN: usize = 63;
+
+/// The "unicast response" bit in the class of a question.
+pub const QU_BIT: u16 = 0x8000;
+
+const MAX_RECORDS: usize = 16;
+
+/// A single DNS label, like the host name or the name of a service instance.
+pub type Name = heapless::String<MAX_LABEL_LEN>;
+
+/// A unique name of ours which is already used by another host on the network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict(Name);
+
+impl Conflict {
+    /// The conflicting name, i.e. the host name or the service instance name, without the `.local` suffix.
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+
+    /// Return the next name to try instead of the conflicting one,
+    /// i.e. "device" is renamed to "device-2", and "device-2" to "device-3".
+    ///
+    /// Names which are too long are shortened, so that the suffix still fits in a label.
+    pub fn rename(&self) -> Result<Name, MdnsError> {
+        let (base, n) = match self.0.rsplit_once('-') {
+            Some((base, n))
+                if !n.starts_with('0')
+                    && !n.is_empty()
+                    && n.bytes().all(|b| b.is_ascii_digit()) =>
+            {
+                match n.parse::<u32>().ok().and_then(|n| n.checked_add(1)) {
+                    Some(n) => (base, n),
+                    // The counter cannot be incremented, so start a new one
+                    None => (self.name(), 2),
+                }
+            }
+            _ => (self.name(), 2),
+        };
+
+        let mut suffix = heapless::String::<11>::new();
+        write!(suffix, "-{n}").map_err(|_| MdnsError::ShortBuf)?;
+
+        // Shorten the base if necessary, so that the renamed name still fits in a label
+        let mut len = base.len().min(MAX_LABEL_LEN - suffix.len());
+        while !base.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        let mut name = Name::new();
+        name.push_str(&base[..len])
+            .and_then(|_| name.push_str(&suffix))
+            .map_err(|_| MdnsError::ShortBuf)?;
+
+        Ok(name)
+    }
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Name conflict: {}", self.0)
+    }
+}
+
+/// The outcome of `check`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProbeCheck {
+    /// The message does not conflict with our unique records
+    None,
+    /// Another host uses one of our unique names
+    Conflict(Conflict),
+    /// Another host is probing for one of our unique names at the same time, and won the tie-break.
+    /// Probing should restart after `PROBE_DEFER_MS`
+    Lost,
+}
+
+/// Construct a probe query in `buf` for the unique names of `answers`, returning its length.
+///
+/// The query contains an ANY question for each unique name, and the proposed unique records
+/// in the authority section. `unicast` should be `true` for the first probe query only.
+///
+/// Return 0 if `answers` has no unique records.
+pub fn probe_query<T>(
+    answers: &T,
+    id: u16,
+    unicast: bool,
+    buf: &mut [u8],
+) -> Result<usize, MdnsError>
+where
+    T: HostAnswers,
+{
+    let buf = Buf(buf, 0);
+
+    let mut mb = MessageBuilder::from_target(buf)?;
+
+    set_header(&mut mb, id, false);
+
+    let mut qb = mb.question();
+
+    let class = if unicast {
+        Class::from_int(Class::IN.to_int() | QU_BIT)
+    } else {
+        Class::IN
+    };
+
+    let mut pushed = false;
+    let mut index = 0;
+
+    answers.visit(|answer| {
+        if is_unique(&answer) && first_of_owner(answers, index, answer.owner())? {
+            qb.push(Question::new(answer.owner().clone(), Rtype::ANY, class))?;
+
+            pushed = true;
+        }
+
+        index += 1;
+
+        Ok::<_, MdnsError>(())
+    })?;
+
+    let mut ab = qb.authority();
+
+    answers.visit(|answer| {
+        if is_unique(&answer) {
+            ab.push(answer)?;
+        }
+
+        Ok::<_, MdnsError>(())
+    })?;
+
+    let buf = ab.finish();
+
+    if pushed {
+        Ok(buf.1)
+    } else {
+        Ok(0)
+    }
+}
+
+/// Check an incoming mDNS message for conflicts with the unique records of `answers`.
+///
+/// Responses conflict if they contain a record with one of our unique names, class and type, but different data.
+/// While `probing`, probe queries from other hosts for the same names are resolved with the tie-breaking
+/// of RFC 6762 §8.2, in which the host with the lexicographically later records wins.
+pub fn check<T>(answers: &T, data: &[u8], probing: bool) -> Result<ProbeCheck, MdnsError>
+where
+    T: HostAnswers,
+{
+    let message = Message::from_octets(data)?;
+
+    if !matches!(message.header().opcode(), Opcode::QUERY)
+        || !matches!(message.header().rcode(), Rcode::NOERROR)
+    {
+        return Ok(ProbeCheck::None);
+    }
+
+    if message.header().qr() {
+        for record in message.answer()?.chain(message.additional()?) {
+            let Some(record) = record?.into_record::<AllRecordData<_, _>>()? else {
+                continue;
+            };
+
+            if let Some(conflict) = conflict(answers, &record)? {
+                return Ok(ProbeCheck::Conflict(conflict));
+            }
+        }
+    } else if probing && message.header_counts().nscount() > 0 {
+        let mut lost = false;
+        let mut index = 0;
+
+        answers.visit(|answer| {
+            if !lost
+                && is_unique(&answer)
+                && first_of_owner(answers, index, answer.owner())?
+                && tie_break(answers, &message, answer.owner())? == Ordering::Less
+            {
+                lost = true;
+            }
+
+            index += 1;
+
+            Ok::<_, MdnsError>(())
+        })?;
+
+        if lost {
+            return Ok(ProbeCheck::Lost);
+        }
+    }
+
+    Ok(ProbeCheck::None)
+}
+
+fn is_unique(answer: &HostAnswer) -> bool {
+    !matches!(answer.data(), RecordDataChain::Next(AllRecordData::Ptr(_)))
+}
+
+/// Return `true` if the unique answer at `index` is the first one with that owner name.
+fn first_of_owner<T>(answers: &T, index: usize, owner: &NameSlice) -> Result<bool, MdnsError>
+where
+    T: HostAnswers,
+{
+    let mut first = true;
+    let mut current = 0;
+
+    answers.visit(|answer| {
+        if current < index && is_unique(&answer) && answer.owner().name_eq(owner) {
+            first = false;
+        }
+
+        current += 1;
+
+        Ok::<_, MdnsError>(())
+    })?;
+
+    Ok(first)
+}
+
+fn conflict<T, N, D>(answers: &T, record: &Record<N, D>) -> Result<Option<Conflict>, MdnsError>
+where
+    T: HostAnswers,
+    N: ToName,
+    D: RecordData + ComposeRecordData,
+{
+    let mut conflict = None;
+    let mut equal = false;
+
+    answers.visit(|answer| {
+        if is_unique(&answer)
+            && answer.rtype() == record.rtype()
+            && class_of(answer.class()) == class_of(record.class())
+            && answer.owner().name_eq(record.owner())
+        {
+            if record_eq(&answer, record) {
+                equal = true;
+            } else if conflict.is_none() {
+                let mut name = Name::new();
+                name.push_str(answer.owner().0.first().copied().unwrap_or(""))
+                    .map_err(|_| MdnsError::ShortBuf)?;
+
+                conflict = Some(Conflict(name));
+            }
+        }
+
+        Ok::<_, MdnsError>(())
+    })?;
+
+    // Our own records (e.g. looped back to us) do not conflict
+    Ok(conflict.filter(|_| !equal))
+}
+
+/// Compare our unique records with `owner` to the records with `owner` in the authority section of the
+/// probe query of another host, returning `Ordering::Less` if we lose.
+///
+/// Both sets of records are sorted by class and type. For simplicity, records with the same class and type
+/// are compared in the order in which they appear.
+fn tie_break<T>(
+    answers: &T,
+    message: &Message<&[u8]>,
+    owner: &NameSlice,
+) -> Result<Ordering, MdnsError>
+where
+    T: HostAnswers,
+{
+    let mut theirs = heapless::Vec::<(u32, usize), MAX_RECORDS>::new();
+
+    for (index, record) in message.authority()?.enumerate() {
+        let record = record?;
+
+        if record.owner().name_eq(owner) {
+            let _ = theirs.push((sort_key(record.class(), record.rtype()), index));
+        }
+    }
+
+    if theirs.is_empty() {
+        return Ok(Ordering::Equal);
+    }
+
+    let mut ours = heapless::Vec::<(u32, usize), MAX_RECORDS>::new();
+    let mut index = 0;
+
+    answers.visit(|answer| {
+        if is_unique(&answer) && answer.owner().name_eq(owner) {
+            let _ = ours.push((sort_key(answer.class(), answer.rtype()), index));
+        }
+
+        index += 1;
+
+        Ok::<_, MdnsError>(())
+    })?;
+
+    ours.sort_unstable();
+    theirs.sort_unstable();
+
+    for ((our_key, our_index), (their_key, their_index)) in ours.iter().zip(theirs.iter()) {
+        let mut ordering = our_key.cmp(their_key);
+
+        if ordering == Ordering::Equal {
+            let mut our_buf = [0; MAX_RDATA_LEN];
+            let mut their_buf = [0; MAX_RDATA_LEN];
+
+            let ours = our_rdata(answers, *our_index, &mut our_buf)?;
+            let theirs = their_rdata(message, *their_index, &mut their_buf)?;
+
+            ordering = ours.cmp(theirs);
+        }
+
+        if ordering != Ordering::Equal {
+            return Ok(ordering);
+        }
+    }
+
+    Ok(ours.len().cmp(&theirs.len()))
+}
+
+fn our_rdata<'b, T>(answers: &T, index: usize, buf: &'b mut [u8]) -> Result<&'b [u8], MdnsError>
+where
+    T: HostAnswers,
+{
+    let mut current = 0;
+    let mut len = 0;
+
+    answers.visit(|answer| {
+        if current == index {
+            len = compose_rdata(answer.data(), buf).len();
+        }
+
+        current += 1;
+
+        Ok::<_, MdnsError>(())
+    })?;
+
+    Ok(&buf[..len])
+}
+
+fn their_rdata<'b>(
+    message: &Message<&[u8]>,
+    index: usize,
+    buf: &'b mut [u8],
+) -> Result<&'b [u8], MdnsError> {
+    let record = message
+        .authority()?
+        .nth(index)
+        .ok_or(MdnsError::InvalidMessage)??;
+
+    match record.into_record::<AllRecordData<_, _>>()? {
+        Some(record) => Ok(compose_rdata(record.data(), buf)),
+        None => Ok(&[]),
+    }
+}
+
+fn sort_key(class: Class, rtype: Rtype) -> u32 {
+    ((class_of(class) as u32) << 16) | rtype.to_int() as u32
+}
+
+#[cfg(test)]
+mod test {
+    use core::net::Ipv4Addr;
+
+    use crate::fixtures::{announce, myhost};
+    use crate::host::Host;
+
+    use super::*;
+
+    const IP1: Ipv4Addr = Ipv4Addr::new(192, 168, 0, 1);
+    const IP2: Ipv4Addr = Ipv4Addr::new(192, 168, 0, 2);
+    const IP3: Ipv4Addr = Ipv4Addr::new(192, 168, 0, 3);
+
+    const LONG_NAME: &str = "abcdefghijklmnopqrstuvwxyzabcdefghijklmnopqrstuvwxyzabcdefghijk";
+
+    fn rename(name: &str) -> Name {
+        Conflict(name.try_into().unwrap()).rename().unwrap()
+    }
+
+    #[test]
+    fn test_rename() {
+        assert_eq!(rename("device"), "device-2");
+        assert_eq!(rename("device-2"), "device-3");
+        assert_eq!(rename("device-41"), "device-42");
+        assert_eq!(rename("dev-01"), "dev-01-2");
+        assert_eq!(rename("device-"), "device--2");
+        assert_eq!(rename("device-4294967295"), "device-4294967295-2");
+
+        assert_eq!(LONG_NAME.len(), MAX_LABEL_LEN);
+
+        // The name is shortened so that the suffix fits
+        let renamed = rename(LONG_NAME);
+        assert_eq!(renamed.len(), MAX_LABEL_LEN);
+        assert_eq!(
+            renamed.split_at(MAX_LABEL_LEN - 2),
+            (&LONG_NAME[..MAX_LABEL_LEN - 2], "-2")
+        );
+
+        let renamed = rename(&renamed);
+        assert_eq!(renamed.len(), MAX_LABEL_LEN);
+        assert_eq!(
+            renamed.split_at(MAX_LABEL_LEN - 2),
+            (&LONG_NAME[..MAX_LABEL_LEN - 2], "-3")
+        );
+    }
+
+    #[test]
+    fn test_probe_query() {
+        let ours = myhost(&[IP1]);
+
+        let mut buf = [0; 1500];
+        let len = probe_query(&ours, 0, true, &mut buf).unwrap();
+
+        let message = Message::from_octets(&buf[..len]).unwrap();
+
+        assert!(!message.header().qr());
+        assert_eq!(message.header_counts().qdcount(), 1);
+        assert_eq!(message.header_counts().nscount(), 1);
+
+        let question = message.first_question().unwrap();
+        assert_eq!(question.qtype(), Rtype::ANY);
+        assert_eq!(question.qclass().to_int(), Class::IN.to_int() | QU_BIT);
+
+        // Nothing to probe for hosts without addresses
+        assert_eq!(probe_query(&myhost(&[]), 0, true, &mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_conflict() {
+        let ours = myhost(&[IP1]);
+
+        let mut buf = [0; 1500];
+
+        // Another host announcing our name with a different address
+        let theirs = myhost(&[IP2]);
+        let data = announce(&theirs, &mut buf);
+
+        let ProbeCheck::Conflict(conflict) = check(&ours, data, false).unwrap() else {
+            panic!("No conflict");
+        };
+        assert_eq!(conflict.name(), "myhost");
+        assert_eq!(
+            check(&ours, data, true).unwrap(),
+            ProbeCheck::Conflict(conflict)
+        );
+
+        // Our own announcement, looped back to us
+        let data = announce(&ours, &mut buf);
+        assert_eq!(check(&ours, data, false).unwrap(), ProbeCheck::None);
+
+        // Another host with another name
+        let data = announce(
+            &Host {
+                hostname: "other",
+                ..myhost(&[IP2])
+            },
+            &mut buf,
+        );
+        assert_eq!(check(&ours, data, false).unwrap(), ProbeCheck::None);
+    }
+
+    #[test]
+    fn test_tie_break() {
+        let ours = myhost(&[IP2]);
+
+        let mut buf = [0; 1500];
+
+        // The other host proposes a lexicographically later address, so it wins
+        let theirs = myhost(&[IP3]);
+        let len = probe_query(&theirs, 0, false, &mut buf).unwrap();
+
+        assert_eq!(check(&ours, &buf[..len], true).unwrap(), ProbeCheck::Lost);
+        // Tie-breaks only apply while probing
+        assert_eq!(check(&ours, &buf[..len], false).unwrap(), ProbeCheck::None);
+
+        // ... and here we win
+        let theirs = myhost(&[IP1]);
+        let len = probe_query(&theirs, 0, false, &mut buf).unwrap();
+
+        assert_eq!(check(&ours, &buf[..len], true).unwrap(), ProbeCheck::None);
+
+        // Our own probe query, looped back to us
+        let len = probe_query(&ours, 0, false, &mut buf).unwrap();
+
+        assert_eq!(check(&ours, &buf[..len], true).unwrap(), ProbeCheck::None);
+    }
+}
@@ -0,0 +1,433 @@
+//! A fixed-capacity registry of the DNS-SD services of a host, which can be changed while the mDNS responder is running.
+//!
+//! The handler returned by `ServiceRegistry::handler` answers with the records of the host and of the registered
+//! services, and should be passed to `io::Mdns::run`. Adding, updating or removing a service signals the broadcast
+//! signal of the responder, which then announces the changed services, or sends goodbyes for the removed ones.
+//!
+//! The registry does not probe the services it announces. The instance name of a new service should be probed
+//! with `io::Mdns::probe` (e.g. with `ServiceAnswers::new(host, &service)`) before the service is added.
+//! Probing does not require stopping `io::Mdns::run`, which hands over its receiver to `io::Mdns::probe`
+//! until probing is done.
+
+use core::cell::RefCell;
+use core::fmt;
+
+use embassy_sync::blocking_mutex::{self, raw::RawMutex};
+use embassy_sync::signal::Signal;
+
+use embassy_time::{Duration, Instant};
+
+use crate::host::{Host, Service, ServiceAnswers};
+use crate::{
+    ChainedHostAnswers, GoodbyeHostAnswers, HostAnswer, HostAnswers, HostAnswersMdnsHandler,
+    MdnsError, MdnsHandler, MdnsRequest, MdnsResponse,
+};
+
+/// For how long the answers of a removed service are still provided (with a TTL of 0),
+/// so that the announcements triggered by the removal carry the goodbyes of the service.
+pub const GOODBYE_DURATION: Duration = Duration::from_secs(5);
+
+/// An error returned by the `ServiceRegistry` methods.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RegistryError {
+    /// All slots of the registry are occupied
+    Full,
+    /// A service with the same name, type and protocol is already registered
+    Duplicate,
+    /// No service with the provided name, type and protocol is registered
+    NotFound,
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full => write!(f, "Registry is full"),
+            Self::Duplicate => write!(f, "Service already registered"),
+            Self::NotFound => write!(f, "Service not registered"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RegistryError {}
+
+struct Slot<'a> {
+    service: Service<'a>,
+    removed: Option<Instant>,
+}
+
+impl<'a> Slot<'a> {
+    fn is(&self, name: &str, service: &str, protocol: &str) -> bool {
+        self.service.name.eq_ignore_ascii_case(name)
+            && self.service.service.eq_ignore_ascii_case(service)
+            && self.service.protocol.eq_ignore_ascii_case(protocol)
+    }
+
+    fn is_active(&self) -> bool {
+        self.removed.is_none()
+    }
+
+    fn is_leaving(&self, now: Instant) -> bool {
+        self.removed
+            .map(|removed| now.saturating_duration_since(removed) < GOODBYE_DURATION)
+            .unwrap_or(false)
+    }
+}
+
+struct State<'a, const N: usize> {
+    slots: heapless::Vec<Slot<'a>, N>,
+    /// The time of the reply being composed, as sampled by the handler of the registry
+    now: Instant,
+}
+
+/// A registry with `N` slots for the DNS-SD services of a host, which can be added, updated and
+/// removed at runtime.
+pub struct ServiceRegistry<'a, M, const N: usize>
+where
+    M: RawMutex,
+{
+    host: &'a Host<'a>,
+    broadcast_signal: &'a Signal<M, ()>,
+    state: blocking_mutex::Mutex<M, RefCell<State<'a, N>>>,
+}
+
+impl<'a, M, const N: usize> ServiceRegistry<'a, M, N>
+where
+    M: RawMutex,
+{
+    /// Create a new, empty `ServiceRegistry` for the services of `host`.
+    ///
+    /// `broadcast_signal` should be the broadcast signal of the `io::Mdns` instance running the registry.
+    pub const fn new(host: &'a Host<'a>, broadcast_signal: &'a Signal<M, ()>) -> Self {
+        Self {
+            host,
+            broadcast_signal,
+            state: blocking_mutex::Mutex::new(RefCell::new(State {
+                slots: heapless::Vec::new(),
+                now: Instant::MIN,
+            })),
+        }
+    }
+
+    /// Create the `MdnsHandler` of the registry, which answers with the records of the host
+    /// and of the registered services.
+    pub fn handler(&self) -> ServiceRegistryMdnsHandler<'_, 'a, M, N> {
+        ServiceRegistryMdnsHandler {
+            registry: self,
+            handler: HostAnswersMdnsHandler::new(ChainedHostAnswers::new(self.host, self)),
+        }
+    }
+
+    /// Add a service, and announce it.
+    ///
+    /// Fails if a service with the same name, type and protocol is already registered,
+    /// or if all slots are occupied.
+    ///
+    /// The instance name of the service should be probed before it is added (see the module documentation).
+    pub fn add(&self, service: Service<'a>) -> Result<(), RegistryError> {
+        // Rather than the time sampled by the handler, which is stale outside of a reply, or not sampled yet
+        self.add_at(service, Instant::now())
+    }
+
+    fn add_at(&self, service: Service<'a>, now: Instant) -> Result<(), RegistryError> {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            let slots = &mut state.slots;
+
+            if slots.iter().any(|slot| {
+                slot.is_active() && slot.is(service.name, service.service, service.protocol)
+            }) {
+                return Err(RegistryError::Duplicate);
+            }
+
+            // Re-adding a service which is being removed, or reusing the slot of a service
+            // which had already said goodbye
+            let slot = slots
+                .iter_mut()
+                .find(|slot| slot.is(service.name, service.service, service.protocol));
+
+            if let Some(slot) = slot {
+                *slot = Slot {
+                    service,
+                    removed: None,
+                };
+            } else {
+                slots.retain(|slot| slot.is_active() || slot.is_leaving(now));
+
+                slots
+                    .push(Slot {
+                        service,
+                        removed: None,
+                    })
+                    .map_err(|_| RegistryError::Full)?;
+            }
+
+            Ok(())
+        })?;
+
+        self.broadcast_signal.signal(());
+
+        Ok(())
+    }
+
+    /// Update a registered service (i.e. its port or TXT key-value pairs), and announce the change.
+    ///
+    /// The service to update is identified by the name, type and protocol of `service`.
+    pub fn update(&self, service: Service<'a>) -> Result<(), RegistryError> {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+
+            let slot = state
+                .slots
+                .iter_mut()
+                .find(|slot| {
+                    slot.is_active() && slot.is(service.name, service.service, service.protocol)
+                })
+                .ok_or(RegistryError::NotFound)?;
+
+            slot.service = service;
+
+            Ok(())
+        })?;
+
+        self.broadcast_signal.signal(());
+
+        Ok(())
+    }
+
+    /// Remove a registered service, and send goodbyes for it.
+    pub fn remove(&self, name: &str, service: &str, protocol: &str) -> Result<(), RegistryError> {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+
+            let slot = state
+                .slots
+                .iter_mut()
+                .find(|slot| slot.is_active() && slot.is(name, service, protocol))
+                .ok_or(RegistryError::NotFound)?;
+
+            slot.removed = Some(Instant::now());
+
+            Ok(())
+        })?;
+
+        self.broadcast_signal.signal(());
+
+        Ok(())
+    }
+
+    /// Visit the registered services.
+    pub fn services<F>(&self, mut f: F)
+    where
+        F: FnMut(&Service<'a>),
+    {
+        self.state.lock(|state| {
+            for slot in state.borrow().slots.iter().filter(|slot| slot.is_active()) {
+                f(&slot.service);
+            }
+        })
+    }
+
+    fn set_now(&self, now: Instant) {
+        self.state.lock(|state| state.borrow_mut().now = now);
+    }
+}
+
+impl<'a, M, const N: usize> HostAnswers for ServiceRegistry<'a, M, N>
+where
+    M: RawMutex,
+{
+    fn visit<F, E>(&self, mut f: F) -> Result<(), E>
+    where
+        F: FnMut(HostAnswer) -> Result<(), E>,
+        E: From<MdnsError>,
+    {
+        self.state.lock(|state| {
+            let state = state.borrow();
+
+            for slot in state.slots.iter() {
+                let answers = ServiceAnswers::new(self.host, &slot.service);
+
+                if slot.is_active() {
+                    answers.visit(&mut f)?;
+                } else if slot.is_leaving(state.now) {
+                    GoodbyeHostAnswers::new(answers).visit(&mut f)?;
+                }
+            }
+
+            Ok(())
+        })
+    }
+}
+
+/// The `MdnsHandler` of a `ServiceRegistry`, as returned by `ServiceRegistry::handler`.
+///
+/// Samples the current time once per reply, so that all packets of a reply agree on
+/// the removed services which are still sending goodbyes.
+pub struct ServiceRegistryMdnsHandler<'r, 'a, M, const N: usize>
+where
+    M: RawMutex,
+{
+    registry: &'r ServiceRegistry<'a, M, N>,
+    handler:
+        HostAnswersMdnsHandler<ChainedHostAnswers<&'a Host<'a>, &'r ServiceRegistry<'a, M, N>>>,
+}
+
+impl<'r, 'a, M, const N: usize> MdnsHandler for ServiceRegistryMdnsHandler<'r, 'a, M, N>
+where
+    M: RawMutex,
+{
+    fn handle<'b>(
+        &mut self,
+        request: MdnsRequest<'_>,
+        response_buf: &'b mut [u8],
+    ) -> Result<MdnsResponse<'b>, MdnsError> {
+        // Not in the middle of a multi-packet reply
+        if !self.handler.is_replying(&request) {
+            self.registry.set_now(Instant::now());
+        }
+
+        self.handler.handle(request, response_buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use crate::fixtures::host;
+
+    use super::*;
+
+    /// An HTTP service instance named `name`, on `port`
+    fn sensor(name: &'static str, port: u16) -> Service<'static> {
+        Service {
+            name,
+            port,
+            ..crate::fixtures::sensor()
+        }
+    }
+
+    /// The TTLs of the answers of the registered services
+    fn ttls<const N: usize>(
+        registry: &ServiceRegistry<'_, NoopRawMutex, N>,
+    ) -> heapless::Vec<u32, 16> {
+        let mut ttls = heapless::Vec::new();
+
+        registry
+            .visit(|answer| {
+                ttls.push(answer.ttl().as_secs()).unwrap();
+
+                Ok::<_, MdnsError>(())
+            })
+            .unwrap();
+
+        ttls
+    }
+
+    #[test]
+    fn test_registry() {
+        let host = host();
+        let signal = Signal::<NoopRawMutex, _>::new();
+        let registry = ServiceRegistry::<_, 2>::new(&host, &signal);
+
+        registry.add(sensor("sensor1", 80)).unwrap();
+        assert!(signal.signaled());
+
+        // The SRV, TXT and the two PTR records of the service
+        assert_eq!(ttls(&registry), [60, 60, 60, 60]);
+
+        signal.reset();
+
+        assert_eq!(
+            registry.add(sensor("Sensor1", 8080)),
+            Err(RegistryError::Duplicate)
+        );
+        assert_eq!(
+            registry.update(sensor("sensor2", 8080)),
+            Err(RegistryError::NotFound)
+        );
+        assert_eq!(
+            registry.remove("sensor2", "_http", "_tcp"),
+            Err(RegistryError::NotFound)
+        );
+        assert!(!signal.signaled());
+
+        registry.update(sensor("sensor1", 8080)).unwrap();
+        assert!(signal.signaled());
+
+        registry.add(sensor("sensor2", 80)).unwrap();
+        assert_eq!(
+            registry.add(sensor("sensor3", 80)),
+            Err(RegistryError::Full)
+        );
+
+        let mut ports = heapless::Vec::<u16, 2>::new();
+        registry.services(|service| ports.push(service.port).unwrap());
+        assert_eq!(ports, [8080, 80]);
+    }
+
+    #[test]
+    fn test_goodbye() {
+        let host = host();
+        let signal = Signal::<NoopRawMutex, _>::new();
+        let registry = ServiceRegistry::<_, 2>::new(&host, &signal);
+
+        registry.add(sensor("sensor1", 80)).unwrap();
+        registry.add(sensor("sensor2", 80)).unwrap();
+
+        signal.reset();
+
+        registry.remove("sensor1", "_http", "_tcp").unwrap();
+        assert!(signal.signaled());
+
+        // The removed service is still answered - with goodbyes
+        assert_eq!(ttls(&registry), [0, 0, 0, 0, 60, 60, 60, 60]);
+
+        let mut count = 0;
+        registry.services(|_| count += 1);
+        assert_eq!(count, 1);
+
+        // Re-adding the service while it is saying goodbye reuses its slot
+        registry.add(sensor("sensor1", 80)).unwrap();
+        assert_eq!(ttls(&registry), [60, 60, 60, 60, 60, 60, 60, 60]);
+
+        // Once the goodbyes are over, the removed service is not answered anymore,
+        // and its slot can be reused by other services
+        registry.remove("sensor1", "_http", "_tcp").unwrap();
+        registry.set_now(Instant::now() + GOODBYE_DURATION);
+        assert_eq!(ttls(&registry), [60, 60, 60, 60]);
+
+        // ... which is decided by the current time, rather than by the time of the last reply
+        assert_eq!(
+            registry.add(sensor("sensor3", 80)),
+            Err(RegistryError::Full)
+        );
+
+        registry
+            .add_at(sensor("sensor3", 80), Instant::now() + GOODBYE_DURATION)
+            .unwrap();
+        assert_eq!(ttls(&registry), [60, 60, 60, 60, 60, 60, 60, 60]);
+    }
+
+    #[test]
+    fn test_handler() {
+        let host = host();
+        let signal = Signal::<NoopRawMutex, _>::new();
+        let registry = ServiceRegistry::<_, 2>::new(&host, &signal);
+
+        registry.add(sensor("sensor1", 80)).unwrap();
+
+        let mut handler = registry.handler();
+        let mut buf = [0; 1500];
+
+        let MdnsResponse::Reply { data, .. } = handler.handle(MdnsRequest::None, &mut buf).unwrap()
+        else {
+            panic!("Expected a reply");
+        };
+
+        // The A, AAAA and PTR records of the host, followed by the records of the service
+        let message = crate::domain::base::Message::from_octets(data).unwrap();
+        assert_eq!(message.header_counts().ancount(), 8);
+    }
+}
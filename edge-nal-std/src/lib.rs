@@ -212,6 +212,10 @@ impl UdpBind for Stack {
 
         socket.as_ref().set_broadcast(true)?;
 
+        // Best-effort, as `receive_with_destination` then just reports an unknown destination
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let _ = pktinfo::enable(socket.as_ref(), local.is_ipv6());
+
         Ok(UdpSocket(socket))
     }
 }
@@ -329,6 +333,20 @@ impl UdpReceive for &UdpSocket {
 
         Ok((len, remote))
     }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    async fn receive_with_destination(
+        &mut self,
+        buffer: &mut [u8],
+    ) -> Result<(usize, SocketAddr, Option<IpAddr>), Self::Error> {
+        use std::os::fd::{AsFd, AsRawFd};
+
+        let fut = pin!(self
+            .0
+            .read_with(|io| pktinfo::recvmsg(io.as_fd().as_raw_fd(), buffer)));
+
+        fut.await
+    }
 }
 
 impl UdpSend for &UdpSocket {
@@ -422,6 +440,16 @@ impl UdpReceive for UdpSocket {
         let fut = pin!(rself.receive(buffer));
         fut.await
     }
+
+    async fn receive_with_destination(
+        &mut self,
+        buffer: &mut [u8],
+    ) -> Result<(usize, SocketAddr, Option<IpAddr>), Self::Error> {
+        let mut rself = &*self;
+
+        let fut = pin!(rself.receive_with_destination(buffer));
+        fut.await
+    }
 }
 
 impl UdpSend for UdpSocket {
@@ -762,6 +790,143 @@ mod raw {
     }
 }
 
+/// Reporting of the destination address of received UDP datagrams via `IP_PKTINFO` and `IPV6_PKTINFO`
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod pktinfo {
+    use core::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+
+    use std::io::{self, ErrorKind};
+    use std::net::UdpSocket;
+    use std::os::fd::{AsRawFd, RawFd};
+
+    use crate::sys;
+    use crate::syscall_los;
+
+    /// Enable the `IP_PKTINFO` (and for IPv6 sockets - the `IPV6_RECVPKTINFO`) control messages on the socket
+    pub fn enable(socket: &UdpSocket, ipv6: bool) -> io::Result<()> {
+        let fd = socket.as_raw_fd();
+
+        if ipv6 {
+            setsockopt(fd, sys::IPPROTO_IPV6, sys::IPV6_RECVPKTINFO)?;
+
+            // For IPv4 datagrams received on dual-stack sockets; not supported on IPv6-only sockets
+            let _ = setsockopt(fd, sys::IPPROTO_IP, sys::IP_PKTINFO);
+        } else {
+            setsockopt(fd, sys::IPPROTO_IP, sys::IP_PKTINFO)?;
+        }
+
+        Ok(())
+    }
+
+    /// Receive a datagram, returning its length, its remote address and - if reported - its destination address
+    pub fn recvmsg(
+        fd: RawFd,
+        buffer: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, Option<IpAddr>)> {
+        let mut storage: sys::sockaddr_storage = unsafe { core::mem::zeroed() };
+
+        let mut iov = sys::iovec {
+            iov_base: buffer.as_mut_ptr() as *mut _,
+            iov_len: buffer.len(),
+        };
+
+        // Large enough for both an `in_pktinfo` and an `in6_pktinfo` control message
+        let mut control = [0_u64; 16];
+
+        let mut msg: sys::msghdr = unsafe { core::mem::zeroed() };
+        msg.msg_name = &mut storage as *mut _ as *mut _;
+        msg.msg_namelen = core::mem::size_of_val(&storage) as _;
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut _;
+        msg.msg_controllen = core::mem::size_of_val(&control) as _;
+
+        let len = syscall_los!(unsafe { sys::recvmsg(fd, &mut msg, 0) })?;
+
+        let remote = as_socket_addr(&storage)?;
+
+        let mut destination = None;
+
+        let mut cmsg = unsafe { sys::CMSG_FIRSTHDR(&msg) };
+
+        while !cmsg.is_null() {
+            let header = unsafe { &*cmsg };
+            let data = unsafe { sys::CMSG_DATA(cmsg) };
+
+            if header.cmsg_level == sys::IPPROTO_IP
+                && header.cmsg_type == sys::IP_PKTINFO
+                && header.cmsg_len as usize >= cmsg_len::<sys::in_pktinfo>()
+            {
+                let info = unsafe { core::ptr::read_unaligned(data as *const sys::in_pktinfo) };
+
+                destination = Some(IpAddr::V4(Ipv4Addr::from(
+                    info.ipi_addr.s_addr.to_ne_bytes(),
+                )));
+            } else if header.cmsg_level == sys::IPPROTO_IPV6
+                && header.cmsg_type == sys::IPV6_PKTINFO
+                && header.cmsg_len as usize >= cmsg_len::<sys::in6_pktinfo>()
+            {
+                let info = unsafe { core::ptr::read_unaligned(data as *const sys::in6_pktinfo) };
+
+                // IPv4 datagrams on dual-stack sockets are reported with an IPv4-mapped address
+                destination =
+                    Some(IpAddr::V6(Ipv6Addr::from(info.ipi6_addr.s6_addr)).to_canonical());
+            }
+
+            cmsg = unsafe { sys::CMSG_NXTHDR(&msg, cmsg) };
+        }
+
+        Ok((len as usize, remote, destination))
+    }
+
+    fn cmsg_len<T>() -> usize {
+        unsafe { sys::CMSG_LEN(core::mem::size_of::<T>() as _) as usize }
+    }
+
+    fn setsockopt(fd: RawFd, level: sys::c_int, option: sys::c_int) -> io::Result<()> {
+        let value: sys::c_int = 1;
+
+        syscall_los!(unsafe {
+            sys::setsockopt(
+                fd,
+                level,
+                option,
+                &value as *const _ as *const _,
+                core::mem::size_of_val(&value) as _,
+            )
+        })?;
+
+        Ok(())
+    }
+
+    fn as_socket_addr(storage: &sys::sockaddr_storage) -> io::Result<SocketAddr> {
+        match storage.ss_family as sys::c_int {
+            sys::AF_INET => {
+                let addr = unsafe { &*(storage as *const _ as *const sys::sockaddr_in) };
+
+                Ok(SocketAddr::V4(SocketAddrV4::new(
+                    Ipv4Addr::from(addr.sin_addr.s_addr.to_ne_bytes()),
+                    u16::from_be(addr.sin_port),
+                )))
+            }
+            sys::AF_INET6 => {
+                let addr = unsafe { &*(storage as *const _ as *const sys::sockaddr_in6) };
+
+                Ok(SocketAddr::V6(SocketAddrV6::new(
+                    Ipv6Addr::from(addr.sin6_addr.s6_addr),
+                    u16::from_be(addr.sin6_port),
+                    addr.sin6_flowinfo,
+                    addr.sin6_scope_id,
+                )))
+            }
+            _ => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "invalid address family",
+            )),
+        }
+    }
+}
+
 mod sys {
     pub use libc::*;
 
@@ -1,6 +1,6 @@
 //! Traits for modeling UDP sending/receiving functionality on embedded devices
 
-use core::net::SocketAddr;
+use core::net::{IpAddr, SocketAddr};
 
 use embedded_io_async::ErrorType;
 
@@ -23,6 +23,22 @@ pub trait UdpReceive: ErrorType {
     ///
     /// The remote addresses is given in the result along with the number of bytes.
     async fn receive(&mut self, buffer: &mut [u8]) -> Result<(usize, SocketAddr), Self::Error>;
+
+    /// Receive a datagram into the provided buffer, like `receive`.
+    ///
+    /// In addition to the number of bytes and the remote address, the result contains the local
+    /// (destination) IP address of the datagram, if the implementation is able to determine it.
+    /// For datagrams sent to a multicast group, this is the multicast address.
+    ///
+    /// The default implementation never determines the destination address.
+    async fn receive_with_destination(
+        &mut self,
+        buffer: &mut [u8],
+    ) -> Result<(usize, SocketAddr, Option<IpAddr>), Self::Error> {
+        let (len, remote) = self.receive(buffer).await?;
+
+        Ok((len, remote, None))
+    }
 }
 
 /// This trait is implemented by UDP sockets and models their datagram sending functionality.
@@ -51,6 +67,13 @@ where
     async fn receive(&mut self, buffer: &mut [u8]) -> Result<(usize, SocketAddr), Self::Error> {
         (**self).receive(buffer).await
     }
+
+    async fn receive_with_destination(
+        &mut self,
+        buffer: &mut [u8],
+    ) -> Result<(usize, SocketAddr, Option<IpAddr>), Self::Error> {
+        (**self).receive_with_destination(buffer).await
+    }
 }
 
 impl<T> UdpSend for &mut T
@@ -0,0 +1,440 @@
+//! A sans-IO WebSocket frame codec, for transports which do not implement the
+//! `embedded_io_async` traits, like UART bridges or BLE characteristics.
+//!
+//! `FrameDecoder` is fed arbitrary slices of the incoming byte stream and yields
+//! frames or chunks of their payload, while `FrameEncoder` writes frames into buffers.
+//!
+//! Both operate on single frames only: the reassembly of fragmented messages,
+//! the UTF-8 validation of Text messages and the handling of control frames are
+//! left to the user.
+
+use crate::{Error, FrameHeader, FrameType};
+
+/// What `FrameDecoder::decode` yielded.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Decoded<'a> {
+    /// A complete frame with its unmasked payload, when the whole frame was available
+    Frame(FrameHeader, &'a [u8]),
+    /// The header of a frame, whose payload follows in `Payload` chunks
+    Header(FrameHeader),
+    /// A chunk of the unmasked payload of the current frame; `last` is `true` for the last chunk
+    Payload { data: &'a [u8], last: bool },
+}
+
+/// A decoder of WebSocket frames, fed with the incoming byte stream in slices of any length.
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    header_buf: [u8; FrameHeader::MAX_LEN],
+    header_len: usize,
+    payload: Option<PayloadState>,
+}
+
+impl FrameDecoder {
+    pub const fn new() -> Self {
+        Self {
+            header_buf: [0; FrameHeader::MAX_LEN],
+            header_len: 0,
+            payload: None,
+        }
+    }
+
+    /// Decode the next item from `input`, returning the number of bytes consumed from `input`
+    /// together with the decoded item, or `None` if more input is needed.
+    ///
+    /// The consumed bytes should be dropped from the input before calling `decode` again.
+    /// Payloads are unmasked in place, in `input`.
+    pub fn decode<'a>(
+        &mut self,
+        input: &'a mut [u8],
+    ) -> Result<(usize, Option<Decoded<'a>>), Error<()>> {
+        if let Some(payload) = self.payload.as_mut() {
+            if input.is_empty() {
+                return Ok((0, None));
+            }
+
+            let len = payload.remaining.min(input.len() as u64) as usize;
+
+            let data = &mut input[..len];
+            FrameHeader::mask_with(data, payload.mask_key, payload.mask_offset);
+
+            payload.mask_offset = (payload.mask_offset + len) % 4;
+            payload.remaining -= len as u64;
+
+            let last = payload.remaining == 0;
+            if last {
+                self.payload = None;
+            }
+
+            return Ok((len, Some(Decoded::Payload { data, last })));
+        }
+
+        if self.header_len == 0 {
+            match FrameHeader::deserialize(input) {
+                Ok((header, header_len)) => {
+                    if header.payload_len <= (input.len() - header_len) as u64 {
+                        let len = header_len + header.payload_len as usize;
+
+                        let payload = &mut input[header_len..len];
+                        header.mask(payload, 0);
+
+                        Ok((len, Some(Decoded::Frame(header, payload))))
+                    } else {
+                        Ok((header_len, Some(self.start(header))))
+                    }
+                }
+                Err(Error::Incomplete(_)) => {
+                    // A header is never longer than `FrameHeader::MAX_LEN`, so the input is shorter
+                    self.header_buf[..input.len()].copy_from_slice(input);
+                    self.header_len = input.len();
+
+                    Ok((input.len(), None))
+                }
+                Err(e) => Err(e),
+            }
+        } else {
+            let mut consumed = 0;
+
+            loop {
+                match FrameHeader::deserialize(&self.header_buf[..self.header_len]) {
+                    Ok((header, _)) => {
+                        self.header_len = 0;
+
+                        let decoded = if header.payload_len == 0 {
+                            Decoded::Frame(header, &[])
+                        } else {
+                            self.start(header)
+                        };
+
+                        break Ok((consumed, Some(decoded)));
+                    }
+                    Err(Error::Incomplete(missing)) => {
+                        let len = missing.min(input.len() - consumed);
+                        if len == 0 {
+                            break Ok((consumed, None));
+                        }
+
+                        self.header_buf[self.header_len..self.header_len + len]
+                            .copy_from_slice(&input[consumed..consumed + len]);
+                        self.header_len += len;
+                        consumed += len;
+                    }
+                    Err(e) => {
+                        self.header_len = 0;
+
+                        break Err(e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Return `true` if the decoder is not in the middle of a frame.
+    pub fn is_idle(&self) -> bool {
+        self.header_len == 0 && self.payload.is_none()
+    }
+
+    /// Discard the partially decoded frame, if any.
+    pub fn reset(&mut self) {
+        self.header_len = 0;
+        self.payload = None;
+    }
+
+    fn start<'a>(&mut self, header: FrameHeader) -> Decoded<'a> {
+        self.payload = Some(PayloadState {
+            mask_key: header.mask_key,
+            mask_offset: 0,
+            remaining: header.payload_len,
+        });
+
+        Decoded::Header(header)
+    }
+}
+
+/// An encoder of WebSocket frames, which can write the payload of a frame in multiple chunks.
+#[derive(Debug, Default)]
+pub struct FrameEncoder {
+    payload: Option<PayloadState>,
+}
+
+impl FrameEncoder {
+    pub const fn new() -> Self {
+        Self { payload: None }
+    }
+
+    /// Encode a complete frame into `buf`, returning the length of the encoded frame.
+    ///
+    /// `mask_key` should be `Some` for frames sent by clients and `None` for frames sent by servers.
+    pub fn encode(
+        &mut self,
+        frame_type: FrameType,
+        mask_key: Option<u32>,
+        payload: &[u8],
+        buf: &mut [u8],
+    ) -> Result<usize, Error<()>> {
+        let header = FrameHeader {
+            frame_type,
+            payload_len: payload.len() as _,
+            mask_key,
+        };
+
+        if buf.len() < header.serialized_len() + payload.len() {
+            return Err(Error::BufferOverflow);
+        }
+
+        let header_len = self.encode_header(&header, buf)?;
+        let payload_len = self.encode_payload(payload, &mut buf[header_len..])?;
+
+        Ok(header_len + payload_len)
+    }
+
+    /// Encode the header of a frame into `buf`, returning the length of the encoded header.
+    ///
+    /// The payload of the frame should then be encoded with `encode_payload`.
+    /// Fails with `Error::Invalid` if the payload of the previous frame is not complete.
+    pub fn encode_header(
+        &mut self,
+        header: &FrameHeader,
+        buf: &mut [u8],
+    ) -> Result<usize, Error<()>> {
+        if self.payload.is_some() {
+            return Err(Error::Invalid);
+        }
+
+        let len = header.serialize(buf)?;
+
+        if header.payload_len > 0 {
+            self.payload = Some(PayloadState {
+                mask_key: header.mask_key,
+                mask_offset: 0,
+                remaining: header.payload_len,
+            });
+        }
+
+        Ok(len)
+    }
+
+    /// Encode the next chunk of the payload of the current frame into `buf`, masking it if necessary.
+    ///
+    /// Fails with `Error::InvalidLen` if the chunk exceeds the payload length of the frame header,
+    /// and with `Error::BufferOverflow` if `buf` is shorter than the chunk.
+    pub fn encode_payload(&mut self, data: &[u8], buf: &mut [u8]) -> Result<usize, Error<()>> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        let Some(payload) = self.payload.as_mut() else {
+            return Err(Error::InvalidLen);
+        };
+
+        if data.len() as u64 > payload.remaining {
+            return Err(Error::InvalidLen);
+        }
+
+        if buf.len() < data.len() {
+            return Err(Error::BufferOverflow);
+        }
+
+        let buf = &mut buf[..data.len()];
+        buf.copy_from_slice(data);
+        FrameHeader::mask_with(buf, payload.mask_key, payload.mask_offset);
+
+        payload.mask_offset = (payload.mask_offset + data.len()) % 4;
+        payload.remaining -= data.len() as u64;
+
+        if payload.remaining == 0 {
+            self.payload = None;
+        }
+
+        Ok(data.len())
+    }
+
+    /// Return `true` if the encoder is not in the middle of a frame.
+    pub fn is_idle(&self) -> bool {
+        self.payload.is_none()
+    }
+}
+
+#[derive(Debug)]
+struct PayloadState {
+    mask_key: Option<u32>,
+    /// The offset of the next payload byte, modulo the length of the mask key,
+    /// so that it does not overflow for payloads larger than `usize::MAX` bytes
+    mask_offset: usize,
+    remaining: u64,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MASKED_HELLO: &[u8] = b"\x81\x85\x37\xfa\x21\x3d\x7f\x9f\x4d\x51\x58";
+
+    #[test]
+    fn test_decode_complete() {
+        let mut input = [MASKED_HELLO, b"\x89\x00"].concat();
+
+        let mut decoder = FrameDecoder::new();
+
+        let (len, decoded) = decoder.decode(&mut input).unwrap();
+        assert_eq!(len, MASKED_HELLO.len());
+
+        let Some(Decoded::Frame(header, payload)) = decoded else {
+            panic!("Expected a frame");
+        };
+        assert_eq!(header.frame_type, FrameType::Text(false));
+        assert_eq!(header.mask_key, Some(0x37fa213d));
+        assert_eq!(payload, b"Hello");
+
+        let (len, decoded) = decoder.decode(&mut input[MASKED_HELLO.len()..]).unwrap();
+        assert_eq!(len, 2);
+        assert!(matches!(
+            decoded,
+            Some(Decoded::Frame(
+                FrameHeader {
+                    frame_type: FrameType::Ping,
+                    ..
+                },
+                b""
+            ))
+        ));
+
+        assert!(decoder.is_idle());
+        assert_eq!(decoder.decode(&mut []), Ok((0, None)));
+    }
+
+    #[test]
+    fn test_decode_bytewise() {
+        let mut input = [MASKED_HELLO, b"\x82\x00"].concat();
+
+        let mut decoder = FrameDecoder::new();
+
+        let mut headers = 0;
+        let mut frames = 0;
+        let mut payload = [0; 5];
+        let mut payload_len = 0;
+
+        for offset in 0..input.len() {
+            let (len, decoded) = decoder.decode(&mut input[offset..offset + 1]).unwrap();
+            assert_eq!(len, 1);
+
+            match decoded {
+                Some(Decoded::Header(header)) => {
+                    assert_eq!(header.payload_len, 5);
+                    headers += 1;
+                }
+                Some(Decoded::Payload { data, last }) => {
+                    payload[payload_len..payload_len + data.len()].copy_from_slice(data);
+                    payload_len += data.len();
+                    assert_eq!(last, payload_len == 5);
+                }
+                Some(Decoded::Frame(header, data)) => {
+                    assert_eq!(header.frame_type, FrameType::Binary(false));
+                    assert!(data.is_empty());
+                    frames += 1;
+                }
+                None => (),
+            }
+        }
+
+        assert_eq!((headers, frames), (1, 1));
+        assert_eq!(&payload, b"Hello");
+        assert!(decoder.is_idle());
+    }
+
+    #[test]
+    fn test_decode_partial_header() {
+        let mut decoder = FrameDecoder::new();
+
+        // A 256 bytes Binary frame, with its extended length split across two slices
+        assert_eq!(decoder.decode(&mut [0x82, 0x7e, 0x01]), Ok((3, None)));
+        assert!(!decoder.is_idle());
+
+        let mut input = [0x00, 0xaa, 0xbb];
+        let (len, decoded) = decoder.decode(&mut input).unwrap();
+        assert_eq!(len, 1);
+        assert!(matches!(
+            decoded,
+            Some(Decoded::Header(FrameHeader {
+                payload_len: 256,
+                ..
+            }))
+        ));
+
+        let (len, decoded) = decoder.decode(&mut input[1..]).unwrap();
+        assert_eq!(len, 2);
+        assert_eq!(
+            decoded,
+            Some(Decoded::Payload {
+                data: &[0xaa, 0xbb],
+                last: false
+            })
+        );
+
+        decoder.reset();
+        assert!(decoder.is_idle());
+
+        // Reserved opcode
+        assert_eq!(decoder.decode(&mut [0x83]), Ok((1, None)));
+        assert_eq!(decoder.decode(&mut [0x00]), Err(Error::Protocol));
+        assert!(decoder.is_idle());
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut encoder = FrameEncoder::new();
+        let mut buf = [0; 16];
+
+        let len = encoder
+            .encode(FrameType::Text(false), Some(0x37fa213d), b"Hello", &mut buf)
+            .unwrap();
+        assert_eq!(&buf[..len], MASKED_HELLO);
+
+        assert_eq!(
+            encoder.encode(FrameType::Text(false), None, b"Hello", &mut buf[..6]),
+            Err(Error::BufferOverflow)
+        );
+
+        // The same frame, with its payload encoded in chunks
+        let header = FrameHeader {
+            frame_type: FrameType::Text(false),
+            payload_len: 5,
+            mask_key: Some(0x37fa213d),
+        };
+
+        let mut len = encoder.encode_header(&header, &mut buf).unwrap();
+        assert_eq!(
+            encoder.encode_header(&header, &mut buf),
+            Err(Error::Invalid)
+        );
+
+        for chunk in [&b"He"[..], b"l", b"lo"] {
+            len += encoder.encode_payload(chunk, &mut buf[len..]).unwrap();
+        }
+
+        assert_eq!(&buf[..len], MASKED_HELLO);
+        assert!(encoder.is_idle());
+        assert_eq!(
+            encoder.encode_payload(b"!", &mut buf),
+            Err(Error::InvalidLen)
+        );
+
+        // Round trip
+        let len = encoder
+            .encode(FrameType::Binary(true), None, &[1, 2, 3], &mut buf)
+            .unwrap();
+
+        let mut decoder = FrameDecoder::new();
+        let (_, decoded) = decoder.decode(&mut buf[..len]).unwrap();
+        assert!(matches!(
+            decoded,
+            Some(Decoded::Frame(
+                FrameHeader {
+                    frame_type: FrameType::Binary(true),
+                    mask_key: None,
+                    ..
+                },
+                &[1, 2, 3]
+            ))
+        ));
+    }
+}
@@ -0,0 +1,457 @@
+//! The `permessage-deflate` extension (RFC 7692).
+//!
+//! Both the compressor and the decompressor work on whole message payloads and use LZ77 windows
+//! supplied by the user, so that the extension can be used without an allocator.
+//!
+//! The parameters of the extension are negotiated during the upgrade handshake
+//! (see `edge_http::ws::DeflateParams`). Compressed messages are sent with the RSV1 bit set in the header
+//! of their first frame (see `FrameHeader::serialize_compressed` and `FrameHeader::deserialize_compressed`).
+
+use miniz_oxide::inflate::core::{decompress, inflate_flags, DecompressorOxide};
+use miniz_oxide::inflate::TINFLStatus;
+
+use crate::Error;
+
+/// The trailer removed from the end of each compressed message, as per RFC 7692.
+const TRAILER: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+const HASH_BITS: usize = 10;
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+const LEN_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LEN_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// A decompressor of `permessage-deflate` messages.
+pub struct Inflater<'w> {
+    decompressor: DecompressorOxide,
+    window: &'w mut [u8],
+    offset: usize,
+    no_context_takeover: bool,
+}
+
+impl<'w> Inflater<'w> {
+    /// Create a new decompressor.
+    ///
+    /// `window_bits` is the base-2 logarithm of the LZ77 window negotiated for the peer (8 - 15, i.e. 15 if not limited).
+    ///
+    /// The length of `window` must be a power of two, and at least `2 ^ window_bits` bytes,
+    /// otherwise `Error::InvalidLen` is returned.
+    ///
+    /// `no_context_takeover` should be set to the value negotiated for the peer.
+    pub fn new(
+        window: &'w mut [u8],
+        window_bits: u8,
+        no_context_takeover: bool,
+    ) -> Result<Self, Error<()>> {
+        if !window.len().is_power_of_two() || window.len() < 1 << window_bits.clamp(8, 15) {
+            return Err(Error::InvalidLen);
+        }
+
+        Ok(Self {
+            decompressor: DecompressorOxide::new(),
+            window,
+            offset: 0,
+            no_context_takeover,
+        })
+    }
+
+    /// Decompress the payload of a whole compressed message into `buf`, returning the decompressed length.
+    ///
+    /// On error, the compression context is lost, and the connection should be failed.
+    pub fn inflate(&mut self, payload: &[u8], buf: &mut [u8]) -> Result<usize, Error<()>> {
+        if self.no_context_takeover {
+            self.reset();
+        }
+
+        let mut len = 0;
+
+        for mut input in [payload, &TRAILER] {
+            loop {
+                let (status, consumed, written) = decompress(
+                    &mut self.decompressor,
+                    input,
+                    self.window,
+                    self.offset,
+                    inflate_flags::TINFL_FLAG_HAS_MORE_INPUT,
+                );
+
+                input = &input[consumed..];
+
+                if written > 0 {
+                    let Some(out) = buf.get_mut(len..len + written) else {
+                        self.reset();
+                        return Err(Error::BufferOverflow);
+                    };
+
+                    out.copy_from_slice(&self.window[self.offset..self.offset + written]);
+
+                    len += written;
+                    self.offset = (self.offset + written) & (self.window.len() - 1);
+                }
+
+                match status {
+                    TINFLStatus::HasMoreOutput => (),
+                    TINFLStatus::NeedsMoreInput => break,
+                    TINFLStatus::Done => {
+                        // The peer ended the DEFLATE stream with a final block;
+                        // the next message starts a new one
+                        self.decompressor.init();
+                        return Ok(len);
+                    }
+                    _ => {
+                        self.reset();
+                        return Err(Error::Invalid);
+                    }
+                }
+            }
+        }
+
+        Ok(len)
+    }
+
+    /// Reset the compression context.
+    pub fn reset(&mut self) {
+        self.decompressor.init();
+        self.offset = 0;
+    }
+}
+
+/// A compressor of `permessage-deflate` messages.
+///
+/// The compressor does a greedy LZ77 match search and emits fixed Huffman codes, which keeps its state small
+/// while still being effective on repetitive payloads like JSON.
+pub struct Deflater<'w> {
+    window: &'w mut [u8],
+    history_len: usize,
+    max_distance: usize,
+    no_context_takeover: bool,
+    head: [u32; 1 << HASH_BITS],
+}
+
+impl<'w> Deflater<'w> {
+    /// Create a new compressor.
+    ///
+    /// `window_bits` is the base-2 logarithm of the LZ77 window negotiated for this side (8 - 15).
+    ///
+    /// `window` keeps the tail of the previous messages, which can then be referenced by the next ones.
+    /// It is only used if `no_context_takeover` is `false`, and more than `2 ^ window_bits` bytes of it are never used.
+    pub fn new(window: &'w mut [u8], window_bits: u8, no_context_takeover: bool) -> Self {
+        Self {
+            window,
+            history_len: 0,
+            max_distance: 1 << window_bits.clamp(8, 15),
+            no_context_takeover,
+            head: [0; 1 << HASH_BITS],
+        }
+    }
+
+    /// Compress the payload of a whole message into `buf`, returning the compressed length.
+    ///
+    /// If the compressed payload does not fit in `buf`, `Error::BufferOverflow` is returned and the compression
+    /// context is left intact, so that the message can be sent uncompressed instead.
+    pub fn deflate(&mut self, payload: &[u8], buf: &mut [u8]) -> Result<usize, Error<()>> {
+        let history_len = if self.no_context_takeover {
+            0
+        } else {
+            self.history_len
+        };
+
+        let history = &self.window[..history_len];
+        let at = |pos: usize| {
+            if pos < history_len {
+                history[pos]
+            } else {
+                payload[pos - history_len]
+            }
+        };
+
+        let end = history_len + payload.len();
+
+        let head = &mut self.head;
+        head.fill(0);
+
+        let mut insert = |pos: usize| {
+            let hash = Self::hash(at(pos), at(pos + 1), at(pos + 2));
+            let candidate = head[hash];
+            head[hash] = pos as u32 + 1;
+
+            candidate.checked_sub(1).map(|candidate| candidate as usize)
+        };
+
+        for pos in 0..history_len.min(end.saturating_sub(MIN_MATCH - 1)) {
+            insert(pos);
+        }
+
+        let mut writer = BitWriter::new(buf);
+
+        // A non-final block with fixed Huffman codes
+        writer.write_bits(0b010, 3)?;
+
+        let mut pos = history_len;
+
+        while pos < end {
+            let mut match_len = 0;
+            let mut distance = 0;
+
+            if pos + MIN_MATCH <= end {
+                if let Some(candidate) = insert(pos) {
+                    if pos - candidate <= self.max_distance {
+                        let max_len = (end - pos).min(MAX_MATCH);
+
+                        while match_len < max_len
+                            && at(candidate + match_len) == at(pos + match_len)
+                        {
+                            match_len += 1;
+                        }
+
+                        distance = pos - candidate;
+                    }
+                }
+            }
+
+            if match_len >= MIN_MATCH {
+                writer.write_length(match_len)?;
+                writer.write_distance(distance)?;
+
+                for pos in pos + 1..(pos + match_len).min(end.saturating_sub(MIN_MATCH - 1)) {
+                    insert(pos);
+                }
+
+                pos += match_len;
+            } else {
+                writer.write_symbol(at(pos) as _)?;
+
+                pos += 1;
+            }
+        }
+
+        // End of block
+        writer.write_symbol(256)?;
+
+        // An empty, non-final stored block, whose `00 00 ff ff` tail is removed as per RFC 7692
+        writer.write_bits(0, 3)?;
+
+        let len = writer.finish()?;
+
+        if !self.no_context_takeover {
+            self.push_history(payload);
+        }
+
+        Ok(len)
+    }
+
+    /// Reset the compression context.
+    pub fn reset(&mut self) {
+        self.history_len = 0;
+    }
+
+    fn push_history(&mut self, payload: &[u8]) {
+        let keep = self.window.len().min(self.max_distance);
+
+        if payload.len() >= keep {
+            self.window[..keep].copy_from_slice(&payload[payload.len() - keep..]);
+            self.history_len = keep;
+        } else {
+            let kept = self.history_len.min(keep - payload.len());
+
+            self.window
+                .copy_within(self.history_len - kept..self.history_len, 0);
+            self.window[kept..kept + payload.len()].copy_from_slice(payload);
+
+            self.history_len = kept + payload.len();
+        }
+    }
+
+    fn hash(b0: u8, b1: u8, b2: u8) -> usize {
+        let value = (b0 as u32) << 16 | (b1 as u32) << 8 | b2 as u32;
+
+        (value.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+    }
+}
+
+struct BitWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    bits: u32,
+    bits_count: u32,
+}
+
+impl<'a> BitWriter<'a> {
+    fn new(buf: &'a mut [u8]) -> Self {
+        Self {
+            buf,
+            len: 0,
+            bits: 0,
+            bits_count: 0,
+        }
+    }
+
+    fn write_bits(&mut self, value: u32, count: u32) -> Result<(), Error<()>> {
+        self.bits |= value << self.bits_count;
+        self.bits_count += count;
+
+        while self.bits_count >= 8 {
+            self.push(self.bits as u8)?;
+
+            self.bits >>= 8;
+            self.bits_count -= 8;
+        }
+
+        Ok(())
+    }
+
+    /// Huffman codes are written starting from their most significant bit
+    fn write_code(&mut self, code: u32, len: u32) -> Result<(), Error<()>> {
+        self.write_bits(code.reverse_bits() >> (32 - len), len)
+    }
+
+    fn write_symbol(&mut self, symbol: u16) -> Result<(), Error<()>> {
+        let symbol = symbol as u32;
+
+        match symbol {
+            0..=143 => self.write_code(0x30 + symbol, 8),
+            144..=255 => self.write_code(0x190 + symbol - 144, 9),
+            256..=279 => self.write_code(symbol - 256, 7),
+            _ => self.write_code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn write_length(&mut self, len: usize) -> Result<(), Error<()>> {
+        let index = LEN_BASE
+            .iter()
+            .rposition(|base| *base as usize <= len)
+            .unwrap();
+
+        self.write_symbol(257 + index as u16)?;
+        self.write_bits((len - LEN_BASE[index] as usize) as _, LEN_EXTRA[index] as _)
+    }
+
+    fn write_distance(&mut self, distance: usize) -> Result<(), Error<()>> {
+        let index = DIST_BASE
+            .iter()
+            .rposition(|base| *base as usize <= distance)
+            .unwrap();
+
+        self.write_code(index as _, 5)?;
+        self.write_bits(
+            (distance - DIST_BASE[index] as usize) as _,
+            DIST_EXTRA[index] as _,
+        )
+    }
+
+    fn finish(mut self) -> Result<usize, Error<()>> {
+        if self.bits_count > 0 {
+            self.push(self.bits as u8)?;
+        }
+
+        Ok(self.len)
+    }
+
+    fn push(&mut self, byte: u8) -> Result<(), Error<()>> {
+        *self.buf.get_mut(self.len).ok_or(Error::BufferOverflow)? = byte;
+        self.len += 1;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_inflate() {
+        let mut window = [0; 256];
+        let mut inflater = Inflater::new(&mut window, 8, false).unwrap();
+
+        let mut buf = [0; 16];
+
+        // The examples of RFC 7692, section 7.2.3
+        let len = inflater
+            .inflate(&[0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00], &mut buf)
+            .unwrap();
+        assert_eq!(&buf[..len], b"Hello");
+
+        let len = inflater
+            .inflate(&[0xf2, 0x00, 0x11, 0x00, 0x00], &mut buf)
+            .unwrap();
+        assert_eq!(&buf[..len], b"Hello");
+
+        let len = inflater.inflate(&[0x00], &mut buf).unwrap();
+        assert_eq!(len, 0);
+    }
+
+    #[test]
+    fn test_inflater_window() {
+        assert!(Inflater::new(&mut [0; 32768], 15, false).is_ok());
+
+        // Smaller than the negotiated window
+        assert!(matches!(
+            Inflater::new(&mut [0; 256], 9, false),
+            Err(Error::InvalidLen)
+        ));
+        assert!(matches!(
+            Inflater::new(&mut [0; 16384], 15, true),
+            Err(Error::InvalidLen)
+        ));
+
+        // Not a power of two
+        assert!(matches!(
+            Inflater::new(&mut [0; 768], 9, false),
+            Err(Error::InvalidLen)
+        ));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for no_context_takeover in [false, true] {
+            let mut deflater_window = [0; 512];
+            let mut deflater = Deflater::new(&mut deflater_window, 9, no_context_takeover);
+
+            let mut inflater_window = [0; 512];
+            let mut inflater = Inflater::new(&mut inflater_window, 9, no_context_takeover).unwrap();
+
+            let mut compressed = [0; 256];
+            let mut decompressed = [0; 256];
+
+            for message in [
+                &br#"{"temperature": 21.5, "humidity": 40, "pressure": 1013}"#[..],
+                &br#"{"temperature": 21.6, "humidity": 40, "pressure": 1013}"#[..],
+                &br#"[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]"#[..],
+                &[0xff; 255][..],
+                &[][..],
+            ] {
+                let len = deflater.deflate(message, &mut compressed).unwrap();
+                assert!(len <= message.len().max(2));
+
+                let len = inflater
+                    .inflate(&compressed[..len], &mut decompressed)
+                    .unwrap();
+                assert_eq!(&decompressed[..len], message);
+            }
+        }
+
+        let mut deflater = Deflater::new(&mut [], 15, true);
+
+        assert!(matches!(
+            deflater.deflate(b"Hello", &mut [0; 4]),
+            Err(Error::BufferOverflow)
+        ));
+    }
+}
@@ -1,12 +1,20 @@
 use core::cmp::min;
 
-use embedded_io_async::{self, Read, ReadExactError, Write};
+use embedded_io_async::{self, ErrorType, Read, ReadExactError, Write};
 
 use super::*;
 
 #[cfg(feature = "embedded-svc")]
 pub use embedded_svc_compat::*;
 
+pub use session::*;
+
+#[cfg(feature = "client")]
+pub mod client;
+#[cfg(feature = "hub")]
+pub mod hub;
+mod session;
+
 pub type Error<E> = super::Error<E>;
 
 impl<E> From<ReadExactError<E>> for Error<E> {
@@ -18,8 +26,55 @@ impl<E> From<ReadExactError<E>> for Error<E> {
     }
 }
 
+impl<E> embedded_io_async::Error for Error<E>
+where
+    E: embedded_io_async::Error,
+{
+    fn kind(&self) -> embedded_io_async::ErrorKind {
+        match self {
+            Self::Io(e) => e.kind(),
+            _ => embedded_io_async::ErrorKind::Other,
+        }
+    }
+}
+
 impl FrameHeader {
-    pub async fn recv<R>(mut read: R) -> Result<Self, Error<R::Error>>
+    pub async fn recv<R>(read: R) -> Result<Self, Error<R::Error>>
+    where
+        R: Read,
+    {
+        Self::recv_rsv1(read, false).await.map(|(header, _)| header)
+    }
+
+    /// Like `recv`, but also failing with `Error::Protocol` if the frame is not masked as required by RFC 6455 §5.1,
+    /// i.e. frames sent by clients must be masked, while frames sent by servers must not be, or if the most
+    /// significant bit of a 64-bit payload length is set.
+    ///
+    /// `masked` should be `true` on the server side, and `false` on the client side.
+    pub async fn recv_strict<R>(read: R, masked: bool) -> Result<Self, Error<R::Error>>
+    where
+        R: Read,
+    {
+        let header = Self::recv(read).await?;
+
+        if header.mask_key.is_some() != masked || header.payload_len >> 63 != 0 {
+            Err(Error::Protocol)
+        } else {
+            Ok(header)
+        }
+    }
+
+    /// Like `recv`, but for connections which negotiated the `permessage-deflate` extension.
+    ///
+    /// Return the header, and whether the RSV1 bit is set (see `FrameHeader::deserialize_compressed`).
+    pub async fn recv_compressed<R>(read: R) -> Result<(Self, bool), Error<R::Error>>
+    where
+        R: Read,
+    {
+        Self::recv_rsv1(read, true).await
+    }
+
+    async fn recv_rsv1<R>(mut read: R, compression: bool) -> Result<(Self, bool), Error<R::Error>>
     where
         R: Read,
     {
@@ -32,8 +87,8 @@ impl FrameHeader {
                 .await
                 .map_err(Error::from)?;
 
-            match FrameHeader::deserialize(&header_buf[..read_end]) {
-                Ok((header, _)) => return Ok(header),
+            match FrameHeader::deserialize_rsv1(&header_buf[..read_end], compression) {
+                Ok((header, compressed, _)) => return Ok((header, compressed)),
                 Err(Error::Incomplete(more)) => {
                     read_offset = read_end;
                     read_end += more;
@@ -43,12 +98,26 @@ impl FrameHeader {
         }
     }
 
-    pub async fn send<W>(&self, mut write: W) -> Result<(), Error<W::Error>>
+    pub async fn send<W>(&self, write: W) -> Result<(), Error<W::Error>>
+    where
+        W: Write,
+    {
+        self.send_compressed(false, write).await
+    }
+
+    /// Like `send`, but setting the RSV1 bit if `compressed` is `true` (see `FrameHeader::serialize_compressed`).
+    pub async fn send_compressed<W>(
+        &self,
+        compressed: bool,
+        mut write: W,
+    ) -> Result<(), Error<W::Error>>
     where
         W: Write,
     {
         let mut header_buf = [0; FrameHeader::MAX_LEN];
-        let header_len = self.serialize(&mut header_buf).unwrap();
+        let header_len = self
+            .serialize_compressed(compressed, &mut header_buf)
+            .map_err(Error::recast)?;
 
         write
             .write_all(&header_buf[..header_len])
@@ -79,6 +148,36 @@ impl FrameHeader {
         }
     }
 
+    /// Return a reader of the payload of this frame, which unmasks the payload incrementally.
+    ///
+    /// Unlike `recv_payload`, the payload does not need to fit in a buffer.
+    pub fn payload_reader<R>(&self, read: R) -> PayloadReader<R>
+    where
+        R: Read,
+    {
+        PayloadReader {
+            read,
+            payload_len: self.payload_len,
+            mask_key: self.mask_key,
+            offset: 0,
+        }
+    }
+
+    /// Return a writer of the payload of this frame, which masks the payload incrementally.
+    ///
+    /// The header itself should have been sent already. Exactly `payload_len` bytes should be written.
+    pub fn payload_writer<W>(&self, write: W) -> PayloadWriter<W>
+    where
+        W: Write,
+    {
+        PayloadWriter {
+            write,
+            payload_len: self.payload_len,
+            mask_key: self.mask_key,
+            offset: 0,
+        }
+    }
+
     pub async fn send_payload<'a, W>(
         &'a self,
         mut write: W,
@@ -119,6 +218,128 @@ impl FrameHeader {
     }
 }
 
+/// A reader of the payload of a single frame, as returned by `FrameHeader::payload_reader`.
+pub struct PayloadReader<R> {
+    read: R,
+    payload_len: u64,
+    mask_key: Option<u32>,
+    offset: u64,
+}
+
+impl<R> PayloadReader<R> {
+    /// Return the number of payload bytes which are not read yet.
+    pub fn remaining(&self) -> u64 {
+        self.payload_len - self.offset
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    pub fn release(self) -> R {
+        self.read
+    }
+}
+
+impl<R> ErrorType for PayloadReader<R>
+where
+    R: ErrorType,
+{
+    type Error = Error<R::Error>;
+}
+
+impl<R> Read for PayloadReader<R>
+where
+    R: Read,
+{
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let len = min(buf.len() as u64, self.remaining()) as usize;
+
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let buf = &mut buf[..len];
+
+        let read = self.read.read(buf).await.map_err(Error::Io)?;
+
+        if read == 0 {
+            return Err(Error::Incomplete(self.remaining() as _));
+        }
+
+        FrameHeader::mask_with(&mut buf[..read], self.mask_key, (self.offset % 4) as _);
+
+        self.offset += read as u64;
+
+        Ok(read)
+    }
+}
+
+/// A writer of the payload of a single frame, as returned by `FrameHeader::payload_writer`.
+pub struct PayloadWriter<W> {
+    write: W,
+    payload_len: u64,
+    mask_key: Option<u32>,
+    offset: u64,
+}
+
+impl<W> PayloadWriter<W> {
+    /// Return the number of payload bytes which are not written yet.
+    pub fn remaining(&self) -> u64 {
+        self.payload_len - self.offset
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    pub fn release(self) -> W {
+        self.write
+    }
+}
+
+impl<W> ErrorType for PayloadWriter<W>
+where
+    W: ErrorType,
+{
+    type Error = Error<W::Error>;
+}
+
+impl<W> Write for PayloadWriter<W>
+where
+    W: Write,
+{
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.len() as u64 > self.remaining() {
+            return Err(Error::InvalidLen);
+        }
+
+        let written = if self.mask_key.is_some() {
+            let mut mask_buf = [0_u8; 32];
+
+            let len = min(mask_buf.len(), buf.len());
+            let mask_buf = &mut mask_buf[..len];
+
+            mask_buf.copy_from_slice(&buf[..len]);
+            FrameHeader::mask_with(mask_buf, self.mask_key, (self.offset % 4) as _);
+
+            self.write.write_all(mask_buf).await.map_err(Error::Io)?;
+
+            len
+        } else {
+            self.write.write(buf).await.map_err(Error::Io)?
+        };
+
+        self.offset += written as u64;
+
+        Ok(written)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.write.flush().await.map_err(Error::Io)
+    }
+}
+
 pub async fn recv<R>(
     mut read: R,
     frame_data_buf: &mut [u8],
@@ -132,6 +353,73 @@ where
     Ok((header.frame_type, header.payload_len as _))
 }
 
+/// The state of `recv_strict` across the frames of a connection.
+#[derive(Clone, Debug, Default)]
+pub struct RecvState {
+    /// Whether the fragmented message being received, if any, is a Text message
+    text: Option<bool>,
+    utf8: Utf8Validator,
+}
+
+impl RecvState {
+    pub const fn new() -> Self {
+        Self {
+            text: None,
+            utf8: Utf8Validator::new(),
+        }
+    }
+}
+
+/// Like `recv`, but strictly validating the received frame as per RFC 6455, and failing with `Error::Protocol`
+/// or `Error::InvalidUtf8` otherwise (see `Error::close_code`).
+///
+/// On top of the checks of `FrameHeader::recv_strict`, the UTF-8 encoding of Text messages (including characters
+/// split across fragments), the payload of Close frames and the order of the fragments of messages are checked.
+///
+/// `state` should be the same for all frames received on the connection.
+pub async fn recv_strict<R>(
+    mut read: R,
+    frame_data_buf: &mut [u8],
+    masked: bool,
+    state: &mut RecvState,
+) -> Result<(FrameType, usize), Error<R::Error>>
+where
+    R: Read,
+{
+    let header = FrameHeader::recv_strict(&mut read, masked).await?;
+    let payload = header.recv_payload(read, frame_data_buf).await?;
+
+    match header.frame_type {
+        FrameType::Close => {
+            CloseCode::decode_payload(payload).map_err(Error::recast)?;
+        }
+        FrameType::Ping | FrameType::Pong => (),
+        FrameType::Text(_) | FrameType::Binary(_) if state.text.is_some() => {
+            return Err(Error::Protocol)
+        }
+        FrameType::Continue(_) if state.text.is_none() => return Err(Error::Protocol),
+        frame_type => {
+            let text = *state
+                .text
+                .get_or_insert(matches!(frame_type, FrameType::Text(_)));
+
+            if text {
+                state.utf8.push(payload).map_err(Error::recast)?;
+            }
+
+            if frame_type.is_final() {
+                state.text = None;
+
+                if text {
+                    state.utf8.finish().map_err(Error::recast)?;
+                }
+            }
+        }
+    }
+
+    Ok((header.frame_type, payload.len()))
+}
+
 pub async fn send<W>(
     mut write: W,
     frame_type: FrameType,
@@ -151,6 +439,224 @@ where
     header.send_payload(write, frame_data_buf).await
 }
 
+#[cfg(test)]
+mod test {
+    use embassy_futures::block_on;
+
+    use super::*;
+
+    #[test]
+    fn test_recv_rsv1() {
+        // A compressed Text frame
+        const FRAME: &[u8] = b"\xc1\x02ab";
+
+        let mut buf = [0; 16];
+
+        // ... on a connection which did not negotiate `permessage-deflate`
+        assert_eq!(block_on(recv(FRAME, &mut buf)), Err(Error::Protocol));
+        assert_eq!(block_on(FrameHeader::recv(FRAME)), Err(Error::Protocol));
+
+        let (header, compressed) = block_on(FrameHeader::recv_compressed(FRAME)).unwrap();
+        assert_eq!(header.frame_type, FrameType::Text(false));
+        assert!(compressed);
+
+        let mut output = [0; 16];
+        let mut write = &mut output[..];
+        block_on(header.send_compressed(true, &mut write)).unwrap();
+        assert_eq!(&output[..2], &FRAME[..2]);
+    }
+
+    const PAYLOAD: &[u8] = b"The quick brown fox jumps over the lazy dog";
+
+    fn header(payload_len: usize) -> FrameHeader {
+        FrameHeader {
+            frame_type: FrameType::Binary(false),
+            payload_len: payload_len as _,
+            mask_key: Some(0x12345678),
+        }
+    }
+
+    /// A reader returning at most `chunk` bytes per read
+    struct Chunked<'a> {
+        data: &'a [u8],
+        chunk: usize,
+    }
+
+    impl ErrorType for Chunked<'_> {
+        type Error = core::convert::Infallible;
+    }
+
+    impl Read for Chunked<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let len = min(min(buf.len(), self.chunk), self.data.len());
+
+            buf[..len].copy_from_slice(&self.data[..len]);
+            self.data = &self.data[len..];
+
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn test_payload_reader() {
+        let header = header(PAYLOAD.len());
+
+        let mut masked = [0; PAYLOAD.len()];
+        masked.copy_from_slice(PAYLOAD);
+        header.mask(&mut masked, 0);
+
+        // Reads end at every offset within the mask key
+        for chunk in 1..=5 {
+            for buf_len in [1, 3, 64] {
+                let mut reader = header.payload_reader(Chunked {
+                    data: &masked,
+                    chunk,
+                });
+
+                let mut payload = [0; PAYLOAD.len()];
+                let mut offset = 0;
+
+                while !reader.is_complete() {
+                    let end = min(offset + buf_len, payload.len());
+                    offset += block_on(reader.read(&mut payload[offset..end])).unwrap();
+                }
+
+                assert_eq!(&payload[..], PAYLOAD);
+                assert_eq!(block_on(reader.read(&mut payload)), Ok(0));
+            }
+        }
+    }
+
+    #[test]
+    fn test_payload_reader_eof() {
+        let header = header(PAYLOAD.len());
+
+        let mut reader = header.payload_reader(&PAYLOAD[..10]);
+
+        let mut payload = [0; PAYLOAD.len()];
+        assert_eq!(block_on(reader.read(&mut payload)), Ok(10));
+        assert_eq!(
+            block_on(reader.read(&mut payload[10..])),
+            Err(Error::Incomplete(PAYLOAD.len() - 10))
+        );
+    }
+
+    #[test]
+    fn test_payload_writer() {
+        let header = header(PAYLOAD.len());
+
+        let mut expected = [0; PAYLOAD.len()];
+        block_on(header.send_payload(&mut expected[..], PAYLOAD)).unwrap();
+
+        // Writes end at every offset within the mask key
+        for chunk in 1..=5 {
+            let mut output = [0; PAYLOAD.len()];
+            let mut writer = header.payload_writer(&mut output[..]);
+
+            for data in PAYLOAD.chunks(chunk) {
+                block_on(writer.write_all(data)).unwrap();
+            }
+
+            assert!(writer.is_complete());
+            assert_eq!(output, expected);
+        }
+    }
+
+    #[test]
+    fn test_payload_writer_len() {
+        let header = header(4);
+
+        let mut output = [0; 16];
+        let mut writer = header.payload_writer(&mut output[..]);
+
+        assert_eq!(block_on(writer.write(b"abcde")), Err(Error::InvalidLen));
+
+        block_on(writer.write_all(b"abc")).unwrap();
+        assert_eq!(block_on(writer.write(b"de")), Err(Error::InvalidLen));
+
+        block_on(writer.write_all(b"d")).unwrap();
+        assert!(writer.is_complete());
+    }
+
+    /// Receive `frames` with `recv_strict` on the server side, returning the result for the last one
+    fn recv_all(frames: &[&[u8]]) -> Result<(FrameType, usize), Error<core::convert::Infallible>> {
+        let mut state = RecvState::new();
+        let mut buf = [0; 16];
+
+        let mut result = Err(Error::Invalid);
+
+        for frame in frames {
+            result = block_on(recv_strict(*frame, &mut buf, true, &mut state));
+
+            if result.is_err() {
+                break;
+            }
+        }
+
+        result
+    }
+
+    #[test]
+    fn test_recv_strict() {
+        // Masked with a zero key, so that the payloads are readable
+        assert_eq!(
+            recv_all(&[b"\x81\x82\0\0\0\0ok"]),
+            Ok((FrameType::Text(false), 2))
+        );
+
+        // "€" split across fragments, with a Ping in between
+        assert_eq!(
+            recv_all(&[
+                b"\x01\x82\0\0\0\0a\xe2",
+                b"\x89\x80\0\0\0\0",
+                b"\x80\x82\0\0\0\0\x82\xac",
+            ]),
+            Ok((FrameType::Continue(true), 2))
+        );
+
+        // ... and truncated
+        assert_eq!(
+            recv_all(&[b"\x01\x82\0\0\0\0a\xe2", b"\x80\x81\0\0\0\0\x82"]),
+            Err(Error::InvalidUtf8)
+        );
+
+        // Invalid UTF-8 in the first fragment is detected right away
+        assert_eq!(
+            recv_all(&[b"\x01\x81\0\0\0\0\xff"]),
+            Err(Error::InvalidUtf8)
+        );
+
+        // Binary messages are not checked
+        assert_eq!(
+            recv_all(&[b"\x82\x81\0\0\0\0\xff"]),
+            Ok((FrameType::Binary(false), 1))
+        );
+
+        for invalid in [
+            // Unmasked
+            &[&b"\x81\x02ok"[..]][..],
+            // A continuation frame without a fragmented message
+            &[&b"\x80\x80\0\0\0\0"[..]][..],
+            // A new message within a fragmented one
+            &[&b"\x01\x80\0\0\0\0"[..], &b"\x81\x80\0\0\0\0"[..]][..],
+            // A Close frame with a one-byte payload
+            &[&b"\x88\x81\0\0\0\0\x03"[..]][..],
+            // The most significant bit of a 64-bit payload length
+            &[&b"\x82\xff\x80\0\0\0\0\0\0\0\0\0\0\0"[..]][..],
+        ] {
+            assert_eq!(recv_all(invalid), Err(Error::Protocol));
+        }
+
+        // Clients receive unmasked frames
+        let mut state = RecvState::new();
+        let mut buf = [0; 16];
+        assert_eq!(
+            block_on(recv_strict(&b"\x81\x02ok"[..], &mut buf, false, &mut state)),
+            Ok((FrameType::Text(false), 2))
+        );
+    }
+}
+
 #[cfg(feature = "embedded-svc")]
 mod embedded_svc_compat {
     use core::convert::TryInto;
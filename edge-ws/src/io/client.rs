@@ -0,0 +1,555 @@
+//! A one-call WebSocket client handshake, on top of the HTTP client of `edge-http`.
+
+use core::fmt;
+use core::net::{IpAddr, SocketAddr};
+use core::pin::pin;
+
+use edge_http::io::client::Connection;
+use edge_http::ws::{
+    upgrade_request_headers_with, WsUpgradeOptions, MAX_BASE64_KEY_LEN,
+    MAX_BASE64_KEY_RESPONSE_LEN, NONCE_LEN, UPGRADE_REQUEST_HEADERS_WITH_LEN,
+};
+use edge_http::Method;
+use edge_nal::{AddrType, Dns, TcpConnect};
+
+use super::WsSession;
+
+/// The maximum number of additional headers which can be sent with the upgrade request.
+pub const MAX_EXTRA_HEADERS: usize = 8;
+
+/// The maximum length of the path and the query of URLs with a query.
+pub const MAX_TARGET_LEN: usize = 256;
+
+const DEFAULT_PORT: u16 = 80;
+
+/// A parsed `ws://host[:port][/path][?query]` URL.
+///
+/// Secure (`wss://`) URLs are not supported.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct WsUrl<'a> {
+    /// The host name or IP address, without the brackets of IPv6 addresses
+    pub host: &'a str,
+    /// The host and the port as they appear in the URL, suitable for the `Host` header
+    pub authority: &'a str,
+    pub port: u16,
+    /// The path, `/` if the URL has none
+    pub path: &'a str,
+    /// The query, without the leading `?`
+    pub query: Option<&'a str>,
+}
+
+impl<'a> WsUrl<'a> {
+    /// Parse a `ws://` URL, returning `None` if it is invalid or not a `ws://` URL.
+    pub fn parse(url: &'a str) -> Option<Self> {
+        let scheme = url.get(..5)?;
+        if !scheme.eq_ignore_ascii_case("ws://") {
+            return None;
+        }
+
+        // The fragment is never sent
+        let rest = url[5..].split('#').next().unwrap_or("");
+
+        // The query may follow the authority directly, as in `ws://host?query`
+        let (rest, query) = match rest.split_once('?') {
+            Some((rest, query)) => (rest, Some(query)),
+            None => (rest, None),
+        };
+
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+
+        if authority.contains('@') {
+            return None;
+        }
+
+        let (host, port) = if let Some(rest) = authority.strip_prefix('[') {
+            let (host, port) = rest.split_once(']')?;
+
+            let port = match port {
+                "" => DEFAULT_PORT,
+                port => port.strip_prefix(':')?.parse().ok()?,
+            };
+
+            (host, port)
+        } else {
+            match authority.split_once(':') {
+                Some((host, port)) => (host, port.parse().ok()?),
+                None => (authority, DEFAULT_PORT),
+            }
+        };
+
+        if host.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            host,
+            authority,
+            port,
+            path,
+            query,
+        })
+    }
+}
+
+/// The options of the upgrade request sent by `connect`.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectOptions<'a> {
+    /// The value of the `Origin` header, if any
+    pub origin: Option<&'a str>,
+    /// A comma-separated list of the subprotocols to offer, if any
+    pub protocols: Option<&'a str>,
+    /// Additional headers, up to `MAX_EXTRA_HEADERS`
+    pub headers: &'a [(&'a str, &'a str)],
+}
+
+/// A WebSocket connection established by `connect`.
+pub struct Connected<'b, 'o, T, M> {
+    pub session: WsSession<T, M>,
+    /// The subprotocol selected by the server, if any
+    pub protocol: Option<&'o str>,
+    /// The buffer passed to `connect`, which is not used anymore
+    pub buf: &'b mut [u8],
+}
+
+/// Connect to the WebSocket server at the provided `ws://` URL.
+///
+/// The host of the URL is resolved with `stack` unless it is an IP address. The upgrade response
+/// is then validated, including the `Sec-WebSocket-Accept` key and the selected subprotocol.
+///
+/// `nonce` should be random, and `mask_gen` should return a new random mask key for each call,
+/// as required from WebSocket clients.
+///
+/// `buf` is used for the HTTP handshake and should be large enough for the upgrade response headers.
+///
+/// The path and the query of URLs with a query should not be longer than `MAX_TARGET_LEN`.
+pub async fn connect<'b, 'o, S, M>(
+    stack: &'b S,
+    url: &str,
+    options: &ConnectOptions<'o>,
+    nonce: &[u8; NONCE_LEN],
+    mask_gen: M,
+    buf: &'b mut [u8],
+) -> Result<
+    Connected<'b, 'o, S::Socket<'b>, M>,
+    ConnectError<<S as TcpConnect>::Error, <S as Dns>::Error>,
+>
+where
+    S: TcpConnect + Dns,
+    M: FnMut() -> Option<u32>,
+{
+    let url = WsUrl::parse(url).ok_or(ConnectError::InvalidUrl)?;
+
+    if options.headers.len() > MAX_EXTRA_HEADERS {
+        return Err(ConnectError::Http(edge_http::io::Error::TooManyHeaders));
+    }
+
+    let mut target_buf = [0; MAX_TARGET_LEN];
+
+    let target = match url.query {
+        Some(query) => {
+            let path_len = url.path.len();
+
+            let target = target_buf
+                .get_mut(..path_len + 1 + query.len())
+                .ok_or(ConnectError::InvalidUrl)?;
+
+            target[..path_len].copy_from_slice(url.path.as_bytes());
+            target[path_len] = b'?';
+            target[path_len + 1..].copy_from_slice(query.as_bytes());
+
+            core::str::from_utf8(target).map_err(|_| ConnectError::InvalidUrl)?
+        }
+        None => url.path,
+    };
+
+    let ip = match url.host.parse::<IpAddr>() {
+        Ok(ip) => ip,
+        Err(_) => stack
+            .get_host_by_name(url.host, AddrType::Either)
+            .await
+            .map_err(ConnectError::Dns)?,
+    };
+
+    let mut conn: Connection<_> = Connection::new(buf, stack, SocketAddr::new(ip, url.port));
+
+    let mut key_buf = [0; MAX_BASE64_KEY_LEN];
+
+    let mut extensions_buf = Default::default();
+
+    let upgrade_options = WsUpgradeOptions {
+        host: Some(url.authority),
+        origin: options.origin,
+        protocols: options.protocols,
+        ..Default::default()
+    };
+
+    let upgrade_headers =
+        upgrade_request_headers_with(&upgrade_options, nonce, &mut key_buf, &mut extensions_buf);
+
+    let mut headers = [("", ""); UPGRADE_REQUEST_HEADERS_WITH_LEN + MAX_EXTRA_HEADERS];
+    headers[..UPGRADE_REQUEST_HEADERS_WITH_LEN].copy_from_slice(&upgrade_headers);
+    headers[UPGRADE_REQUEST_HEADERS_WITH_LEN..][..options.headers.len()]
+        .copy_from_slice(options.headers);
+
+    pin!(conn.initiate_request(true, Method::Get, target, &headers)).await?;
+    pin!(conn.initiate_response()).await?;
+
+    let mut accept_buf = [0; MAX_BASE64_KEY_RESPONSE_LEN];
+
+    if !conn.is_ws_upgrade_accepted(nonce, &mut accept_buf)? {
+        return Err(ConnectError::Rejected(conn.headers()?.code));
+    }
+
+    let protocol = conn.ws_upgrade_protocol(options.protocols)?;
+
+    pin!(conn.complete()).await?;
+
+    let (socket, buf) = conn.release();
+
+    Ok(Connected {
+        session: WsSession::new(socket, mask_gen),
+        protocol,
+        buf,
+    })
+}
+
+/// An error returned by `connect`.
+#[derive(Debug)]
+pub enum ConnectError<C, D> {
+    InvalidUrl,
+    Dns(D),
+    Http(edge_http::io::Error<C>),
+    /// The server did not accept the upgrade; contains the status code of the response
+    Rejected(Option<u16>),
+}
+
+impl<C, D> From<edge_http::io::Error<C>> for ConnectError<C, D> {
+    fn from(e: edge_http::io::Error<C>) -> Self {
+        Self::Http(e)
+    }
+}
+
+impl<C, D> fmt::Display for ConnectError<C, D>
+where
+    C: fmt::Display,
+    D: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidUrl => write!(f, "Invalid WebSocket URL"),
+            Self::Dns(e) => write!(f, "DNS error: {}", e),
+            Self::Http(e) => write!(f, "HTTP error: {}", e),
+            Self::Rejected(Some(code)) => write!(f, "Upgrade rejected with status {}", code),
+            Self::Rejected(None) => write!(f, "Upgrade rejected"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<C, D> std::error::Error for ConnectError<C, D>
+where
+    C: std::error::Error,
+    D: std::error::Error,
+{
+}
+
+#[cfg(test)]
+mod test {
+    use core::cell::{Cell, RefCell};
+    use core::convert::Infallible;
+    use core::net::Ipv4Addr;
+
+    use embedded_io_async::{ErrorType, Read, Write};
+
+    use edge_nal::Readable;
+
+    use super::*;
+
+    /// The nonce of the handshake example of RFC 6455
+    const NONCE: &[u8; NONCE_LEN] = b"the sample nonce";
+
+    const ACCEPTED: &[u8] = b"HTTP/1.1 101 Switching Protocols\r\n\
+        Upgrade: websocket\r\nConnection: Upgrade\r\n\
+        Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\
+        Sec-WebSocket-Protocol: chat\r\n\r\n";
+
+    const IP: Ipv4Addr = Ipv4Addr::new(192, 168, 0, 1);
+
+    /// A server replying with a canned response, and recording the request, the resolved host name
+    /// and the address connected to
+    struct Server {
+        response: &'static [u8],
+        request: RefCell<[u8; 512]>,
+        written: Cell<usize>,
+        read: Cell<usize>,
+        resolved: Cell<bool>,
+        connected: Cell<Option<SocketAddr>>,
+    }
+
+    impl Server {
+        const fn new(response: &'static [u8]) -> Self {
+            Self {
+                response,
+                request: RefCell::new([0; 512]),
+                written: Cell::new(0),
+                read: Cell::new(0),
+                resolved: Cell::new(false),
+                connected: Cell::new(None),
+            }
+        }
+
+        /// Call `f` with the recorded request
+        fn with_request<F, R>(&self, f: F) -> R
+        where
+            F: FnOnce(&str) -> R,
+        {
+            f(core::str::from_utf8(&self.request.borrow()[..self.written.get()]).unwrap())
+        }
+    }
+
+    struct Socket<'a>(&'a Server);
+
+    impl ErrorType for Socket<'_> {
+        type Error = Infallible;
+    }
+
+    impl Read for Socket<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let offset = self.0.read.get();
+            let response = self.0.response;
+            let len = buf.len().min(response.len() - offset);
+
+            buf[..len].copy_from_slice(&response[offset..offset + len]);
+            self.0.read.set(offset + len);
+
+            Ok(len)
+        }
+    }
+
+    impl Write for Socket<'_> {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            let offset = self.0.written.get();
+
+            self.0.request.borrow_mut()[offset..offset + buf.len()].copy_from_slice(buf);
+            self.0.written.set(offset + buf.len());
+
+            Ok(buf.len())
+        }
+    }
+
+    impl Readable for Socket<'_> {
+        async fn readable(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    impl TcpConnect for Server {
+        type Error = Infallible;
+
+        type Socket<'a>
+            = Socket<'a>
+        where
+            Self: 'a;
+
+        async fn connect(&self, remote: SocketAddr) -> Result<Self::Socket<'_>, Self::Error> {
+            self.connected.set(Some(remote));
+
+            Ok(Socket(self))
+        }
+    }
+
+    impl Dns for Server {
+        type Error = Infallible;
+
+        async fn get_host_by_name(
+            &self,
+            host: &str,
+            _addr_type: AddrType,
+        ) -> Result<IpAddr, Self::Error> {
+            assert_eq!(host, "example.com");
+
+            self.resolved.set(true);
+
+            Ok(IpAddr::V4(IP))
+        }
+
+        async fn get_host_by_address(
+            &self,
+            _addr: IpAddr,
+            _result: &mut [u8],
+        ) -> Result<usize, Self::Error> {
+            unreachable!()
+        }
+    }
+
+    /// Connect to `url` through `server`, returning the selected subprotocol
+    fn connect_to(
+        server: &Server,
+        url: &str,
+        options: &ConnectOptions<'static>,
+    ) -> Result<Option<&'static str>, ConnectError<Infallible, Infallible>> {
+        embassy_futures::block_on(async {
+            let mut buf = [0; 512];
+
+            let connected = pin!(connect(
+                server,
+                url,
+                options,
+                NONCE,
+                || Some(0x12345678),
+                &mut buf
+            ))
+            .await?;
+
+            Ok(connected.protocol)
+        })
+    }
+
+    const OPTIONS: ConnectOptions<'static> = ConnectOptions {
+        origin: Some("http://example.com"),
+        protocols: Some("superchat, chat"),
+        headers: &[("Authorization", "Bearer token")],
+    };
+
+    #[test]
+    fn test_connect() {
+        let server = Server::new(ACCEPTED);
+
+        let protocol = connect_to(&server, "ws://example.com:8080/chat?room=1", &OPTIONS);
+        assert!(matches!(protocol, Ok(Some("chat"))));
+
+        assert!(server.resolved.get());
+        assert_eq!(
+            server.connected.get(),
+            Some(SocketAddr::new(IpAddr::V4(IP), 8080))
+        );
+
+        server.with_request(|request| {
+            assert!(request.starts_with("GET /chat?room=1 HTTP/1.1\r\n"));
+            assert!(request.contains("Host: example.com:8080\r\n"));
+            assert!(request.contains("Origin: http://example.com\r\n"));
+            assert!(request.contains("Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n"));
+            assert!(request.contains("Sec-WebSocket-Protocol: superchat, chat\r\n"));
+            assert!(request.contains("Authorization: Bearer token\r\n"));
+        });
+
+        // IP addresses are not resolved, and URLs with a query but without a path are requested at `/`
+        let server = Server::new(ACCEPTED);
+
+        let protocol = connect_to(&server, "ws://192.168.0.1?room=1", &OPTIONS);
+        assert!(protocol.is_ok());
+
+        assert!(!server.resolved.get());
+        assert_eq!(
+            server.connected.get(),
+            Some(SocketAddr::new(IpAddr::V4(IP), 80))
+        );
+
+        server.with_request(|request| {
+            assert!(request.starts_with("GET /?room=1 HTTP/1.1\r\n"));
+        });
+    }
+
+    #[test]
+    fn test_connect_errors() {
+        // A wrong `Sec-WebSocket-Accept` key
+        let server = Server::new(
+            b"HTTP/1.1 101 Switching Protocols\r\n\
+            Upgrade: websocket\r\nConnection: Upgrade\r\n\
+            Sec-WebSocket-Accept: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+        );
+        assert!(matches!(
+            connect_to(&server, "ws://example.com/", &OPTIONS),
+            Err(ConnectError::Rejected(Some(101)))
+        ));
+
+        // A subprotocol which was not offered
+        let server = Server::new(ACCEPTED);
+        let options = ConnectOptions {
+            protocols: Some("superchat"),
+            ..OPTIONS
+        };
+        assert!(matches!(
+            connect_to(&server, "ws://example.com/", &options),
+            Err(ConnectError::Http(edge_http::io::Error::WsUpgradeError(_)))
+        ));
+
+        let server = Server::new(b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n");
+        assert!(matches!(
+            connect_to(&server, "ws://example.com/", &OPTIONS),
+            Err(ConnectError::Rejected(Some(403)))
+        ));
+
+        // Too many headers, which are refused before connecting
+        let server = Server::new(ACCEPTED);
+        let options = ConnectOptions {
+            headers: &[("X-Header", "value"); MAX_EXTRA_HEADERS + 1],
+            ..OPTIONS
+        };
+        assert!(matches!(
+            connect_to(&server, "ws://example.com/", &options),
+            Err(ConnectError::Http(edge_http::io::Error::TooManyHeaders))
+        ));
+        assert!(server.connected.get().is_none());
+
+        assert!(matches!(
+            connect_to(&server, "ws://user@example.com/", &OPTIONS),
+            Err(ConnectError::InvalidUrl)
+        ));
+    }
+
+    #[test]
+    fn test_url() {
+        assert_eq!(
+            WsUrl::parse("ws://example.com:8080/chat?room=1#top"),
+            Some(WsUrl {
+                host: "example.com",
+                authority: "example.com:8080",
+                port: 8080,
+                path: "/chat",
+                query: Some("room=1"),
+            })
+        );
+
+        assert_eq!(
+            WsUrl::parse("WS://192.168.0.1"),
+            Some(WsUrl {
+                host: "192.168.0.1",
+                authority: "192.168.0.1",
+                port: 80,
+                path: "/",
+                query: None,
+            })
+        );
+
+        assert_eq!(
+            WsUrl::parse("ws://example.com?room=1").map(|url| (url.authority, url.path, url.query)),
+            Some(("example.com", "/", Some("room=1")))
+        );
+
+        assert_eq!(
+            WsUrl::parse("ws://example.com:81?next=/chat").map(|url| (url.port, url.path)),
+            Some((81, "/"))
+        );
+
+        assert_eq!(
+            WsUrl::parse("ws://[::1]:81/").map(|url| (url.host, url.port)),
+            Some(("::1", 81))
+        );
+
+        for invalid in [
+            "wss://example.com/",
+            "http://example.com/",
+            "ws://",
+            "ws://:80/",
+            "ws://example.com:http/",
+            "ws://user@example.com/",
+            "ws://[::1/",
+        ] {
+            assert_eq!(WsUrl::parse(invalid), None);
+        }
+    }
+}
@@ -0,0 +1,303 @@
+//! A broadcast hub, which fans out WebSocket frames published by any task
+//! to all the WebSocket connections subscribed to it.
+//!
+//! A typical use is an HTTP server handler which subscribes to the hub after
+//! `initiate_ws_upgrade_response` and then forwards the published frames to its own connection.
+
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::task::Poll;
+
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::waitqueue::WakerRegistration;
+
+use embedded_io_async::Write;
+
+use super::{Error, FrameType};
+
+/// What to do with a subscriber which did not consume the published frames quickly enough,
+/// when a new frame is published and the queue of the hub is full.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    /// Drop the oldest frame not yet received by the subscriber
+    DropOldest,
+    /// Disconnect the subscriber
+    Disconnect,
+}
+
+/// A broadcast hub with `N` subscriber slots, keeping the last `Q` published frames
+/// with payloads of up to `L` bytes.
+pub struct Hub<M, const N: usize, const Q: usize, const L: usize>
+where
+    M: RawMutex,
+{
+    state: Mutex<M, RefCell<State<N, Q, L>>>,
+}
+
+impl<M, const N: usize, const Q: usize, const L: usize> Hub<M, N, Q, L>
+where
+    M: RawMutex,
+{
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(RefCell::new(State {
+                frames: [Frame::EMPTY; Q],
+                next_seq: 0,
+                subscribers: [SubscriberState::NONE; N],
+            })),
+        }
+    }
+
+    /// Subscribe to the frames published from now on.
+    ///
+    /// Return `None` if all subscriber slots are taken.
+    pub fn subscribe(&self, overflow: Overflow) -> Option<Subscriber<'_, M, N, Q, L>> {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            let next_seq = state.next_seq;
+
+            let (index, slot) = state
+                .subscribers
+                .iter_mut()
+                .enumerate()
+                .find(|(_, slot)| slot.is_none())?;
+
+            *slot = Some(SubscriberState {
+                next_seq,
+                overflow,
+                disconnected: false,
+                waker: WakerRegistration::new(),
+            });
+
+            Some(Subscriber { hub: self, index })
+        })
+    }
+
+    /// Publish a frame to all subscribers, returning the number of subscribers it was published to.
+    ///
+    /// Subscribers which are too slow to consume the published frames are handled according to their `Overflow` policy.
+    ///
+    /// Fails with `Error::InvalidLen` if the payload is longer than `L` bytes.
+    pub fn publish(&self, frame_type: FrameType, payload: &[u8]) -> Result<usize, Error<()>> {
+        if payload.len() > L || Q == 0 {
+            return Err(Error::InvalidLen);
+        }
+
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            let state = &mut *state;
+
+            let next_seq = state.next_seq;
+            let mut published = 0;
+
+            for subscriber in state.subscribers.iter_mut().flatten() {
+                if subscriber.disconnected {
+                    continue;
+                }
+
+                // The frame being overwritten was not received by the subscriber yet
+                if subscriber.next_seq + Q as u64 <= next_seq {
+                    match subscriber.overflow {
+                        Overflow::DropOldest => subscriber.next_seq += 1,
+                        Overflow::Disconnect => {
+                            subscriber.disconnected = true;
+                            subscriber.waker.wake();
+
+                            continue;
+                        }
+                    }
+                }
+
+                subscriber.waker.wake();
+                published += 1;
+            }
+
+            let frame = &mut state.frames[(next_seq % Q as u64) as usize];
+
+            frame.frame_type = frame_type;
+            frame.len = payload.len();
+            frame.data[..payload.len()].copy_from_slice(payload);
+
+            state.next_seq += 1;
+
+            Ok(published)
+        })
+    }
+}
+
+impl<M, const N: usize, const Q: usize, const L: usize> Default for Hub<M, N, Q, L>
+where
+    M: RawMutex,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A subscription to a `Hub`, which frees its slot when dropped.
+pub struct Subscriber<'a, M, const N: usize, const Q: usize, const L: usize>
+where
+    M: RawMutex,
+{
+    hub: &'a Hub<M, N, Q, L>,
+    index: usize,
+}
+
+impl<M, const N: usize, const Q: usize, const L: usize> Subscriber<'_, M, N, Q, L>
+where
+    M: RawMutex,
+{
+    /// Wait for the next published frame and copy its payload into `buf`,
+    /// returning the frame type and the length of the payload.
+    ///
+    /// Return `None` if the subscriber was disconnected for being too slow.
+    pub async fn recv(&mut self, buf: &mut [u8; L]) -> Option<(FrameType, usize)> {
+        poll_fn(|cx| {
+            self.hub.state.lock(|state| {
+                let mut state = state.borrow_mut();
+                let state = &mut *state;
+
+                let subscriber = state.subscribers[self.index].as_mut().unwrap();
+
+                if subscriber.disconnected {
+                    Poll::Ready(None)
+                } else if subscriber.next_seq < state.next_seq {
+                    let frame = &state.frames[(subscriber.next_seq % Q as u64) as usize];
+                    buf[..frame.len].copy_from_slice(&frame.data[..frame.len]);
+                    subscriber.next_seq += 1;
+
+                    Poll::Ready(Some((frame.frame_type, frame.len)))
+                } else {
+                    subscriber.waker.register(cx.waker());
+
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+
+    /// Wait for the next published frame and send it over `write`, using `buf` for its payload.
+    ///
+    /// `mask_key` should be `None` for connections accepted by a server.
+    ///
+    /// Return `false` if the subscriber was disconnected for being too slow, in which case
+    /// the WebSocket connection should be closed.
+    pub async fn forward<W>(
+        &mut self,
+        write: W,
+        mask_key: Option<u32>,
+        buf: &mut [u8; L],
+    ) -> Result<bool, Error<W::Error>>
+    where
+        W: Write,
+    {
+        let Some((frame_type, len)) = self.recv(buf).await else {
+            return Ok(false);
+        };
+
+        super::send(write, frame_type, mask_key, &buf[..len]).await?;
+
+        Ok(true)
+    }
+}
+
+impl<M, const N: usize, const Q: usize, const L: usize> Drop for Subscriber<'_, M, N, Q, L>
+where
+    M: RawMutex,
+{
+    fn drop(&mut self) {
+        self.hub.state.lock(|state| {
+            state.borrow_mut().subscribers[self.index] = None;
+        });
+    }
+}
+
+struct State<const N: usize, const Q: usize, const L: usize> {
+    frames: [Frame<L>; Q],
+    next_seq: u64,
+    subscribers: [Option<SubscriberState>; N],
+}
+
+struct Frame<const L: usize> {
+    frame_type: FrameType,
+    len: usize,
+    data: [u8; L],
+}
+
+impl<const L: usize> Frame<L> {
+    const EMPTY: Self = Self {
+        frame_type: FrameType::Binary(false),
+        len: 0,
+        data: [0; L],
+    };
+}
+
+struct SubscriberState {
+    next_seq: u64,
+    overflow: Overflow,
+    disconnected: bool,
+    waker: WakerRegistration,
+}
+
+impl SubscriberState {
+    const NONE: Option<Self> = None;
+}
+
+#[cfg(test)]
+mod test {
+    use core::pin::pin;
+
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+
+    #[test]
+    fn test_hub() {
+        let hub = Hub::<NoopRawMutex, 2, 2, 8>::new();
+
+        let mut fast = hub.subscribe(Overflow::DropOldest).unwrap();
+        let mut slow = hub.subscribe(Overflow::Disconnect).unwrap();
+
+        assert!(hub.subscribe(Overflow::DropOldest).is_none());
+        assert_eq!(
+            hub.publish(FrameType::Text(false), b"too long!"),
+            Err(Error::InvalidLen)
+        );
+
+        let mut buf = [0; 8];
+
+        embassy_futures::block_on(async {
+            assert_eq!(hub.publish(FrameType::Text(false), b"one"), Ok(2));
+            assert_eq!(hub.publish(FrameType::Text(false), b"two"), Ok(2));
+
+            assert_eq!(fast.recv(&mut buf).await, Some((FrameType::Text(false), 3)));
+            assert_eq!(&buf[..3], b"one");
+
+            // The queue is full for the slow subscriber only
+            assert_eq!(hub.publish(FrameType::Binary(false), b"three"), Ok(1));
+            assert_eq!(hub.publish(FrameType::Binary(false), b"four"), Ok(1));
+
+            assert_eq!(slow.recv(&mut buf).await, None);
+
+            // The fast subscriber lost "two"
+            assert_eq!(
+                fast.recv(&mut buf).await,
+                Some((FrameType::Binary(false), 5))
+            );
+            assert_eq!(&buf[..5], b"three");
+
+            let mut out = [0; 16];
+            assert_eq!(
+                pin!(fast.forward(&mut out[..], None, &mut buf)).await,
+                Ok(true)
+            );
+            assert_eq!(&out[..6], b"\x82\x04four");
+        });
+
+        drop(slow);
+
+        assert!(hub.subscribe(Overflow::Disconnect).is_some());
+    }
+}
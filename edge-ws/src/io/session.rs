@@ -0,0 +1,1028 @@
+use core::str;
+
+use embedded_io_async::{ErrorType, Read, Write};
+
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::mutex::Mutex;
+
+#[cfg(feature = "heartbeat")]
+use embassy_futures::select::{select, Either};
+#[cfg(feature = "heartbeat")]
+use embassy_time::{Duration, Instant, Timer};
+
+use super::{CloseCode, Error, FrameHeader, FrameType, Utf8Validator, MAX_CONTROL_PAYLOAD_LEN};
+
+/// A whole WebSocket message, as returned by `WsSession::recv_message`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Message<'a> {
+    Text(&'a str),
+    Binary(&'a [u8]),
+    /// A close frame, with an optional status code and reason
+    Close(Option<(CloseCode, &'a str)>),
+}
+
+/// A stateful WebSocket session on top of an already upgraded connection.
+///
+/// Unlike the single-frame `recv` and `send` primitives, the session:
+/// - Answers Ping frames with Pong frames automatically and skips Pong frames
+/// - Reassembles fragmented messages into the caller-provided buffer
+/// - Performs the close handshake
+/// - Strictly validates the received frames as per RFC 6455, including the UTF-8 encoding of Text messages,
+///   and fails the connection with status code 1002 (protocol error) or 1007 (invalid payload data)
+///   if the peer violates the protocol
+///
+/// `mask_gen` provides the mask key of each sent frame: clients must return a new random key
+/// for each call, while servers must return `None`.
+///
+/// Messages cannot be sent while `recv_message` is pending. To send from another task, split the connection
+/// (e.g. with `edge_nal::TcpSplit::split`) and use a `WsSessionReader` and a `WsSessionWriter` instead.
+pub struct WsSession<T, M> {
+    io: T,
+    sender: Sender<M>,
+    receiver: Receiver,
+}
+
+impl<T, M> WsSession<T, M>
+where
+    T: Read + Write,
+    M: FnMut() -> Option<u32>,
+{
+    pub const fn new(io: T, mask_gen: M) -> Self {
+        Self {
+            io,
+            sender: Sender::new(mask_gen),
+            receiver: Receiver::new(),
+        }
+    }
+
+    /// Enable keep-alive Pings.
+    ///
+    /// While waiting for a message in `recv_message`, a Ping frame is sent every `interval`.
+    /// If `max_missed` consecutive Pings are not answered with a matching Pong frame, the session is closed
+    /// with status code 1011 (internal error) and `recv_message` fails with `Error::Timeout`.
+    #[cfg(feature = "heartbeat")]
+    pub fn with_heartbeat(mut self, interval: Duration, max_missed: u8) -> Self {
+        self.receiver.heartbeat = Some(Heartbeat::new(interval, max_missed));
+
+        self
+    }
+
+    /// Return `true` once both sides have sent a Close frame.
+    /// The underlying connection should then be closed.
+    pub fn is_closed(&self) -> bool {
+        self.sender.close_sent && self.receiver.close_received
+    }
+
+    /// Receive the next whole message into `buf`.
+    ///
+    /// Control frames received in the meantime are handled transparently, except for Close frames
+    /// which are answered (unless the close handshake was initiated by us) and returned as `Message::Close`.
+    ///
+    /// `buf` should be at least `MAX_CONTROL_PAYLOAD_LEN` bytes long, so that Close frames fit in it.
+    ///
+    /// If the peer violates the protocol, a Close frame with the status code of the error
+    /// (see `Error::close_code`) is sent before the error is returned, and the connection should then be closed.
+    pub async fn recv_message<'a>(
+        &mut self,
+        buf: &'a mut [u8],
+    ) -> Result<Message<'a>, Error<T::Error>> {
+        let mut conn = Unsplit {
+            io: &mut self.io,
+            sender: &mut self.sender,
+        };
+
+        self.receiver.recv_message(&mut conn, buf).await
+    }
+
+    /// Send a whole message as a single frame.
+    ///
+    /// Sending `Message::Close` initiates the close handshake; the session then
+    /// keeps receiving messages until the peer answers with its own Close frame.
+    pub async fn send_message(&mut self, message: &Message<'_>) -> Result<(), Error<T::Error>> {
+        self.sender.send_message(&mut self.io, message).await
+    }
+
+    /// Send a Ping frame with the provided payload.
+    pub async fn ping(&mut self, payload: &[u8]) -> Result<(), Error<T::Error>> {
+        self.sender.ping(&mut self.io, payload).await
+    }
+
+    /// Perform the close handshake with the provided status code and reason.
+    ///
+    /// Messages received until the peer answers with its own Close frame are read into `buf` and discarded.
+    pub async fn close(
+        &mut self,
+        code: CloseCode,
+        reason: &str,
+        buf: &mut [u8],
+    ) -> Result<(), Error<T::Error>> {
+        if !self.sender.close_sent {
+            self.send_message(&Message::Close(Some((code, reason))))
+                .await?;
+        }
+
+        while !self.receiver.close_received {
+            self.recv_message(buf).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Release the underlying connection.
+    pub fn release(self) -> T {
+        self.io
+    }
+}
+
+/// The sending half of a WebSocket session, which can be shared between tasks.
+///
+/// The frames are sent under an async mutex, so that the Pong and Close frames sent
+/// by the `WsSessionReader` of the session do not interleave with the sent messages.
+///
+/// See `WsSession` for the meaning of `mask_gen`.
+pub struct WsSessionWriter<RM, W, M>
+where
+    RM: RawMutex,
+{
+    inner: Mutex<RM, (W, Sender<M>)>,
+}
+
+impl<RM, W, M> WsSessionWriter<RM, W, M>
+where
+    RM: RawMutex,
+    W: Write,
+    M: FnMut() -> Option<u32>,
+{
+    pub const fn new(io: W, mask_gen: M) -> Self {
+        Self {
+            inner: Mutex::new((io, Sender::new(mask_gen))),
+        }
+    }
+
+    /// Send a whole message as a single frame.
+    ///
+    /// Sending `Message::Close` initiates the close handshake; the `WsSessionReader` of the session
+    /// should then keep receiving messages until it returns the Close frame of the peer.
+    pub async fn send_message(&self, message: &Message<'_>) -> Result<(), Error<W::Error>> {
+        let mut inner = self.inner.lock().await;
+        let (io, sender) = &mut *inner;
+
+        sender.send_message(io, message).await
+    }
+
+    /// Send a Ping frame with the provided payload.
+    pub async fn ping(&self, payload: &[u8]) -> Result<(), Error<W::Error>> {
+        let mut inner = self.inner.lock().await;
+        let (io, sender) = &mut *inner;
+
+        sender.ping(io, payload).await
+    }
+
+    /// Release the underlying connection half.
+    pub fn release(self) -> W {
+        self.inner.into_inner().0
+    }
+}
+
+/// The receiving half of a WebSocket session, answering Pings and Close frames
+/// through the `WsSessionWriter` of the session.
+///
+/// See `WsSession::recv_message` for the handling of the received frames.
+pub struct WsSessionReader<'a, R, RM, W, M>
+where
+    RM: RawMutex,
+{
+    io: R,
+    writer: &'a WsSessionWriter<RM, W, M>,
+    receiver: Receiver,
+}
+
+impl<'a, R, RM, W, M> WsSessionReader<'a, R, RM, W, M>
+where
+    R: Read,
+    RM: RawMutex,
+    W: Write<Error = R::Error>,
+    M: FnMut() -> Option<u32>,
+{
+    pub const fn new(io: R, writer: &'a WsSessionWriter<RM, W, M>) -> Self {
+        Self {
+            io,
+            writer,
+            receiver: Receiver::new(),
+        }
+    }
+
+    /// Enable keep-alive Pings, as per `WsSession::with_heartbeat`.
+    #[cfg(feature = "heartbeat")]
+    pub fn with_heartbeat(mut self, interval: Duration, max_missed: u8) -> Self {
+        self.receiver.heartbeat = Some(Heartbeat::new(interval, max_missed));
+
+        self
+    }
+
+    /// Return `true` once the Close frame of the peer was received.
+    ///
+    /// As received Close frames are answered, both sides have then sent a Close frame,
+    /// and the underlying connection should be closed.
+    pub fn is_closed(&self) -> bool {
+        self.receiver.close_received
+    }
+
+    /// Receive the next whole message into `buf`, as per `WsSession::recv_message`.
+    pub async fn recv_message<'b>(
+        &mut self,
+        buf: &'b mut [u8],
+    ) -> Result<Message<'b>, Error<R::Error>> {
+        let mut conn = Split {
+            io: &mut self.io,
+            writer: self.writer,
+        };
+
+        self.receiver.recv_message(&mut conn, buf).await
+    }
+
+    /// Release the underlying connection half.
+    pub fn release(self) -> R {
+        self.io
+    }
+}
+
+/// A connection from which frames are received, and through which the control frames are sent.
+trait Control: Read {
+    /// Send a control frame, unless a Close frame was already sent.
+    async fn send_control(
+        &mut self,
+        frame_type: FrameType,
+        payload: &[u8],
+    ) -> Result<(), Error<Self::Error>>;
+}
+
+struct Unsplit<'a, T, M> {
+    io: &'a mut T,
+    sender: &'a mut Sender<M>,
+}
+
+impl<T, M> ErrorType for Unsplit<'_, T, M>
+where
+    T: ErrorType,
+{
+    type Error = T::Error;
+}
+
+impl<T, M> Read for Unsplit<'_, T, M>
+where
+    T: Read,
+{
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.io.read(buf).await
+    }
+}
+
+impl<T, M> Control for Unsplit<'_, T, M>
+where
+    T: Read + Write,
+    M: FnMut() -> Option<u32>,
+{
+    async fn send_control(
+        &mut self,
+        frame_type: FrameType,
+        payload: &[u8],
+    ) -> Result<(), Error<Self::Error>> {
+        self.sender
+            .send_control(&mut *self.io, frame_type, payload)
+            .await
+    }
+}
+
+struct Split<'a, R, RM, W, M>
+where
+    RM: RawMutex,
+{
+    io: &'a mut R,
+    writer: &'a WsSessionWriter<RM, W, M>,
+}
+
+impl<R, RM, W, M> ErrorType for Split<'_, R, RM, W, M>
+where
+    R: ErrorType,
+    RM: RawMutex,
+{
+    type Error = R::Error;
+}
+
+impl<R, RM, W, M> Read for Split<'_, R, RM, W, M>
+where
+    R: Read,
+    RM: RawMutex,
+{
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.io.read(buf).await
+    }
+}
+
+impl<R, RM, W, M> Control for Split<'_, R, RM, W, M>
+where
+    R: Read,
+    RM: RawMutex,
+    W: Write<Error = R::Error>,
+    M: FnMut() -> Option<u32>,
+{
+    async fn send_control(
+        &mut self,
+        frame_type: FrameType,
+        payload: &[u8],
+    ) -> Result<(), Error<Self::Error>> {
+        let mut inner = self.writer.inner.lock().await;
+        let (io, sender) = &mut *inner;
+
+        sender.send_control(io, frame_type, payload).await
+    }
+}
+
+/// The sending state of a session.
+struct Sender<M> {
+    mask_gen: M,
+    close_sent: bool,
+}
+
+impl<M> Sender<M>
+where
+    M: FnMut() -> Option<u32>,
+{
+    const fn new(mask_gen: M) -> Self {
+        Self {
+            mask_gen,
+            close_sent: false,
+        }
+    }
+
+    async fn send_message<W>(&mut self, io: W, message: &Message<'_>) -> Result<(), Error<W::Error>>
+    where
+        W: Write,
+    {
+        if self.close_sent {
+            return Err(Error::Invalid);
+        }
+
+        match message {
+            Message::Text(text) => {
+                self.send_frame(io, FrameType::Text(false), text.as_bytes())
+                    .await
+            }
+            Message::Binary(data) => self.send_frame(io, FrameType::Binary(false), data).await,
+            Message::Close(close) => {
+                let mut payload_buf = [0; MAX_CONTROL_PAYLOAD_LEN];
+                let len =
+                    CloseCode::encode_payload(*close, &mut payload_buf).map_err(Error::recast)?;
+
+                self.close_sent = true;
+
+                self.send_frame(io, FrameType::Close, &payload_buf[..len])
+                    .await
+            }
+        }
+    }
+
+    async fn ping<W>(&mut self, io: W, payload: &[u8]) -> Result<(), Error<W::Error>>
+    where
+        W: Write,
+    {
+        if self.close_sent {
+            return Err(Error::Invalid);
+        }
+
+        if payload.len() > MAX_CONTROL_PAYLOAD_LEN {
+            return Err(Error::InvalidLen);
+        }
+
+        self.send_frame(io, FrameType::Ping, payload).await
+    }
+
+    async fn send_control<W>(
+        &mut self,
+        io: W,
+        frame_type: FrameType,
+        payload: &[u8],
+    ) -> Result<(), Error<W::Error>>
+    where
+        W: Write,
+    {
+        if self.close_sent {
+            return Ok(());
+        }
+
+        if frame_type == FrameType::Close {
+            self.close_sent = true;
+        }
+
+        self.send_frame(io, frame_type, payload).await
+    }
+
+    async fn send_frame<W>(
+        &mut self,
+        io: W,
+        frame_type: FrameType,
+        payload: &[u8],
+    ) -> Result<(), Error<W::Error>>
+    where
+        W: Write,
+    {
+        super::send(io, frame_type, (self.mask_gen)(), payload).await
+    }
+}
+
+/// The receiving state of a session.
+struct Receiver {
+    close_received: bool,
+    #[cfg(feature = "heartbeat")]
+    heartbeat: Option<Heartbeat>,
+}
+
+impl Receiver {
+    const fn new() -> Self {
+        Self {
+            close_received: false,
+            #[cfg(feature = "heartbeat")]
+            heartbeat: None,
+        }
+    }
+
+    async fn recv_message<'a, C>(
+        &mut self,
+        conn: &mut C,
+        buf: &'a mut [u8],
+    ) -> Result<Message<'a>, Error<C::Error>>
+    where
+        C: Control,
+    {
+        if self.close_received {
+            return Err(Error::Invalid);
+        }
+
+        let result = self.recv(conn, buf).await;
+
+        if let Err(e) = &result {
+            if let Some(code) = e.close_code() {
+                let mut payload_buf = [0; MAX_CONTROL_PAYLOAD_LEN];
+
+                if let Ok(len) = CloseCode::encode_payload(Some((code, "")), &mut payload_buf) {
+                    // The connection is failed anyway, so the original error is more relevant
+                    // than a failure to send the Close frame
+                    let _ = conn
+                        .send_control(FrameType::Close, &payload_buf[..len])
+                        .await;
+                }
+            }
+        }
+
+        result
+    }
+
+    async fn recv<'a, C>(
+        &mut self,
+        conn: &mut C,
+        buf: &'a mut [u8],
+    ) -> Result<Message<'a>, Error<C::Error>>
+    where
+        C: Control,
+    {
+        let mut text = None;
+        let mut utf8 = Utf8Validator::new();
+        let mut len = 0;
+
+        loop {
+            let header = self.recv_header(conn).await?;
+
+            match header.frame_type {
+                FrameType::Ping | FrameType::Pong => {
+                    let mut control_buf = [0; MAX_CONTROL_PAYLOAD_LEN];
+                    let payload = header.recv_payload(&mut *conn, &mut control_buf).await?;
+
+                    if matches!(header.frame_type, FrameType::Ping) {
+                        conn.send_control(FrameType::Pong, payload).await?;
+                    } else {
+                        #[cfg(feature = "heartbeat")]
+                        if let Some(heartbeat) = &mut self.heartbeat {
+                            heartbeat.pong(payload);
+                        }
+                    }
+                }
+                FrameType::Close => {
+                    let payload = header.recv_payload(&mut *conn, buf).await?;
+
+                    self.close_received = true;
+
+                    let close = CloseCode::decode_payload(payload).map_err(Error::recast)?;
+
+                    // Echo the status code back (unless we initiated the close handshake), as recommended by the RFC
+                    conn.send_control(FrameType::Close, &payload[..payload.len().min(2)])
+                        .await?;
+
+                    return Ok(Message::Close(close));
+                }
+                FrameType::Text(_) | FrameType::Binary(_) if text.is_some() => {
+                    return Err(Error::Protocol)
+                }
+                FrameType::Continue(_) if text.is_none() => return Err(Error::Protocol),
+                frame_type => {
+                    if text.is_none() {
+                        text = Some(matches!(frame_type, FrameType::Text(_)));
+                    }
+
+                    let payload = header.recv_payload(&mut *conn, &mut buf[len..]).await?;
+                    len += payload.len();
+
+                    if text == Some(true) {
+                        // Fail fast, without waiting for the rest of the message
+                        utf8.push(payload).map_err(Error::recast)?;
+                    }
+
+                    if frame_type.is_final() {
+                        let buf: &'a [u8] = buf;
+                        let data = &buf[..len];
+
+                        return if text == Some(true) {
+                            utf8.finish().map_err(Error::recast)?;
+
+                            str::from_utf8(data)
+                                .map(Message::Text)
+                                .map_err(|_| Error::InvalidUtf8)
+                        } else {
+                            Ok(Message::Binary(data))
+                        };
+                    }
+                }
+            }
+        }
+    }
+
+    #[cfg(not(feature = "heartbeat"))]
+    async fn recv_header<C>(&mut self, conn: &mut C) -> Result<FrameHeader, Error<C::Error>>
+    where
+        C: Control,
+    {
+        FrameHeader::recv(conn).await
+    }
+
+    #[cfg(feature = "heartbeat")]
+    async fn recv_header<C>(&mut self, conn: &mut C) -> Result<FrameHeader, Error<C::Error>>
+    where
+        C: Control,
+    {
+        let Some(heartbeat) = &self.heartbeat else {
+            return FrameHeader::recv(conn).await;
+        };
+
+        let mut next_ping = heartbeat.next_ping;
+
+        loop {
+            // Only the wait for the first byte of the next frame is raced with the timer,
+            // as dropping a `read` future does not lose data, unlike dropping a partially read frame
+            let mut first = [0];
+
+            match select(conn.read(&mut first), Timer::at(next_ping)).await {
+                Either::First(result) => {
+                    if result.map_err(Error::Io)? == 0 {
+                        return Err(Error::Invalid);
+                    }
+
+                    return FrameHeader::recv(Prefixed {
+                        first: Some(first[0]),
+                        read: conn,
+                    })
+                    .await;
+                }
+                Either::Second(_) => {
+                    let Some(heartbeat) = &mut self.heartbeat else {
+                        unreachable!();
+                    };
+
+                    if heartbeat.missed >= heartbeat.max_missed {
+                        return Err(Error::Timeout);
+                    }
+
+                    let payload = heartbeat.ping();
+                    next_ping = heartbeat.next_ping;
+
+                    conn.send_control(FrameType::Ping, &payload).await?;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "heartbeat")]
+struct Heartbeat {
+    interval: Duration,
+    max_missed: u8,
+    missed: u8,
+    counter: u32,
+    next_ping: Instant,
+}
+
+#[cfg(feature = "heartbeat")]
+impl Heartbeat {
+    fn new(interval: Duration, max_missed: u8) -> Self {
+        Self {
+            interval,
+            max_missed,
+            missed: 0,
+            counter: 0,
+            next_ping: Instant::now() + interval,
+        }
+    }
+
+    fn ping(&mut self) -> [u8; 4] {
+        self.counter = self.counter.wrapping_add(1);
+        self.missed = self.missed.saturating_add(1);
+        self.next_ping = Instant::now() + self.interval;
+
+        self.counter.to_be_bytes()
+    }
+
+    fn pong(&mut self, payload: &[u8]) {
+        let Ok(counter) = payload.try_into().map(u32::from_be_bytes) else {
+            // Not an answer to our Pings
+            return;
+        };
+
+        // Late answers to earlier Pings still prove that the peer is alive
+        let newer = self.counter.wrapping_sub(counter);
+
+        if newer < self.missed as u32 {
+            self.missed = newer as u8;
+        }
+    }
+}
+
+/// A reader returning an already read byte before the rest of the data.
+#[cfg(feature = "heartbeat")]
+struct Prefixed<'a, R> {
+    first: Option<u8>,
+    read: &'a mut R,
+}
+
+#[cfg(feature = "heartbeat")]
+impl<R> ErrorType for Prefixed<'_, R>
+where
+    R: Read,
+{
+    type Error = R::Error;
+}
+
+#[cfg(feature = "heartbeat")]
+impl<R> Read for Prefixed<'_, R>
+where
+    R: Read,
+{
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        match self.first {
+            Some(first) if !buf.is_empty() => {
+                buf[0] = first;
+                self.first = None;
+
+                Ok(1)
+            }
+            _ => self.read.read(buf).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::convert::Infallible;
+    use core::pin::pin;
+
+    use embassy_futures::select::{select, Either};
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use embedded_io_async::{ErrorType, Read, Write};
+
+    use super::*;
+
+    /// A connection replaying the frames of a test case and recording the answers
+    struct Conn<'a> {
+        input: &'a [u8],
+        output: &'a mut [u8],
+        written: usize,
+        /// Wait forever once the input is exhausted, like an idle peer
+        idle: bool,
+    }
+
+    impl ErrorType for Conn<'_> {
+        type Error = Infallible;
+    }
+
+    impl Read for Conn<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            if self.idle && self.input.is_empty() {
+                core::future::pending::<()>().await;
+            }
+
+            self.input.read(buf).await
+        }
+    }
+
+    impl Write for Conn<'_> {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            let len = buf.len().min(self.output.len() - self.written);
+
+            self.output[self.written..self.written + len].copy_from_slice(&buf[..len]);
+            self.written += len;
+
+            Ok(len)
+        }
+    }
+
+    /// Run a test case, returning the results of the received messages and the frames sent in response
+    fn run(
+        input: &[u8],
+        messages: usize,
+        check: impl Fn(usize, Result<Message<'_>, Error<Infallible>>),
+    ) -> ([u8; 256], usize) {
+        let mut output = [0; 256];
+
+        let written = embassy_futures::block_on(async {
+            let mut session = WsSession::new(
+                Conn {
+                    input,
+                    output: &mut output,
+                    written: 0,
+                    idle: false,
+                },
+                || None,
+            );
+
+            let mut buf = [0; 256];
+
+            for index in 0..messages {
+                check(index, pin!(session.recv_message(&mut buf)).await);
+            }
+
+            session.release().written
+        });
+
+        (output, written)
+    }
+
+    fn frame(fin: bool, opcode: u8, payload: &[u8], out: &mut [u8]) -> usize {
+        out[0] = if fin { 0x80 } else { 0 } | opcode;
+        out[1] = payload.len() as u8;
+        out[2..2 + payload.len()].copy_from_slice(payload);
+
+        2 + payload.len()
+    }
+
+    fn close_code(output: &[u8]) -> Option<u16> {
+        let (header, offset) = FrameHeader::deserialize(output).unwrap();
+
+        assert_eq!(header.frame_type, FrameType::Close);
+
+        (header.payload_len >= 2).then(|| u16::from_be_bytes([output[offset], output[offset + 1]]))
+    }
+
+    fn fails_with(input: &[u8], error: Error<Infallible>, code: u16) {
+        let (output, written) = run(input, 1, |_, result| assert_eq!(result, Err(error.clone())));
+
+        assert_eq!(close_code(&output[..written]), Some(code));
+    }
+
+    #[test]
+    fn test_fragmentation() {
+        // Autobahn 5.6: a fragmented Text message with a Ping in between
+        let mut input = [0; 64];
+        let mut len = frame(false, 1, b"frag", &mut input);
+        len += frame(true, 9, b"ping", &mut input[len..]);
+        len += frame(true, 0, b"ment", &mut input[len..]);
+
+        let (output, written) = run(&input[..len], 1, |_, result| {
+            assert_eq!(result, Ok(Message::Text("fragment")))
+        });
+
+        // The Ping is answered with a Pong carrying the same payload
+        assert_eq!(&output[..written], b"\x8a\x04ping");
+
+        // Autobahn 5.1, 5.2: fragmented Ping and Pong
+        fails_with(b"\x09\x00", Error::Protocol, 1002);
+        fails_with(b"\x0a\x00", Error::Protocol, 1002);
+
+        // Autobahn 5.9: a continuation frame without a message to continue
+        fails_with(b"\x80\x00", Error::Protocol, 1002);
+
+        // Autobahn 5.18: a new message before the end of a fragmented one
+        fails_with(b"\x01\x01a\x81\x01b", Error::Protocol, 1002);
+    }
+
+    #[test]
+    fn test_reserved_bits_and_opcodes() {
+        // Autobahn 3.1 - 3.3: RSV1, RSV2, RSV3 without a negotiated extension
+        fails_with(b"\xc1\x00", Error::Protocol, 1002);
+        fails_with(b"\xa1\x00", Error::Protocol, 1002);
+        fails_with(b"\x91\x00", Error::Protocol, 1002);
+
+        // Autobahn 4.1.1, 4.2.1: reserved non-control and control opcodes
+        fails_with(b"\x83\x00", Error::Protocol, 1002);
+        fails_with(b"\x8b\x00", Error::Protocol, 1002);
+    }
+
+    #[test]
+    fn test_control_frames() {
+        // Autobahn 2.5: a Ping with a payload longer than 125 bytes
+        fails_with(b"\x89\x7e\x00\x7e", Error::Protocol, 1002);
+
+        // Autobahn 7.3.2: a Close frame with a 1 byte payload
+        fails_with(b"\x88\x01\x03", Error::Protocol, 1002);
+
+        // Autobahn 7.9.x: a Close frame with a status code which may not be sent on the wire
+        fails_with(b"\x88\x02\x03\xed", Error::Protocol, 1002);
+
+        // Autobahn 7.5.1: a Close frame with an invalid UTF-8 reason
+        fails_with(b"\x88\x05\x03\xe8\xce\xba\xe1", Error::InvalidUtf8, 1007);
+
+        // Autobahn 7.1.1: the status code is echoed back
+        let (output, written) = run(b"\x88\x04\x03\xe8ok", 1, |_, result| {
+            assert_eq!(result, Ok(Message::Close(Some((CloseCode::Normal, "ok")))))
+        });
+
+        assert_eq!(close_code(&output[..written]), Some(1000));
+    }
+
+    #[test]
+    fn test_utf8() {
+        // Autobahn 6.2.3: valid UTF-8 split in the middle of characters across fragments
+        let text = "Hello-µ@ßöäüàá-UTF-8!!".as_bytes();
+
+        let mut input = [0; 64];
+        let mut len = frame(false, 1, &text[..7], &mut input);
+        len += frame(false, 0, &text[7..14], &mut input[len..]);
+        len += frame(true, 0, &text[14..], &mut input[len..]);
+
+        let (_, written) = run(&input[..len], 1, |_, result| {
+            assert_eq!(result, Ok(Message::Text("Hello-µ@ßöäüàá-UTF-8!!")))
+        });
+
+        assert_eq!(written, 0);
+
+        // Autobahn 6.3.1: invalid UTF-8 in a single frame
+        fails_with(
+            b"\x81\x0a\xce\xba\xe1\xbd\xb9\xcf\x83\xce\xbc\xce",
+            Error::InvalidUtf8,
+            1007,
+        );
+
+        // Autobahn 6.4.1: invalid UTF-8 detected in the first fragment, before the message ends
+        fails_with(b"\x01\x04\xf4\x90\x80\x80", Error::InvalidUtf8, 1007);
+
+        // Autobahn 6.3.2: a message ending in the middle of a character
+        fails_with(b"\x01\x02\xce\xba\x80\x01\xe1", Error::InvalidUtf8, 1007);
+    }
+
+    #[cfg(feature = "heartbeat")]
+    #[test]
+    fn test_heartbeat() {
+        let mut output = [0; 64];
+
+        // An unsolicited Pong, which does not count as an answer to the Pings sent later, then nothing
+        let input = b"\x8a\x04\x00\x00\x00\x01";
+
+        let written = embassy_futures::block_on(async {
+            let mut session = WsSession::new(
+                Conn {
+                    input,
+                    output: &mut output,
+                    written: 0,
+                    idle: true,
+                },
+                || None,
+            )
+            .with_heartbeat(Duration::from_millis(10), 2);
+
+            let mut buf = [0; 16];
+
+            assert_eq!(
+                pin!(session.recv_message(&mut buf)).await,
+                Err(Error::Timeout)
+            );
+
+            session.release().written
+        });
+
+        // Two unanswered Pings, then the Close frame
+        let mut frames = &output[..written];
+
+        for payload in [[0, 0, 0, 1], [0, 0, 0, 2]] {
+            let (header, offset) = FrameHeader::deserialize(frames).unwrap();
+
+            assert_eq!(header.frame_type, FrameType::Ping);
+            assert_eq!(&frames[offset..offset + 4], &payload);
+
+            frames = &frames[offset + 4..];
+        }
+
+        assert_eq!(close_code(frames), Some(1011));
+    }
+
+    #[test]
+    fn test_split() {
+        let mut output = [0; 64];
+        let mut unused = [0; 0];
+
+        let input = b"\x89\x04ping\x81\x02hi";
+
+        let written = embassy_futures::block_on(async {
+            let writer = WsSessionWriter::<NoopRawMutex, _, _>::new(
+                Conn {
+                    input: &[],
+                    output: &mut output,
+                    written: 0,
+                    idle: false,
+                },
+                || None,
+            );
+
+            let mut reader = WsSessionReader::new(
+                Conn {
+                    input,
+                    output: &mut unused,
+                    written: 0,
+                    idle: true,
+                },
+                &writer,
+            );
+
+            let mut buf = [0; 16];
+
+            assert_eq!(
+                pin!(reader.recv_message(&mut buf)).await,
+                Ok(Message::Text("hi"))
+            );
+
+            // A message can be sent while the reader waits for the next one
+            let result = pin!(select(
+                reader.recv_message(&mut buf),
+                writer.send_message(&Message::Binary(b"data")),
+            ))
+            .await;
+
+            assert!(matches!(result, Either::Second(Ok(()))));
+
+            writer.release().written
+        });
+
+        // The Pong is sent through the writer
+        assert_eq!(&output[..written], b"\x8a\x04ping\x82\x04data");
+    }
+
+    #[test]
+    fn test_split_close() {
+        let mut output = [0; 64];
+        let mut unused = [0; 0];
+
+        // The answer of the peer to our Close frame
+        let input = b"\x88\x02\x03\xe8";
+
+        let written = embassy_futures::block_on(async {
+            let writer = WsSessionWriter::<NoopRawMutex, _, _>::new(
+                Conn {
+                    input: &[],
+                    output: &mut output,
+                    written: 0,
+                    idle: false,
+                },
+                || None,
+            );
+
+            let mut reader = WsSessionReader::new(
+                Conn {
+                    input,
+                    output: &mut unused,
+                    written: 0,
+                    idle: false,
+                },
+                &writer,
+            );
+
+            pin!(writer.send_message(&Message::Close(Some((CloseCode::Normal, "")))))
+                .await
+                .unwrap();
+
+            let mut buf = [0; 16];
+
+            assert_eq!(
+                pin!(reader.recv_message(&mut buf)).await,
+                Ok(Message::Close(Some((CloseCode::Normal, ""))))
+            );
+            assert!(reader.is_closed());
+
+            assert_eq!(
+                pin!(writer.send_message(&Message::Text("late"))).await,
+                Err(Error::Invalid)
+            );
+
+            writer.release().written
+        });
+
+        // No echo of the Close frame of the peer, as we initiated the close handshake
+        assert_eq!(&output[..written], b"\x88\x02\x03\xe8");
+    }
+}
@@ -11,6 +11,9 @@ pub type Final = bool;
 #[cfg(feature = "embedded-svc")]
 pub use embedded_svc_compat::*;
 
+pub mod codec;
+#[cfg(feature = "deflate")]
+pub mod deflate;
 #[cfg(feature = "io")]
 pub mod io;
 
@@ -63,15 +66,216 @@ impl fmt::Display for FrameType {
     }
 }
 
+/// The maximum length of the payload of a control frame (Ping, Pong or Close), as per RFC 6455.
+pub const MAX_CONTROL_PAYLOAD_LEN: usize = 125;
+
+/// The status code of a Close frame, as per the RFC 6455 registry.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "std", derive(Hash))]
+pub enum CloseCode {
+    /// 1000
+    Normal,
+    /// 1001
+    GoingAway,
+    /// 1002
+    ProtocolError,
+    /// 1003
+    UnsupportedData,
+    /// 1005; never sent on the wire, denotes a Close frame without a status code
+    NoStatusReceived,
+    /// 1006; never sent on the wire, denotes a connection closed without a Close frame
+    Abnormal,
+    /// 1007
+    InvalidPayload,
+    /// 1008
+    PolicyViolation,
+    /// 1009
+    MessageTooBig,
+    /// 1010
+    MandatoryExtension,
+    /// 1011
+    InternalError,
+    /// 1012
+    ServiceRestart,
+    /// 1013
+    TryAgainLater,
+    /// 1014
+    BadGateway,
+    /// 1015; never sent on the wire, denotes a failed TLS handshake
+    TlsHandshake,
+    /// 3000 - 3999, registered with IANA by libraries, frameworks and applications
+    Registered(u16),
+    /// 4000 - 4999, for private use by applications
+    Private(u16),
+    /// Any other code, reserved by the RFC
+    Reserved(u16),
+}
+
+impl CloseCode {
+    pub const fn new(code: u16) -> Self {
+        match code {
+            1000 => Self::Normal,
+            1001 => Self::GoingAway,
+            1002 => Self::ProtocolError,
+            1003 => Self::UnsupportedData,
+            1005 => Self::NoStatusReceived,
+            1006 => Self::Abnormal,
+            1007 => Self::InvalidPayload,
+            1008 => Self::PolicyViolation,
+            1009 => Self::MessageTooBig,
+            1010 => Self::MandatoryExtension,
+            1011 => Self::InternalError,
+            1012 => Self::ServiceRestart,
+            1013 => Self::TryAgainLater,
+            1014 => Self::BadGateway,
+            1015 => Self::TlsHandshake,
+            3000..=3999 => Self::Registered(code),
+            4000..=4999 => Self::Private(code),
+            _ => Self::Reserved(code),
+        }
+    }
+
+    pub const fn code(&self) -> u16 {
+        match self {
+            Self::Normal => 1000,
+            Self::GoingAway => 1001,
+            Self::ProtocolError => 1002,
+            Self::UnsupportedData => 1003,
+            Self::NoStatusReceived => 1005,
+            Self::Abnormal => 1006,
+            Self::InvalidPayload => 1007,
+            Self::PolicyViolation => 1008,
+            Self::MessageTooBig => 1009,
+            Self::MandatoryExtension => 1010,
+            Self::InternalError => 1011,
+            Self::ServiceRestart => 1012,
+            Self::TryAgainLater => 1013,
+            Self::BadGateway => 1014,
+            Self::TlsHandshake => 1015,
+            Self::Registered(code) | Self::Private(code) | Self::Reserved(code) => *code,
+        }
+    }
+
+    /// Return `true` if the code may be sent in a Close frame.
+    pub const fn is_allowed_on_wire(&self) -> bool {
+        !matches!(
+            Self::new(self.code()),
+            Self::NoStatusReceived | Self::Abnormal | Self::TlsHandshake | Self::Reserved(_)
+        )
+    }
+
+    /// Decode the payload of a Close frame into an optional status code and reason.
+    ///
+    /// Fails with `Error::Protocol` if the payload is one byte long or if the status code
+    /// may not be sent on the wire, and with `Error::InvalidUtf8` if the reason is not valid UTF-8.
+    pub fn decode_payload(payload: &[u8]) -> Result<Option<(Self, &str)>, Error<()>> {
+        match payload.len() {
+            0 => Ok(None),
+            1 => Err(Error::Protocol),
+            _ => {
+                let code = Self::new(u16::from_be_bytes([payload[0], payload[1]]));
+
+                if !code.is_allowed_on_wire() {
+                    return Err(Error::Protocol);
+                }
+
+                let reason = core::str::from_utf8(&payload[2..]).map_err(|_| Error::InvalidUtf8)?;
+
+                Ok(Some((code, reason)))
+            }
+        }
+    }
+
+    /// Encode an optional status code and reason into the payload of a Close frame.
+    ///
+    /// Fails with `Error::Invalid` if the status code may not be sent on the wire
+    /// and with `Error::InvalidLen` if the payload would not fit in `buf` or in a control frame.
+    pub fn encode_payload(close: Option<(Self, &str)>, buf: &mut [u8]) -> Result<usize, Error<()>> {
+        let Some((code, reason)) = close else {
+            return Ok(0);
+        };
+
+        if !code.is_allowed_on_wire() {
+            return Err(Error::Invalid);
+        }
+
+        let len = 2 + reason.len();
+
+        if len > buf.len() || len > MAX_CONTROL_PAYLOAD_LEN {
+            return Err(Error::InvalidLen);
+        }
+
+        buf[..2].copy_from_slice(&code.code().to_be_bytes());
+        buf[2..len].copy_from_slice(reason.as_bytes());
+
+        Ok(len)
+    }
+}
+
+impl From<u16> for CloseCode {
+    fn from(code: u16) -> Self {
+        Self::new(code)
+    }
+}
+
+impl From<CloseCode> for u16 {
+    fn from(code: CloseCode) -> Self {
+        code.code()
+    }
+}
+
+impl fmt::Display for CloseCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Normal => write!(f, "Normal"),
+            Self::GoingAway => write!(f, "Going away"),
+            Self::ProtocolError => write!(f, "Protocol error"),
+            Self::UnsupportedData => write!(f, "Unsupported data"),
+            Self::NoStatusReceived => write!(f, "No status received"),
+            Self::Abnormal => write!(f, "Abnormal closure"),
+            Self::InvalidPayload => write!(f, "Invalid payload data"),
+            Self::PolicyViolation => write!(f, "Policy violation"),
+            Self::MessageTooBig => write!(f, "Message too big"),
+            Self::MandatoryExtension => write!(f, "Mandatory extension"),
+            Self::InternalError => write!(f, "Internal error"),
+            Self::ServiceRestart => write!(f, "Service restart"),
+            Self::TryAgainLater => write!(f, "Try again later"),
+            Self::BadGateway => write!(f, "Bad gateway"),
+            Self::TlsHandshake => write!(f, "TLS handshake"),
+            Self::Registered(code) => write!(f, "Registered ({code})"),
+            Self::Private(code) => write!(f, "Private ({code})"),
+            Self::Reserved(code) => write!(f, "Reserved ({code})"),
+        }
+    }
+}
+
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub enum Error<E> {
     Incomplete(usize),
     Invalid,
     BufferOverflow,
     InvalidLen,
+    /// The peer violated RFC 6455
+    Protocol,
+    /// The peer sent a Text message or a Close reason which is not valid UTF-8
+    InvalidUtf8,
+    /// The peer did not answer Ping frames in time
+    Timeout,
     Io(E),
 }
 
+impl<E> Error<E> {
+    /// Return the status code with which the connection should be closed because of this error, if any.
+    pub fn close_code(&self) -> Option<CloseCode> {
+        match self {
+            Self::Protocol => Some(CloseCode::ProtocolError),
+            Self::InvalidUtf8 => Some(CloseCode::InvalidPayload),
+            Self::Timeout => Some(CloseCode::InternalError),
+            _ => None,
+        }
+    }
+}
+
 impl Error<()> {
     pub fn recast<E>(self) -> Error<E> {
         match self {
@@ -79,6 +283,9 @@ impl Error<()> {
             Self::Invalid => Error::Invalid,
             Self::BufferOverflow => Error::BufferOverflow,
             Self::InvalidLen => Error::InvalidLen,
+            Self::Protocol => Error::Protocol,
+            Self::InvalidUtf8 => Error::InvalidUtf8,
+            Self::Timeout => Error::Timeout,
             Self::Io(_) => panic!(),
         }
     }
@@ -94,6 +301,9 @@ where
             Self::Invalid => write!(f, "Invalid"),
             Self::BufferOverflow => write!(f, "Buffer overflow"),
             Self::InvalidLen => write!(f, "Invalid length"),
+            Self::Protocol => write!(f, "Protocol error"),
+            Self::InvalidUtf8 => write!(f, "Invalid UTF-8"),
+            Self::Timeout => write!(f, "Timeout"),
             Self::Io(err) => write!(f, "IO error: {}", err),
         }
     }
@@ -102,7 +312,7 @@ where
 #[cfg(feature = "std")]
 impl<E> std::error::Error for Error<E> where E: std::error::Error {}
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct FrameHeader {
     pub frame_type: FrameType,
     pub payload_len: u64,
@@ -119,6 +329,18 @@ impl FrameHeader {
     .serialized_len();
 
     pub fn deserialize(buf: &[u8]) -> Result<(Self, usize), Error<()>> {
+        Self::deserialize_rsv1(buf, false).map(|(header, _, offset)| (header, offset))
+    }
+
+    /// Like `deserialize`, but for connections which negotiated the `permessage-deflate` extension,
+    /// where the RSV1 bit marks the first frame of a compressed Text or Binary message.
+    ///
+    /// Return the header, whether the RSV1 bit is set, and the length of the header.
+    pub fn deserialize_compressed(buf: &[u8]) -> Result<(Self, bool, usize), Error<()>> {
+        Self::deserialize_rsv1(buf, true)
+    }
+
+    fn deserialize_rsv1(buf: &[u8], compression: bool) -> Result<(Self, bool, usize), Error<()>> {
         let mut expected_len = 2_usize;
 
         if buf.len() < expected_len {
@@ -126,19 +348,32 @@ impl FrameHeader {
         } else {
             let final_frame = buf[0] & 0x80 != 0;
 
-            let rsv = buf[0] & 0x70;
+            let compressed = buf[0] & 0x40 != 0;
+
+            // RSV2 and RSV3 are not used by any supported extension
+            let rsv = buf[0] & 0x30;
             if rsv != 0 {
-                return Err(Error::Invalid);
+                return Err(Error::Protocol);
             }
 
             let opcode = buf[0] & 0x0f;
             if (3..=7).contains(&opcode) || opcode >= 11 {
-                return Err(Error::Invalid);
+                return Err(Error::Protocol);
+            }
+
+            // RSV1 is only used by `permessage-deflate`, on the first frame of a message
+            if compressed && (!compression || !(1..=2).contains(&opcode)) {
+                return Err(Error::Protocol);
             }
 
             let mut payload_len = (buf[1] & 0x7f) as u64;
             let mut payload_offset = 2;
 
+            // Control frames cannot be fragmented, and their payload is at most 125 bytes long
+            if opcode >= 8 && (!final_frame || payload_len as usize > MAX_CONTROL_PAYLOAD_LEN) {
+                return Err(Error::Protocol);
+            }
+
             if payload_len == 126 {
                 expected_len += 2;
 
@@ -193,16 +428,11 @@ impl FrameHeader {
 
             let frame_header = FrameHeader {
                 frame_type,
-                payload_len: matches!(
-                    frame_type,
-                    FrameType::Binary(_) | FrameType::Text(_) | FrameType::Continue(_)
-                )
-                .then(|| payload_len)
-                .unwrap_or(0),
+                payload_len,
                 mask_key,
             };
 
-            Ok((frame_header, payload_offset))
+            Ok((frame_header, compressed, payload_offset))
         }
     }
 
@@ -219,6 +449,26 @@ impl FrameHeader {
     }
 
     pub fn serialize(&self, buf: &mut [u8]) -> Result<usize, Error<()>> {
+        self.serialize_rsv1(false, buf)
+    }
+
+    /// Like `serialize`, but setting the RSV1 bit if `compressed` is `true`, i.e. if the frame is
+    /// the first frame of a message compressed with the `permessage-deflate` extension.
+    ///
+    /// Fails with `Error::Invalid` if `compressed` is `true` for a frame which is not a Text or Binary frame.
+    pub fn serialize_compressed(
+        &self,
+        compressed: bool,
+        buf: &mut [u8],
+    ) -> Result<usize, Error<()>> {
+        if compressed && !matches!(self.frame_type, FrameType::Text(_) | FrameType::Binary(_)) {
+            return Err(Error::Invalid);
+        }
+
+        self.serialize_rsv1(compressed, buf)
+    }
+
+    fn serialize_rsv1(&self, compressed: bool, buf: &mut [u8]) -> Result<usize, Error<()>> {
         if buf.len() < self.serialized_len() {
             return Err(Error::InvalidLen);
         }
@@ -230,6 +480,10 @@ impl FrameHeader {
             buf[0] |= 0x80;
         }
 
+        if compressed {
+            buf[0] |= 0x40;
+        }
+
         let opcode = match self.frame_type {
             FrameType::Text(_) => 1,
             FrameType::Binary(_) => 2,
@@ -299,6 +553,76 @@ impl FrameHeader {
     }
 }
 
+/// An incremental UTF-8 validator, for Text messages received in multiple fragments or chunks.
+///
+/// Unlike validating the whole message at the end, invalid data is detected
+/// as soon as it is received, even if a character is split across fragments.
+#[derive(Clone, Debug, Default)]
+pub struct Utf8Validator {
+    pending: [u8; 4],
+    pending_len: usize,
+}
+
+impl Utf8Validator {
+    pub const fn new() -> Self {
+        Self {
+            pending: [0; 4],
+            pending_len: 0,
+        }
+    }
+
+    /// Validate the next chunk of the message.
+    pub fn push(&mut self, mut data: &[u8]) -> Result<(), Error<()>> {
+        if self.pending_len > 0 {
+            let char_len = match self.pending[0] {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                _ => 4,
+            };
+
+            let len = (char_len - self.pending_len).min(data.len());
+
+            self.pending[self.pending_len..self.pending_len + len].copy_from_slice(&data[..len]);
+            self.pending_len += len;
+
+            data = &data[len..];
+
+            match core::str::from_utf8(&self.pending[..self.pending_len]) {
+                Ok(_) => self.pending_len = 0,
+                Err(e) if e.error_len().is_none() => return Ok(()),
+                Err(_) => return Err(Error::InvalidUtf8),
+            }
+        }
+
+        match core::str::from_utf8(data) {
+            Ok(_) => Ok(()),
+            Err(e) if e.error_len().is_none() => {
+                // An incomplete character at the end of the chunk
+                let tail = &data[e.valid_up_to()..];
+
+                self.pending[..tail.len()].copy_from_slice(tail);
+                self.pending_len = tail.len();
+
+                Ok(())
+            }
+            Err(_) => Err(Error::InvalidUtf8),
+        }
+    }
+
+    /// Complete the validation of the message, and reset the validator for the next one.
+    pub fn finish(&mut self) -> Result<(), Error<()>> {
+        let complete = self.pending_len == 0;
+
+        self.pending_len = 0;
+
+        if complete {
+            Ok(())
+        } else {
+            Err(Error::InvalidUtf8)
+        }
+    }
+}
+
 impl fmt::Display for FrameHeader {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
@@ -309,6 +633,153 @@ impl fmt::Display for FrameHeader {
     }
 }
 
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_close_code() {
+        for code in [1000, 1001, 1002, 1003, 1007, 1011, 1014, 3000, 4999] {
+            assert_eq!(CloseCode::new(code).code(), code);
+            assert!(CloseCode::new(code).is_allowed_on_wire());
+        }
+
+        for code in [0, 999, 1004, 1005, 1006, 1015, 1016, 2999, 5000] {
+            assert_eq!(CloseCode::new(code).code(), code);
+            assert!(!CloseCode::new(code).is_allowed_on_wire());
+        }
+
+        let mut buf = [0; MAX_CONTROL_PAYLOAD_LEN];
+
+        let len = CloseCode::encode_payload(Some((CloseCode::GoingAway, "bye")), &mut buf).unwrap();
+        assert_eq!(&buf[..len], b"\x03\xe9bye");
+        assert_eq!(
+            CloseCode::decode_payload(&buf[..len]),
+            Ok(Some((CloseCode::GoingAway, "bye")))
+        );
+
+        assert_eq!(CloseCode::decode_payload(&[]), Ok(None));
+        assert_eq!(CloseCode::decode_payload(&[0x03]), Err(Error::Protocol));
+        assert_eq!(
+            CloseCode::decode_payload(&[0x03, 0xed]),
+            Err(Error::Protocol)
+        );
+        assert_eq!(
+            CloseCode::decode_payload(&[0x03, 0xe8, 0xff]),
+            Err(Error::InvalidUtf8)
+        );
+
+        assert_eq!(
+            CloseCode::encode_payload(Some((CloseCode::Abnormal, "")), &mut buf),
+            Err(Error::Invalid)
+        );
+        assert_eq!(
+            CloseCode::encode_payload(Some((CloseCode::Normal, &"x".repeat(124))), &mut buf),
+            Err(Error::InvalidLen)
+        );
+    }
+
+    #[test]
+    fn test_deserialize_strict() {
+        // Unmasked "Hello"
+        let (header, offset) = FrameHeader::deserialize(b"\x81\x05Hello").unwrap();
+        assert_eq!(offset, 2);
+        assert_eq!(header.frame_type, FrameType::Text(false));
+        assert_eq!(
+            FrameHeader::deserialize_compressed(b"\x81\x05Hello"),
+            Ok((header, false, 2))
+        );
+
+        // RSV1 without and with a negotiated extension
+        assert_eq!(
+            FrameHeader::deserialize(b"\xc1\x05").map(|_| ()),
+            Err(Error::Protocol)
+        );
+
+        let (header, compressed, _) = FrameHeader::deserialize_compressed(b"\xc1\x05").unwrap();
+        assert_eq!(header.frame_type, FrameType::Text(false));
+        assert!(compressed);
+
+        let mut buf = [0; FrameHeader::MAX_LEN];
+        assert_eq!(header.serialize_compressed(true, &mut buf), Ok(2));
+        assert_eq!(&buf[..2], b"\xc1\x05");
+
+        // RSV1 on continuation and control frames
+        for invalid in [&b"\xc0\x05"[..], &b"\xc9\x00"[..]] {
+            assert_eq!(
+                FrameHeader::deserialize_compressed(invalid).map(|_| ()),
+                Err(Error::Protocol)
+            );
+        }
+
+        let ping = FrameHeader {
+            frame_type: FrameType::Ping,
+            payload_len: 0,
+            mask_key: None,
+        };
+        assert_eq!(
+            ping.serialize_compressed(true, &mut buf),
+            Err(Error::Invalid)
+        );
+
+        for invalid in [
+            // RSV2, RSV3
+            &b"\xa1\x00"[..],
+            &b"\x91\x00"[..],
+            // Reserved opcodes
+            &b"\x83\x00"[..],
+            &b"\x8b\x00"[..],
+            // Fragmented Ping
+            &b"\x09\x00"[..],
+            // Ping and Close with a payload of 126 bytes
+            &b"\x89\x7e\x00\x7e"[..],
+            &b"\x88\x7e\x00\x7e"[..],
+        ] {
+            assert_eq!(
+                FrameHeader::deserialize(invalid).map(|_| ()),
+                Err(Error::Protocol)
+            );
+        }
+
+        assert_eq!(
+            Error::<()>::Protocol.close_code(),
+            Some(CloseCode::ProtocolError)
+        );
+        assert_eq!(
+            Error::<()>::InvalidUtf8.close_code(),
+            Some(CloseCode::InvalidPayload)
+        );
+    }
+
+    #[test]
+    fn test_utf8_validator() {
+        let text = "κόσμε €𝄞";
+
+        for split in 0..text.len() {
+            let (first, second) = text.as_bytes().split_at(split);
+
+            let mut validator = Utf8Validator::new();
+            assert!(validator.push(first).is_ok());
+            assert!(validator.push(second).is_ok());
+            assert!(validator.finish().is_ok());
+        }
+
+        let mut validator = Utf8Validator::new();
+
+        // Truncated at the end of the message
+        assert!(validator.push(b"ok\xe2\x82").is_ok());
+        assert_eq!(validator.finish(), Err(Error::InvalidUtf8));
+
+        // Invalid continuation of a split character, detected without waiting for the end
+        assert!(validator.push(b"\xf0\x9d").is_ok());
+        assert_eq!(validator.push(b"\x28"), Err(Error::InvalidUtf8));
+
+        // Encoded surrogates
+        let mut validator = Utf8Validator::new();
+        assert_eq!(validator.push(b"\xed\xa0\x80"), Err(Error::InvalidUtf8));
+    }
+}
+
 #[cfg(feature = "embedded-svc")]
 mod embedded_svc_compat {
     use core::convert::TryFrom;
@@ -0,0 +1,95 @@
+use core::net::Ipv4Addr;
+use core::pin::pin;
+
+use edge_mdns::browse::{BrowseEvent, Browser};
+use edge_mdns::buf::{BufferAccess, VecBufAccess};
+use edge_mdns::io::{self, MdnsIoError, DEFAULT_SOCKET};
+use edge_mdns::PeerAnswersMdnsHandler;
+use edge_nal::{UdpBind, UdpSplit};
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::signal::Signal;
+
+use log::*;
+
+use rand::{thread_rng, RngCore};
+
+// Change this to the service type you would like to browse
+const SERVICE: &str = "_http";
+const PROTOCOL: &str = "_tcp";
+
+fn main() {
+    env_logger::init_from_env(
+        env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "info"),
+    );
+
+    let stack = edge_nal_std::Stack::new();
+
+    let (recv_buf, send_buf) = (
+        VecBufAccess::<NoopRawMutex, 1500>::new(),
+        VecBufAccess::<NoopRawMutex, 1500>::new(),
+    );
+
+    futures_lite::future::block_on(run::<edge_nal_std::Stack, _, _>(
+        &stack, &recv_buf, &send_buf,
+    ))
+    .unwrap();
+}
+
+async fn run<T, RB, SB>(stack: &T, recv_buf: RB, send_buf: SB) -> Result<(), MdnsIoError<T::Error>>
+where
+    T: UdpBind,
+    RB: BufferAccess<[u8]>,
+    SB: BufferAccess<[u8]>,
+{
+    info!("About to browse the {SERVICE}.{PROTOCOL}.local services on the local network.");
+
+    let mut socket = io::bind(stack, DEFAULT_SOCKET, Some(Ipv4Addr::UNSPECIFIED), Some(0)).await?;
+
+    let (recv, send) = socket.split();
+
+    // Not used, as we do not announce anything
+    let signal = Signal::new();
+
+    let mdns = io::Mdns::<NoopRawMutex, _, _, _, _>::new(
+        Some(Ipv4Addr::UNSPECIFIED),
+        Some(0),
+        recv,
+        send,
+        recv_buf,
+        send_buf,
+        |buf| thread_rng().fill_bytes(buf),
+        &signal,
+    );
+
+    let browser = Browser::<NoopRawMutex, _, 16>::new(
+        SERVICE,
+        PROTOCOL,
+        |event: BrowseEvent<'_>| match event {
+            BrowseEvent::Added(instance) | BrowseEvent::Updated(instance) => {
+                info!(
+                    "{}: {}.local:{} ({:?}, {:?})",
+                    instance.name(),
+                    instance.hostname().unwrap_or_default(),
+                    instance.port().unwrap_or_default(),
+                    instance.ipv4(),
+                    instance.ipv6(),
+                );
+
+                for (key, value) in instance.txt() {
+                    info!("    {key}={value}");
+                }
+            }
+            BrowseEvent::Removed(instance) => info!("{}: gone", instance.name()),
+        },
+    );
+
+    let mut run = pin!(mdns.run(PeerAnswersMdnsHandler::new(&browser)));
+    let mut browse = pin!(mdns.browse(&browser));
+
+    match select(&mut run, &mut browse).await {
+        Either::First(result) => result,
+        Either::Second(result) => result,
+    }
+}
@@ -1,9 +1,9 @@
-use core::net::{Ipv4Addr, Ipv6Addr};
+use core::net::Ipv4Addr;
 
 use edge_mdns::buf::{BufferAccess, VecBufAccess};
 use edge_mdns::domain::base::Ttl;
+use edge_mdns::host::Host;
 use edge_mdns::io::{self, MdnsIoError, DEFAULT_SOCKET};
-use edge_mdns::{host::Host, HostAnswersMdnsHandler};
 use edge_nal::{UdpBind, UdpSplit};
 
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
@@ -54,13 +54,6 @@ where
 
     let (recv, send) = socket.split();
 
-    let host = Host {
-        hostname: our_name,
-        ipv4: our_ip,
-        ipv6: Ipv6Addr::UNSPECIFIED,
-        ttl: Ttl::from_secs(60),
-    };
-
     // A way to notify the mDNS responder that the data in `Host` had changed
     // We don't use it in this example, because the data is hard-coded
     let signal = Signal::new();
@@ -76,5 +69,16 @@ where
         &signal,
     );
 
-    mdns.run(HostAnswersMdnsHandler::new(&host)).await
+    let host = Host {
+        hostname: our_name,
+        ipv4: &[our_ip],
+        ipv6: &[],
+        ttl: Ttl::from_secs(60),
+    };
+
+    // Our name is probed first, and renamed to e.g. "mypc-2" if another host on the network already uses it
+    mdns.run_probed(&host, &[], |_, name| {
+        info!("Our name is already taken, use {name}.local instead");
+    })
+    .await
 }